@@ -0,0 +1,82 @@
+use super::{Hai, State};
+
+/// Errors of parsing tehai and operating the game, for library users to match on.
+/// `Display` gives the same messages as the former `String` errors.
+///
+/// # Member
+/// * InvalidTile: A hai is invalid for the number of players.
+/// * FifthTile: A hai appears more than four times.
+/// * TooManyTiles: Several types of hai appear more than four times, with their numbers.
+/// * WrongTileCount: Tehai has a number of hai not supported, such as initializing the game.
+/// * HaiyamaExhausted: No such hai left in haiyama.
+/// * IllegalState: An operation not supported at current state, `op` is its debug string.
+/// * Other: Any other error, such as a syntax error of input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MahjongError {
+    InvalidTile(Hai),
+    FifthTile(Hai),
+    TooManyTiles(Vec<(Hai, u8)>),
+    WrongTileCount { expected: Vec<usize>, got: usize },
+    HaiyamaExhausted(Hai),
+    IllegalState { op: String, state: State },
+    Other(String),
+}
+
+impl std::fmt::Display for MahjongError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MahjongError::InvalidTile(hai) => write!(f, "'{}' is invalid hai.", hai),
+            MahjongError::FifthTile(hai) => write!(f, "Fifth {} found.", hai),
+            MahjongError::TooManyTiles(over_limit) => {
+                let over_limit: Vec<String> = over_limit
+                    .iter()
+                    .map(|(hai, number)| format!("{}({})", hai, number))
+                    .collect();
+                write!(f, "Too many: {}.", over_limit.join(", "))
+            }
+            MahjongError::WrongTileCount { expected, got } => {
+                let expected: Vec<String> =
+                    expected.iter().map(|number| number.to_string()).collect();
+                write!(
+                    f,
+                    "Cannot initialize tehai with {} juntehai, only {} are supported.",
+                    got,
+                    expected.join(" and ")
+                )
+            }
+            MahjongError::HaiyamaExhausted(hai) => write!(
+                f,
+                "Already no '{}' in haiyama, cannot discard more one.",
+                hai
+            ),
+            MahjongError::IllegalState { op, state } => {
+                write!(f, "Unsupported opretion '{}' at state '{:?}'.", op, state)
+            }
+            MahjongError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for MahjongError {}
+
+impl From<String> for MahjongError {
+    fn from(message: String) -> Self {
+        MahjongError::Other(message)
+    }
+}
+
+/// Hai over the limit of 4, see `TooManyTiles`. A single one becomes `FifthTile`.
+impl From<Vec<(Hai, u8)>> for MahjongError {
+    fn from(over_limit: Vec<(Hai, u8)>) -> Self {
+        match over_limit.as_slice() {
+            [(hai, _)] => MahjongError::FifthTile(*hai),
+            _ => MahjongError::TooManyTiles(over_limit),
+        }
+    }
+}
+
+impl From<MahjongError> for String {
+    fn from(error: MahjongError) -> Self {
+        error.to_string()
+    }
+}
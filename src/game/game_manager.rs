@@ -1,17 +1,34 @@
-use super::{Hai, Haiyama, MachiCondition, Mentsu, PlayerNumber, Tehai};
+use super::{
+    Hai, Haiyama, MachiCondition, MahjongError, Mentsu, PlayerNumber, ScoringRules, Suit, Tehai,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+
+/// An operation in history, with state, sutehai type and temporary furiten before it.
+pub type HistoryEntry = (Operation, State, BTreeSet<Hai>, bool);
 
 /// The game manager.
 /// Include everything that a complete mahjong game need.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GameManager {
     haiyama: Haiyama,
     tehai: Option<Tehai>,
     sutehai_type: BTreeSet<Hai>,
     pub state: State,
     player_number: PlayerNumber,
-    history: Vec<(Operation, State, BTreeSet<Hai>)>,
+    history: Vec<HistoryEntry>,
+    temporary_furiten: bool,
+    warnings: Vec<String>,
+    ruleset: Ruleset,
+    seat_wind: Hai,
+    round_wind: Hai,
+    dora_indicators: Vec<Hai>,
+    dora_indicators_in_haiyama: Vec<Hai>,
+    kita: u8,
+    set_aside: Vec<Hai>,
+    river: Vec<Hai>,
 }
 
 /// Type of kan.
@@ -22,7 +39,7 @@ pub struct GameManager {
 /// * Ankan: 暗槓
 /// * kantsu: 槓子
 /// * rinshanhai: 嶺上牌
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Kan {
     Daiminkan {
         kantsu: Mentsu,
@@ -50,7 +67,7 @@ pub enum Kan {
 /// * Pon: ポン
 /// * Kan: カン
 /// * nakihai: 鳴き牌
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Naku {
     Chii { juntsu: Mentsu, nakihai: Hai },
     Pon(Mentsu),
@@ -58,33 +75,103 @@ pub enum Naku {
 }
 
 /// Operation on haiyama.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum HaiyamaOperation {
     Add(Vec<Hai>),
     Discard(Vec<Hai>),
 }
 
 /// Operation on tehai.
-#[derive(Clone, Debug)]
+///
+/// # Japanese
+/// * Kita: 北抜き, setting aside a `4z` as dora on 3-players mode.
+///
+/// `SetAside` moves a hai out of juntehai without discarding it, so it is neither sutehai
+/// nor put back to haiyama, such as for modeling a part of tehai.
+///
+/// `taken` of `Naku` is the hai taken from juntehai, aka included. It is filled by
+/// `GameManager::operate` and given back by `GameManager::back`, so leave it empty.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum TehaiOperation {
     Initialize(Tehai),
-    Add { hai: Hai, haiyama_sensitive: bool },
+    Add {
+        hai: Hai,
+        haiyama_sensitive: bool,
+    },
     Discard(Hai),
-    Naku { kind: Naku, haiyama_sensitive: bool },
+    Naku {
+        kind: Naku,
+        haiyama_sensitive: bool,
+        taken: Vec<Hai>,
+    },
+    Kita {
+        haiyama_sensitive: bool,
+    },
+    SetAside(Hai),
 }
 
 /// Valid operation for game manager.
-#[derive(Clone, Debug)]
+///
+/// `River` appends hai to the river of the player in order. They are also sutehai for
+/// furiten and discarded from haiyama, unlike `HaiyamaOperation::Discard` which only edits
+/// haiyama.
+///
+/// # Japanese
+/// * River: 河
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Operation {
     Haiyama {
         kind: HaiyamaOperation,
         haiyama_sensitive: bool,
     },
     Tehai(TehaiOperation),
+    River {
+        hai_vec: Vec<Hai>,
+        haiyama_sensitive: bool,
+    },
+}
+
+/// Rules differing between venues.
+///
+/// Only `kuikae_allowed` and `kita_dora` are enforced by `GameManager` for now, others are
+/// kept for analysis to consult. The default follows the behavior without a ruleset.
+///
+/// # Japanese
+/// * kuitan: 喰いタン
+/// * kuikae: 喰い替え
+/// * atozuke: 後付け
+/// * kita: 北抜き
+/// * aka: 赤
+///
+/// # Member
+/// * kuitan: if tanyao is available with naku.
+/// * kuikae_allowed: if hai forbidden by kuikae can be discarded right after chii and pon.
+/// * atozuke: if agari is allowed when yaku depends on the machihai.
+/// * kita_dora: if `4z` can be set aside as kita (counted as dora) on 3-players mode.
+/// * aka_count: number of aka in all, at most one for each suit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ruleset {
+    pub kuitan: bool,
+    pub kuikae_allowed: bool,
+    pub atozuke: bool,
+    pub kita_dora: bool,
+    pub aka_count: u8,
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Self {
+            kuitan: true,
+            kuikae_allowed: false,
+            atozuke: true,
+            kita_dora: true,
+            aka_count: 3,
+        }
+    }
 }
 
 /// Game state.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum State {
     WaitToInit,
     FullHai,
@@ -92,7 +179,73 @@ pub enum State {
     WaitForRinshanhai,
 }
 
+/// How safe a hai is to discard against ryanmen, from the safest.
+///
+/// # Japanese
+/// * Genbutsu: 現物, already in sutehai.
+/// * Suji: 筋, every ryanmen waiting on it would be furiten.
+/// * NoChance: ノーチャンス, every ryanmen waiting on it is furiten or impossible because
+///   no hai of it left (kabe, 壁).
+/// * OneChance: ワンチャンス, as `NoChance` but some ryanmen need a hai with only one left.
+/// * Dangerous: 危険, none of above.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Safety {
+    Genbutsu,
+    Suji,
+    NoChance,
+    OneChance,
+    Dangerous,
+}
+
+impl Safety {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!(match self {
+            Safety::Genbutsu => "genbutsu",
+            Safety::Suji => "suji",
+            Safety::NoChance => "no_chance",
+            Safety::OneChance => "one_chance",
+            Safety::Dangerous => "dangerous",
+        })
+    }
+}
+
+impl std::fmt::Display for Safety {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Safety::Genbutsu => "現物",
+                Safety::Suji => "筋",
+                Safety::NoChance => "ノーチャンス",
+                Safety::OneChance => "ワンチャンス",
+                Safety::Dangerous => "危険",
+            }
+        )
+    }
+}
+
 impl Kan {
+    /// Return kantsu of any type of kan.
+    pub fn kantsu(&self) -> &Mentsu {
+        match self {
+            Kan::Daiminkan { kantsu, .. }
+            | Kan::Kakan { kantsu, .. }
+            | Kan::Ankan { kantsu, .. }
+            | Kan::Unknown { kantsu, .. } => kantsu,
+        }
+    }
+
+    /// Return rinshanhai of any type of kan.
+    pub fn rinshanhai(&self) -> &Option<Hai> {
+        match self {
+            Kan::Daiminkan { rinshanhai, .. }
+            | Kan::Kakan { rinshanhai, .. }
+            | Kan::Ankan { rinshanhai, .. }
+            | Kan::Unknown { rinshanhai, .. } => rinshanhai,
+        }
+    }
+
     pub fn to_json(&self) -> serde_json::Value {
         let (tp, kantsu, rinshanhai) = match self {
             Kan::Daiminkan { kantsu, rinshanhai } => ("daiminkan", kantsu, rinshanhai),
@@ -157,11 +310,20 @@ impl TehaiOperation {
             TehaiOperation::Naku {
                 kind,
                 haiyama_sensitive,
+                ..
             } => json!({
                 "operation": "naku",
                 "naku": kind.to_json(),
                 "haiyama_sensitive": haiyama_sensitive,
             }),
+            TehaiOperation::Kita { haiyama_sensitive } => json!({
+                "operation": "kita",
+                "haiyama_sensitive": haiyama_sensitive,
+            }),
+            TehaiOperation::SetAside(hai) => json!({
+                "operation": "set_aside",
+                "hai": hai.to_string(),
+            }),
         }
     }
 }
@@ -208,6 +370,102 @@ impl Operation {
                 "operation": kind.to_json(),
                 "haiyama_sensitive": haiyama_sensitive,
             }),
+            Operation::River {
+                hai_vec,
+                haiyama_sensitive,
+            } => json!({
+                "object": "river",
+                "hai": hai_vec.iter().map(|hai| hai.to_string()).collect::<Vec<String>>(),
+                "haiyama_sensitive": haiyama_sensitive,
+            }),
+        }
+    }
+
+    /// Return the input command of this operation, such as `+4m`, `-1s` and `>234m`, so that
+    /// operations in history can be replayed by input them again.
+    ///
+    /// Chii puts nakihai last, kan of a known type uses the forced form like `>a1111m`, and
+    /// initialization gives the tehai string.
+    pub fn to_command_string(&self) -> String {
+        // Short form keeping the order, like `234m` for `2m3m4m`.
+        fn hai_string(hai_vec: &[Hai]) -> String {
+            let mut string = String::new();
+            for (index, hai) in hai_vec.iter().enumerate() {
+                let hai_string = hai.to_string();
+                string += &hai_string[..1];
+                match hai_vec.get(index + 1) {
+                    Some(next) if next.to_string()[1..] == hai_string[1..] => (),
+                    _ => string += &hai_string[1..],
+                }
+            }
+            string
+        }
+        fn mentsu_hai(mentsu: &Mentsu) -> Vec<Hai> {
+            match mentsu {
+                Mentsu::Juntsu(a, b, c) => vec![*a, *b, *c],
+                Mentsu::Koutsu(hai) => vec![*hai; 3],
+                Mentsu::Kantsu(hai) => vec![*hai; 4],
+            }
+        }
+        let mark = |haiyama_sensitive: bool| if haiyama_sensitive { "" } else { "!" };
+
+        match self {
+            Operation::Haiyama {
+                kind,
+                haiyama_sensitive,
+            } => match kind {
+                HaiyamaOperation::Add(hai_vec) => {
+                    format!("*{}+{}", mark(*haiyama_sensitive), hai_string(hai_vec))
+                }
+                HaiyamaOperation::Discard(hai_vec) => {
+                    format!("*{}-{}", mark(*haiyama_sensitive), hai_string(hai_vec))
+                }
+            },
+            Operation::River {
+                hai_vec,
+                haiyama_sensitive,
+            } => format!("river{} {}", mark(*haiyama_sensitive), hai_string(hai_vec)),
+            Operation::Tehai(TehaiOperation::Initialize(tehai)) => tehai.to_canonical_string(),
+            Operation::Tehai(TehaiOperation::Add {
+                hai,
+                haiyama_sensitive,
+            }) => format!("+{}{}", mark(*haiyama_sensitive), hai),
+            Operation::Tehai(TehaiOperation::Discard(hai)) => format!("-{}", hai),
+            Operation::Tehai(TehaiOperation::Kita { haiyama_sensitive }) => {
+                format!("kita{}", mark(*haiyama_sensitive))
+            }
+            Operation::Tehai(TehaiOperation::SetAside(hai)) => format!("set {}", hai),
+            Operation::Tehai(TehaiOperation::Naku {
+                kind,
+                haiyama_sensitive,
+                ..
+            }) => {
+                let naku = match kind {
+                    Naku::Chii { juntsu, nakihai } => {
+                        let mut hai_vec = mentsu_hai(juntsu);
+                        if let Some(index) =
+                            hai_vec.iter().position(|hai| *hai == nakihai.normalized())
+                        {
+                            hai_vec.remove(index);
+                        }
+                        hai_vec.push(*nakihai);
+                        hai_string(&hai_vec)
+                    }
+                    Naku::Pon(koutsu) => hai_string(&mentsu_hai(koutsu)),
+                    Naku::Kan(kan) => {
+                        let (forced, kantsu, rinshanhai) = match kan {
+                            Kan::Daiminkan { kantsu, rinshanhai } => ("d", kantsu, rinshanhai),
+                            Kan::Kakan { kantsu, rinshanhai } => ("k", kantsu, rinshanhai),
+                            Kan::Ankan { kantsu, rinshanhai } => ("a", kantsu, rinshanhai),
+                            Kan::Unknown { kantsu, rinshanhai } => ("", kantsu, rinshanhai),
+                        };
+                        let mut hai_vec = mentsu_hai(kantsu);
+                        hai_vec.extend(rinshanhai);
+                        format!("{}{}", forced, hai_string(&hai_vec))
+                    }
+                };
+                format!(">{}{}", mark(*haiyama_sensitive), naku)
+            }
         }
     }
 }
@@ -222,73 +480,610 @@ impl GameManager {
             state: State::WaitToInit,
             player_number,
             history: vec![],
+            temporary_furiten: false,
+            warnings: vec![],
+            ruleset: Ruleset::default(),
+            seat_wind: Hai::Jihai(1),
+            round_wind: Hai::Jihai(1),
+            dora_indicators: vec![],
+            dora_indicators_in_haiyama: vec![],
+            kita: 0,
+            set_aside: vec![],
+            river: vec![],
+        }
+    }
+
+    /// Set seat wind and round wind, both of which must be `1z` ~ `4z`. Default is `1z`
+    /// for both.
+    ///
+    /// # Japanese
+    /// * seat wind: 自風
+    /// * round wind: 場風
+    pub fn with_winds(mut self, seat_wind: Hai, round_wind: Hai) -> Result<Self, String> {
+        for wind in [seat_wind, round_wind].iter() {
+            match wind {
+                Hai::Jihai(1..=4) => (),
+                _ => return Err(format!("Invalid wind '{}', expected 1z ~ 4z.", wind)),
+            }
         }
+        self.seat_wind = seat_wind;
+        self.round_wind = round_wind;
+        Ok(self)
+    }
+
+    /// Allow discarding hai forbidden by kuikae after chii and pon.
+    ///
+    /// # Japanese
+    /// * kuikae: 喰い替え
+    pub fn with_kuikae_allowed(mut self, kuikae_allowed: bool) -> Self {
+        self.ruleset.kuikae_allowed = kuikae_allowed;
+        self
+    }
+
+    /// Set rules differing between venues, see `Ruleset`. Default is `Ruleset::default()`.
+    pub fn with_ruleset(mut self, ruleset: Ruleset) -> Self {
+        self.ruleset = ruleset;
+        self
     }
 
     pub fn reinitialize(&mut self, player_number: PlayerNumber) -> &mut Self {
-        *self = Self::new(player_number);
+        *self = Self {
+            seat_wind: self.seat_wind,
+            round_wind: self.round_wind,
+            ..Self::new(player_number).with_ruleset(self.ruleset)
+        };
         self
     }
 
+    /// Return the number of players.
+    pub fn player_number(&self) -> PlayerNumber {
+        self.player_number
+    }
+
+    /// Return rules of the game.
+    pub fn ruleset(&self) -> Ruleset {
+        self.ruleset
+    }
+
+    /// Check if the game is consistent, such as a game loaded from a save file. Fail if
+    /// haiyama or tehai is illegal for the number of players, or tehai does not match the
+    /// state: no tehai before initialized, 3*k+2 juntehai when full and 3*k+1 otherwise.
+    pub fn validate(&self) -> Result<(), MahjongError> {
+        self.haiyama.validate(self.player_number)?;
+        let consistent = match (&self.tehai, self.state) {
+            (None, State::WaitToInit) => true,
+            (Some(tehai), State::FullHai) => tehai.juntehai.len() % 3 == 2,
+            (Some(tehai), State::LackOneHai | State::WaitForRinshanhai) => {
+                tehai.juntehai.len() % 3 == 1
+            }
+            _ => false,
+        };
+        if !consistent {
+            return Err(MahjongError::Other(format!(
+                "Tehai does not match state '{:?}'.",
+                self.state
+            )));
+        }
+        match &self.tehai {
+            Some(tehai) => Ok(tehai.validate(self.player_number)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Serialize self to compact bytes with bincode, much faster than json for frequent
+    /// snapshots. Restore it by `from_bytes`.
+    #[cfg(feature = "bincode")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(self).map_err(|error| error.to_string())
+    }
+
+    /// Deserialize from bytes made by `to_bytes`, and check it as a save file.
+    #[cfg(feature = "bincode")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let game_manager: GameManager =
+            bincode::deserialize(bytes).map_err(|error| error.to_string())?;
+        game_manager.validate().map_err(|error| error.to_string())?;
+        Ok(game_manager)
+    }
+
+    /// Return seat wind.
+    pub fn seat_wind(&self) -> Hai {
+        self.seat_wind
+    }
+
+    /// Return round wind.
+    pub fn round_wind(&self) -> Hai {
+        self.round_wind
+    }
+
     /// Return a reference of haiyama
     pub fn haiyama(&self) -> &Haiyama {
         &self.haiyama
     }
 
+    /// Return if tehai is menzen, which is true before initializing. See `Tehai::is_menzen`.
+    ///
+    /// # Japanese
+    /// * menzen: 門前
+    pub fn is_menzen(&self) -> bool {
+        self.tehai.as_ref().is_none_or(Tehai::is_menzen)
+    }
+
     /// Return a reference of the set within sutehai.
     pub fn sutehai_type(&self) -> &BTreeSet<Hai> {
         &self.sutehai_type
     }
 
+    /// Return all types of sutehai in the order of display, see `Hai::sort_key`.
+    pub fn sutehai_type_sorted(&self) -> Vec<Hai> {
+        let mut sutehai_vec: Vec<Hai> = self.sutehai_type.iter().copied().collect();
+        sutehai_vec.sort_by_key(Hai::sort_key);
+        sutehai_vec
+    }
+
+    /// Return if any of waits is in sutehai, which means furiten. Aka is regarded as five.
+    /// Only keys of waits are checked, whatever the number of each is.
+    ///
+    /// # Japanese
+    /// * furiten: 振り聴
+    pub fn is_furiten(&self, waits: &BTreeMap<Hai, u8>) -> bool {
+        waits.keys().any(|hai| {
+            self.sutehai_type
+                .iter()
+                .any(|sutehai| sutehai.normalized() == hai.normalized())
+        })
+    }
+
     /// Return operation history.
-    pub fn history(&self) -> &Vec<(Operation, State, BTreeSet<Hai>)> {
+    pub fn history(&self) -> &Vec<HistoryEntry> {
         &self.history
     }
 
+    /// Return the fuuro that `op` would add to tehai, without operating it.
+    ///
+    /// Ankan is also fuuro, while kakan only turns an existing koutsu of fuuro into kantsu.
+    pub fn fuuro_of(&self, op: &Operation) -> Option<Mentsu> {
+        let kind = match op {
+            Operation::Tehai(TehaiOperation::Naku { kind, .. }) => kind,
+            _ => return None,
+        };
+        match kind {
+            Naku::Chii { juntsu, .. } => Some(*juntsu),
+            Naku::Pon(koutsu) => Some(*koutsu),
+            Naku::Kan(Kan::Kakan { .. }) => None,
+            Naku::Kan(kan) => {
+                let kantsu = *kan.kantsu();
+                let is_kakan = match (kan, kantsu, &self.tehai) {
+                    (Kan::Unknown { .. }, Mentsu::Kantsu(hai), Some(tehai)) => {
+                        self.state != State::LackOneHai
+                            && tehai.fuuro.contains(&Mentsu::Koutsu(hai.normalized()))
+                    }
+                    _ => false,
+                };
+                if is_kakan {
+                    None
+                } else {
+                    Some(kantsu)
+                }
+            }
+        }
+    }
+
     /// Return the reference of tehai.
     pub fn tehai(&self) -> Option<&Tehai> {
-        return self.tehai.as_ref();
+        self.tehai.as_ref()
     }
 
     /// Return the analysis of tehai.
     pub fn tehai_analyze(&self) -> Result<(i32, Vec<MachiCondition>), String> {
+        self.tehai_analyze_with_deadline(None)
+    }
+
+    /// Return the analysis of tehai, or a `TIMEOUT` error once `deadline` has passed.
+    pub fn tehai_analyze_with_deadline(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, Vec<MachiCondition>), String> {
         let tehai = self.tehai.as_ref().ok_or("Not initialized.".to_string())?;
-        tehai.analyze(self.player_number, Some(&self))
+        tehai.analyze_with_deadline(self.player_number, Some(self), deadline)
+    }
+
+    /// Return the hai drawn by last operation, including rinshanhai.
+    /// It is the agari hai if tehai is agari now.
+    pub fn last_drawn_hai(&self) -> Option<Hai> {
+        match self.history.last() {
+            Some((Operation::Tehai(TehaiOperation::Add { hai, .. }), ..)) => Some(*hai),
+            Some((
+                Operation::Tehai(TehaiOperation::Naku {
+                    kind:
+                        Naku::Kan(
+                            Kan::Daiminkan { rinshanhai, .. }
+                            | Kan::Kakan { rinshanhai, .. }
+                            | Kan::Ankan { rinshanhai, .. }
+                            | Kan::Unknown { rinshanhai, .. },
+                        ),
+                    ..
+                }),
+                ..,
+            )) => *rinshanhai,
+            _ => None,
+        }
+    }
+
+    /// Replace a hai in tehai for analysis only. It is not an operation of the game, so
+    /// neither haiyama nor history will be changed. Note that `back` may fail later if
+    /// the replaced hai is required to undo an operation.
+    pub fn edit_tehai(&mut self, old: &Hai, new: &Hai) -> Result<(), String> {
+        self.tehai
+            .as_mut()
+            .ok_or("Not initialized.".to_string())?
+            .replace(old, new)
+    }
+
+    /// Calculate point changes of all players at ryuukyoku.
+    ///
+    /// Noten players share paying `rules.noten_bappu` and tenpai players share receiving
+    /// it, divided by integer division. If nagashi mangan is adopted and any player
+    /// achieves it, each of them collects mangan as tsumo instead, and no noten bappu
+    /// is paid.
+    ///
+    /// # Parameters
+    /// * tenpai: If each player is tenpai, in seat order.
+    /// * nagashi: If each player achieves nagashi mangan, in seat order.
+    /// * oya: Seat index of the dealer.
+    /// * rules: Scoring rules.
+    ///
+    /// # Japanese
+    /// * ryuukyoku: 流局
+    /// * oya: 親
+    pub fn tenpai_players_settlement(
+        &self,
+        tenpai: &[bool],
+        nagashi: &[bool],
+        oya: usize,
+        rules: &ScoringRules,
+    ) -> Result<Vec<i32>, String> {
+        let player_count = match self.player_number {
+            PlayerNumber::Four => 4,
+            PlayerNumber::Three => 3,
+        };
+        if tenpai.len() != player_count || nagashi.len() != player_count {
+            return Err(format!("Expected {} players.", player_count));
+        }
+        if oya >= player_count {
+            return Err(format!("Invalid oya seat {}.", oya));
+        }
+
+        let mut delta = vec![0i32; player_count];
+        if rules.nagashi_mangan && nagashi.iter().any(|n| *n) {
+            for winner in (0..player_count).filter(|i| nagashi[*i]) {
+                for payer in (0..player_count).filter(|i| *i != winner) {
+                    let payment = if winner == oya || payer == oya {
+                        4000
+                    } else {
+                        2000
+                    };
+                    delta[payer] -= payment;
+                    delta[winner] += payment;
+                }
+            }
+            return Ok(delta);
+        }
+
+        let tenpai_count = tenpai.iter().filter(|t| **t).count();
+        if tenpai_count == 0 || tenpai_count == player_count {
+            return Ok(delta);
+        }
+        let receive = (rules.noten_bappu as usize / tenpai_count) as i32;
+        let pay = (rules.noten_bappu as usize / (player_count - tenpai_count)) as i32;
+        for (index, is_tenpai) in tenpai.iter().enumerate() {
+            delta[index] = if *is_tenpai { receive } else { -pay };
+        }
+        Ok(delta)
+    }
+
+    /// Return if temporary furiten, which means a ron was skipped since the last own turn.
+    ///
+    /// # Japanese
+    /// * temporary furiten: 同巡内振り聴
+    pub fn temporary_furiten(&self) -> bool {
+        self.temporary_furiten
+    }
+
+    /// Skip a ron on a hai discarded by others, which causes temporary furiten until the
+    /// next own discard. Tsumo is still allowed before that.
+    pub fn skip_ron(&mut self) -> Result<(), String> {
+        match self.state {
+            State::LackOneHai => {
+                self.temporary_furiten = true;
+                Ok(())
+            }
+            _ => Err("Can only skip ron when waiting for a hai.".to_string()),
+        }
+    }
+
+    /// Return dora indicators in the order they were added.
+    ///
+    /// # Japanese
+    /// * dora indicator: ドラ表示牌
+    pub fn dora_indicators(&self) -> &Vec<Hai> {
+        &self.dora_indicators
+    }
+
+    /// Return dora resolved from each dora indicator, in the same order.
+    pub fn dora(&self) -> Vec<Hai> {
+        self.dora_indicators
+            .iter()
+            .map(|indicator| indicator.dora_from_indicator(self.player_number))
+            .collect()
+    }
+
+    /// Return the number of `4z` set aside as kita on 3-players mode.
+    ///
+    /// # Japanese
+    /// * kita: 北抜き
+    pub fn kita(&self) -> u8 {
+        self.kita
+    }
+
+    /// Return hai set aside from juntehai by `TehaiOperation::SetAside`, in order.
+    pub fn set_aside(&self) -> &Vec<Hai> {
+        &self.set_aside
+    }
+
+    /// Return hai in the river by `Operation::River`, in order.
+    ///
+    /// # Japanese
+    /// * river: 河
+    pub fn river(&self) -> &Vec<Hai> {
+        &self.river
+    }
+
+    /// Add dora indicators, at most 5 in total. If `discard_from_haiyama`, they are also
+    /// discarded from haiyama as other visible hai, and nothing is changed on failure.
+    /// It is not a game operation, so it is not recorded in history.
+    pub fn add_dora_indicators(
+        &mut self,
+        indicators: &[Hai],
+        discard_from_haiyama: bool,
+    ) -> Result<(), String> {
+        if self.dora_indicators.len() + indicators.len() > 5 {
+            return Err("Can not have more than 5 dora indicators.".to_string());
+        }
+        if discard_from_haiyama {
+            let mut haiyama = self.haiyama.clone();
+            for indicator in indicators {
+                haiyama.discard(indicator)?;
+            }
+            self.haiyama = haiyama;
+        } else {
+            self.dora_indicators_in_haiyama
+                .extend_from_slice(indicators);
+        }
+        self.dora_indicators.extend_from_slice(indicators);
+        Ok(())
+    }
+
+    /// Return hai which cannot be discarded right after the last chii or pon because of
+    /// kuikae: the nakihai itself (genbutsu), and for chii on an end of the juntsu, the
+    /// hai on the other side (suji). Empty if the last tehai operation is not chii or pon.
+    ///
+    /// # Japanese
+    /// * kuikae: 喰い替え
+    /// * genbutsu: 現物
+    /// * suji: 筋
+    pub fn forbidden_discards(&self) -> BTreeSet<Hai> {
+        let mut forbidden = BTreeSet::new();
+        if self.state != State::FullHai {
+            return forbidden;
+        }
+        let last_tehai_op = self.history.iter().rev().find_map(|(op, ..)| match op {
+            Operation::Tehai(op) => Some(op),
+            Operation::Haiyama { .. } | Operation::River { .. } => None,
+        });
+        match last_tehai_op {
+            Some(TehaiOperation::Naku {
+                kind:
+                    Naku::Chii {
+                        juntsu: Mentsu::Juntsu(a, _, c),
+                        nakihai,
+                    },
+                ..
+            }) => {
+                let nakihai = nakihai.normalized();
+                forbidden.insert(nakihai);
+                let suji = if nakihai == a.normalized() {
+                    c.normalized().next(self.player_number, false)
+                } else if nakihai == c.normalized() {
+                    a.normalized().previous(self.player_number, false)
+                } else {
+                    None
+                };
+                forbidden.extend(suji);
+            }
+            Some(TehaiOperation::Naku {
+                kind: Naku::Pon(Mentsu::Koutsu(hai)),
+                ..
+            }) => {
+                forbidden.insert(hai.normalized());
+            }
+            _ => (),
+        }
+        forbidden
+    }
+
+    /// Return the number of each type of hai unseen by the player, that is not in tehai,
+    /// fuuro or any visible hai discarded from haiyama. Aka is counted as five.
+    /// Dora indicators not discarded from haiyama are visible too, so they are not counted.
+    ///
+    /// Unlike iterating haiyama, every type of hai is included, even if none is left.
+    pub fn unseen(&self) -> BTreeMap<Hai, u8> {
+        Hai::all_type(self.player_number)
+            .into_iter()
+            .map(|hai| {
+                let indicators = self
+                    .dora_indicators_in_haiyama
+                    .iter()
+                    .filter(|indicator| indicator.normalized() == hai)
+                    .count() as u8;
+                (hai, self.haiyama.count(&hai).saturating_sub(indicators))
+            })
+            .collect()
+    }
+
+    /// Return the safety of discarding each type of hai, judged from sutehai and hai left
+    /// in haiyama. Aka is judged as five.
+    ///
+    /// Only ryanmen is considered for suhai. Jihai is `NoChance` if no one left in haiyama,
+    /// otherwise `Dangerous` unless genbutsu.
+    pub fn defense_hint(&self) -> BTreeMap<Hai, Safety> {
+        let discarded = |hai: &Hai| {
+            self.sutehai_type.contains(hai)
+                || hai
+                    .make_aka()
+                    .is_some_and(|aka| self.sutehai_type.contains(&aka))
+        };
+        let safety_of = |hai: &Hai| {
+            if discarded(hai) {
+                return Safety::Genbutsu;
+            }
+            let suit = hai.suit();
+            if suit == Suit::Ji {
+                return if self.haiyama.count(hai) == 0 {
+                    Safety::NoChance
+                } else {
+                    Safety::Dangerous
+                };
+            }
+            let number = hai.number();
+            let make_hai = |number| Hai::new(suit, number).unwrap();
+
+            // Each ryanmen waiting on this hai, with the hai of the other side.
+            let mut ryanmen = vec![];
+            if number >= 4 {
+                ryanmen.push((
+                    [make_hai(number - 2), make_hai(number - 1)],
+                    make_hai(number - 3),
+                ));
+            }
+            if number <= 6 {
+                ryanmen.push((
+                    [make_hai(number + 1), make_hai(number + 2)],
+                    make_hai(number + 3),
+                ));
+            }
+
+            let mut safety = Safety::Suji;
+            for (taatsu, suji) in ryanmen {
+                if discarded(&suji) {
+                    continue;
+                }
+                let least = taatsu
+                    .iter()
+                    .map(|hai| self.haiyama.count(hai))
+                    .min()
+                    .unwrap_or(0);
+                safety = safety.max(match least {
+                    0 => Safety::NoChance,
+                    1 => Safety::OneChance,
+                    _ => Safety::Dangerous,
+                });
+            }
+            safety
+        };
+
+        Hai::all_type(self.player_number)
+            .into_iter()
+            .map(|hai| (hai, safety_of(&hai)))
+            .collect()
+    }
+
+    /// Return warnings of the last operation, such as discarding hai already used up in
+    /// haiyama when not haiyama sensitive.
+    pub fn warnings(&self) -> &Vec<String> {
+        &self.warnings
     }
 
     /// Main function to control the game.
-    pub fn operate(&mut self, mut op: Operation) -> Result<(), String> {
+    pub fn operate(&mut self, mut op: Operation) -> Result<(), MahjongError> {
         let last_state = self.state;
-        match last_state {
-            State::WaitToInit => self.operate_wait_to_init(&op)?,
-            State::FullHai => self.operate_full_hai(&mut op)?,
-            State::LackOneHai => self.operate_lack_one_hai(&mut op)?,
-            State::WaitForRinshanhai => self.operate_wait_for_rinshanhai(&op)?,
+        let last_sutehai_type = self.sutehai_type.clone();
+        let last_temporary_furiten = self.temporary_furiten;
+        let last_juntehai = self
+            .tehai
+            .as_ref()
+            .map(|tehai| tehai.juntehai.clone())
+            .unwrap_or_default();
+        self.warnings.clear();
+        // Kan of a forced type is checked first, so the type inferred by tehai is the same.
+        if let Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Kan(kan),
+            ..
+        }) = &op
+        {
+            self.check_forced_kan(kan)?;
+        }
+        let result = match (&op, last_state) {
+            // Available at any state.
+            (
+                Operation::River {
+                    hai_vec,
+                    haiyama_sensitive,
+                },
+                _,
+            ) => self.operate_river(hai_vec, *haiyama_sensitive),
+            (_, State::WaitToInit) => self.operate_wait_to_init(&op),
+            (_, State::FullHai) => self.operate_full_hai(&mut op),
+            (_, State::LackOneHai) => self.operate_lack_one_hai(&mut op),
+            (_, State::WaitForRinshanhai) => self.operate_wait_for_rinshanhai(&mut op),
+        };
+        if result.is_err() {
+            self.warnings.clear();
+            return result;
+        }
+        if let Operation::Tehai(TehaiOperation::Discard(_)) = op {
+            self.temporary_furiten = false;
+        }
+        if let Operation::Tehai(TehaiOperation::Naku { taken, .. }) = &mut op {
+            let mut juntehai = self.tehai.as_ref().unwrap().juntehai.clone();
+            *taken = last_juntehai
+                .into_iter()
+                .filter(|hai| match juntehai.iter().position(|item| item == hai) {
+                    Some(index) => {
+                        juntehai.remove(index);
+                        false
+                    }
+                    None => true,
+                })
+                .collect();
         }
         self.history
-            .push((op, last_state, self.sutehai_type.clone()));
+            .push((op, last_state, last_sutehai_type, last_temporary_furiten));
         Ok(())
     }
 
     /// Undo last operation.
     pub fn back(&mut self, haiyama_sensitive: bool) -> Result<(Operation, State), String> {
-        let (op, last_state, sutehai_type) = self
+        let (op, last_state, sutehai_type, temporary_furiten) = self
             .history
             .pop()
             .ok_or("No more operation history.".to_string())?;
-        match match last_state {
-            State::WaitToInit => self.back_wait_to_init(&op, haiyama_sensitive),
-            State::FullHai => self.back_full_hai(&op, haiyama_sensitive),
-            State::LackOneHai => self.back_lack_one_hai(&op, haiyama_sensitive),
-            State::WaitForRinshanhai => self.back_wait_for_rinshanhai(&op, haiyama_sensitive),
+        match match (&op, last_state) {
+            (Operation::River { hai_vec, .. }, _) => self.back_river(hai_vec, haiyama_sensitive),
+            (_, State::WaitToInit) => self.back_wait_to_init(&op, haiyama_sensitive),
+            (_, State::FullHai) => self.back_full_hai(&op, haiyama_sensitive),
+            (_, State::LackOneHai) => self.back_lack_one_hai(&op, haiyama_sensitive),
+            (_, State::WaitForRinshanhai) => self.back_wait_for_rinshanhai(&op, haiyama_sensitive),
         } {
             Ok(_) => {
                 self.state = last_state;
                 self.sutehai_type = sutehai_type;
+                self.temporary_furiten = temporary_furiten;
                 Ok((op, last_state))
             }
             Err(error) => {
-                self.history.push((op, last_state, sutehai_type));
+                self.history
+                    .push((op, last_state, sutehai_type, temporary_furiten));
                 Err(error)
             }
         }
@@ -297,7 +1092,7 @@ impl GameManager {
     /// Print self to json.
     pub fn to_json(&self) -> serde_json::Value {
         let mut sutehai_type_string_vec = vec![];
-        for hai in self.sutehai_type.iter() {
+        for hai in self.sutehai_type_sorted().iter() {
             sutehai_type_string_vec.push(hai.to_string());
         }
 
@@ -310,27 +1105,176 @@ impl GameManager {
             "haiyama": self.haiyama.to_json(),
             "sutehai_type": json!(sutehai_type_string_vec),
             "tehai": tehai_json,
+            "temporary_furiten": self.temporary_furiten,
+            "warnings": self.warnings,
+            "seat_wind": self.seat_wind.to_string(),
+            "round_wind": self.round_wind.to_string(),
+            "dora_indicators": self
+                .dora_indicators
+                .iter()
+                .map(|hai| hai.to_string())
+                .collect::<Vec<String>>(),
+            "dora": self
+                .dora()
+                .iter()
+                .map(|hai| hai.to_string())
+                .collect::<Vec<String>>(),
+            "kita": self.kita,
+            "set_aside": self
+                .set_aside
+                .iter()
+                .map(|hai| hai.to_string())
+                .collect::<Vec<String>>(),
+            "river": self
+                .river
+                .iter()
+                .map(|hai| hai.to_string())
+                .collect::<Vec<String>>(),
         })
     }
 
-    fn operate_wait_to_init(&mut self, op: &Operation) -> Result<(), String> {
-        fn operate_tehai_init(self_: &mut GameManager, tehai: &Tehai) -> Result<(), String> {
-            if tehai.fuuro.len() != 0 {
-                return Err("Cannot initialized with fuuro.".to_string());
+    /// Print self to json with haiyama as a single object. See `Haiyama::to_compact_json`.
+    pub fn to_compact_json(&self) -> serde_json::Value {
+        let mut json = self.to_json();
+        json["haiyama"] = self.haiyama.to_compact_json();
+        json
+    }
+
+    /// Discard a hai from haiyama. If not haiyama sensitive, hai already used up is
+    /// skipped with a warning instead of an error.
+    fn discard_from_haiyama(
+        &mut self,
+        hai: &Hai,
+        haiyama_sensitive: bool,
+    ) -> Result<(), MahjongError> {
+        if haiyama_sensitive {
+            self.haiyama.discard(hai)
+        } else {
+            if self.haiyama.discard_checked(hai) == 0 {
+                self.warnings
+                    .push(format!("No '{}' left in haiyama, ignored.", hai));
+            }
+            Ok(())
+        }
+    }
+
+    /// Same as `discard_from_haiyama` for a vec of hai. Haiyama is restored on error.
+    fn discard_vec_from_haiyama(
+        &mut self,
+        hai_vec: &Vec<Hai>,
+        haiyama_sensitive: bool,
+    ) -> Result<(), MahjongError> {
+        if haiyama_sensitive {
+            return self
+                .haiyama
+                .discard_with_vec(hai_vec, true)
+                .map_err(MahjongError::from);
+        }
+        for hai in hai_vec {
+            self.discard_from_haiyama(hai, false)?;
+        }
+        Ok(())
+    }
+
+    /// Check if a kan of a forced type is consistent with tehai and state. Daiminkan needs
+    /// 3 hai in tehai lacking one hai, ankan needs 4 in full tehai, and kakan needs a pon
+    /// and the fourth hai in full tehai. While waiting for rinshanhai, the rinshanhai is
+    /// regarded as the hai of kantsu.
+    fn check_forced_kan(&self, kan: &Kan) -> Result<(), MahjongError> {
+        let (name, hai) = match kan {
+            Kan::Daiminkan {
+                kantsu: Mentsu::Kantsu(hai),
+                ..
+            } => ("daiminkan", hai),
+            Kan::Ankan {
+                kantsu: Mentsu::Kantsu(hai),
+                ..
+            } => ("ankan", hai),
+            Kan::Kakan {
+                kantsu: Mentsu::Kantsu(hai),
+                ..
+            } => ("kakan", hai),
+            _ => return Ok(()),
+        };
+        // Not initialized, left to the state check.
+        let tehai = match &self.tehai {
+            Some(tehai) => tehai,
+            None => return Ok(()),
+        };
+
+        let mut number = tehai
+            .juntehai
+            .iter()
+            .filter(|x| x.normalized() == hai.normalized())
+            .count();
+        if self.state == State::WaitForRinshanhai {
+            number += 1;
+        }
+        let exist_koutsu = tehai.fuuro.contains(&Mentsu::Koutsu(hai.normalized()));
+        let consistent = match kan {
+            Kan::Daiminkan { .. } => self.state == State::LackOneHai && number == 3,
+            Kan::Ankan { .. } => self.state != State::LackOneHai && number == 4,
+            _ => self.state != State::LackOneHai && number == 1 && exist_koutsu,
+        };
+        if consistent {
+            Ok(())
+        } else {
+            Err(MahjongError::Other(format!(
+                "Cannot {} '{}' with current tehai.",
+                name, hai
+            )))
+        }
+    }
+
+    /// Check if one more kita is available.
+    fn check_kita(&self) -> Result<(), MahjongError> {
+        if self.player_number != PlayerNumber::Three {
+            return Err(MahjongError::Other(
+                "Kita is only available on 3-players mode.".to_string(),
+            ));
+        }
+        if !self.ruleset.kita_dora {
+            return Err(MahjongError::Other(
+                "Kita is disabled by the ruleset.".to_string(),
+            ));
+        }
+        if self.kita >= 4 {
+            return Err(MahjongError::Other("Already four kita.".to_string()));
+        }
+        Ok(())
+    }
+
+    fn operate_river(
+        &mut self,
+        hai_vec: &Vec<Hai>,
+        haiyama_sensitive: bool,
+    ) -> Result<(), MahjongError> {
+        self.discard_vec_from_haiyama(hai_vec, haiyama_sensitive)?;
+        self.river.extend_from_slice(hai_vec);
+        self.sutehai_type.extend(hai_vec.iter().copied());
+        Ok(())
+    }
+
+    fn operate_wait_to_init(&mut self, op: &Operation) -> Result<(), MahjongError> {
+        fn operate_tehai_init(self_: &mut GameManager, tehai: &Tehai) -> Result<(), MahjongError> {
+            if !tehai.fuuro.is_empty() {
+                return Err(MahjongError::Other(
+                    "Cannot initialized with fuuro.".to_string(),
+                ));
             }
             match tehai.juntehai.len() {
                 13 => self_.state = State::LackOneHai,
                 14 => self_.state = State::FullHai,
-                num @ _ => {
-                    return Err(format!(
-                        "Cannot initialize tehai with {} juntehai, only 13 and 14 are supported.",
-                        num
-                    ))
+                num => {
+                    return Err(MahjongError::WrongTileCount {
+                        expected: vec![13, 14],
+                        got: num,
+                    })
                 }
             }
             if let Err(error) = self_.haiyama.discard_with_vec(&tehai.juntehai, true) {
                 self_.state = State::WaitToInit;
-                return Err(error);
+                return Err(error.into());
             }
             self_.tehai = Some(tehai.clone());
 
@@ -347,7 +1291,7 @@ impl GameManager {
             } => {
                 if let Err(error) = self.haiyama.add_with_vec(hai_vec, *haiyama_sensitive) {
                     if *haiyama_sensitive {
-                        return Err(error);
+                        return Err(error.into());
                     }
                 }
             }
@@ -355,42 +1299,57 @@ impl GameManager {
                 kind: HaiyamaOperation::Discard(hai_vec),
                 haiyama_sensitive,
             } => {
-                if let Err(error) = self.haiyama.discard_with_vec(hai_vec, *haiyama_sensitive) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_vec_from_haiyama(hai_vec, *haiyama_sensitive)?;
             }
             _ => {
-                return Err(format!(
-                    "Unsupported opretion '{:?}' at state '{:?}'.",
-                    op, self.state
-                ))
+                return Err(MahjongError::IllegalState {
+                    op: format!("{:?}", op),
+                    state: self.state,
+                })
             }
         }
 
         Ok(())
     }
 
-    fn operate_full_hai(&mut self, op: &mut Operation) -> Result<(), String> {
+    fn operate_full_hai(&mut self, op: &mut Operation) -> Result<(), MahjongError> {
         match &*op {
             Operation::Tehai(TehaiOperation::Discard(hai)) => {
+                if !self.ruleset.kuikae_allowed
+                    && self.forbidden_discards().contains(&hai.normalized())
+                {
+                    return Err(MahjongError::Other(format!(
+                        "Cannot discard '{}' because of kuikae.",
+                        hai
+                    )));
+                }
+                self.tehai.as_mut().unwrap().discard(hai)?;
+                self.state = State::LackOneHai;
+            }
+            // Set aside a `4z` from tehai, then a hai is drawn as replacement.
+            Operation::Tehai(TehaiOperation::Kita { .. }) => {
+                self.check_kita()?;
+                self.tehai.as_mut().unwrap().discard(&Hai::Jihai(4))?;
+                self.kita += 1;
+                self.state = State::LackOneHai;
+            }
+            // Unlike discarding, it makes neither furiten nor kuikae.
+            Operation::Tehai(TehaiOperation::SetAside(hai)) => {
                 self.tehai.as_mut().unwrap().discard(hai)?;
+                self.set_aside.push(*hai);
                 self.state = State::LackOneHai;
             }
             Operation::Tehai(TehaiOperation::Naku {
-                kind: Naku::Kan(Kan::Unknown { kantsu, rinshanhai }),
+                kind: Naku::Kan(kan),
                 haiyama_sensitive,
+                ..
             }) => {
+                let (kantsu, rinshanhai) = (kan.kantsu(), kan.rinshanhai());
                 let haiyama_backup = self.haiyama.clone();
                 let state_backup = self.state;
                 let tehai_backup = self.tehai.clone();
                 if let Some(rinshanhai) = rinshanhai {
-                    if let Err(error) = self.haiyama.discard(rinshanhai) {
-                        if *haiyama_sensitive {
-                            return Err(error);
-                        }
-                    }
+                    self.discard_from_haiyama(rinshanhai, *haiyama_sensitive)?;
                     self.state = State::FullHai;
                 } else {
                     self.state = State::WaitForRinshanhai;
@@ -402,20 +1361,21 @@ impl GameManager {
                             *op = Operation::Tehai(TehaiOperation::Naku {
                                 kind: Naku::Kan(kan),
                                 haiyama_sensitive: *haiyama_sensitive,
+                                taken: vec![],
                             })
                         } else {
                             self.haiyama = haiyama_backup;
                             self.state = state_backup;
                             self.tehai = tehai_backup;
-                            return Err(
-                                "Logic error: Tehai currently is not able to kan.".to_string()
-                            );
+                            return Err(MahjongError::Other(
+                                "Logic error: Tehai currently is not able to kan.".to_string(),
+                            ));
                         }
                     }
                     Err(error) => {
                         self.haiyama = haiyama_backup;
                         self.state = state_backup;
-                        return Err(error);
+                        return Err(error.into());
                     }
                 }
             }
@@ -425,7 +1385,7 @@ impl GameManager {
             } => {
                 if let Err(error) = self.haiyama.add_with_vec(hai_vec, *haiyama_sensitive) {
                     if *haiyama_sensitive {
-                        return Err(error);
+                        return Err(error.into());
                     }
                 }
             }
@@ -433,92 +1393,88 @@ impl GameManager {
                 kind: HaiyamaOperation::Discard(hai_vec),
                 haiyama_sensitive,
             } => {
-                if let Err(error) = self.haiyama.discard_with_vec(hai_vec, *haiyama_sensitive) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_vec_from_haiyama(hai_vec, *haiyama_sensitive)?;
             }
             _ => {
-                return Err(format!(
-                    "Unsupported opretion '{:?}' at state '{:?}'.",
-                    op, self.state
-                ))
+                return Err(MahjongError::IllegalState {
+                    op: format!("{:?}", op),
+                    state: self.state,
+                })
             }
         }
 
         Ok(())
     }
 
-    fn operate_lack_one_hai(&mut self, op: &mut Operation) -> Result<(), String> {
+    fn operate_lack_one_hai(&mut self, op: &mut Operation) -> Result<(), MahjongError> {
         match &*op {
             Operation::Tehai(TehaiOperation::Add {
                 hai,
                 haiyama_sensitive,
             }) => {
-                if let Err(error) = self.haiyama.discard(hai) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_from_haiyama(hai, *haiyama_sensitive)?;
                 self.tehai.as_mut().unwrap().juntehai.push(*hai);
-                self.tehai.as_mut().unwrap().juntehai.sort();
+                self.tehai
+                    .as_mut()
+                    .unwrap()
+                    .juntehai
+                    .sort_by_key(Hai::sort_key);
                 self.state = State::FullHai;
             }
+            // The `4z` just drawn is set aside at once, without adding it to tehai.
+            Operation::Tehai(TehaiOperation::Kita { haiyama_sensitive }) => {
+                self.check_kita()?;
+                self.discard_from_haiyama(&Hai::Jihai(4), *haiyama_sensitive)?;
+                self.kita += 1;
+            }
             Operation::Tehai(TehaiOperation::Naku {
                 kind: Naku::Chii { juntsu, nakihai },
                 haiyama_sensitive,
+                ..
             }) => {
                 let haiyama_backup = self.haiyama.clone();
-                if let Err(error) = self.haiyama.discard(nakihai) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_from_haiyama(nakihai, *haiyama_sensitive)?;
                 if let Err(error) = self.tehai.as_mut().unwrap().chii(juntsu, nakihai) {
                     self.haiyama = haiyama_backup;
-                    return Err(error);
+                    return Err(error.into());
                 }
                 self.state = State::FullHai;
             }
             Operation::Tehai(TehaiOperation::Naku {
                 kind: Naku::Pon(koutsu @ Mentsu::Koutsu(hai)),
                 haiyama_sensitive,
+                ..
             }) => {
                 let haiyama_backup = self.haiyama.clone();
-                if let Err(error) = self.haiyama.discard(hai) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_from_haiyama(hai, *haiyama_sensitive)?;
                 if let Err(error) = self.tehai.as_mut().unwrap().pon(koutsu) {
                     self.haiyama = haiyama_backup;
-                    return Err(error);
+                    return Err(error.into());
                 }
                 self.state = State::FullHai;
             }
             Operation::Tehai(TehaiOperation::Naku {
-                kind:
-                    Naku::Kan(Kan::Unknown {
-                        kantsu: kantsu @ Mentsu::Kantsu(hai),
-                        rinshanhai,
-                    }),
+                kind: Naku::Kan(kan),
                 haiyama_sensitive,
+                ..
             }) => {
+                let (kantsu, rinshanhai) = (kan.kantsu(), kan.rinshanhai());
+                let hai = match kantsu {
+                    Mentsu::Kantsu(hai) => hai,
+                    _ => {
+                        return Err(MahjongError::Other(
+                            "Logic error: Kan can only include Kantsu.".to_string(),
+                        ))
+                    }
+                };
                 let haiyama_backup = self.haiyama.clone();
                 let state_backup = self.state;
                 let tehai_backup = self.tehai.clone();
-                if let Err(error) = self.haiyama.discard(hai) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_from_haiyama(hai, *haiyama_sensitive)?;
                 if let Some(rinshanhai) = rinshanhai {
-                    if let Err(error) = self.haiyama.discard(rinshanhai) {
-                        if *haiyama_sensitive {
-                            self.haiyama = haiyama_backup;
-                            return Err(error);
-                        }
+                    if let Err(error) = self.discard_from_haiyama(rinshanhai, *haiyama_sensitive) {
+                        self.haiyama = haiyama_backup;
+                        return Err(error);
                     }
                     self.state = State::FullHai;
                 } else {
@@ -531,20 +1487,21 @@ impl GameManager {
                             *op = Operation::Tehai(TehaiOperation::Naku {
                                 kind: Naku::Kan(kan),
                                 haiyama_sensitive: *haiyama_sensitive,
+                                taken: vec![],
                             })
                         } else {
                             self.haiyama = haiyama_backup;
                             self.state = state_backup;
                             self.tehai = tehai_backup;
-                            return Err(
-                                "Logic error: Tehai currently is not able to kan.".to_string()
-                            );
+                            return Err(MahjongError::Other(
+                                "Logic error: Tehai currently is not able to kan.".to_string(),
+                            ));
                         }
                     }
                     Err(error) => {
                         self.haiyama = haiyama_backup;
                         self.state = state_backup;
-                        return Err(error);
+                        return Err(error.into());
                     }
                 }
             }
@@ -554,7 +1511,7 @@ impl GameManager {
             } => {
                 if let Err(error) = self.haiyama.add_with_vec(hai_vec, *haiyama_sensitive) {
                     if *haiyama_sensitive {
-                        return Err(error);
+                        return Err(error.into());
                     }
                 }
             }
@@ -562,37 +1519,77 @@ impl GameManager {
                 kind: HaiyamaOperation::Discard(hai_vec),
                 haiyama_sensitive,
             } => {
-                if let Err(error) = self.haiyama.discard_with_vec(hai_vec, *haiyama_sensitive) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_vec_from_haiyama(hai_vec, *haiyama_sensitive)?;
             }
             _ => {
-                return Err(format!(
-                    "Unsupported opretion '{:?}' at state '{:?}'.",
-                    op, self.state
-                ))
+                return Err(MahjongError::IllegalState {
+                    op: format!("{:?}", op),
+                    state: self.state,
+                })
             }
         }
 
         Ok(())
     }
 
-    fn operate_wait_for_rinshanhai(&mut self, op: &Operation) -> Result<(), String> {
-        match op {
+    fn operate_wait_for_rinshanhai(&mut self, op: &mut Operation) -> Result<(), MahjongError> {
+        match &*op {
             Operation::Tehai(TehaiOperation::Add {
                 hai,
                 haiyama_sensitive,
             }) => {
-                if let Err(error) = self.haiyama.discard(hai) {
-                    if *haiyama_sensitive {
-                        return Err(error);
+                self.discard_from_haiyama(hai, *haiyama_sensitive)?;
+                self.tehai.as_mut().unwrap().juntehai.push(*hai);
+                self.tehai
+                    .as_mut()
+                    .unwrap()
+                    .juntehai
+                    .sort_by_key(Hai::sort_key);
+                self.state = State::FullHai;
+            }
+            // Kan again right after kan, and the rinshanhai drawn must be the hai of kantsu.
+            Operation::Tehai(TehaiOperation::Naku {
+                kind: Naku::Kan(kan),
+                haiyama_sensitive,
+                ..
+            }) => {
+                let (hai, haiyama_sensitive) = match kan.kantsu() {
+                    Mentsu::Kantsu(hai) => (*hai, *haiyama_sensitive),
+                    _ => {
+                        return Err(MahjongError::Other(
+                            "Logic error: Kan can only include Kantsu.".to_string(),
+                        ))
                     }
+                };
+                let tehai = self.tehai.as_ref().unwrap();
+                if tehai
+                    .juntehai
+                    .iter()
+                    .filter(|x| x.normalized() == hai.normalized())
+                    .count()
+                    >= 4
+                {
+                    return Err(MahjongError::Other(format!(
+                        "Already four '{}' in tehai, draw rinshanhai before kan.",
+                        hai
+                    )));
                 }
-                self.tehai.as_mut().unwrap().juntehai.push(*hai);
-                self.tehai.as_mut().unwrap().juntehai.sort();
+                let haiyama_backup = self.haiyama.clone();
+                let tehai_backup = self.tehai.clone();
+                self.discard_from_haiyama(&hai, haiyama_sensitive)?;
+                self.tehai.as_mut().unwrap().juntehai.push(hai);
+                self.tehai
+                    .as_mut()
+                    .unwrap()
+                    .juntehai
+                    .sort_by_key(Hai::sort_key);
                 self.state = State::FullHai;
+                if let Err(error) = self.operate_full_hai(op) {
+                    self.haiyama = haiyama_backup;
+                    self.tehai = tehai_backup;
+                    self.state = State::WaitForRinshanhai;
+                    return Err(error);
+                }
             }
             Operation::Haiyama {
                 kind: HaiyamaOperation::Add(hai_vec),
@@ -600,7 +1597,7 @@ impl GameManager {
             } => {
                 if let Err(error) = self.haiyama.add_with_vec(hai_vec, *haiyama_sensitive) {
                     if *haiyama_sensitive {
-                        return Err(error);
+                        return Err(error.into());
                     }
                 }
             }
@@ -608,22 +1605,29 @@ impl GameManager {
                 kind: HaiyamaOperation::Discard(hai_vec),
                 haiyama_sensitive,
             } => {
-                if let Err(error) = self.haiyama.discard_with_vec(hai_vec, *haiyama_sensitive) {
-                    if *haiyama_sensitive {
-                        return Err(error);
-                    }
-                }
+                self.discard_vec_from_haiyama(hai_vec, *haiyama_sensitive)?;
             }
             _ => {
-                return Err(format!(
-                    "Unsupported opretion '{:?}' at state '{:?}'.",
-                    op, self.state
-                ))
+                return Err(MahjongError::IllegalState {
+                    op: format!("{:?}", op),
+                    state: self.state,
+                })
             }
         }
         Ok(())
     }
 
+    // Sutehai type is restored from history by `back`.
+    fn back_river(&mut self, hai_vec: &Vec<Hai>, haiyama_sensitive: bool) -> Result<(), String> {
+        if let Err(error) = self.haiyama.add_with_vec(hai_vec, haiyama_sensitive) {
+            if haiyama_sensitive {
+                return Err(error);
+            }
+        }
+        self.river.truncate(self.river.len() - hai_vec.len());
+        Ok(())
+    }
+
     fn back_wait_to_init(&mut self, op: &Operation, haiyama_sensitive: bool) -> Result<(), String> {
         match op {
             Operation::Tehai(TehaiOperation::Initialize(tehai)) => {
@@ -658,7 +1662,7 @@ impl GameManager {
                     }
                 }
             }
-            _ => return Err(format!("Logic error: confused with impossible state.")),
+            _ => return Err("Logic error: confused with impossible state.".to_string()),
         }
         Ok(())
     }
@@ -667,10 +1671,33 @@ impl GameManager {
         match op {
             Operation::Tehai(TehaiOperation::Discard(hai)) => {
                 self.tehai.as_mut().unwrap().juntehai.push(*hai);
-                self.tehai.as_mut().unwrap().juntehai.sort();
+                self.tehai
+                    .as_mut()
+                    .unwrap()
+                    .juntehai
+                    .sort_by_key(Hai::sort_key);
+            }
+            Operation::Tehai(TehaiOperation::Kita { .. }) => {
+                self.tehai.as_mut().unwrap().juntehai.push(Hai::Jihai(4));
+                self.tehai
+                    .as_mut()
+                    .unwrap()
+                    .juntehai
+                    .sort_by_key(Hai::sort_key);
+                self.kita -= 1;
+            }
+            Operation::Tehai(TehaiOperation::SetAside(hai)) => {
+                self.tehai.as_mut().unwrap().juntehai.push(*hai);
+                self.tehai
+                    .as_mut()
+                    .unwrap()
+                    .juntehai
+                    .sort_by_key(Hai::sort_key);
+                self.set_aside.pop();
             }
             Operation::Tehai(TehaiOperation::Naku {
                 kind: Naku::Kan(kan),
+                taken,
                 ..
             }) => {
                 let backup = self.haiyama.clone();
@@ -703,7 +1730,7 @@ impl GameManager {
                         }
                     }
                 }
-                if let Err(error) = self.tehai.as_mut().unwrap().de_kan(kan) {
+                if let Err(error) = self.tehai.as_mut().unwrap().de_kan(kan, taken) {
                     self.haiyama = backup;
                     return Err(error);
                 }
@@ -728,7 +1755,7 @@ impl GameManager {
                     }
                 }
             }
-            _ => return Err(format!("Logic error: confused with impossible state.")),
+            _ => return Err("Logic error: confused with impossible state.".to_string()),
         }
         Ok(())
     }
@@ -736,7 +1763,25 @@ impl GameManager {
     fn back_lack_one_hai(&mut self, op: &Operation, haiyama_sensitive: bool) -> Result<(), String> {
         match op {
             Operation::Tehai(TehaiOperation::Add { hai, .. }) => {
-                self.tehai.as_mut().unwrap().discard(hai)?;
+                let backup = self.haiyama.clone();
+
+                if let Err(error) = self.haiyama.add(hai) {
+                    if haiyama_sensitive {
+                        return Err(error);
+                    }
+                }
+                if let Err(error) = self.tehai.as_mut().unwrap().discard(hai) {
+                    self.haiyama = backup;
+                    return Err(error);
+                }
+            }
+            Operation::Tehai(TehaiOperation::Kita { .. }) => {
+                if let Err(error) = self.haiyama.add(&Hai::Jihai(4)) {
+                    if haiyama_sensitive {
+                        return Err(error);
+                    }
+                }
+                self.kita -= 1;
             }
             Operation::Tehai(TehaiOperation::Naku {
                 kind:
@@ -744,6 +1789,7 @@ impl GameManager {
                         juntsu: juntsu @ Mentsu::Juntsu(..),
                         nakihai,
                     },
+                taken,
                 ..
             }) => {
                 let backup = self.haiyama.clone();
@@ -753,13 +1799,14 @@ impl GameManager {
                         return Err(error);
                     }
                 }
-                if let Err(error) = self.tehai.as_mut().unwrap().de_chii(juntsu, nakihai) {
+                if let Err(error) = self.tehai.as_mut().unwrap().de_chii(juntsu, taken) {
                     self.haiyama = backup;
                     return Err(error);
                 }
             }
             Operation::Tehai(TehaiOperation::Naku {
                 kind: Naku::Pon(koutsu @ Mentsu::Koutsu(hai)),
+                taken,
                 ..
             }) => {
                 let backup = self.haiyama.clone();
@@ -768,13 +1815,14 @@ impl GameManager {
                         return Err(error);
                     }
                 }
-                if let Err(error) = self.tehai.as_mut().unwrap().de_pon(koutsu) {
+                if let Err(error) = self.tehai.as_mut().unwrap().de_pon(koutsu, taken) {
                     self.haiyama = backup;
                     return Err(error);
                 }
             }
             Operation::Tehai(TehaiOperation::Naku {
                 kind: Naku::Kan(kan),
+                taken,
                 ..
             }) => {
                 let backup = self.haiyama.clone();
@@ -807,7 +1855,7 @@ impl GameManager {
                         }
                     }
                 }
-                if let Err(error) = self.tehai.as_mut().unwrap().de_kan(kan) {
+                if let Err(error) = self.tehai.as_mut().unwrap().de_kan(kan, taken) {
                     self.haiyama = backup;
                     return Err(error);
                 }
@@ -832,7 +1880,7 @@ impl GameManager {
                     }
                 }
             }
-            _ => return Err(format!("Logic error: confused with impossible state.")),
+            _ => return Err("Logic error: confused with impossible state.".to_string()),
         }
         Ok(())
     }
@@ -844,7 +1892,44 @@ impl GameManager {
     ) -> Result<(), String> {
         match op {
             Operation::Tehai(TehaiOperation::Add { hai, .. }) => {
-                self.tehai.as_mut().unwrap().discard(hai)?;
+                let backup = self.haiyama.clone();
+
+                if let Err(error) = self.haiyama.add(hai) {
+                    if haiyama_sensitive {
+                        return Err(error);
+                    }
+                }
+                if let Err(error) = self.tehai.as_mut().unwrap().discard(hai) {
+                    self.haiyama = backup;
+                    return Err(error);
+                }
+            }
+            Operation::Tehai(TehaiOperation::Naku {
+                kind:
+                    Naku::Kan(
+                        Kan::Ankan {
+                            kantsu: Mentsu::Kantsu(hai),
+                            ..
+                        }
+                        | Kan::Kakan {
+                            kantsu: Mentsu::Kantsu(hai),
+                            ..
+                        },
+                    ),
+                ..
+            }) => {
+                let haiyama_backup = self.haiyama.clone();
+                let tehai_backup = self.tehai.clone();
+                // The hai of kantsu drawn as rinshanhai is not in `taken`, so only haiyama
+                // is left to undo drawing it.
+                self.back_full_hai(op, haiyama_sensitive)?;
+                if let Err(error) = self.haiyama.add(hai) {
+                    if haiyama_sensitive {
+                        self.haiyama = haiyama_backup;
+                        self.tehai = tehai_backup;
+                        return Err(error);
+                    }
+                }
             }
             Operation::Haiyama {
                 kind: HaiyamaOperation::Add(hai_vec),
@@ -866,7 +1951,7 @@ impl GameManager {
                     }
                 }
             }
-            _ => return Err(format!("Logic error: confused with impossible state.")),
+            _ => return Err("Logic error: confused with impossible state.".to_string()),
         }
         Ok(())
     }
@@ -875,10 +1960,10 @@ impl GameManager {
 impl std::fmt::Display for GameManager {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut sutehai_type_string = "".to_string();
-        if self.sutehai_type.len() == 0 {
+        if self.sutehai_type.is_empty() {
             sutehai_type_string += "無し";
         } else {
-            for hai in self.sutehai_type.iter() {
+            for hai in self.sutehai_type_sorted().iter() {
                 sutehai_type_string += &hai.to_string();
                 sutehai_type_string += " ";
             }
@@ -886,14 +1971,37 @@ impl std::fmt::Display for GameManager {
 
         write!(
             f,
-            "牌山:\n  {}\n捨て牌の種類:\n  {}\n手牌:\n  {}\n状態:\n  {:?}",
-            self.haiyama.to_string(),
+            "牌山:\n  {}\n捨て牌の種類:\n  {}\n手牌:\n  {}{}{}{}\n状態:\n  {:?}{}",
+            self.haiyama,
             sutehai_type_string,
             match &self.tehai {
                 Some(tehai) => tehai.to_string(),
                 None => "Not initialized.".to_string(),
             },
-            self.state
+            if self.kita > 0 {
+                format!("\n北抜き:\n  {}", self.kita)
+            } else {
+                "".to_string()
+            },
+            if self.set_aside.is_empty() {
+                "".to_string()
+            } else {
+                let hai_vec: Vec<String> =
+                    self.set_aside.iter().map(|hai| hai.to_string()).collect();
+                format!("\n抜いた牌:\n  {}", hai_vec.join(" "))
+            },
+            if self.river.is_empty() {
+                "".to_string()
+            } else {
+                let hai_vec: Vec<String> = self.river.iter().map(|hai| hai.to_string()).collect();
+                format!("\n河:\n  {}", hai_vec.join(" "))
+            },
+            self.state,
+            if self.temporary_furiten {
+                "\n  同巡内振り聴"
+            } else {
+                ""
+            }
         )
     }
 }
@@ -1,4 +1,5 @@
 use super::{Hai, PlayerNumber};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 /// Type of mentsu(meld).
@@ -8,7 +9,7 @@ use serde_json::json;
 /// Juntsu: 順子
 /// Koutsu: 刻子
 /// Kantsu: 槓子
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Mentsu {
     Juntsu(Hai, Hai, Hai),
     Koutsu(Hai),
@@ -19,27 +20,53 @@ pub enum Mentsu {
 ///
 /// # Japanese
 /// * Taatsu: 搭子
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Taatsu(pub Hai, pub Hai);
 
 /// Two same hai.
 ///
 /// # Japanese
 /// * Toitsu: 対子
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Toitsu(pub Hai);
 
 /// An isolated hai.
 ///
 /// # Japanese
 /// * Ukihai: 浮き牌
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Ukihai(pub Hai);
 
+/// Reason why some hai can not make up a mentsu.
+///
+/// # Member
+/// * InvalidHai: A hai is invalid for the number of players.
+/// * WrongNumber: Neither 3 nor 4 hai provided.
+/// * NotSame: 4 hai that are not the same one.
+/// * MixedSuits: 3 hai of different suits.
+/// * JihaiJuntsu: 3 different jihai, which can not make up a juntsu.
+/// * SanmaManzuJuntsu: 3 different manzu on 3-players mode, which has no 2\~8m.
+/// * NotSequential: 3 hai of the same suit that are not sequential.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MentsuError {
+    InvalidHai(Hai),
+    WrongNumber(usize),
+    NotSame,
+    MixedSuits,
+    JihaiJuntsu,
+    SanmaManzuJuntsu,
+    NotSequential,
+}
+
 impl Mentsu {
     /// Create a mentsu from input vec of hai if they can make up a valid mentsu.
-    pub fn new(hai_vec: &Vec<Hai>, player_number: PlayerNumber) -> Option<Self> {
-        fn check_juntsu(mut a: u8, mut b: u8, mut c: u8) -> Option<(u8, u8, u8)> {
+    pub fn new(hai_vec: &[Hai], player_number: PlayerNumber) -> Option<Self> {
+        Self::try_new(hai_vec, player_number).ok()
+    }
+
+    /// Create a mentsu from input vec of hai, or report why they can not make up a mentsu.
+    pub fn try_new(hai_vec: &[Hai], player_number: PlayerNumber) -> Result<Self, MentsuError> {
+        fn check_juntsu(mut a: u8, mut b: u8, mut c: u8) -> Result<(u8, u8, u8), MentsuError> {
             if a > b {
                 std::mem::swap(&mut a, &mut b)
             }
@@ -50,45 +77,50 @@ impl Mentsu {
                 std::mem::swap(&mut b, &mut c)
             }
             if a + 1 == b && b + 1 == c {
-                Some((a, b, c))
+                Ok((a, b, c))
             } else {
-                None
+                Err(MentsuError::NotSequential)
             }
         }
 
-        if !Hai::check_iter_valid(hai_vec.iter(), player_number) {
-            None
-        } else if hai_vec.len() == 4 {
+        if let Some(hai) = hai_vec.iter().find(|hai| !hai.is_valid(player_number)) {
+            return Err(MentsuError::InvalidHai(*hai));
+        }
+
+        // Aka is regarded as five in mentsu.
+        let hai_vec: Vec<Hai> = hai_vec.iter().map(Hai::normalized).collect();
+        if hai_vec.len() == 4 {
             if hai_vec[0] == hai_vec[1] && hai_vec[0] == hai_vec[2] && hai_vec[0] == hai_vec[3] {
-                Some(Mentsu::Kantsu(hai_vec[0]))
+                Ok(Mentsu::Kantsu(hai_vec[0]))
             } else {
-                None
+                Err(MentsuError::NotSame)
             }
         } else if hai_vec.len() == 3 {
             if hai_vec[0] == hai_vec[1] && hai_vec[0] == hai_vec[2] {
-                Some(Mentsu::Koutsu(hai_vec[0]))
+                Ok(Mentsu::Koutsu(hai_vec[0]))
             } else {
                 match (hai_vec[0], hai_vec[1], hai_vec[2]) {
                     (Hai::Manzu(a), Hai::Manzu(b), Hai::Manzu(c)) => match player_number {
                         PlayerNumber::Four => {
                             let (a, b, c) = check_juntsu(a, b, c)?;
-                            Some(Mentsu::Juntsu(Hai::Manzu(a), Hai::Manzu(b), Hai::Manzu(c)))
+                            Ok(Mentsu::Juntsu(Hai::Manzu(a), Hai::Manzu(b), Hai::Manzu(c)))
                         }
-                        PlayerNumber::Three => None,
+                        PlayerNumber::Three => Err(MentsuError::SanmaManzuJuntsu),
                     },
                     (Hai::Pinzu(a), Hai::Pinzu(b), Hai::Pinzu(c)) => {
                         let (a, b, c) = check_juntsu(a, b, c)?;
-                        Some(Mentsu::Juntsu(Hai::Pinzu(a), Hai::Pinzu(b), Hai::Pinzu(c)))
+                        Ok(Mentsu::Juntsu(Hai::Pinzu(a), Hai::Pinzu(b), Hai::Pinzu(c)))
                     }
                     (Hai::Souzu(a), Hai::Souzu(b), Hai::Souzu(c)) => {
                         let (a, b, c) = check_juntsu(a, b, c)?;
-                        Some(Mentsu::Juntsu(Hai::Souzu(a), Hai::Souzu(b), Hai::Souzu(c)))
+                        Ok(Mentsu::Juntsu(Hai::Souzu(a), Hai::Souzu(b), Hai::Souzu(c)))
                     }
-                    _ => None,
+                    (Hai::Jihai(_), Hai::Jihai(_), Hai::Jihai(_)) => Err(MentsuError::JihaiJuntsu),
+                    _ => Err(MentsuError::MixedSuits),
                 }
             }
         } else {
-            None
+            Err(MentsuError::WrongNumber(hai_vec.len()))
         }
     }
 
@@ -133,7 +165,7 @@ impl std::fmt::Display for Mentsu {
             "{}",
             match self {
                 Mentsu::Juntsu(a, b, c) => {
-                    format!("[{}{}{}]", a.to_string(), b.to_string(), c.to_string())
+                    format!("[{}{}{}]", a, b, c)
                 }
                 Mentsu::Koutsu(a) => {
                     let tile = a.to_string();
@@ -150,7 +182,7 @@ impl std::fmt::Display for Mentsu {
 
 impl std::fmt::Display for Taatsu {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", self.0.to_string(), self.1.to_string())
+        write!(f, "{}{}", self.0, self.1)
     }
 }
 
@@ -166,3 +198,21 @@ impl std::fmt::Display for Ukihai {
         self.0.fmt(f)
     }
 }
+
+impl std::fmt::Display for MentsuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MentsuError::InvalidHai(hai) => write!(f, "'{}' is invalid hai.", hai),
+            MentsuError::WrongNumber(number) => {
+                write!(f, "A mentsu needs 3 or 4 hai, but {} provided.", number)
+            }
+            MentsuError::NotSame => write!(f, "4 hai of a kantsu must be the same."),
+            MentsuError::MixedSuits => write!(f, "Hai of a juntsu must be of the same suit."),
+            MentsuError::JihaiJuntsu => write!(f, "Jihai can not make up a juntsu."),
+            MentsuError::SanmaManzuJuntsu => {
+                write!(f, "Manzu can not make up a juntsu on 3-players mode.")
+            }
+            MentsuError::NotSequential => write!(f, "Hai of a juntsu must be sequential."),
+        }
+    }
+}
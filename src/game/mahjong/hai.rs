@@ -1,4 +1,5 @@
 use super::PlayerNumber;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::BTreeSet;
 
 /// Type of hai(tile).
@@ -15,6 +16,8 @@ use std::collections::BTreeSet;
 /// * Pinzu: p
 /// * Souzu: s
 /// * Jihai: z
+///
+/// Number `0` of manzu, pinzu and souzu stands for aka(red five), see [`Hai::is_aka`].
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Hai {
     Manzu(u8),
@@ -23,9 +26,73 @@ pub enum Hai {
     Jihai(u8),
 }
 
+/// Suit of hai, see [`Hai::suit`]. Display gives the abbreviation, like `m` for manzu.
+///
+/// # Japanese
+/// * Man: 萬子
+/// * Pin: 筒子
+/// * Sou: 索子
+/// * Ji: 字牌
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Suit {
+    Man,
+    Pin,
+    Sou,
+    Ji,
+}
+
 impl Hai {
+    /// Return hai of a suit and a number, or `None` if the number is out of range, which is
+    /// 0\~9 for manzu, pinzu and souzu (0 is aka) and 1\~7 for jihai.
+    ///
+    /// It does not check the player number, see [`Hai::is_valid`].
+    pub fn new(suit: Suit, number: u8) -> Option<Hai> {
+        match (suit, number) {
+            (Suit::Man, 0..=9) => Some(Hai::Manzu(number)),
+            (Suit::Pin, 0..=9) => Some(Hai::Pinzu(number)),
+            (Suit::Sou, 0..=9) => Some(Hai::Souzu(number)),
+            (Suit::Ji, 1..=7) => Some(Hai::Jihai(number)),
+            _ => None,
+        }
+    }
+
+    /// Return the suit.
+    pub fn suit(&self) -> Suit {
+        match self {
+            Hai::Manzu(_) => Suit::Man,
+            Hai::Pinzu(_) => Suit::Pin,
+            Hai::Souzu(_) => Suit::Sou,
+            Hai::Jihai(_) => Suit::Ji,
+        }
+    }
+
+    /// Return the number, which is 0 for aka. Use `normalized` first to get 5 for it.
+    pub fn number(&self) -> u8 {
+        match self {
+            Hai::Manzu(num) | Hai::Pinzu(num) | Hai::Souzu(num) | Hai::Jihai(num) => *num,
+        }
+    }
+
+    /// Return the key to sort hai for display: manzu, pinzu, souzu, then jihai from `1z` to
+    /// `7z`, each suit by number. Aka is right after the normal five.
+    ///
+    /// Unlike the derived `Ord`, it does not depend on the order of enum variants, and
+    /// aka is not placed before `1`.
+    pub fn sort_key(&self) -> u32 {
+        let suit = match self.suit() {
+            Suit::Man => 0,
+            Suit::Pin => 1,
+            Suit::Sou => 2,
+            Suit::Ji => 3,
+        };
+        suit * 32 + self.normalized().number() as u32 * 2 + self.is_aka() as u32
+    }
+
     /// Parse string to a vec of hai. Order of hai is equal with input string.
-    pub fn from_string_unordered(string: &str, player_number: PlayerNumber) -> Result<Vec<Hai>, String> {
+    pub fn from_string_unordered(
+        string: &str,
+        player_number: PlayerNumber,
+    ) -> Result<Vec<Hai>, String> {
         fn handle_char_stash(
             hai_type: char,
             hai_type_char_index: usize,
@@ -33,7 +100,7 @@ impl Hai {
             char_stash: &mut Vec<char>,
             output: &mut Vec<Hai>,
         ) -> Result<(), String> {
-            if char_stash.len() == 0 {
+            if char_stash.is_empty() {
                 Err(format!(
                     "Unused type character '{}' at index {}.",
                     hai_type, hai_type_char_index
@@ -51,7 +118,7 @@ impl Hai {
                         output.push(hai);
                     } else {
                         char_stash.clear();
-                        return Err(format!("'{}' is invalid hai.", hai.to_string()));
+                        return Err(format!("'{}' is invalid hai.", hai));
                     }
                 }
                 char_stash.clear();
@@ -61,13 +128,13 @@ impl Hai {
 
         let mut char_stash: Vec<char> = vec![];
         let mut hai_vec = vec![];
-        
+
         for (index, chr) in string.chars().enumerate() {
             match chr {
                 'm' | 'p' | 's' | 'z' => {
                     handle_char_stash(chr, index, player_number, &mut char_stash, &mut hai_vec)?;
                 }
-                '1'..='9' => char_stash.push(chr),
+                '0'..='9' => char_stash.push(chr),
                 // Ignore all spaces.
                 ' ' => (),
                 _ => {
@@ -79,18 +146,18 @@ impl Hai {
         Ok(hai_vec)
     }
 
-    /// Return if valid -- it means 1\~9m, 1\~9p, 1\~9s, 1\~7z on 4-players mode
-    /// and 1m, 9m, 1\~9p, 1\~9s, 1\~7z on 3-players mode.
+    /// Return if valid -- it means 0\~9m, 0\~9p, 0\~9s, 1\~7z on 4-players mode
+    /// and 1m, 9m, 0\~9p, 0\~9s, 1\~7z on 3-players mode, where 0 is aka.
     pub fn is_valid(&self, player_number: PlayerNumber) -> bool {
-        match (self, player_number) {
-            (Hai::Manzu(1..=9), PlayerNumber::Four)
-            | (Hai::Manzu(1), PlayerNumber::Three)
-            | (Hai::Manzu(9), PlayerNumber::Three)
-            | (Hai::Pinzu(1..=9), _)
-            | (Hai::Souzu(1..=9), _)
-            | (Hai::Jihai(1..=7), _) => true,
-            _ => false,
-        }
+        matches!(
+            (self, player_number),
+            (Hai::Manzu(0..=9), PlayerNumber::Four)
+                | (Hai::Manzu(1), PlayerNumber::Three)
+                | (Hai::Manzu(9), PlayerNumber::Three)
+                | (Hai::Pinzu(0..=9), _)
+                | (Hai::Souzu(0..=9), _)
+                | (Hai::Jihai(1..=7), _)
+        )
     }
 
     /// Return ture when **all** hai in iterator is valid. Otherwise return false.
@@ -107,6 +174,52 @@ impl Hai {
         true
     }
 
+    /// Return if it is aka(red five) -- 0m, 0p or 0s.
+    ///
+    /// # Japanese
+    /// * aka: 赤
+    pub fn is_aka(&self) -> bool {
+        matches!(self, Hai::Manzu(0) | Hai::Pinzu(0) | Hai::Souzu(0))
+    }
+
+    /// Return the normal five for aka, and itself otherwise.
+    ///
+    /// Structural comparisons, like shanten and mentsu, should be done on normalized hai.
+    pub fn normalized(&self) -> Hai {
+        match self {
+            Hai::Manzu(0) => Hai::Manzu(5),
+            Hai::Pinzu(0) => Hai::Pinzu(5),
+            Hai::Souzu(0) => Hai::Souzu(5),
+            _ => *self,
+        }
+    }
+
+    /// Return aka of a five(or an aka itself). Return `None` for other hai.
+    pub fn make_aka(&self) -> Option<Hai> {
+        match self {
+            Hai::Manzu(0 | 5) => Some(Hai::Manzu(0)),
+            Hai::Pinzu(0 | 5) => Some(Hai::Pinzu(0)),
+            Hai::Souzu(0 | 5) => Some(Hai::Souzu(0)),
+            _ => None,
+        }
+    }
+
+    /// Return ture when there is at most one aka for each suit in iterator.
+    /// Otherwise return false.
+    pub fn check_iter_aka<'a, T>(iter: T) -> bool
+    where
+        T: Iterator<Item = &'a Self>,
+    {
+        let mut aka_set = BTreeSet::new();
+        for hai in iter {
+            if hai.is_aka() && !aka_set.insert(*hai) {
+                return false;
+            }
+        }
+
+        true
+    }
+
     /// Return a BTreeSet including all yaochuupai -- 1m, 9m, 1p, 9p, 1s, 9s, 1\~7z.
     pub fn yaochuupai_type() -> BTreeSet<Hai> {
         let mut yaochuupai_vec = BTreeSet::new();
@@ -124,7 +237,8 @@ impl Hai {
         yaochuupai_vec
     }
 
-    /// Return a BTreeSet including all valid types of hai.
+    /// Return a BTreeSet including all valid types of hai. Aka is not included, since it
+    /// is the same as five except for scoring.
     pub fn all_type(player_number: PlayerNumber) -> BTreeSet<Hai> {
         let mut all_hai_type = BTreeSet::new();
 
@@ -157,11 +271,12 @@ impl Hai {
     /// # Parameters
     /// * player_number: Number of players. No 2\~8m on 3-players mode.
     /// * dora_loop: If true, `Manzu(1).previous()`, `Pinzu(1).previous()` and
-    /// `Souzu(1).previous()` will be `Some(Manzu(9))`, `Some(Pinzu(9))` and
-    /// `Some(souzu(9))`, `Jihai(1).previous()` will be `Some(Jihai(4))`,
-    /// `Jihai(5).previous()` will be `Some(Jihai(7))`. Otherwise, `Manzu(1).previous()`,
-    /// `Pinzu(1).previous()`, `Souzu(1).previous()` and `Jihai(1).previous()`
-    /// will all be `None`.
+    ///   `Souzu(1).previous()` will be `Some(Manzu(9))`, `Some(Pinzu(9))` and
+    ///   `Some(souzu(9))`, `Jihai(1).previous()` will be `Some(Jihai(4))`,
+    ///   `Jihai(5).previous()` will be `Some(Jihai(7))`. Otherwise, `Manzu(1).previous()`,
+    ///   `Pinzu(1).previous()`, `Souzu(1).previous()` and `Jihai(1).previous()`
+    ///   will all be `None`. On 3-players mode, `Manzu(1)` and `Manzu(9)` are previous
+    ///   of each other if dora_loop, and manzu never has previous hai otherwise.
     ///
     /// # Japanese
     /// * dora: ドラ
@@ -232,11 +347,13 @@ impl Hai {
     /// # Parameters
     /// * player_number: Number of players. No 2\~8m on 3-players mode.
     /// * dora_loop: If true, `Manzu(9).next()`, `Pinzu(9).next()` and
-    /// `Souzu(9).next()` will be `Some(Manzu(1))`, `Some(Pinzu(1))` and
-    /// `Some(souzu(1))`, `Jihai(4).next()` will be `Some(Jihai(1))`,
-    /// `Jihai(7).next()` will be `Some(Jihai(5))`. Otherwise, `Manzu(1).next()`,
-    /// `Pinzu(9).next()`, `Souzu(9).next()` and `Jihai(7).next()` will
-    /// all be `None`.
+    ///   `Souzu(9).next()` will be `Some(Manzu(1))`, `Some(Pinzu(1))` and
+    ///   `Some(souzu(1))`, `Jihai(4).next()` will be `Some(Jihai(1))`,
+    ///   `Jihai(7).next()` will be `Some(Jihai(5))`. Otherwise, `Manzu(9).next()`,
+    ///   `Pinzu(9).next()`, `Souzu(9).next()` and `Jihai(7).next()` will
+    ///   all be `None`. On 3-players mode, `Manzu(1)` and `Manzu(9)` are next
+    ///   of each other if dora_loop, so dora of indicator `1m` is `9m` but not `2m`,
+    ///   and manzu never has next hai otherwise.
     ///
     /// # Japanese
     /// * dora: ドラ
@@ -301,6 +418,53 @@ impl Hai {
             }
         }
     }
+
+    /// Return dora of self as dora indicator, `9` to `1` in each suit, `4z` to `1z` in winds
+    /// and `7z` to `5z` in sangenpai. On 3-players mode, `1m` and `9m` indicate each other.
+    /// Aka indicates the same as five.
+    ///
+    /// An invalid indicator for the player number, like `2m` on 3-players mode, indicates
+    /// itself.
+    ///
+    /// # Japanese
+    /// * dora: ドラ
+    /// * dora indicator: ドラ表示牌
+    pub fn dora_from_indicator(&self, player_number: PlayerNumber) -> Hai {
+        let indicator = self.normalized();
+        indicator.next(player_number, true).unwrap_or(indicator)
+    }
+
+    /// Count dora in hand for all indicators. A hai is counted once for each indicator
+    /// pointing to it. Aka is counted as five, but not as extra dora itself.
+    pub fn count_dora(hand: &[Hai], indicators: &[Hai], player_number: PlayerNumber) -> u32 {
+        indicators
+            .iter()
+            .map(|indicator| {
+                let dora = indicator.dora_from_indicator(player_number);
+                hand.iter().filter(|hai| hai.normalized() == dora).count() as u32
+            })
+            .sum()
+    }
+
+    /// Return the character of Unicode mahjong tiles block, such as `🀇` for `1m`.
+    ///
+    /// Unicode has no red five, so aka is rendered as the normal five. Jihai follow the
+    /// order of this crate (1z-7z: 東南西北白發中), not the order of Unicode, which places
+    /// 中 before 發 and 白.
+    pub fn to_unicode(&self) -> char {
+        let code = match self.normalized() {
+            Hai::Manzu(num) => 0x1F007 + num as u32 - 1,
+            Hai::Souzu(num) => 0x1F010 + num as u32 - 1,
+            Hai::Pinzu(num) => 0x1F019 + num as u32 - 1,
+            Hai::Jihai(num @ 1..=4) => 0x1F000 + num as u32 - 1,
+            Hai::Jihai(5) => 0x1F006,
+            Hai::Jihai(6) => 0x1F005,
+            Hai::Jihai(7) => 0x1F004,
+            // Invalid hai has no tile, use the back of tile.
+            Hai::Jihai(_) => 0x1F02B,
+        };
+        std::char::from_u32(code).unwrap_or('\u{1F02B}')
+    }
 }
 
 impl std::fmt::Display for Hai {
@@ -317,3 +481,65 @@ impl std::fmt::Display for Hai {
         )
     }
 }
+
+impl std::fmt::Display for Suit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Suit::Man => 'm',
+                Suit::Pin => 'p',
+                Suit::Sou => 's',
+                Suit::Ji => 'z',
+            }
+        )
+    }
+}
+
+/// Serialized as its string like `5m`, so that hai can be a key of json object, such as
+/// in haiyama. Formats which are not human readable, like bincode, take a single byte
+/// instead: the number, plus 10 for pinzu, 20 for souzu and 30 for jihai.
+impl Serialize for Hai {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            let offset = match self {
+                Hai::Manzu(_) => 0,
+                Hai::Pinzu(_) => 10,
+                Hai::Souzu(_) => 20,
+                Hai::Jihai(_) => 30,
+            };
+            return serializer.serialize_u8(offset + self.number());
+        }
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hai {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            let code = u8::deserialize(deserializer)?;
+            return match (code / 10, code % 10) {
+                (0, number) => Ok(Hai::Manzu(number)),
+                (1, number) => Ok(Hai::Pinzu(number)),
+                (2, number) => Ok(Hai::Souzu(number)),
+                (3, number @ 1..=7) => Ok(Hai::Jihai(number)),
+                _ => Err(serde::de::Error::custom(format!(
+                    "Invalid hai code {}.",
+                    code
+                ))),
+            };
+        }
+        let string = String::deserialize(deserializer)?;
+        match Hai::from_string_unordered(&string, PlayerNumber::Four)
+            .map_err(serde::de::Error::custom)?
+            .as_slice()
+        {
+            [hai] => Ok(*hai),
+            _ => Err(serde::de::Error::custom(format!(
+                "Expected exactly one hai, found '{}'.",
+                string
+            ))),
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use super::{Hai, PlayerNumber};
+use super::{Hai, MahjongError, PlayerNumber};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{collections::BTreeMap, ops::Index};
 
@@ -6,68 +7,150 @@ use std::{collections::BTreeMap, ops::Index};
 ///
 /// # Japanese
 /// * Haiyama: 牌山
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Haiyama {
     map: BTreeMap<Hai, u8>,
 }
 
 impl Haiyama {
-    /// Create a new haiyama with 4 of each type of hai.
+    /// Create a new haiyama with 4 of each type of hai, one of the four fives of each suit
+    /// being aka.
     pub fn new(player_number: PlayerNumber) -> Self {
         let mut map = BTreeMap::new();
         for hai in Hai::all_type(player_number) {
-            map.insert(hai, 4);
+            match hai.make_aka() {
+                Some(aka) => {
+                    map.insert(hai, 3);
+                    map.insert(aka, 1);
+                }
+                None => {
+                    map.insert(hai, 4);
+                }
+            }
         }
         Self { map }
     }
 
-    /// Add one hai to haiyama, limited to 4.
+    /// Check if haiyama is legal for the number of players, such as a haiyama loaded from
+    /// a save file. Fail if any type of hai is missing or unexpected, or more than 4 of it
+    /// are left (3 for five and 1 for aka).
+    pub fn validate(&self, player_number: PlayerNumber) -> Result<(), MahjongError> {
+        let full = Haiyama::new(player_number);
+        for (hai, limit) in &full.map {
+            match self.map.get(hai) {
+                Some(number) if number > limit => {
+                    return Err(MahjongError::Other(format!(
+                        "{} '{}' in haiyama, more than {}.",
+                        number, hai, limit
+                    )))
+                }
+                Some(_) => (),
+                None => {
+                    return Err(MahjongError::Other(format!(
+                        "Number of '{}' in haiyama is missing.",
+                        hai
+                    )))
+                }
+            }
+        }
+        match self.map.keys().find(|hai| !full.map.contains_key(hai)) {
+            Some(hai) => Err(MahjongError::InvalidTile(*hai)),
+            None => Ok(()),
+        }
+    }
+
+    /// Add one hai to haiyama, limited to 4, or 3 for five and 1 for aka.
+    /// Adding a five restores aka if the other three fives are already in haiyama.
     pub fn add(&mut self, hai: &Hai) -> Result<(), String> {
+        let hai = &self.resolve(hai, 3);
         let number = self.map[hai];
-        if number < 4 {
+        let limit = match (hai.is_aka(), hai.make_aka()) {
+            (true, _) => 1,
+            (false, Some(_)) => 3,
+            (false, None) => 4,
+        };
+        if number < limit {
             self.map.insert(*hai, number + 1);
             Ok(())
         } else {
             Err(format!(
-                "Already 4 '{}' in haiyama, cannot add more one.",
-                hai.to_string()
+                "Already {} '{}' in haiyama, cannot add more one.",
+                limit, hai
             ))
         }
     }
 
+    /// Return the number of a type of hai left, aka included for five.
+    pub fn count(&self, hai: &Hai) -> u8 {
+        let number = self.map.get(hai).copied().unwrap_or(0);
+        match hai.make_aka() {
+            Some(aka) if aka != *hai => number + self.map.get(&aka).copied().unwrap_or(0),
+            _ => number,
+        }
+    }
+
+    /// Return the number of exactly this hai left, with aka and five apart like `Index`,
+    /// but 0 instead of panic for hai not in haiyama, like `2m` on 3-players mode.
+    pub fn remaining(&self, hai: &Hai) -> u8 {
+        self.map.get(hai).copied().unwrap_or(0)
+    }
+
+    /// Return the number of all hai left.
+    pub fn remaining_total(&self) -> u32 {
+        self.map.values().map(|&number| number as u32).sum()
+    }
+
+    /// Iterate over all types of hai and the number left, in the order of hai.
+    /// Aka is a separate item, which comes first in its suit since its number is 0.
+    pub fn iter(&self) -> impl Iterator<Item = (&Hai, u8)> {
+        self.map.iter().map(|(hai, &number)| (hai, number))
+    }
+
     /// Add a vec of hai to haiyama, limited to 4.
     ///
     /// # Parameters
     /// * auto_restore: If ture, haiyama will restore to original state
-    /// when error occured.
+    ///   when error occured. Otherwise hai over the limit are skipped and the rest
+    ///   are still added, then the first error is returned.
     pub fn add_with_vec(&mut self, hai_vec: &Vec<Hai>, auto_restore: bool) -> Result<(), String> {
         let backup = if auto_restore {
             self.map.clone()
         } else {
             BTreeMap::new()
         };
+        let mut result = Ok(());
         for hai in hai_vec {
             if let Err(error) = self.add(hai) {
                 if auto_restore {
                     self.map = backup;
+                    return Err(error);
+                }
+                if result.is_ok() {
+                    result = Err(error);
                 }
-                return Err(error);
             }
         }
-        Ok(())
+        result
     }
 
-    /// Discard one hai from haiyama.
-    pub fn discard(&mut self, hai: &Hai) -> Result<(), String> {
+    /// Discard one hai from haiyama. Discarding a five takes aka if no other five is left.
+    pub fn discard(&mut self, hai: &Hai) -> Result<(), MahjongError> {
+        let hai = &self.resolve(hai, 0);
         let number = self.map[hai];
         if number > 0 {
             self.map.insert(*hai, number - 1);
             Ok(())
         } else {
-            Err(format!(
-                "Already no '{}' in haiyama, cannot discard more one.",
-                hai.to_string()
-            ))
+            Err(MahjongError::HaiyamaExhausted(*hai))
+        }
+    }
+
+    /// Discard one hai from haiyama like `discard`, but return the number actually
+    /// discarded, 0 if no such hai left, instead of an error.
+    pub fn discard_checked(&mut self, hai: &Hai) -> u8 {
+        match self.discard(hai) {
+            Ok(_) => 1,
+            Err(_) => 0,
         }
     }
 
@@ -75,7 +158,8 @@ impl Haiyama {
     ///
     /// # Parameters
     /// * auto_restore: If ture, haiyama will restore to original state
-    /// when error occured.
+    ///   when error occured. Otherwise hai already used up are skipped and the rest
+    ///   are still discarded, then the first error is returned.
     pub fn discard_with_vec(
         &mut self,
         hai_vec: &Vec<Hai>,
@@ -86,19 +170,29 @@ impl Haiyama {
         } else {
             BTreeMap::new()
         };
+        let mut result = Ok(());
         for hai in hai_vec {
-            if let Err(_) = self.discard(hai) {
+            if self.discard(hai).is_err() {
+                let error = format!("Not enough '{}' in haiyama to discard.", hai);
                 if auto_restore {
                     self.map = backup;
+                    return Err(error);
+                }
+                if result.is_ok() {
+                    result = Err(error);
                 }
-                return Err(format!(
-                    "Not enough '{}' in haiyama to discard.",
-                    hai.to_string()
-                ));
             }
         }
+        result
+    }
 
-        Ok(())
+    /// Return aka instead of five if the number of five is `full`, which means five can not
+    /// be added or discarded any more. Return hai itself otherwise.
+    fn resolve(&self, hai: &Hai, full: u8) -> Hai {
+        match hai.make_aka() {
+            Some(aka) if aka != *hai && self.map.get(hai) == Some(&full) => aka,
+            _ => *hai,
+        }
     }
 
     /// Print self to json.
@@ -111,6 +205,15 @@ impl Haiyama {
         }
         json!(json_vec)
     }
+
+    /// Print self to json as a single object, like `{"1m":4,"2m":4}`.
+    pub fn to_compact_json(&self) -> serde_json::Value {
+        let mut json = serde_json::Map::new();
+        for (hai, number) in &self.map {
+            json.insert(hai.to_string(), json!(number));
+        }
+        serde_json::Value::Object(json)
+    }
 }
 
 impl<'a> Index<&'a Hai> for Haiyama {
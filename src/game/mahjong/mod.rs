@@ -2,12 +2,18 @@ mod combination;
 mod hai;
 mod haiyama;
 mod player_number;
+pub mod shanten;
 mod tehai;
+mod yaku;
 
-use super::{GameManager, Kan};
+use super::{GameManager, Kan, MahjongError, Naku};
 
-pub use combination::{Mentsu, Taatsu, Toitsu, Ukihai};
-pub use hai::Hai;
+pub use combination::{Mentsu, MentsuError, Taatsu, Toitsu, Ukihai};
+pub use hai::{Hai, Suit};
 pub use haiyama::Haiyama;
 pub use player_number::PlayerNumber;
-pub use tehai::{MachiCondition, Tehai};
+pub use tehai::{
+    Decomposition, DecompositionCount, DiscardEfficiency, HandBreakdown, Hourakei, MachiCondition,
+    Tehai, WaitKind,
+};
+pub use yaku::{AgariContext, Yaku};
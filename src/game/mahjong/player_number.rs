@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 /// Number of players. Support 4-players mode and
 /// 3-players mode yet.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PlayerNumber {
     Three,
     Four,
@@ -18,3 +20,16 @@ impl std::fmt::Display for PlayerNumber {
         )
     }
 }
+
+impl std::str::FromStr for PlayerNumber {
+    type Err = String;
+
+    /// Parse `3`/`sanma` as 3-players mode and `4`/`yonma` as 4-players mode.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "3" | "sanma" => Ok(PlayerNumber::Three),
+            "4" | "yonma" => Ok(PlayerNumber::Four),
+            _ => Err(format!("Not support {}-players mode.", s)),
+        }
+    }
+}
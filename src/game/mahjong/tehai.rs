@@ -1,6 +1,37 @@
-use super::{GameManager, Hai, Kan, Mentsu, PlayerNumber, Taatsu, Toitsu, Ukihai};
+use super::{
+    shanten, AgariContext, GameManager, Hai, Haiyama, Kan, MahjongError, Mentsu, Naku,
+    PlayerNumber, Suit, Taatsu, Toitsu, Ukihai,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Key of the split cache: player number, suit and the count of each number in that suit.
+type SplitKey = (PlayerNumber, Suit, [u8; 9]);
+
+/// Numbers of decompositions with the minimum shanten and of all explored, see
+/// `Tehai::decomposition_count`.
+pub type DecompositionCount = (usize, usize);
+
+/// Each ukeire and tehai after drawing it.
+type UkeireTehai = Vec<(Hai, Tehai)>;
+
+/// Lookahead deeper than this takes too long, see `Tehai::analyze_lookahead`.
+const MAX_LOOKAHEAD_DEPTH: usize = 2;
+
+/// The cache is dropped as a whole once it grows to this size.
+const SPLIT_CACHE_CAPACITY: usize = 1 << 14;
+
+/// Numbers of mentsu, taatsu and toitsu of a split, and whether any toitsu is duplicated.
+type SplitProfile = (usize, usize, usize, bool);
+
+thread_local! {
+    static SPLIT_CACHE: RefCell<HashMap<SplitKey, Rc<SuitSplit>>> =
+        RefCell::new(HashMap::new());
+}
 
 /// hai on hand.
 ///
@@ -12,6 +43,10 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 /// # Member
 /// * juntehai: Vec of hai which not formed mentsu.
 /// * fuuro: Mentsu which already formed.
+/// * ankan: Hai of kantsu in fuuro which are ankan, that is, not called from others.
+///
+/// Equality and hash ignore the order of juntehai and fuuro, so that the same hand
+/// always collides to one entry as a key of `HashMap` or `HashSet`.
 ///
 /// # Examples
 /// ```rust
@@ -20,10 +55,11 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 /// std::io::stdin().read_line(&mut input).expect("error: unable to read user input");
 /// println!("{:?}", Tehai::new(input.trim(), PlayerNumber::Four).unwrap());
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tehai {
     pub juntehai: Vec<Hai>,
     pub fuuro: Vec<Mentsu>,
+    ankan: BTreeSet<Hai>,
 }
 
 /// Form of tehai when winning.
@@ -35,20 +71,41 @@ pub struct Tehai {
 /// * Mentsute: 面子手
 /// * Chiitoitsu: 七対子
 /// * Kokushimusou: 国士無双
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Hourakei {
     Mentsute,
     Chiitoitsu,
     Kokushimusou,
 }
 
+/// Shape of waiting when tenpai.
+///
+/// # Japanese
+/// * Ryanmen: 両面
+/// * Kanchan: 嵌張
+/// * Penchan: 辺張
+/// * Shanpon: 双碰
+/// * Tanki: 単騎
+/// * Nobetan: 延べ単
+/// * Multi: 多面張
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WaitKind {
+    Ryanmen,
+    Kanchan,
+    Penchan,
+    Shanpon,
+    Tanki,
+    Nobetan,
+    Multi,
+}
+
 /// Decompose a tehai to mentsu, taatsu, toitsu and ukihai for analyzing hai waiting for.
 ///
 /// # Member
 /// * valid_ukihai_vec: Ukihai that can provide shanten. such as `3p`, `5p`, `6p` and `8p` in
-/// `11224477m356778p`, or any yaochuupai in kokushimusou type.
+///   `11224477m356778p`, or any yaochuupai in kokushimusou type.
 /// * invalid_ukihai_vec: Ukihai that cannot provide shanten, absolutely useless. such as `1m`
-/// in `111224477m34577p`, or any non-yaochuupai in kokushimusou type.
+///   in `111224477m34577p`, or any non-yaochuupai in kokushimusou type.
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 struct Decomposer {
     mentsu_vec: Vec<Mentsu>,
@@ -59,22 +116,115 @@ struct Decomposer {
     hourakei: Hourakei,
 }
 
+/// All splits of hai of one suit, see `Tehai::split_suit`.
+///
+/// # Member
+/// * decomposers: every split.
+/// * groups: indices of splits grouped by `SplitProfile`, filled on first use.
+struct SuitSplit {
+    decomposers: Vec<Decomposer>,
+    groups: OnceCell<Vec<(SplitProfile, Vec<usize>)>>,
+}
+
+/// A public view of one decomposition of juntehai, used to explain where machihai come from.
+///
+/// # Member
+/// * hourakei: form of tehai this decomposition aims at.
+/// * mentsu: mentsu formed in juntehai (fuuro excluded).
+/// * toitsu: toitsu in juntehai.
+/// * taatsu: taatsu in juntehai.
+/// * ukihai: the rest hai, sutehai excluded.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Decomposition {
+    pub hourakei: Hourakei,
+    pub mentsu: Vec<Mentsu>,
+    pub toitsu: Vec<Toitsu>,
+    pub taatsu: Vec<Taatsu>,
+    pub ukihai: Vec<Ukihai>,
+}
+
+/// A public view of a decomposition found by analysis, mirroring the internal decomposer.
+/// Unlike `Decomposition`, ukihai are kept apart by whether they can reduce shanten.
+///
+/// # Member
+/// * hourakei: form of tehai this breakdown aims at.
+/// * mentsu: mentsu formed in juntehai (fuuro excluded).
+/// * toitsu: toitsu in juntehai.
+/// * taatsu: taatsu in juntehai.
+/// * valid_ukihai: ukihai that can provide shanten.
+/// * invalid_ukihai: ukihai that cannot provide shanten, absolutely useless.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct HandBreakdown {
+    pub hourakei: Hourakei,
+    pub mentsu: Vec<Mentsu>,
+    pub toitsu: Vec<Toitsu>,
+    pub taatsu: Vec<Taatsu>,
+    pub valid_ukihai: Vec<Ukihai>,
+    pub invalid_ukihai: Vec<Ukihai>,
+}
+
 /// Condition of different sutehai.
 ///
 /// # Japanese
 /// * sutehai: 捨て牌
 /// * machihai: 待ち牌
 /// * furiten: 振り聴
+/// * temporary furiten: 同巡内振り聴
+/// * karaten: 空聴
 ///
 /// # Member
 /// * sutehai: which ukihai will be discarded.
 /// * machihai: hai waiting for.
 /// * furiten: if machihai included prevenient sutehai.
+/// * temporary_furiten: if a ron was skipped since the last own discard, which is reported
+///   apart from furiten caused by sutehai.
+/// * draw_probability: probability of drawing any machihai as the next hai, only known with
+///   haiyama of a game manager.
+/// * decomposition: a representative decomposition which provides the most machihai.
+/// * dead_machihai: machihai with no hai left, which are removed from machihai.
+/// * karaten: tenpai after discarding sutehai, but no machihai left at all.
+/// * by_shape: machihai of each shape in decompositions which provides them, like `3p` and
+///   `6p` for taatsu `4p5p`, including dead machihai. Keys are shapes as strings, or
+///   `chiitoitsu` and `kokushimusou` for hai wanted by the whole tehai.
 #[derive(Clone, Debug)]
 pub struct MachiCondition {
     pub sutehai: Hai,
     pub machihai: BTreeMap<Hai, u8>,
+    pub dead_machihai: BTreeSet<Hai>,
+    pub karaten: bool,
     pub furiten: bool,
+    pub temporary_furiten: bool,
+    pub draw_probability: Option<f64>,
+    pub decomposition: Option<Decomposition>,
+    pub by_shape: BTreeMap<String, BTreeSet<Hai>>,
+}
+
+/// Efficiency of discarding a hai, including hai which make shanten worse.
+///
+/// # Japanese
+/// * ukeire: 受け入れ
+///
+/// # Member
+/// * shanten: the number of shanten after discarding.
+/// * condition: sutehai, and ukeire as machihai, which reduce shanten after discarding.
+/// * tenpai_machihai: only for 1 shanten, the most machihai of tenpai after drawing any
+///   ukeire.
+#[derive(Clone, Debug)]
+pub struct DiscardEfficiency {
+    pub shanten: i32,
+    pub condition: MachiCondition,
+    pub tenpai_machihai: Option<usize>,
+}
+
+/// Count pairs of identical juntsu, so 1 for iipeikou and 2 for ryanpeikou.
+fn count_iipeikou(mentsu_vec: &[Mentsu]) -> u32 {
+    let mut juntsu_count = BTreeMap::new();
+    for mentsu in mentsu_vec {
+        if let Mentsu::Juntsu(..) = mentsu {
+            *juntsu_count.entry(*mentsu).or_insert(0) += 1;
+        }
+    }
+    juntsu_count.values().map(|count| count / 2).sum()
 }
 
 fn remove_once<T: Eq>(container: &mut Vec<T>, item: &T) {
@@ -96,25 +246,30 @@ impl Tehai {
     /// * shorter: `123445m4445p8s[111z]`
     /// * with spaces: `123445m 4445p 8s [111z]`
     /// * chaos: `45p 8s14 4m[11 1z]2 5m44p 3m`
+    /// * with aka: `123405m4445p8s[111z]`, `0` stands for aka and at most one aka of each suit.
+    ///   Aka in `[]` is regarded as five.
+    /// * with naki markers: `123445m4445p8s p111z`, a marker before a meld instead of `[]`
+    ///   tells how it was formed, `c` for chii, `p` for pon, `m` for daiminkan, `k` for kakan
+    ///   and `a` for ankan. Only ankan is regarded as not called.
     ///
     /// # Examples
     /// ```rust
     /// use japanese_mahjong_theory::{Tehai, PlayerNumber};
     /// let tehai = Tehai::new("45p8s144m[111z]25m44p3m", PlayerNumber::Four);
     /// ```
-    pub fn new(string: &str, player_number: PlayerNumber) -> Result<Self, String> {
+    pub fn new(string: &str, player_number: PlayerNumber) -> Result<Self, MahjongError> {
         fn handle_char_stash(
             hai_type: char,
             hai_type_char_index: usize,
             player_number: PlayerNumber,
             char_stash: &mut Vec<char>,
             output: &mut Vec<Hai>,
-        ) -> Result<(), String> {
-            if char_stash.len() == 0 {
-                Err(format!(
+        ) -> Result<(), MahjongError> {
+            if char_stash.is_empty() {
+                Err(MahjongError::Other(format!(
                     "Unused type character '{}' at index {}.",
                     hai_type, hai_type_char_index
-                ))
+                )))
             } else {
                 for hai in char_stash.iter() {
                     let hai = match hai_type {
@@ -128,7 +283,7 @@ impl Tehai {
                         output.push(hai);
                     } else {
                         char_stash.clear();
-                        return Err(format!("'{}' is invalid hai.", hai.to_string()));
+                        return Err(MahjongError::InvalidTile(hai));
                     }
                 }
                 char_stash.clear();
@@ -141,11 +296,44 @@ impl Tehai {
             player_number: PlayerNumber,
             hai_in_mentsu_stash: &mut Vec<Hai>,
             output: &mut Vec<Mentsu>,
-        ) -> Result<(), String> {
-            let mentsu = Mentsu::new(hai_in_mentsu_stash, player_number).ok_or(format!(
-                "Not a valid meld on '[]' before index {}.",
-                char_index
-            ))?;
+        ) -> Result<(), MahjongError> {
+            let mentsu = Mentsu::try_new(hai_in_mentsu_stash, player_number).map_err(|error| {
+                // A group containing a mentsu but not being exactly one mentsu could be
+                // read as several different melds, like `[1234m]`.
+                let group = hai_in_mentsu_stash.len();
+                let ambiguous = group > 3
+                    && (0..group).any(|i| {
+                        (i + 1..group).any(|j| {
+                            (j + 1..group).any(|k| {
+                                Mentsu::new(
+                                    &[
+                                        hai_in_mentsu_stash[i],
+                                        hai_in_mentsu_stash[j],
+                                        hai_in_mentsu_stash[k],
+                                    ],
+                                    player_number,
+                                )
+                                .is_some()
+                            })
+                        })
+                    });
+                if ambiguous {
+                    let mut group_string = String::new();
+                    for hai in hai_in_mentsu_stash.iter() {
+                        group_string += &hai.to_string();
+                    }
+                    format!(
+                        "Ambiguous meld group '[{}]' before index {}. \
+                        Each '[]' must be exactly one mentsu.",
+                        group_string, char_index
+                    )
+                } else {
+                    format!(
+                        "Not a valid meld on '[]' before index {}. {}",
+                        char_index, error
+                    )
+                }
+            })?;
 
             output.push(mentsu);
             hai_in_mentsu_stash.clear();
@@ -157,9 +345,73 @@ impl Tehai {
         let mut char_stash: Vec<char> = vec![];
         let mut hai_in_mentsu_stash: Vec<Hai> = vec![];
         let mut in_mentsu = false;
+        let mut ankan = BTreeSet::new();
+        // Naki marker of the current meld and its index.
+        let mut naki_marker: Option<(char, usize)> = None;
 
         for (index, chr) in string.chars().enumerate() {
             match chr {
+                'c' | 'p' | 'm' | 'k' | 'a'
+                    if char_stash.is_empty() && !in_mentsu && naki_marker.is_none() =>
+                {
+                    naki_marker = Some((chr, index));
+                }
+                'c' | 'k' | 'a' => {
+                    if in_mentsu {
+                        return Err(MahjongError::Other(format!(
+                            "Naki marker '{}' found in '[]' at index {}.",
+                            chr, index
+                        )));
+                    }
+                    if naki_marker.is_some() && char_stash.is_empty() {
+                        return Err(MahjongError::Other(format!(
+                            "Second naki marker found at index {}.",
+                            index
+                        )));
+                    }
+                    return Err(MahjongError::Other(format!(
+                        "Need 'm' 'p' 's' 'z' but find '{}' at index {}.",
+                        chr, index
+                    )));
+                }
+                'm' | 'p' | 's' | 'z' if naki_marker.is_some() => {
+                    if char_stash.is_empty() {
+                        return Err(MahjongError::Other(format!(
+                            "Second naki marker found at index {}.",
+                            index
+                        )));
+                    }
+                    handle_char_stash(
+                        chr,
+                        index,
+                        player_number,
+                        &mut char_stash,
+                        &mut hai_in_mentsu_stash,
+                    )?;
+                    let (marker, marker_index) = naki_marker.take().unwrap();
+                    handle_hai_in_mentsu_stash(
+                        index,
+                        player_number,
+                        &mut hai_in_mentsu_stash,
+                        &mut fuuro,
+                    )?;
+                    let mentsu = fuuro[fuuro.len() - 1];
+                    match (marker, mentsu) {
+                        ('c', Mentsu::Juntsu(_, _, _))
+                        | ('p', Mentsu::Koutsu(_))
+                        | ('m', Mentsu::Kantsu(_))
+                        | ('k', Mentsu::Kantsu(_)) => (),
+                        ('a', Mentsu::Kantsu(hai)) => {
+                            ankan.insert(hai);
+                        }
+                        _ => {
+                            return Err(MahjongError::Other(format!(
+                                "Naki marker '{}' at index {} does not match {}.",
+                                marker, marker_index, mentsu
+                            )))
+                        }
+                    }
+                }
                 'm' | 'p' | 's' | 'z' => {
                     if in_mentsu {
                         handle_char_stash(
@@ -179,28 +431,40 @@ impl Tehai {
                         )?;
                     }
                 }
-                '1'..='9' => char_stash.push(chr),
+                '0'..='9' => char_stash.push(chr),
                 '[' => {
                     if in_mentsu {
-                        return Err(format!("Second '[' found at index {}.", index));
+                        return Err(MahjongError::Other(format!(
+                            "Second '[' found at index {}.",
+                            index
+                        )));
+                    }
+                    if naki_marker.is_some() {
+                        return Err(MahjongError::Other(format!(
+                            "Naki marker followed by '[' at index {}.",
+                            index
+                        )));
                     }
-                    if char_stash.len() > 0 {
-                        return Err(format!(
+                    if !char_stash.is_empty() {
+                        return Err(MahjongError::Other(format!(
                             "Need 'm' 'p' 's' 'z' but find '[' at index {}.",
                             index
-                        ));
+                        )));
                     };
                     in_mentsu = true;
                 }
                 ']' => {
                     if !in_mentsu {
-                        return Err(format!("Unmatched ']' found at index {}.", index));
+                        return Err(MahjongError::Other(format!(
+                            "Unmatched ']' found at index {}.",
+                            index
+                        )));
                     }
-                    if char_stash.len() > 0 {
-                        return Err(format!(
+                    if !char_stash.is_empty() {
+                        return Err(MahjongError::Other(format!(
                             "Need 'm' 'p' 's' 'z' but find ']' at index {}.",
                             index
-                        ));
+                        )));
                     };
                     handle_hai_in_mentsu_stash(
                         index,
@@ -213,25 +477,248 @@ impl Tehai {
                 // Ignore all spaces.
                 ' ' => (),
                 _ => {
-                    return Err(format!("Unknown character '{}' at index {}.", chr, index));
+                    return Err(MahjongError::Other(format!(
+                        "Unknown character '{}' at index {}.",
+                        chr, index
+                    )));
                 }
             }
         }
 
-        if char_stash.len() > 0 {
-            return Err(format!(
+        if !char_stash.is_empty() {
+            return Err(MahjongError::Other(format!(
                 "No type specified for '{:?}' at the end of input string.",
                 char_stash
+            )));
+        }
+        if in_mentsu {
+            return Err(MahjongError::Other(
+                "Unclosed '[' at the end of input string.".to_string(),
+            ));
+        }
+        if let Some((marker, marker_index)) = naki_marker {
+            return Err(MahjongError::Other(format!(
+                "Naki marker '{}' at index {} is not followed by a meld.",
+                marker, marker_index
+            )));
+        }
+
+        juntehai.sort_by_key(Hai::sort_key);
+        let tehai = Self {
+            juntehai,
+            fuuro,
+            ankan,
+        };
+
+        tehai.validate(player_number)?;
+        Ok(tehai)
+    }
+
+    /// Create tehai from juntehai and fuuro directly, without formatting a string.
+    ///
+    /// Fail if any hai or mentsu is invalid, or any hai appears more than four times.
+    /// Juntehai will be sorted.
+    pub fn with_fuuro(
+        mut juntehai: Vec<Hai>,
+        fuuro: Vec<Mentsu>,
+        player_number: PlayerNumber,
+    ) -> Result<Self, String> {
+        juntehai.sort_by_key(Hai::sort_key);
+        let tehai = Self {
+            juntehai,
+            fuuro,
+            ankan: BTreeSet::new(),
+        };
+        tehai.validate(player_number)?;
+        Ok(tehai)
+    }
+
+    /// Check if tehai is legal for the number of players, such as a tehai whose fields
+    /// are edited directly.
+    ///
+    /// Fail if any hai is invalid, any fuuro can not be made up on this mode (such as
+    /// `[234m]` on 3-players mode), more than one aka of a suit is in juntehai, or any hai
+    /// appears more than four times.
+    pub fn validate(&self, player_number: PlayerNumber) -> Result<(), MahjongError> {
+        for hai in &self.juntehai {
+            if !hai.is_valid(player_number) {
+                return Err(MahjongError::InvalidTile(*hai));
+            }
+        }
+        for mentsu in &self.fuuro {
+            let hai_vec = match mentsu {
+                Mentsu::Juntsu(a, b, c) => vec![*a, *b, *c],
+                Mentsu::Koutsu(hai) => vec![*hai; 3],
+                Mentsu::Kantsu(hai) => vec![*hai; 4],
+            };
+            match Mentsu::try_new(&hai_vec, player_number) {
+                Ok(valid) if valid == *mentsu => (),
+                Ok(_) => {
+                    return Err(MahjongError::Other(format!(
+                        "Mentsu '{}' is not sorted.",
+                        mentsu
+                    )))
+                }
+                Err(error) => {
+                    return Err(MahjongError::Other(format!(
+                        "Invalid mentsu '{}': {}",
+                        mentsu, error
+                    )))
+                }
+            }
+        }
+        if !Hai::check_iter_aka(self.juntehai.iter()) {
+            return Err(MahjongError::Other(
+                "More than one aka of a suit found.".to_string(),
+            ));
+        }
+        self.check_hai_number().map_err(MahjongError::from)
+    }
+
+    /// Create tehai from the number of each hai in juntehai, avoiding the string round-trip
+    /// of `new`. Index 0 of each suit is `1`, and jihai are `1z` ~ `7z`. Aka is not
+    /// supported here.
+    ///
+    /// Fail if any number is more than 4, juntehai is not 3*k+2, or as `with_fuuro` does.
+    pub fn from_counts(
+        manzu: [u8; 9],
+        pinzu: [u8; 9],
+        souzu: [u8; 9],
+        jihai: [u8; 7],
+        fuuro: Vec<Mentsu>,
+        player_number: PlayerNumber,
+    ) -> Result<Self, String> {
+        let mut juntehai = vec![];
+        let mut push = |counts: &[u8], make_hai: fn(u8) -> Hai| {
+            for (index, count) in counts.iter().enumerate() {
+                let hai = make_hai(index as u8 + 1);
+                if *count > 4 {
+                    return Err(format!("{} of '{}' found.", count, hai));
+                }
+                juntehai.extend(std::iter::repeat_n(hai, *count as usize));
+            }
+            Ok(())
+        };
+        push(&manzu, Hai::Manzu)?;
+        push(&pinzu, Hai::Pinzu)?;
+        push(&souzu, Hai::Souzu)?;
+        push(&jihai, Hai::Jihai)?;
+        if juntehai.len() % 3 != 2 {
+            return Err(format!(
+                "Juntehai should be 3*k+2, but {} hai found.",
+                juntehai.len()
             ));
         }
+        Self::with_fuuro(juntehai, fuuro, player_number)
+    }
 
-        juntehai.sort();
-        let tehai = Self { juntehai, fuuro };
+    /// Create tehai from json, in the same shape as `to_json`.
+    ///
+    /// # Input
+    /// * juntehai: array of hai strings, such as `["1m", "2m", "0p"]`.
+    /// * fuuro: optional array of objects with `type` (`"juntsu"`, `"koutsu"` or `"kantsu"`)
+    ///   and `hai`, which is an array of hai strings, or a single hai string for koutsu
+    ///   and kantsu. A kantsu with `"called": false` is ankan.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use japanese_mahjong_theory::{Tehai, PlayerNumber};
+    /// let value = serde_json::json!({
+    ///     "juntehai": ["1m", "2m", "3m", "4p", "5p", "6p", "7s", "8s", "9s", "1z"],
+    ///     "fuuro": [{"type": "koutsu", "hai": "5z"}]
+    /// });
+    /// let tehai = Tehai::from_json(&value, PlayerNumber::Four).unwrap();
+    /// assert_eq!(tehai, Tehai::new("123m456p789s1z[555z]", PlayerNumber::Four).unwrap());
+    /// ```
+    pub fn from_json(
+        value: &serde_json::Value,
+        player_number: PlayerNumber,
+    ) -> Result<Self, String> {
+        fn parse_hai(
+            value: &serde_json::Value,
+            player_number: PlayerNumber,
+        ) -> Result<Hai, String> {
+            let string = value
+                .as_str()
+                .ok_or_else(|| format!("Hai must be a string, but {} provided.", value))?;
+            match Hai::from_string_unordered(string, player_number)?[..] {
+                [hai] => Ok(hai),
+                _ => Err(format!("'{}' is not exactly one hai.", string)),
+            }
+        }
 
-        match tehai.check_hai_number() {
-            Ok(_) => Ok(tehai),
-            Err(hai) => Err(format!("Fifth {} found.", hai.to_string())),
+        let juntehai = value["juntehai"]
+            .as_array()
+            .ok_or_else(|| "Need an array 'juntehai' in json input.".to_string())?
+            .iter()
+            .map(|hai| parse_hai(hai, player_number))
+            .collect::<Result<Vec<Hai>, String>>()?;
+        if !Hai::check_iter_aka(juntehai.iter()) {
+            return Err("More than one aka of a suit found.".to_string());
+        }
+
+        let mut fuuro = vec![];
+        let mut ankan = BTreeSet::new();
+        let fuuro_json = match &value["fuuro"] {
+            serde_json::Value::Null => vec![],
+            serde_json::Value::Array(fuuro_json) => fuuro_json.clone(),
+            _ => return Err("'fuuro' in json input must be an array.".to_string()),
+        };
+        for mentsu_json in fuuro_json.iter() {
+            let mentsu_type = mentsu_json["type"].as_str().unwrap_or_default();
+            let size = match mentsu_type {
+                "juntsu" | "koutsu" => 3,
+                "kantsu" => 4,
+                _ => {
+                    return Err(format!(
+                        "Unknown mentsu type in json input: {}.",
+                        mentsu_json["type"]
+                    ))
+                }
+            };
+            let hai_vec = match &mentsu_json["hai"] {
+                serde_json::Value::Array(hai_vec) => hai_vec
+                    .iter()
+                    .map(|hai| parse_hai(hai, player_number))
+                    .collect::<Result<Vec<Hai>, String>>()?,
+                hai @ serde_json::Value::String(_) if mentsu_type != "juntsu" => {
+                    vec![parse_hai(hai, player_number)?; size]
+                }
+                hai => {
+                    return Err(format!(
+                        "Invalid hai of {} in json input: {}.",
+                        mentsu_type, hai
+                    ))
+                }
+            };
+            let mentsu = Mentsu::try_new(&hai_vec, player_number)
+                .map_err(|error| format!("Invalid {} in json input. {}", mentsu_type, error))?;
+            match (mentsu_type, mentsu) {
+                ("juntsu", Mentsu::Juntsu(..)) | ("koutsu", Mentsu::Koutsu(_)) => (),
+                ("kantsu", Mentsu::Kantsu(hai)) => {
+                    if mentsu_json["called"] == serde_json::Value::Bool(false) {
+                        ankan.insert(hai);
+                    }
+                }
+                _ => return Err(format!("{} is not {} in json input.", mentsu, mentsu_type)),
+            }
+            fuuro.push(mentsu);
         }
+
+        let mut tehai = Self::with_fuuro(juntehai, fuuro, player_number)?;
+        tehai.ankan = ankan;
+        Ok(tehai)
+    }
+
+    /// Return the number of hai in fuuro, 3 for each juntsu and koutsu and 4 for each kantsu.
+    pub fn fuuro_tile_count(&self) -> usize {
+        self.fuuro
+            .iter()
+            .map(|mentsu| match mentsu {
+                Mentsu::Kantsu(_) => 4,
+                _ => 3,
+            })
+            .sum()
     }
 
     /// Analyze conditions of sutehai and machihai.
@@ -244,7 +731,58 @@ impl Tehai {
         player_number: PlayerNumber,
         game_manager: Option<&GameManager>,
     ) -> Result<(i32, Vec<MachiCondition>), String> {
-        let (shanten, decomposers) = self.decompose(player_number)?;
+        self.analyze_with_deadline(player_number, game_manager, None)
+    }
+
+    /// Same as `analyze`, but give up with a `TIMEOUT` error once `deadline` has passed,
+    /// so that pathological input can not hang the caller.
+    pub fn analyze_with_deadline(
+        &self,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, Vec<MachiCondition>), String> {
+        let (shanten, conditions, ..) = self.analyze_impl(player_number, game_manager, deadline)?;
+        Ok((shanten, conditions))
+    }
+
+    /// Same as `analyze_with_deadline`, but also return the numbers of decompositions as
+    /// `decomposition_count` does, counted in the same pass.
+    pub fn analyze_with_decomposition_count(
+        &self,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, Vec<MachiCondition>, DecompositionCount), String> {
+        let (shanten, conditions, decomposers, explored) =
+            self.analyze_impl(player_number, game_manager, deadline)?;
+        Ok((shanten, conditions, (decomposers.len(), explored)))
+    }
+
+    /// Same as `analyze`, but also return all decompositions with the minimum shanten, which
+    /// explain the mentsu, taatsu and toitsu found in juntehai. Breakdowns are sorted and
+    /// deduplicated.
+    pub fn analyze_verbose(
+        &self,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+    ) -> Result<(i32, Vec<MachiCondition>, Vec<HandBreakdown>), String> {
+        let (shanten, conditions, decomposers, _) =
+            self.analyze_impl(player_number, game_manager, None)?;
+        let mut breakdown_vec: Vec<HandBreakdown> =
+            decomposers.iter().map(HandBreakdown::new).collect();
+        breakdown_vec.sort_by_key(|breakdown| (breakdown.hourakei, breakdown.to_string()));
+        breakdown_vec.dedup();
+        Ok((shanten, conditions, breakdown_vec))
+    }
+
+    fn analyze_impl(
+        &self,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, Vec<MachiCondition>, HashSet<Decomposer>, usize), String> {
+        let (shanten, decomposers, explored) = self.decompose(player_number, deadline)?;
         let mut conditions_vec = vec![];
 
         if let i32::MIN..=-2 = shanten {
@@ -253,7 +791,7 @@ impl Tehai {
 
         // Tenpai
         if shanten == -1 {
-            return Ok((shanten, conditions_vec));
+            return Ok((shanten, conditions_vec, decomposers, explored));
         }
 
         let mut sutehai_set = HashSet::new();
@@ -262,24 +800,72 @@ impl Tehai {
                 sutehai_set.insert(ukihai.0);
             }
             // Only chiitoitsu type can discard valid tiles but not ukihai.
-            if decomposer.hourakei == Hourakei::Chiitoitsu {
-                if decomposer.invalid_ukihai_vec.len() == 0 {
-                    for Ukihai(sutehai) in &decomposer.valid_ukihai_vec {
-                        sutehai_set.insert(*sutehai);
-                    }
+            if decomposer.hourakei == Hourakei::Chiitoitsu
+                && decomposer.invalid_ukihai_vec.is_empty()
+            {
+                for Ukihai(sutehai) in &decomposer.valid_ukihai_vec {
+                    sutehai_set.insert(*sutehai);
                 }
             }
         }
         for sutehai in sutehai_set {
             let mut condition = MachiCondition::new(sutehai);
+            let mut representative: Option<(usize, String, Decomposition)> = None;
             for decomposer in &decomposers {
-                condition.handle(decomposer, self.juntehai.len(), player_number)?;
+                let mut single = MachiCondition::new(sutehai);
+                single.handle(decomposer, self.juntehai.len(), player_number)?;
+                if single.machihai.is_empty() {
+                    continue;
+                }
+
+                // Keep the decomposition providing the most machihai. Compare hourakei and
+                // strings on tie to make the choice independent of the order of HashSet.
+                let number = single.machihai.len();
+                if representative
+                    .as_ref()
+                    .is_none_or(|(current_number, ..)| number >= *current_number)
+                {
+                    let decomposition = Decomposition::new(decomposer, Some(&sutehai));
+                    let string = decomposition.to_string();
+                    let better = match &representative {
+                        None => true,
+                        Some((current_number, current_string, current)) => {
+                            number > *current_number
+                                || (decomposition.hourakei, &string)
+                                    < (current.hourakei, current_string)
+                        }
+                    };
+                    if better {
+                        representative = Some((number, string, decomposition));
+                    }
+                }
+                condition.machihai.append(&mut single.machihai);
+                for (shape, machihai) in single.by_shape {
+                    condition
+                        .by_shape
+                        .entry(shape)
+                        .or_default()
+                        .extend(machihai);
+                }
+            }
+            condition.decomposition = representative.map(|(.., decomposition)| decomposition);
+            // Discard aka only if there is no other five.
+            if !self.juntehai.contains(&sutehai) {
+                if let Some(aka) = sutehai.make_aka() {
+                    condition.sutehai = aka;
+                }
             }
             condition.finally(self, game_manager);
             conditions_vec.push(condition);
         }
 
-        conditions_vec.retain(|conditon| conditon.nokori() > 0);
+        // Keep karaten instead of dropping it, otherwise it looks like not tenpai.
+        for condition in &mut conditions_vec {
+            condition.karaten = shanten == 0
+                && condition.machihai.is_empty()
+                && !condition.dead_machihai.is_empty();
+        }
+        conditions_vec.retain(|conditon| conditon.nokori() > 0 || conditon.karaten);
         conditions_vec.sort_by(|lhs, rhs| {
             if lhs.nokori().cmp(&rhs.nokori()) == std::cmp::Ordering::Equal {
                 lhs.sutehai.cmp(&rhs.sutehai)
@@ -288,7 +874,652 @@ impl Tehai {
             }
         });
 
-        Ok((shanten, conditions_vec))
+        Ok((shanten, conditions_vec, decomposers, explored))
+    }
+
+    /// Return the number of shanten of a tehai with 3*k+1 juntehai, such as 13 hai waiting
+    /// for drawing, and hai reducing shanten when drawn with the number left. For tenpai,
+    /// they are exactly machihai.
+    ///
+    /// Counts follow the haiyama of `game_manager` if provided, and hai with no one left are
+    /// excluded.
+    ///
+    /// # Japanese
+    /// * acceptance: 受け入れ
+    pub fn acceptance(
+        &self,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+    ) -> Result<(i32, BTreeMap<Hai, u8>), String> {
+        if self.juntehai.len() % 3 != 1 {
+            return Err(format!(
+                "The number of hai on hand must be 3*k+1, \
+                such as 7, 10, 13, even 16, but {} provided.",
+                self.juntehai.len()
+            ));
+        }
+
+        // Sutehai makes no sense here, only machihai are used.
+        let (shanten, mut condition, _) = self.ukeire(self.juntehai[0], player_number, None)?;
+        condition.finally(self, game_manager);
+        Ok((shanten, condition.machihai))
+    }
+
+    /// Analyze every distinct hai in juntehai as sutehai, even if it makes shanten worse.
+    /// Results are sorted by shanten, then by the number of ukeire.
+    pub fn discard_matrix(
+        &self,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<DiscardEfficiency>, String> {
+        if self.juntehai.len() % 3 != 2 {
+            return Err(format!(
+                "The number of hai on hand must be 3*k+2, \
+                such as 8, 11, 14, even 17, but {} provided.",
+                self.juntehai.len()
+            ));
+        }
+
+        let sutehai_set: BTreeSet<Hai> = self.juntehai.iter().copied().collect();
+        let mut efficiency_vec = vec![];
+        for sutehai in sutehai_set {
+            let (shanten, mut condition, ukeire_tehai) =
+                self.ukeire_after_discard(&sutehai, player_number, deadline)?;
+            let mut tenpai_machihai = None;
+            if shanten == 1 {
+                for (_, drawn) in ukeire_tehai.iter() {
+                    let (_, conditions) =
+                        drawn.analyze_with_deadline(player_number, game_manager, deadline)?;
+                    let best = conditions.first().map(|condition| condition.nokori());
+                    tenpai_machihai = tenpai_machihai.max(best);
+                }
+            }
+            condition.finally(self, game_manager);
+            efficiency_vec.push(DiscardEfficiency {
+                shanten,
+                condition,
+                tenpai_machihai,
+            });
+        }
+
+        efficiency_vec.sort_by(|lhs, rhs| {
+            lhs.shanten
+                .cmp(&rhs.shanten)
+                .then(
+                    lhs.condition
+                        .nokori()
+                        .cmp(&rhs.condition.nokori())
+                        .reverse(),
+                )
+                .then(lhs.condition.sutehai.cmp(&rhs.condition.sutehai))
+        });
+        Ok(efficiency_vec)
+    }
+
+    /// Shanten and the number of ukeire for every distinct hai in juntehai as sutehai,
+    /// sorted by the number of ukeire descending, then by shanten.
+    ///
+    /// Return an empty vec if the number of juntehai is not 3*k+2.
+    ///
+    /// # Japanese
+    /// * ukeire: 受け入れ
+    ///
+    /// # Return
+    /// * Hai: sutehai.
+    /// * i32: the number of shanten after discarding.
+    /// * usize: the number of remaining hai which reduce shanten after discarding.
+    pub fn discard_ukeire(
+        &self,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+    ) -> Vec<(Hai, i32, usize)> {
+        if self.juntehai.len() % 3 != 2 {
+            return vec![];
+        }
+
+        let sutehai_set: BTreeSet<Hai> = self.juntehai.iter().copied().collect();
+        let mut ukeire_vec = vec![];
+        for sutehai in sutehai_set {
+            if let Ok((shanten, mut condition, _)) =
+                self.ukeire_after_discard(&sutehai, player_number, None)
+            {
+                condition.finally(self, game_manager);
+                ukeire_vec.push((sutehai, shanten, condition.nokori()));
+            }
+        }
+
+        ukeire_vec.sort_by(|lhs, rhs| {
+            lhs.2
+                .cmp(&rhs.2)
+                .reverse()
+                .then(lhs.1.cmp(&rhs.1))
+                .then(lhs.0.cmp(&rhs.0))
+        });
+        ukeire_vec
+    }
+
+    /// Look ahead `depth` steps of discarding and drawing for every distinct hai in juntehai
+    /// as sutehai. Only depth 1 and 2 are supported.
+    ///
+    /// With depth 2, each ukeire after discarding is drawn, then the best following discard
+    /// is taken, and its ukeire is averaged weighted by the remaining number of each draw.
+    /// The result of a tehai is cached, but it is still about `sutehai * ukeire * sutehai * 34`
+    /// times of decomposition, which is why deeper lookahead is refused.
+    ///
+    /// Counts follow the haiyama of `game_manager` if provided, so exhausted hai contribute
+    /// nothing. The drawn hai itself is not removed from the haiyama for the second step.
+    ///
+    /// # Japanese
+    /// * ukeire: 受け入れ
+    ///
+    /// # Return
+    /// Sorted by shanten, then by the average ukeire descending.
+    /// * Hai: sutehai.
+    /// * i32: the number of shanten after discarding.
+    /// * usize: the number of ukeire after discarding.
+    /// * f64: the average ukeire after drawing an ukeire and discarding the best, or the same
+    ///   as ukeire for depth 1. Always 0 for tenpai since drawing an ukeire is agari.
+    pub fn analyze_lookahead(
+        &self,
+        depth: usize,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+    ) -> Result<Vec<(Hai, i32, usize, f64)>, String> {
+        if depth == 0 || depth > MAX_LOOKAHEAD_DEPTH {
+            return Err(format!(
+                "Depth of lookahead must be 1 to {}, but {} provided.",
+                MAX_LOOKAHEAD_DEPTH, depth
+            ));
+        }
+        if self.juntehai.len() % 3 != 2 {
+            return Err(format!(
+                "The number of hai on hand must be 3*k+2, \
+                such as 8, 11, 14, even 17, but {} provided.",
+                self.juntehai.len()
+            ));
+        }
+
+        let mut cache = HashMap::new();
+        let sutehai_set: BTreeSet<Hai> = self.juntehai.iter().copied().collect();
+        let mut lookahead_vec = vec![];
+        for sutehai in sutehai_set {
+            let (shanten, ukeire, average) = self.lookahead_after_discard(
+                &sutehai,
+                depth,
+                player_number,
+                game_manager,
+                &mut cache,
+            )?;
+            lookahead_vec.push((sutehai, shanten, ukeire, average));
+        }
+
+        lookahead_vec.sort_by(|lhs, rhs| {
+            lhs.1
+                .cmp(&rhs.1)
+                .then(
+                    lhs.3
+                        .partial_cmp(&rhs.3)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                        .reverse(),
+                )
+                .then(lhs.2.cmp(&rhs.2).reverse())
+                .then(lhs.0.cmp(&rhs.0))
+        });
+        Ok(lookahead_vec)
+    }
+
+    /// Return all decompositions of a winning tehai, or an empty vec if tehai is not agari.
+    ///
+    /// Decompositions are sorted so that mentsute comes first, which means a hand like
+    /// `112233m445566p77z` is shown as mentsute before chiitoitsu.
+    pub fn winning_decompositions(
+        &self,
+        player_number: PlayerNumber,
+    ) -> Result<Vec<Decomposition>, String> {
+        let (shanten, decomposers, _) = self.decompose(player_number, None)?;
+        if shanten != -1 {
+            return Ok(vec![]);
+        }
+
+        let mut decomposition_vec: Vec<Decomposition> = decomposers
+            .iter()
+            .map(|decomposer| Decomposition::new(decomposer, None))
+            .collect();
+        decomposition_vec
+            .sort_by_key(|decomposition| (decomposition.hourakei, decomposition.to_string()));
+        decomposition_vec.dedup();
+        Ok(decomposition_vec)
+    }
+
+    /// Return the number of shanten only, without analyzing sutehai and machihai.
+    /// Much faster than `analyze` for batches. Agari tehai returns -1.
+    pub fn shanten(&self, player_number: PlayerNumber) -> Result<i32, String> {
+        let (shanten, _, _) = self.decompose(player_number, None)?;
+        Ok(shanten)
+    }
+
+    /// Return if tehai is a complete winning form -- mentsute, chiitoitsu or kokushimusou.
+    ///
+    /// Faster than checking if `shanten` is -1, since it stops as soon as any winning
+    /// form is found, and never collects ukihai.
+    ///
+    /// # Japanese
+    /// * agari: 和了
+    pub fn is_agari(&self, player_number: PlayerNumber) -> bool {
+        fn is_mentsu_only(counts: &mut BTreeMap<Hai, u8>, player_number: PlayerNumber) -> bool {
+            let first = match counts.iter().find(|(_, count)| **count > 0) {
+                Some((hai, _)) => *hai,
+                None => return true,
+            };
+
+            if counts[&first] >= 3 {
+                *counts.get_mut(&first).unwrap() -= 3;
+                let agari = is_mentsu_only(counts, player_number);
+                *counts.get_mut(&first).unwrap() += 3;
+                if agari {
+                    return true;
+                }
+            }
+
+            if matches!(first, Hai::Jihai(_)) {
+                return false;
+            }
+            let second = first.next(player_number, false);
+            let third = second.and_then(|second| second.next(player_number, false));
+            if let (Some(second), Some(third)) = (second, third) {
+                let juntsu = [first, second, third];
+                if juntsu
+                    .iter()
+                    .all(|hai| counts.get(hai).copied().unwrap_or(0) > 0)
+                {
+                    for hai in &juntsu {
+                        *counts.get_mut(hai).unwrap() -= 1;
+                    }
+                    let agari = is_mentsu_only(counts, player_number);
+                    for hai in &juntsu {
+                        *counts.get_mut(hai).unwrap() += 1;
+                    }
+                    return agari;
+                }
+            }
+            false
+        }
+
+        if self.juntehai.len() % 3 != 2 {
+            return false;
+        }
+
+        let mut counts: BTreeMap<Hai, u8> = BTreeMap::new();
+        for hai in &self.juntehai {
+            *counts.entry(hai.normalized()).or_insert(0) += 1;
+        }
+
+        if self.juntehai.len() == 14 && self.fuuro.is_empty() {
+            // Chiitoitsu
+            if counts.len() == 7 && counts.values().all(|count| *count == 2) {
+                return true;
+            }
+            // Kokushimusou
+            let yaochuupai_type = Hai::yaochuupai_type();
+            if counts.len() == 13 && counts.keys().all(|hai| yaochuupai_type.contains(hai)) {
+                return true;
+            }
+        }
+
+        // Mentsute
+        let toitsu_vec: Vec<Hai> = counts
+            .iter()
+            .filter(|(_, count)| **count >= 2)
+            .map(|(hai, _)| *hai)
+            .collect();
+        for toitsu in toitsu_vec {
+            *counts.get_mut(&toitsu).unwrap() -= 2;
+            if is_mentsu_only(&mut counts, player_number) {
+                return true;
+            }
+            *counts.get_mut(&toitsu).unwrap() += 2;
+        }
+        false
+    }
+
+    /// Return if tehai is tenpai, which is cheap enough to skip other tehai in batches.
+    ///
+    /// For 3*k+2 juntehai, it means shanten is 0, so agari tehai is not tenpai. For 3*k+1
+    /// juntehai, it means some hai makes it agari. Fuuro is fine, since only juntehai
+    /// counts.
+    ///
+    /// # Japanese
+    /// * tenpai: 聴牌
+    pub fn is_tenpai(&self, player_number: PlayerNumber) -> bool {
+        match self.juntehai.len() % 3 {
+            2 => shanten::fast_shanten(self, player_number) == 0,
+            1 => Hai::all_type(player_number).iter().any(|hai| {
+                let mut tehai = self.clone();
+                tehai.juntehai.push(*hai);
+                tehai.is_agari(player_number)
+            }),
+            _ => false,
+        }
+    }
+
+    /// Return tenpai ones of a batch of tehai in order. See `is_tenpai`.
+    pub fn filter_tenpai(tehai_vec: &[Tehai], player_number: PlayerNumber) -> Vec<&Tehai> {
+        tehai_vec
+            .iter()
+            .filter(|tehai| tehai.is_tenpai(player_number))
+            .collect()
+    }
+
+    /// Return how many decompositions are considered when analyzing, for diagnostics.
+    ///
+    /// # Return
+    /// * The first `usize` data is the number of decompositions with the minimum shanten.
+    /// * The second `usize` data is the number of all decompositions explored.
+    pub fn decomposition_count(
+        &self,
+        player_number: PlayerNumber,
+    ) -> Result<DecompositionCount, String> {
+        let (_, decomposers, explored) = self.decompose(player_number, None)?;
+        Ok((decomposers.len(), explored))
+    }
+
+    /// Calculate fu of an agari tehai, choosing the decomposition and the way of waiting
+    /// with the most fu. Result is rounded up to 10, except 25 of chiitoitsu.
+    ///
+    /// Winds are unknown here, so only sangenpai toitsu gives fu, see
+    /// `calculate_fu_with_context` for winds. Ankan counts as anko and other kantsu in
+    /// fuuro as minkan, and kokushimusou gets the base fu only.
+    ///
+    /// # Parameters
+    /// * agari_hai: the hai completing tehai, which must be in juntehai.
+    /// * is_tsumo: tsumo if true, ron otherwise.
+    ///
+    /// # Japanese
+    /// * fu: 符
+    /// * agari_hai: 和了牌
+    pub fn calculate_fu(
+        &self,
+        agari_hai: Hai,
+        is_tsumo: bool,
+        player_number: PlayerNumber,
+    ) -> Result<u32, String> {
+        self.fu_of(agari_hai, is_tsumo, &[], player_number)
+    }
+
+    /// Calculate fu like `calculate_fu`, with agari hai, tsumo and winds from context.
+    /// Toitsu of seat wind or round wind gives 2 fu, and of double wind 4 fu.
+    ///
+    /// # Japanese
+    /// * double wind: 連風牌
+    pub fn calculate_fu_with_context(
+        &self,
+        context: &AgariContext,
+        player_number: PlayerNumber,
+    ) -> Result<u32, String> {
+        self.fu_of(
+            context.agari_hai,
+            context.is_tsumo,
+            &[context.seat_wind, context.round_wind],
+            player_number,
+        )
+    }
+
+    /// A part of function of calculate_fu. Toitsu gives 2 fu for each of `winds` it matches.
+    fn fu_of(
+        &self,
+        agari_hai: Hai,
+        is_tsumo: bool,
+        winds: &[Hai],
+        player_number: PlayerNumber,
+    ) -> Result<u32, String> {
+        fn is_yaochuu(hai: &Hai) -> bool {
+            Hai::yaochuupai_type().contains(hai)
+        }
+        // Fu of a koutsu or kantsu, minko as base.
+        fn koutsu_fu(hai: &Hai, is_kantsu: bool, is_anko: bool) -> u32 {
+            let mut fu = if is_yaochuu(hai) { 4 } else { 2 };
+            if is_kantsu {
+                fu *= 4;
+            }
+            if is_anko {
+                fu *= 2;
+            }
+            fu
+        }
+
+        let agari_hai = agari_hai.normalized();
+        if !self
+            .juntehai
+            .iter()
+            .any(|hai| hai.normalized() == agari_hai)
+        {
+            return Err(format!("Agari hai {} is not in juntehai.", agari_hai));
+        }
+        let decomposition_vec = self.winning_decompositions(player_number)?;
+        if decomposition_vec.is_empty() {
+            return Err("Tehai is not agari.".to_string());
+        }
+
+        let menzen = self.is_menzen();
+        let base = 20 + if menzen && !is_tsumo { 10 } else { 0 };
+        let round_up = |fu: u32| fu.div_ceil(10) * 10;
+
+        let mut fuuro_fu = 0;
+        for mentsu in &self.fuuro {
+            match mentsu {
+                Mentsu::Koutsu(hai) => fuuro_fu += koutsu_fu(hai, false, false),
+                Mentsu::Kantsu(hai) => fuuro_fu += koutsu_fu(hai, true, !self.is_called(mentsu)),
+                Mentsu::Juntsu(..) => (),
+            }
+        }
+
+        let mut max_fu = 0;
+        for decomposition in &decomposition_vec {
+            match decomposition.hourakei {
+                Hourakei::Chiitoitsu => max_fu = max_fu.max(25),
+                Hourakei::Kokushimusou => {
+                    max_fu = max_fu.max(round_up(base + if is_tsumo { 2 } else { 0 }))
+                }
+                Hourakei::Mentsute => {
+                    let toitsu = match decomposition.toitsu.first() {
+                        Some(Toitsu(hai)) => *hai,
+                        None => continue,
+                    };
+                    let toitsu_fu = if matches!(toitsu, Hai::Jihai(5..=7)) {
+                        2
+                    } else {
+                        2 * winds.iter().filter(|wind| **wind == toitsu).count() as u32
+                    };
+
+                    // Every way of waiting: the index of mentsu completed by agari hai and
+                    // fu of the wait, or None for tanki.
+                    let mut wait_vec: Vec<(Option<usize>, u32)> = vec![];
+                    if toitsu == agari_hai {
+                        wait_vec.push((None, 2));
+                    }
+                    for (index, mentsu) in decomposition.mentsu.iter().enumerate() {
+                        match mentsu {
+                            Mentsu::Koutsu(hai) if *hai == agari_hai => {
+                                wait_vec.push((Some(index), 0))
+                            }
+                            Mentsu::Juntsu(a, b, c) => {
+                                // Kanchan or penchan.
+                                let wait_fu = if *b == agari_hai
+                                    || (*a == agari_hai && c.number() == 9)
+                                    || (*c == agari_hai && a.number() == 1)
+                                {
+                                    2
+                                } else if *a == agari_hai || *c == agari_hai {
+                                    0
+                                } else {
+                                    continue;
+                                };
+                                wait_vec.push((Some(index), wait_fu));
+                            }
+                            _ => (),
+                        }
+                    }
+
+                    for (completed, wait_fu) in wait_vec {
+                        let mut mentsu_fu = 0;
+                        for (index, mentsu) in decomposition.mentsu.iter().enumerate() {
+                            if let Mentsu::Koutsu(hai) = mentsu {
+                                // Koutsu completed by ron is regarded as minko.
+                                let is_anko = is_tsumo || completed != Some(index);
+                                mentsu_fu += koutsu_fu(hai, false, is_anko);
+                            }
+                        }
+
+                        let fu = if menzen
+                            && fuuro_fu == 0
+                            && mentsu_fu == 0
+                            && toitsu_fu == 0
+                            && wait_fu == 0
+                        {
+                            // Pinfu, which ankan also breaks.
+                            if is_tsumo {
+                                20
+                            } else {
+                                30
+                            }
+                        } else {
+                            let fu = base
+                                + fuuro_fu
+                                + mentsu_fu
+                                + toitsu_fu
+                                + wait_fu
+                                + if is_tsumo { 2 } else { 0 };
+                            // Open tehai without any fu is regarded as 30.
+                            if fu == 20 {
+                                30
+                            } else {
+                                round_up(fu)
+                            }
+                        };
+                        max_fu = max_fu.max(fu);
+                    }
+                }
+            }
+        }
+
+        Ok(max_fu)
+    }
+
+    /// Return mentsute shanten if all hai of a suit are abandoned, which means they are
+    /// regarded as ukihai to be discarded. Chiitoitsu and kokushimusou are not considered.
+    ///
+    /// # Parameters
+    /// * suit: Fail if any fuuro is of this suit.
+    pub fn shanten_without_suit(
+        &self,
+        suit: Suit,
+        player_number: PlayerNumber,
+    ) -> Result<i32, String> {
+        self.shanten_of_suits(|s| s != suit, player_number)
+    }
+
+    /// Return mentsute shanten if only hai of a suit and jihai are kept, which is the
+    /// shanten of going for honitsu with this suit.
+    ///
+    /// # Parameters
+    /// * suit: `Suit::Man`, `Suit::Pin` or `Suit::Sou`. Fail if any fuuro is of another suit.
+    ///
+    /// # Japanese
+    /// * honitsu: 混一色
+    pub fn honitsu_shanten(&self, suit: Suit, player_number: PlayerNumber) -> Result<i32, String> {
+        self.shanten_of_suits(|s| s == suit || s == Suit::Ji, player_number)
+    }
+
+    /// Mentsute shanten with only hai of suits satisfying `keep`. Other hai are regarded
+    /// as ukihai.
+    fn shanten_of_suits<F>(&self, keep: F, player_number: PlayerNumber) -> Result<i32, String>
+    where
+        F: Fn(Suit) -> bool,
+    {
+        if self.juntehai.len() % 3 != 2 {
+            return Err(format!(
+                "The number of hai on hand must be 3*k+2, \
+                such as 8, 11, 14, even 17, but {} provided.",
+                self.juntehai.len()
+            ));
+        }
+        for mentsu in &self.fuuro {
+            let hai = match mentsu {
+                Mentsu::Juntsu(hai, ..) | Mentsu::Koutsu(hai) | Mentsu::Kantsu(hai) => hai,
+            };
+            if !keep(hai.suit()) {
+                return Err(format!("Fuuro {} can not be abandoned.", mentsu));
+            }
+        }
+
+        // Aka is regarded as five, as in analyzing.
+        let tehai = Tehai {
+            juntehai: self
+                .juntehai
+                .iter()
+                .filter(|hai| keep(hai.suit()))
+                .map(Hai::normalized)
+                .collect(),
+            fuuro: self.fuuro.clone(),
+            ankan: self.ankan.clone(),
+        };
+        let (shanten, ..) = tehai.split_minimum(self.juntehai.len(), player_number, None)?;
+        Ok(shanten)
+    }
+
+    /// Return the closest suit and shanten toward the shape of chuurenpoutou, that is
+    /// `1112345678999` of one suit plus any hai of the same suit. Only counts of hai are
+    /// checked, so it is a hint but not a yaku claim. Return None if tehai has fuuro or
+    /// is not 14 hai.
+    ///
+    /// # Japanese
+    /// * chuurenpoutou: 九蓮宝燈
+    pub fn chuuren_shanten(&self, player_number: PlayerNumber) -> Option<(Suit, i32)> {
+        const REQUIRED: [u8; 9] = [3, 1, 1, 1, 1, 1, 1, 1, 3];
+
+        if !self.fuuro.is_empty() || self.juntehai.len() != 14 {
+            return None;
+        }
+
+        let suits: &[Suit] = match player_number {
+            PlayerNumber::Four => &[Suit::Man, Suit::Pin, Suit::Sou],
+            PlayerNumber::Three => &[Suit::Pin, Suit::Sou],
+        };
+        suits
+            .iter()
+            .map(|suit| {
+                let mut counts = [0u8; 9];
+                for hai in &self.juntehai {
+                    if hai.suit() == *suit {
+                        counts[hai.normalized().number() as usize - 1] += 1;
+                    }
+                }
+                let mut missing = 0;
+                let mut extra = 0;
+                for (count, required) in counts.iter().zip(REQUIRED.iter()) {
+                    missing += required.saturating_sub(*count) as i32;
+                    extra += count.saturating_sub(*required) as i32;
+                }
+                // The 14th hai must be of the same suit too.
+                let needed = missing + if extra == 0 { 1 } else { 0 };
+                (*suit, needed - 1)
+            })
+            .min_by_key(|(_, shanten)| *shanten)
+    }
+
+    /// Return if tehai is suukantsu -- four kantsu in fuuro and a single toitsu left in juntehai.
+    ///
+    /// # Japanese
+    /// * Suukantsu: 四槓子
+    pub fn is_suukantsu(&self) -> bool {
+        self.fuuro
+            .iter()
+            .filter(|mentsu| matches!(mentsu, Mentsu::Kantsu(_)))
+            .count()
+            == 4
+            && self.juntehai.len() == 2
+            && self.juntehai[0] == self.juntehai[1]
     }
 
     /// Discard a hai from juntehai.
@@ -305,19 +1536,54 @@ impl Tehai {
                 self.juntehai.remove(index);
                 Ok(())
             }
-            None => return Err(format!("No enough {} to discard.", hai.to_string())),
+            None => Err(format!("No enough {} to discard.", hai)),
+        }
+    }
+
+    /// Discard a hai from juntehai regarding aka as five, for hai taken by naku.
+    /// Normal five is taken before aka.
+    fn discard_normalized(&mut self, hai: &Hai) -> Result<(), String> {
+        let index = self
+            .juntehai
+            .iter()
+            .position(|item| item == hai)
+            .or_else(|| {
+                self.juntehai
+                    .iter()
+                    .position(|item| item.normalized() == hai.normalized())
+            });
+        match index {
+            Some(index) => {
+                self.juntehai.remove(index);
+                Ok(())
+            }
+            None => Err(format!("No enough {} to discard.", hai)),
         }
     }
 
-    /// Chii, for an example, 23m catch 4m.
+    /// Replace a hai in juntehai with another one, keeping no more than 4 of each type of hai.
+    /// Tehai will not change if error occured.
+    pub fn replace(&mut self, old: &Hai, new: &Hai) -> Result<(), String> {
+        let backup = self.clone();
+        self.discard(old)?;
+        self.juntehai.push(*new);
+        self.juntehai.sort_by_key(Hai::sort_key);
+        if let Err(over_limit) = self.check_hai_number() {
+            *self = backup;
+            return Err(MahjongError::from(over_limit).to_string());
+        }
+        Ok(())
+    }
+
+    /// Chii, for an example, 23m catch 4m. Aka in juntehai is regarded as five.
     pub fn chii(&mut self, juntsu: &Mentsu, nakihai: &Hai) -> Result<(), String> {
         if let Mentsu::Juntsu(a, b, c) = juntsu {
             let backup = self.clone();
-            for hai in vec![a, b, c] {
-                if hai == nakihai {
+            for hai in [a, b, c] {
+                if *hai == nakihai.normalized() {
                     continue;
                 }
-                if let Err(error) = self.discard(hai) {
+                if let Err(error) = self.discard_normalized(hai) {
                     *self = backup;
                     return Err(error);
                 }
@@ -329,12 +1595,12 @@ impl Tehai {
         }
     }
 
-    /// Pon, for an example, 22m catch 2m.
+    /// Pon, for an example, 22m catch 2m. Aka in juntehai is regarded as five.
     pub fn pon(&mut self, koutsu: &Mentsu) -> Result<(), String> {
         if let Mentsu::Koutsu(hai) = koutsu {
             let backup = self.clone();
             for _ in 0..2 {
-                if let Err(error) = self.discard(hai) {
+                if let Err(error) = self.discard_normalized(hai) {
                     *self = backup;
                     return Err(error);
                 }
@@ -346,19 +1612,19 @@ impl Tehai {
         }
     }
 
-    /// Kan, for an example, 222m catch 2m.
+    /// Kan, for an example, 222m catch 2m. Aka in juntehai is regarded as five.
     pub fn kan(&mut self, kantsu: &Mentsu, rinshanhai: &Option<Hai>) -> Result<Kan, String> {
         if let Mentsu::Kantsu(hai) = kantsu {
             let mut hai_num = 0;
             let mut exist_koutsu = false;
             let mut exist_koutsu_index = 0;
             for i in &self.juntehai {
-                if i == hai {
+                if i.normalized() == hai.normalized() {
                     hai_num += 1;
                 }
             }
             for (index, mentsu) in self.fuuro.iter().enumerate() {
-                if let Mentsu::Kantsu(i) = mentsu {
+                if let Mentsu::Koutsu(i) = mentsu {
                     if i == hai {
                         exist_koutsu = true;
                         exist_koutsu_index = index;
@@ -371,7 +1637,7 @@ impl Tehai {
             if self.juntehai.len() % 3 == 2 {
                 if hai_num == 1 && exist_koutsu {
                     // Undoubtedly exist. Ignore the error.
-                    self.discard(hai)?;
+                    self.discard_normalized(hai)?;
                     self.fuuro[exist_koutsu_index] = *kantsu;
                     kan = Kan::Kakan {
                         kantsu: *kantsu,
@@ -380,12 +1646,13 @@ impl Tehai {
                 } else if hai_num == 4 && !exist_koutsu {
                     for _ in 0..4 {
                         // Undoubtedly exist. Ignore the error.
-                        self.discard(hai)?;
+                        self.discard_normalized(hai)?;
                     }
-                    self.fuuro.push(kantsu.clone());
+                    self.fuuro.push(*kantsu);
+                    self.ankan.insert(*hai);
                     kan = Kan::Ankan {
                         kantsu: *kantsu,
-                        rinshanhai: rinshanhai.clone(),
+                        rinshanhai: *rinshanhai,
                     }
                 } else {
                     return Err("Logic error: Tehai currently is not able to kan.".to_string());
@@ -396,12 +1663,12 @@ impl Tehai {
                 if hai_num == 3 && !exist_koutsu {
                     for _ in 0..3 {
                         // Undoubtedly exist. Ignore the error.
-                        self.discard(hai)?;
+                        self.discard_normalized(hai)?;
                     }
-                    self.fuuro.push(kantsu.clone());
+                    self.fuuro.push(*kantsu);
                     kan = Kan::Daiminkan {
                         kantsu: *kantsu,
-                        rinshanhai: rinshanhai.clone(),
+                        rinshanhai: *rinshanhai,
                     }
                 } else {
                     return Err("Logic error: Tehai currently is not able to kan.".to_string());
@@ -413,7 +1680,7 @@ impl Tehai {
             // Deal with rinshanhai
             if let Some(rinshanhai) = rinshanhai {
                 self.juntehai.push(*rinshanhai);
-                self.juntehai.sort();
+                self.juntehai.sort_by_key(Hai::sort_key);
             }
             Ok(kan)
         } else {
@@ -421,9 +1688,61 @@ impl Tehai {
         }
     }
 
-    /// Undo a operation chii.
-    pub fn de_chii(&mut self, juntsu: &Mentsu, nakihai: &Hai) -> Result<(), String> {
-        if let Mentsu::Juntsu(a, b, c) = juntsu {
+    /// Return all naku available on a hai discarded by others: each chii of a different
+    /// juntsu, pon and daiminkan, in this order. Empty if none, or if juntehai is not 3*k+1.
+    ///
+    /// Aka is regarded as five, like mentsu. Chii is only for manzu, pinzu and souzu,
+    /// and never for manzu on 3-players mode.
+    pub fn possible_naku(&self, discard: Hai, player_number: PlayerNumber) -> Vec<Naku> {
+        let mut naku_vec = vec![];
+        if self.juntehai.len() % 3 != 1 || !discard.is_valid(player_number) {
+            return naku_vec;
+        }
+        let nakihai = discard.normalized();
+        let count = |hai: Hai| {
+            self.juntehai
+                .iter()
+                .filter(|item| item.normalized() == hai)
+                .count()
+        };
+
+        let suit = nakihai.suit();
+        let chii_allowed = match suit {
+            Suit::Man => player_number == PlayerNumber::Four,
+            Suit::Pin | Suit::Sou => true,
+            Suit::Ji => false,
+        };
+        if chii_allowed {
+            let number = nakihai.number();
+            for first in number.saturating_sub(2).max(1)..=number.min(7) {
+                let juntsu: Vec<Hai> = (first..first + 3)
+                    .map(|number| Hai::new(suit, number).unwrap())
+                    .collect();
+                if juntsu.iter().all(|hai| *hai == nakihai || count(*hai) > 0) {
+                    naku_vec.push(Naku::Chii {
+                        juntsu: Mentsu::Juntsu(juntsu[0], juntsu[1], juntsu[2]),
+                        nakihai,
+                    });
+                }
+            }
+        }
+
+        let number = count(nakihai);
+        if number >= 2 {
+            naku_vec.push(Naku::Pon(Mentsu::Koutsu(nakihai)));
+        }
+        if number >= 3 {
+            naku_vec.push(Naku::Kan(Kan::Daiminkan {
+                kantsu: Mentsu::Kantsu(nakihai),
+                rinshanhai: None,
+            }));
+        }
+        naku_vec
+    }
+
+    /// Undo a operation chii, giving back the hai taken from juntehai.
+    pub fn de_chii(&mut self, juntsu: &Mentsu, taken: &[Hai]) -> Result<(), String> {
+        if let Mentsu::Juntsu(..) = juntsu {
             let mut index = None;
             for (i, mentsu) in self.fuuro.iter().enumerate() {
                 if mentsu == juntsu {
@@ -434,21 +1753,17 @@ impl Tehai {
 
             let index = index.ok_or("Logic error: can not find juntsu in fuuro.".to_string())?;
             self.fuuro.remove(index);
-            for hai in vec![a, b, c] {
-                if hai != nakihai {
-                    self.juntehai.push(*hai);
-                }
-            }
-            self.juntehai.sort();
+            self.juntehai.extend(taken);
+            self.juntehai.sort_by_key(Hai::sort_key);
             Ok(())
         } else {
             Err("Logic error: Tehai::de_chii() can only accept Mentsu::Juntsu.".to_string())
         }
     }
 
-    /// Undo a operation pon.
-    pub fn de_pon(&mut self, koutsu: &Mentsu) -> Result<(), String> {
-        if let Mentsu::Koutsu(hai) = koutsu {
+    /// Undo a operation pon, giving back the hai taken from juntehai.
+    pub fn de_pon(&mut self, koutsu: &Mentsu, taken: &[Hai]) -> Result<(), String> {
+        if let Mentsu::Koutsu(_) = koutsu {
             let mut index = None;
             for (i, mentsu) in self.fuuro.iter().enumerate() {
                 if mentsu == koutsu {
@@ -459,18 +1774,16 @@ impl Tehai {
 
             let index = index.ok_or("Logic error: can not find koutsu in fuuro.".to_string())?;
             self.fuuro.remove(index);
-            for _ in 0..2 {
-                self.juntehai.push(*hai);
-            }
-            self.juntehai.sort();
+            self.juntehai.extend(taken);
+            self.juntehai.sort_by_key(Hai::sort_key);
             Ok(())
         } else {
             Err("Logic error: Tehai::de_pon() can only accept Mentsu::Koutsu.".to_string())
         }
     }
 
-    /// Undo a operation kan.
-    pub fn de_kan(&mut self, kan: &Kan) -> Result<(), String> {
+    /// Undo a operation kan, giving back the hai taken from juntehai.
+    pub fn de_kan(&mut self, kan: &Kan, taken: &[Hai]) -> Result<(), String> {
         fn discard_kantsu(fuuro: &mut Vec<Mentsu>, kantsu: &Mentsu) -> Result<(), String> {
             let mut index = None;
             for (i, mentsu) in fuuro.iter().enumerate() {
@@ -488,11 +1801,9 @@ impl Tehai {
         let backup = self.clone();
         let rinshanhai = match kan {
             Kan::Daiminkan { kantsu, rinshanhai } => {
-                if let Mentsu::Kantsu(hai) = kantsu {
+                if let Mentsu::Kantsu(_) = kantsu {
                     discard_kantsu(&mut self.fuuro, kantsu)?;
-                    for _ in 0..3 {
-                        self.juntehai.push(*hai);
-                    }
+                    self.juntehai.extend(taken);
                 } else {
                     return Err(
                         "Logic error: interaction::Kan can only include Kantsu.".to_string()
@@ -503,9 +1814,8 @@ impl Tehai {
             Kan::Ankan { kantsu, rinshanhai } => {
                 if let Mentsu::Kantsu(hai) = kantsu {
                     discard_kantsu(&mut self.fuuro, kantsu)?;
-                    for _ in 0..4 {
-                        self.juntehai.push(*hai);
-                    }
+                    self.ankan.remove(hai);
+                    self.juntehai.extend(taken);
                 } else {
                     return Err(
                         "Logic error: interaction::Kan can only include Kantsu.".to_string()
@@ -517,7 +1827,7 @@ impl Tehai {
                 if let Mentsu::Kantsu(hai) = kantsu {
                     discard_kantsu(&mut self.fuuro, kantsu)?;
                     self.fuuro.push(Mentsu::Koutsu(*hai));
-                    self.juntehai.push(*hai);
+                    self.juntehai.extend(taken);
                 } else {
                     return Err(
                         "Logic error: interaction::Kan can only include Kantsu.".to_string()
@@ -538,6 +1848,65 @@ impl Tehai {
         Ok(())
     }
 
+    /// Return the sorted short form of tehai, like `134m44p8s[111z]`, which is the same for
+    /// the same tehai however it was input, so it can be used as a key or for diffing.
+    ///
+    /// Juntehai comes first grouped by suit in m, p, s, z order, with aka right after five.
+    /// Fuuro comes after in sorted order, ankan with `a` marker and others with `[]`.
+    /// `Tehai::new` on it gives the same tehai.
+    pub fn to_canonical_string(&self) -> String {
+        fn short_string(hai_vec: &[Hai]) -> String {
+            let mut string = String::new();
+            for (index, hai) in hai_vec.iter().enumerate() {
+                string += &hai.to_string()[..1];
+                match hai_vec.get(index + 1) {
+                    Some(next) if next.suit() == hai.suit() => (),
+                    _ => string += &hai.suit().to_string(),
+                }
+            }
+            string
+        }
+
+        let (mut juntehai, fuuro) = self.canonical();
+        juntehai.sort_by_key(Hai::sort_key);
+        let mut string = short_string(&juntehai);
+        for mentsu in fuuro.iter() {
+            let hai_vec = match mentsu {
+                Mentsu::Juntsu(a, b, c) => vec![*a, *b, *c],
+                Mentsu::Koutsu(hai) => vec![*hai; 3],
+                Mentsu::Kantsu(hai) => vec![*hai; 4],
+            };
+            if self.is_called(mentsu) {
+                string += &format!("[{}]", short_string(&hai_vec));
+            } else {
+                string += &format!("a{}", short_string(&hai_vec));
+            }
+        }
+        string
+    }
+
+    /// Return hai of kantsu in fuuro which are ankan.
+    pub fn ankan(&self) -> &BTreeSet<Hai> {
+        &self.ankan
+    }
+
+    /// Return if a mentsu in fuuro was called from others, which is true except ankan.
+    pub fn is_called(&self, mentsu: &Mentsu) -> bool {
+        match mentsu {
+            Mentsu::Kantsu(hai) => !self.ankan.contains(hai),
+            _ => true,
+        }
+    }
+
+    /// Return if tehai is menzen, that is, no mentsu in fuuro was called from others.
+    /// Ankan keeps tehai menzen, while chii, pon, daiminkan and kakan do not.
+    ///
+    /// # Japanese
+    /// * menzen: 門前
+    pub fn is_menzen(&self) -> bool {
+        self.fuuro.iter().all(|mentsu| !self.is_called(mentsu))
+    }
+
     /// Print self to json.
     pub fn to_json(&self) -> serde_json::Value {
         let mut juntehai_string_vec = vec![];
@@ -546,7 +1915,9 @@ impl Tehai {
         }
         let mut fuuro_json_vec = vec![];
         for mentsu in &self.fuuro {
-            fuuro_json_vec.push(mentsu.to_json());
+            let mut mentsu_json = mentsu.to_json();
+            mentsu_json["called"] = json!(self.is_called(mentsu));
+            fuuro_json_vec.push(mentsu_json);
         }
         json!({
            "juntehai": juntehai_string_vec,
@@ -554,12 +1925,38 @@ impl Tehai {
         })
     }
 
+    /// Print self with Unicode mahjong tiles, fuuro in brackets like the `Display` output.
+    /// See [`Hai::to_unicode`].
+    pub fn to_unicode_string(&self) -> String {
+        let mut string: String = self.juntehai.iter().map(|hai| hai.to_unicode()).collect();
+        for mentsu in &self.fuuro {
+            let hai_vec = match mentsu {
+                Mentsu::Juntsu(a, b, c) => vec![*a, *b, *c],
+                Mentsu::Koutsu(a) => vec![*a; 3],
+                Mentsu::Kantsu(a) => vec![*a; 4],
+            };
+            string.push('[');
+            string.extend(hai_vec.iter().map(|hai| hai.to_unicode()));
+            string.push(']');
+        }
+        string
+    }
+
     /// Decompose self to a vec of Decomposer.
     ///
+    /// Any 3*k+2 juntehai works for mentsute, which aims at k+1 mentsu (fuuro included) and
+    /// a toitsu, so 17 hai aim at 5 mentsu and a toitsu, and 20 hai aim at 6. Chiitoitsu and
+    /// kokushimusou are only analyzed for 14 hai without fuuro. Other sizes are errors.
+    ///
     /// # Return
     /// * The `i32` data is the minimum shanten.
     /// * The `HashSet<Decomposer>` data is all decomposers that thier shanten are minimum one.
-    fn decompose(&self, player_number: PlayerNumber) -> Result<(i32, HashSet<Decomposer>), String> {
+    /// * The `usize` data is the number of all decomposers explored.
+    fn decompose(
+        &self,
+        player_number: PlayerNumber,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, HashSet<Decomposer>, usize), String> {
         // Only work for 3*k+2 juntehai.
         if self.juntehai.len() % 3 != 2 {
             return Err(format!(
@@ -569,6 +1966,14 @@ impl Tehai {
             ));
         }
 
+        // Aka is the same as five for shanten.
+        if self.juntehai.iter().any(Hai::is_aka) {
+            let mut normalized = self.clone();
+            normalized.juntehai = self.juntehai.iter().map(Hai::normalized).collect();
+            normalized.juntehai.sort_by_key(Hai::sort_key);
+            return normalized.decompose(player_number, deadline);
+        }
+
         let mut min_shanten = ((self.juntehai.len() / 3) * 2) as i32;
         let mut min_shanten_decomposers = HashSet::new();
 
@@ -583,17 +1988,18 @@ impl Tehai {
         };
 
         // Analyze Mentsute
-        let mut decomposers_vec = vec![];
-        self.split(&mut decomposers_vec, &mut Decomposer::new(), player_number);
+        let (_, decomposers_vec, mut explored) =
+            self.split_minimum(self.juntehai.len(), player_number, deadline)?;
         for mut decomposer in decomposers_vec {
             decomposer.hourakei = Hourakei::Mentsute;
             push_into_decomposers(decomposer);
         }
 
         // Analyze Chiitoitsu and Kokushimusou.
-        if self.juntehai.len() != 14 || self.fuuro.len() != 0 {
-            return Ok((min_shanten, min_shanten_decomposers));
+        if self.juntehai.len() != 14 || !self.fuuro.is_empty() {
+            return Ok((min_shanten, min_shanten_decomposers, explored));
         }
+        explored += 2;
 
         // Analyze Chiitoitsu
         let mut decomposer = Decomposer::new();
@@ -608,20 +2014,20 @@ impl Tehai {
                     if cur == last_hai {
                         if !last_hai_used {
                             last_hai_used = true;
-                            decomposer.toitsu_vec.push(Toitsu { 0: *cur });
+                            decomposer.toitsu_vec.push(Toitsu(*cur));
                         } else {
-                            decomposer.invalid_ukihai_vec.push(Ukihai { 0: *cur });
+                            decomposer.invalid_ukihai_vec.push(Ukihai(*cur));
                         }
                     } else {
                         if !last_hai_used {
-                            decomposer.valid_ukihai_vec.push(Ukihai { 0: *last_hai });
+                            decomposer.valid_ukihai_vec.push(Ukihai(*last_hai));
                         }
                         last_hai = cur;
                         last_hai_used = false;
                     }
                 } else {
                     if !last_hai_used {
-                        decomposer.valid_ukihai_vec.push(Ukihai { 0: *last_hai });
+                        decomposer.valid_ukihai_vec.push(Ukihai(*last_hai));
                     }
                     break;
                 }
@@ -642,272 +2048,445 @@ impl Tehai {
         let mut yaochuupai_value = yaochuupai_iter.next();
         let mut juntehai_value = juntehai_iter.next();
 
-        while yaochuupai_value != None && juntehai_value != None {
+        while yaochuupai_value.is_some() && juntehai_value.is_some() {
             if let (Some(lhs), Some(rhs)) = (yaochuupai_value, juntehai_value) {
                 if lhs < rhs {
                     yaochuupai_value = yaochuupai_iter.next();
                     yaochuupai_iter_changed = true;
                 } else if lhs > rhs {
-                    decomposer.invalid_ukihai_vec.push(Ukihai { 0: *rhs });
+                    decomposer.invalid_ukihai_vec.push(Ukihai(*rhs));
                     juntehai_value = juntehai_iter.next();
                 } else if lhs == rhs {
                     if yaochuupai_iter_changed {
-                        decomposer.valid_ukihai_vec.push(Ukihai { 0: *rhs });
+                        decomposer.valid_ukihai_vec.push(Ukihai(*rhs));
                     } else if !toitsu_included {
                         toitsu_included = true;
-                        decomposer.valid_ukihai_vec.push(Ukihai { 0: *rhs });
+                        decomposer.valid_ukihai_vec.push(Ukihai(*rhs));
                     } else {
-                        decomposer.invalid_ukihai_vec.push(Ukihai { 0: *rhs });
+                        decomposer.invalid_ukihai_vec.push(Ukihai(*rhs));
                     }
                     yaochuupai_iter_changed = false;
                     juntehai_value = juntehai_iter.next();
                 }
-            }
+            }
+        }
+        push_into_decomposers(decomposer);
+
+        Ok((min_shanten, min_shanten_decomposers, explored))
+    }
+
+    /// Discard `sutehai` and try drawing every hai, for `discard_matrix` and `discard_ukeire`.
+    ///
+    /// # Return
+    /// * i32: the number of shanten after discarding.
+    /// * MachiCondition: sutehai and ukeire as machihai, not yet counted by `finally`.
+    /// * UkeireTehai: each ukeire and tehai after drawing it.
+    fn ukeire_after_discard(
+        &self,
+        sutehai: &Hai,
+        player_number: PlayerNumber,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, MachiCondition, UkeireTehai), String> {
+        let mut remained = self.clone();
+        remained.discard(sutehai)?;
+        remained.ukeire(*sutehai, player_number, deadline)
+    }
+
+    /// Try drawing every hai for a tehai with 3*k+1 juntehai, see `ukeire_after_discard`.
+    /// `sutehai` is only recorded in the returned condition.
+    fn ukeire(
+        &self,
+        sutehai: Hai,
+        player_number: PlayerNumber,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, MachiCondition, UkeireTehai), String> {
+        // Drawing a hai never makes shanten worse, and drawing a useless hai keeps it,
+        // so the shanten before drawing is the maximum one among all draws.
+        let mut shanten_map = BTreeMap::new();
+        for hai in Hai::all_type(player_number) {
+            let mut drawn = self.clone();
+            drawn.juntehai.push(hai);
+            drawn.juntehai.sort_by_key(Hai::sort_key);
+            if drawn.check_hai_number().is_err() {
+                continue;
+            }
+            let (shanten, _, _) = drawn.decompose(player_number, deadline)?;
+            shanten_map.insert(hai, (shanten, drawn));
+        }
+        let shanten = shanten_map
+            .values()
+            .map(|(shanten, _)| *shanten)
+            .max()
+            .ok_or_else(|| "Logic Error: No hai can be drawn.".to_string())?;
+
+        let mut condition = MachiCondition::new(sutehai);
+        let mut ukeire_tehai = vec![];
+        for (hai, (drawn_shanten, drawn)) in shanten_map {
+            if drawn_shanten < shanten {
+                condition.machihai.insert(hai, 4);
+                ukeire_tehai.push((hai, drawn));
+            }
+        }
+        Ok((shanten, condition, ukeire_tehai))
+    }
+
+    /// A part of `analyze_lookahead`, for one sutehai.
+    ///
+    /// # Return
+    /// * i32: the number of shanten after discarding.
+    /// * usize: the number of ukeire after discarding.
+    /// * f64: the average of the best lookahead after drawing each ukeire.
+    fn lookahead_after_discard(
+        &self,
+        sutehai: &Hai,
+        depth: usize,
+        player_number: PlayerNumber,
+        game_manager: Option<&GameManager>,
+        cache: &mut HashMap<(Tehai, usize), f64>,
+    ) -> Result<(i32, usize, f64), String> {
+        let (shanten, mut condition, ukeire_tehai) =
+            self.ukeire_after_discard(sutehai, player_number, None)?;
+        condition.finally(self, game_manager);
+        let ukeire = condition.nokori();
+        if depth == 1 {
+            return Ok((shanten, ukeire, ukeire as f64));
+        }
+        if shanten <= 0 || ukeire == 0 {
+            return Ok((shanten, ukeire, 0.0));
+        }
+
+        let mut total = 0.0;
+        for (hai, drawn) in ukeire_tehai {
+            let weight = match condition.machihai.get(&hai) {
+                Some(&weight) if weight > 0 => weight,
+                _ => continue,
+            };
+            let key = (drawn, depth - 1);
+            let best = match cache.get(&key) {
+                Some(&best) => best,
+                None => {
+                    let (drawn, _) = &key;
+                    let mut best: Option<(i32, f64)> = None;
+                    let sutehai_set: BTreeSet<Hai> = drawn.juntehai.iter().copied().collect();
+                    for sutehai in sutehai_set {
+                        let (shanten, _, average) = drawn.lookahead_after_discard(
+                            &sutehai,
+                            depth - 1,
+                            player_number,
+                            game_manager,
+                            cache,
+                        )?;
+                        // Keep the minimum shanten first, then the most ukeire.
+                        best = match best {
+                            Some((best_shanten, best_average))
+                                if best_shanten < shanten
+                                    || (best_shanten == shanten && best_average >= average) =>
+                            {
+                                Some((best_shanten, best_average))
+                            }
+                            _ => Some((shanten, average)),
+                        };
+                    }
+                    let best = best.map(|(_, average)| average).unwrap_or(0.0);
+                    cache.insert(key, best);
+                    best
+                }
+            };
+            total += best * weight as f64;
         }
-        push_into_decomposers(decomposer);
-
-        Ok((min_shanten, min_shanten_decomposers))
+        Ok((shanten, ukeire, total / ukeire as f64))
     }
 
-    fn check_hai_number(&self) -> Result<(), Hai> {
-        let mut tehai_map: HashMap<Hai, u8> = HashMap::new();
+    /// Return sorted copies of juntehai and fuuro.
+    fn canonical(&self) -> (Vec<Hai>, Vec<Mentsu>) {
+        let mut juntehai = self.juntehai.clone();
+        let mut fuuro = self.fuuro.clone();
+        juntehai.sort_by_key(Hai::sort_key);
+        fuuro.sort();
+        (juntehai, fuuro)
+    }
 
-        let mut check_count = |hai| -> bool {
-            if tehai_map.contains_key(hai) {
-                let count = tehai_map[hai] + 1;
-                if count > 4 {
-                    return false;
-                }
-                tehai_map.insert(*hai, count);
-            } else {
-                tehai_map.insert(*hai, 1);
-            }
-            return true;
+    /// Check that no type of hai appears more than 4 times, fuuro included. Return all
+    /// types over the limit with their numbers otherwise, in the order of hai.
+    fn check_hai_number(&self) -> Result<(), Vec<(Hai, u8)>> {
+        let mut tehai_map: BTreeMap<Hai, u8> = BTreeMap::new();
+        // Aka is counted as five.
+        let mut count = |hai: &Hai, number: u8| {
+            *tehai_map.entry(hai.normalized()).or_insert(0) += number;
         };
 
         for hai in self.juntehai.iter() {
-            if !check_count(hai) {
-                return Err(*hai);
-            }
+            count(hai, 1);
         }
         for mentsu in self.fuuro.iter() {
             match mentsu {
                 Mentsu::Juntsu(a, b, c) => {
-                    for hai in vec![a, b, c] {
-                        if !check_count(hai) {
-                            return Err(*hai);
-                        }
-                    }
-                }
-                Mentsu::Koutsu(hai) => {
-                    for _ in 0..3 {
-                        if !check_count(hai) {
-                            return Err(*hai);
-                        }
-                    }
-                }
-                Mentsu::Kantsu(hai) => {
-                    for _ in 0..4 {
-                        if !check_count(hai) {
-                            return Err(*hai);
-                        }
+                    for hai in [a, b, c] {
+                        count(hai, 1);
                     }
                 }
+                Mentsu::Koutsu(hai) => count(hai, 3),
+                Mentsu::Kantsu(hai) => count(hai, 4),
             }
         }
 
-        Ok(())
+        let over_limit: Vec<(Hai, u8)> = tehai_map
+            .into_iter()
+            .filter(|(_, number)| *number > 4)
+            .collect();
+        if over_limit.is_empty() {
+            Ok(())
+        } else {
+            Err(over_limit)
+        }
     }
 
-    /// A part of function of decompose. Split a part of tehai to Decomposer.
+    /// Split juntehai for mentsute and return the minimum shanten for `juntehai_number` hai,
+    /// all splits reaching it and the number of all splits.
     ///
-    /// # Reference
-    /// * http://choco.properties/2019/06/22/%E6%97%A5%E9%BA%BB%E6%8A%98%E8%85%BE%E7%AC%94%E8%AE%B0-02-%E5%90%91%E5%90%AC%E6%95%B0%E7%9A%84%E5%88%A4%E6%96%AD/
-    /// * Original author: 天羽ちよこ
-    fn split(
+    /// Mentsu, taatsu and toitsu never cross suits, so each suit is split alone by
+    /// `split_suit`. Shanten only depends on how many mentsu, taatsu and toitsu there are, so
+    /// splits of each suit are grouped by them, and only groups reaching the minimum are
+    /// combined into splits of the whole juntehai.
+    fn split_minimum(
         &self,
-        decomposers_vec: &mut Vec<Decomposer>,
-        decomposer: &mut Decomposer,
+        juntehai_number: usize,
         player_number: PlayerNumber,
-    ) {
-        fn handle_ukihai(
-            tehai: &Tehai,
-            decomposers_vec: &mut Vec<Decomposer>,
-            decomposer: &mut Decomposer,
-            ukihai: Hai,
-            player_number: PlayerNumber,
-        ) {
-            let mut tehai = tehai.clone();
-            decomposer.invalid_ukihai_vec.push(Ukihai { 0: ukihai });
-            remove_once(&mut tehai.juntehai, &ukihai);
-            tehai.split(decomposers_vec, decomposer, player_number);
-        }
-
-        fn handle_taatsu(
-            tehai: &Tehai,
-            decomposers_vec: &mut Vec<Decomposer>,
-            decomposer: &mut Decomposer,
-            lhs: Hai,
-            rhs: Hai,
-            player_number: PlayerNumber,
-        ) {
-            let mut tehai = tehai.clone();
-            decomposer.taatsu_vec.push(Taatsu { 0: lhs, 1: rhs });
-            remove_once(&mut tehai.juntehai, &lhs);
-            remove_once(&mut tehai.juntehai, &rhs);
-            tehai.split(decomposers_vec, decomposer, player_number);
-        }
-
-        fn handle_toitsu(
-            tehai: &Tehai,
-            decomposers_vec: &mut Vec<Decomposer>,
-            decomposer: &mut Decomposer,
-            toitsu: Hai,
-            player_number: PlayerNumber,
-        ) {
-            let mut tehai = tehai.clone();
-            decomposer.toitsu_vec.push(Toitsu { 0: toitsu });
-            remove_once(&mut tehai.juntehai, &toitsu);
-            remove_once(&mut tehai.juntehai, &toitsu);
-            tehai.split(decomposers_vec, decomposer, player_number);
-        }
-
-        fn handle_juntsu(
-            tehai: &Tehai,
-            decomposers_vec: &mut Vec<Decomposer>,
-            decomposer: &mut Decomposer,
-            first: Hai,
-            second: Hai,
-            third: Hai,
-            player_number: PlayerNumber,
-        ) {
-            let mut tehai = tehai.clone();
-            decomposer
-                .mentsu_vec
-                .push(Mentsu::Juntsu(first, second, third));
-            remove_once(&mut tehai.juntehai, &first);
-            remove_once(&mut tehai.juntehai, &second);
-            remove_once(&mut tehai.juntehai, &third);
-            tehai.split(decomposers_vec, decomposer, player_number);
-        }
-
-        fn handle_koutsu(
-            tehai: &Tehai,
-            decomposers_vec: &mut Vec<Decomposer>,
-            decomposer: &mut Decomposer,
-            koutsu: Hai,
-            player_number: PlayerNumber,
+        deadline: Option<Instant>,
+    ) -> Result<(i32, Vec<Decomposer>, usize), String> {
+        /// Choose a group for each suit from `suit_index` on, and keep the choices with the
+        /// minimum shanten.
+        fn choose(
+            groups: &[&[(SplitProfile, Vec<usize>)]],
+            suit_index: usize,
+            sum: SplitProfile,
+            chosen: &mut Vec<usize>,
+            juntehai_number: usize,
+            min_shanten: &mut i32,
+            min_chosen: &mut Vec<Vec<usize>>,
         ) {
-            let mut tehai = tehai.clone();
-            decomposer.mentsu_vec.push(Mentsu::Koutsu(koutsu));
-            remove_once(&mut tehai.juntehai, &koutsu);
-            remove_once(&mut tehai.juntehai, &koutsu);
-            remove_once(&mut tehai.juntehai, &koutsu);
-            tehai.split(decomposers_vec, decomposer, player_number);
+            if suit_index == groups.len() {
+                let (mentsu, taatsu, toitsu, duplicated) = sum;
+                let shanten = if duplicated {
+                    13
+                } else {
+                    Decomposer::mentsute_shanten(mentsu, taatsu, toitsu, juntehai_number)
+                };
+                if shanten < *min_shanten {
+                    *min_shanten = shanten;
+                    min_chosen.clear();
+                }
+                if shanten == *min_shanten {
+                    min_chosen.push(chosen.clone());
+                }
+                return;
+            }
+            for (group_index, (profile, _)) in groups[suit_index].iter().enumerate() {
+                chosen.push(group_index);
+                choose(
+                    groups,
+                    suit_index + 1,
+                    (
+                        sum.0 + profile.0,
+                        sum.1 + profile.1,
+                        sum.2 + profile.2,
+                        sum.3 || profile.3,
+                    ),
+                    chosen,
+                    juntehai_number,
+                    min_shanten,
+                    min_chosen,
+                );
+                chosen.pop();
+            }
         }
 
-        if self.juntehai.len() == 1 {
-            decomposer.invalid_ukihai_vec.push(Ukihai {
-                0: self.juntehai[0],
-            });
+        let mut counts: BTreeMap<Suit, [u8; 9]> = BTreeMap::new();
+        for hai in self.juntehai.iter().map(Hai::normalized) {
+            counts.entry(hai.suit()).or_insert([0; 9])[hai.number() as usize - 1] += 1;
         }
 
-        if self.juntehai.len() <= 1 {
-            decomposers_vec.push(decomposer.clone());
-            return;
+        let mut parts = vec![];
+        for (&suit, &suit_counts) in counts.iter() {
+            parts.push(Tehai::split_suit(
+                suit,
+                suit_counts,
+                player_number,
+                deadline,
+            )?);
         }
+        let explored = parts.iter().map(|part| part.decomposers.len()).product();
+        let groups: Vec<_> = parts.iter().map(|part| part.groups()).collect();
+
+        let mut min_shanten = ((juntehai_number / 3) * 2) as i32;
+        let mut min_chosen = vec![];
+        choose(
+            &groups,
+            0,
+            (0, 0, 0, false),
+            &mut vec![],
+            juntehai_number,
+            &mut min_shanten,
+            &mut min_chosen,
+        );
 
-        let current = self.juntehai[0];
-        let next = self.juntehai[1];
-        let next_next = self.juntehai.get(2);
-
-        if current == next {
-            handle_toitsu(
-                self,
-                decomposers_vec,
-                &mut decomposer.clone(),
-                current,
-                player_number,
-            );
+        let mut decomposers_vec = vec![];
+        for chosen in min_chosen {
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Err("TIMEOUT: Analysis did not finish in time.".to_string());
+                }
+            }
+            let mut combined = vec![Decomposer::new()];
+            for (suit_index, &group_index) in chosen.iter().enumerate() {
+                let (_, indices) = &groups[suit_index][group_index];
+                let mut next = Vec::with_capacity(combined.len() * indices.len());
+                for lhs in combined.iter() {
+                    for &index in indices {
+                        let mut decomposer = lhs.clone();
+                        decomposer.append(&parts[suit_index].decomposers[index]);
+                        next.push(decomposer);
+                    }
+                }
+                combined = next;
+            }
+            decomposers_vec.append(&mut combined);
         }
 
-        if let Some(&next_next) = next_next {
-            if current == next && current == next_next {
-                handle_koutsu(
-                    self,
-                    decomposers_vec,
-                    &mut decomposer.clone(),
-                    current,
-                    player_number,
-                );
+        Ok((min_shanten, decomposers_vec, explored))
+    }
+
+    /// Every way to split hai of one suit into mentsu, taatsu, toitsu and ukihai, taking the
+    /// smallest number first. `counts` is the count of each number from 1.
+    ///
+    /// Taking a block off the smallest number leaves counts of the same suit, so the rest is
+    /// split by this function again. Results are cached by counts, so the rest shared by many
+    /// blocks, and suits unchanged by a sutehai or tsumohai, are split only once.
+    fn split_suit(
+        suit: Suit,
+        counts: [u8; 9],
+        player_number: PlayerNumber,
+        deadline: Option<Instant>,
+    ) -> Result<Rc<SuitSplit>, String> {
+        let key = (player_number, suit, counts);
+        if let Some(cached) = SPLIT_CACHE.with(|cache| cache.borrow().get(&key).cloned()) {
+            return Ok(cached);
+        }
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                return Err("TIMEOUT: Analysis did not finish in time.".to_string());
             }
         }
 
-        if !matches!(current, Hai::Jihai(_)) {
-            let current_plus_one = current.next(player_number, false);
-            if let Some(current_plus_one) = current_plus_one {
-                let current_plus_two = current_plus_one.next(player_number, false);
+        let hai_of = |index: usize| match suit {
+            Suit::Man => Hai::Manzu(index as u8 + 1),
+            Suit::Pin => Hai::Pinzu(index as u8 + 1),
+            Suit::Sou => Hai::Souzu(index as u8 + 1),
+            Suit::Ji => Hai::Jihai(index as u8 + 1),
+        };
+        let take = |indices: &[usize]| {
+            let mut rest = counts;
+            for &index in indices {
+                rest[index] -= 1;
+            }
+            rest
+        };
 
-                let filtered: Vec<&Hai> = self
-                    .juntehai
-                    .iter()
-                    .filter(|&x| x == &current_plus_one)
-                    .collect();
-                if filtered.len() > 0 {
-                    handle_taatsu(
-                        self,
-                        decomposers_vec,
-                        &mut decomposer.clone(),
-                        current,
-                        current_plus_one,
-                        player_number,
-                    );
+        let mut split_vec = vec![];
+        match counts.iter().position(|&count| count > 0) {
+            None => split_vec.push(Decomposer::new()),
+            Some(index) if counts.iter().sum::<u8>() == 1 => {
+                let mut decomposer = Decomposer::new();
+                decomposer.invalid_ukihai_vec.push(Ukihai(hai_of(index)));
+                split_vec.push(decomposer);
+            }
+            Some(index) => {
+                let current = hai_of(index);
+                // Each block made with `current`, and counts left after taking it.
+                let mut branches = vec![];
+                if counts[index] >= 2 {
+                    let mut decomposer = Decomposer::new();
+                    decomposer.toitsu_vec.push(Toitsu(current));
+                    branches.push((decomposer, take(&[index, index])));
+                }
+                if counts[index] >= 3 {
+                    let mut decomposer = Decomposer::new();
+                    decomposer.mentsu_vec.push(Mentsu::Koutsu(current));
+                    branches.push((decomposer, take(&[index, index, index])));
+                }
 
-                    if let Some(current_plus_two) = current_plus_two {
-                        let filtered: Vec<&Hai> = self
-                            .juntehai
-                            .iter()
-                            .filter(|&x| x == &current_plus_two)
-                            .collect();
-                        if filtered.len() > 0 {
-                            handle_juntsu(
-                                self,
-                                decomposers_vec,
-                                &mut decomposer.clone(),
-                                current,
-                                current_plus_one,
-                                current_plus_two,
-                                player_number,
-                            );
+                // Only 1m and 9m exist in 3-player mode, so manzu never forms juntsu or taatsu.
+                let sanma_manzu = player_number == PlayerNumber::Three && suit == Suit::Man;
+                if suit != Suit::Ji && !sanma_manzu {
+                    if let Some(plus_one) = current.next(player_number, false) {
+                        let plus_two = plus_one.next(player_number, false);
+                        if counts[index + 1] > 0 {
+                            let mut decomposer = Decomposer::new();
+                            decomposer.taatsu_vec.push(Taatsu(current, plus_one));
+                            branches.push((decomposer, take(&[index, index + 1])));
+
+                            if let Some(plus_two) = plus_two {
+                                if counts[index + 2] > 0 {
+                                    let mut decomposer = Decomposer::new();
+                                    decomposer
+                                        .mentsu_vec
+                                        .push(Mentsu::Juntsu(current, plus_one, plus_two));
+                                    branches
+                                        .push((decomposer, take(&[index, index + 1, index + 2])));
+                                }
+                            }
+                        } else if let Some(plus_two) = plus_two {
+                            if counts[index + 2] > 0 {
+                                let mut decomposer = Decomposer::new();
+                                decomposer.taatsu_vec.push(Taatsu(current, plus_two));
+                                branches.push((decomposer, take(&[index, index + 2])));
+                            }
                         }
                     }
-                } else if let Some(current_plus_two) = current_plus_two {
-                    let filtered: Vec<&Hai> = self
-                        .juntehai
-                        .iter()
-                        .filter(|&x| x == &current_plus_two)
-                        .collect();
-                    if filtered.len() > 0 {
-                        handle_taatsu(
-                            self,
-                            decomposers_vec,
-                            &mut decomposer.clone(),
-                            current,
-                            current_plus_two,
-                            player_number,
-                        );
+                }
+
+                let mut decomposer = Decomposer::new();
+                decomposer.invalid_ukihai_vec.push(Ukihai(current));
+                branches.push((decomposer, take(&[index])));
+
+                for (block, rest) in branches {
+                    let rest = Tehai::split_suit(suit, rest, player_number, deadline)?;
+                    for split in rest.decomposers.iter() {
+                        let mut decomposer = block.clone();
+                        decomposer.append(split);
+                        split_vec.push(decomposer);
                     }
                 }
             }
-        };
+        }
 
-        handle_ukihai(
-            self,
-            decomposers_vec,
-            &mut decomposer.clone(),
-            current,
-            player_number,
-        );
+        let split_vec = Rc::new(SuitSplit {
+            decomposers: split_vec,
+            groups: OnceCell::new(),
+        });
+        SPLIT_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if cache.len() >= SPLIT_CACHE_CAPACITY {
+                cache.clear();
+            }
+            cache.insert(key, Rc::clone(&split_vec));
+        });
+        Ok(split_vec)
+    }
+}
+
+impl PartialEq for Tehai {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical() == other.canonical() && self.ankan == other.ankan
+    }
+}
+
+impl Eq for Tehai {}
+
+impl std::hash::Hash for Tehai {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.canonical().hash(state);
+        self.ankan.hash(state);
     }
 }
 
@@ -926,6 +2505,25 @@ impl std::fmt::Display for Tehai {
     }
 }
 
+impl SuitSplit {
+    fn groups(&self) -> &[(SplitProfile, Vec<usize>)] {
+        self.groups.get_or_init(|| {
+            let mut groups: BTreeMap<SplitProfile, Vec<usize>> = BTreeMap::new();
+            for (index, decomposer) in self.decomposers.iter().enumerate() {
+                let toitsu_set: HashSet<&Toitsu> = decomposer.toitsu_vec.iter().collect();
+                let profile = (
+                    decomposer.mentsu_vec.len(),
+                    decomposer.taatsu_vec.len(),
+                    decomposer.toitsu_vec.len(),
+                    toitsu_set.len() != decomposer.toitsu_vec.len(),
+                );
+                groups.entry(profile).or_default().push(index);
+            }
+            groups.into_iter().collect()
+        })
+    }
+}
+
 impl Decomposer {
     fn new() -> Self {
         Self {
@@ -938,6 +2536,34 @@ impl Decomposer {
         }
     }
 
+    /// Append all parts of another decomposer after the parts of this one.
+    fn append(&mut self, other: &Decomposer) {
+        self.mentsu_vec.extend_from_slice(&other.mentsu_vec);
+        self.toitsu_vec.extend_from_slice(&other.toitsu_vec);
+        self.taatsu_vec.extend_from_slice(&other.taatsu_vec);
+        self.valid_ukihai_vec
+            .extend_from_slice(&other.valid_ukihai_vec);
+        self.invalid_ukihai_vec
+            .extend_from_slice(&other.invalid_ukihai_vec);
+    }
+
+    /// Calculate shanten of mentsute with the numbers of mentsu, taatsu and distinct toitsu.
+    fn mentsute_shanten(
+        mentsu: usize,
+        taatsu: usize,
+        toitsu: usize,
+        juntehai_number: usize,
+    ) -> i32 {
+        let max_mentsu_toitsu_taatsu = (juntehai_number + 1) / 3;
+        let taatsu_num = std::cmp::min(max_mentsu_toitsu_taatsu - 1 - mentsu, taatsu);
+        let toitsu_num = std::cmp::min(max_mentsu_toitsu_taatsu - mentsu - taatsu_num, toitsu);
+
+        ((juntehai_number / 3) * 2) as i32
+            - 2 * mentsu as i32
+            - toitsu_num as i32
+            - taatsu_num as i32
+    }
+
     /// Calculate shanten for current decompser.
     ///
     /// # Japanese
@@ -953,20 +2579,12 @@ impl Decomposer {
                     return 13;
                 }
 
-                let max_mentsu_toitsu_taatsu = (juntehai_number + 1) / 3;
-                let taatsu_num = std::cmp::min(
-                    max_mentsu_toitsu_taatsu - 1 - self.mentsu_vec.len(),
+                Decomposer::mentsute_shanten(
+                    self.mentsu_vec.len(),
                     self.taatsu_vec.len(),
-                );
-                let toitsu_num = std::cmp::min(
-                    max_mentsu_toitsu_taatsu - self.mentsu_vec.len() - taatsu_num,
                     self.toitsu_vec.len(),
-                );
-
-                ((juntehai_number / 3) * 2) as i32
-                    - 2 * self.mentsu_vec.len() as i32
-                    - toitsu_num as i32
-                    - taatsu_num as i32
+                    juntehai_number,
+                )
             }
             Hourakei::Chiitoitsu => {
                 13 - 2 * self.toitsu_vec.len() as i32
@@ -977,19 +2595,323 @@ impl Decomposer {
     }
 }
 
+impl Decomposition {
+    fn new(decomposer: &Decomposer, sutehai: Option<&Hai>) -> Self {
+        let mut ukihai = decomposer.valid_ukihai_vec.clone();
+        ukihai.extend(decomposer.invalid_ukihai_vec.iter());
+        if let Some(sutehai) = sutehai {
+            remove_once(&mut ukihai, &Ukihai(*sutehai));
+        }
+        ukihai.sort_by_key(|ukihai| ukihai.0);
+
+        Self {
+            hourakei: decomposer.hourakei,
+            mentsu: decomposer.mentsu_vec.clone(),
+            toitsu: decomposer.toitsu_vec.clone(),
+            taatsu: decomposer.taatsu_vec.clone(),
+            ukihai,
+        }
+    }
+
+    /// Return hai improving a shape of this decomposition, even if shanten is not reduced.
+    /// For mentsute, it means hai upgrading a taatsu or toitsu to a mentsu, or an ukihai to
+    /// a taatsu or toitsu. For chiitoitsu, it means hai pairing an ukihai. For kokushimusou,
+    /// it means missing yaochuupai.
+    pub fn improvement_tiles(&self, player_number: PlayerNumber) -> BTreeSet<Hai> {
+        let mut tiles = BTreeSet::new();
+        match self.hourakei {
+            Hourakei::Mentsute => {
+                for Taatsu(lhs, rhs) in &self.taatsu {
+                    if lhs.next(player_number, false) == Some(*rhs) {
+                        tiles.extend(lhs.previous(player_number, false));
+                        tiles.extend(rhs.next(player_number, false));
+                    } else {
+                        tiles.extend(lhs.next(player_number, false));
+                    }
+                }
+                for Toitsu(hai) in &self.toitsu {
+                    tiles.insert(*hai);
+                }
+                for Ukihai(hai) in &self.ukihai {
+                    tiles.insert(*hai);
+                    if !matches!(hai, Hai::Jihai(_)) {
+                        for near in [
+                            hai.previous(player_number, false),
+                            hai.next(player_number, false),
+                        ]
+                        .iter()
+                        .flatten()
+                        {
+                            tiles.insert(*near);
+                            tiles.extend(near.previous(player_number, false));
+                            tiles.extend(near.next(player_number, false));
+                        }
+                    }
+                }
+            }
+            Hourakei::Chiitoitsu => {
+                for Ukihai(hai) in &self.ukihai {
+                    tiles.insert(*hai);
+                }
+            }
+            Hourakei::Kokushimusou => {
+                tiles = Hai::yaochuupai_type();
+                for Ukihai(hai) in &self.ukihai {
+                    tiles.remove(hai);
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Print self to json.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mentsu_json_vec: Vec<_> = self.mentsu.iter().map(|mentsu| mentsu.to_json()).collect();
+        let toitsu_string_vec: Vec<_> = self
+            .toitsu
+            .iter()
+            .map(|toitsu| toitsu.to_string())
+            .collect();
+        let taatsu_string_vec: Vec<_> = self
+            .taatsu
+            .iter()
+            .map(|taatsu| taatsu.to_string())
+            .collect();
+        let ukihai_string_vec: Vec<_> = self
+            .ukihai
+            .iter()
+            .map(|ukihai| ukihai.to_string())
+            .collect();
+        json!({
+            "hourakei": match self.hourakei {
+                Hourakei::Mentsute => "mentsute",
+                Hourakei::Chiitoitsu => "chiitoitsu",
+                Hourakei::Kokushimusou => "kokushimusou",
+            },
+            "mentsu": mentsu_json_vec,
+            "toitsu": toitsu_string_vec,
+            "taatsu": taatsu_string_vec,
+            "ukihai": ukihai_string_vec,
+        })
+    }
+}
+
+impl std::fmt::Display for Decomposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        parts.extend(self.mentsu.iter().map(|mentsu| mentsu.to_string()));
+        parts.extend(self.toitsu.iter().map(|toitsu| toitsu.to_string()));
+        parts.extend(self.taatsu.iter().map(|taatsu| taatsu.to_string()));
+        parts.extend(self.ukihai.iter().map(|ukihai| ukihai.to_string()));
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl HandBreakdown {
+    fn new(decomposer: &Decomposer) -> Self {
+        Self {
+            hourakei: decomposer.hourakei,
+            mentsu: decomposer.mentsu_vec.clone(),
+            toitsu: decomposer.toitsu_vec.clone(),
+            taatsu: decomposer.taatsu_vec.clone(),
+            valid_ukihai: decomposer.valid_ukihai_vec.clone(),
+            invalid_ukihai: decomposer.invalid_ukihai_vec.clone(),
+        }
+    }
+
+    /// Return the number of pairs of identical juntsu in mentsu, 1 for iipeikou and 2 for
+    /// ryanpeikou. Always 0 for other than mentsute, so among breakdowns of `112233m`, the
+    /// mentsute one with two `[1m2m3m]` gives iipeikou.
+    ///
+    /// # Japanese
+    /// * iipeikou: 一盃口
+    /// * ryanpeikou: 二盃口
+    pub fn count_iipeikou(&self) -> u32 {
+        match self.hourakei {
+            Hourakei::Mentsute => count_iipeikou(&self.mentsu),
+            _ => 0,
+        }
+    }
+
+    /// Print self to json.
+    pub fn to_json(&self) -> serde_json::Value {
+        fn to_string_vec<T: std::fmt::Display>(vec: &[T]) -> Vec<String> {
+            vec.iter().map(|item| item.to_string()).collect()
+        }
+        json!({
+            "hourakei": match self.hourakei {
+                Hourakei::Mentsute => "mentsute",
+                Hourakei::Chiitoitsu => "chiitoitsu",
+                Hourakei::Kokushimusou => "kokushimusou",
+            },
+            "mentsu": self.mentsu.iter().map(|mentsu| mentsu.to_json()).collect::<Vec<_>>(),
+            "toitsu": to_string_vec(&self.toitsu),
+            "taatsu": to_string_vec(&self.taatsu),
+            "valid_ukihai": to_string_vec(&self.valid_ukihai),
+            "invalid_ukihai": to_string_vec(&self.invalid_ukihai),
+        })
+    }
+}
+
+impl std::fmt::Display for HandBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        parts.extend(self.mentsu.iter().map(|mentsu| mentsu.to_string()));
+        parts.extend(self.toitsu.iter().map(|toitsu| toitsu.to_string()));
+        parts.extend(self.taatsu.iter().map(|taatsu| taatsu.to_string()));
+        parts.extend(self.valid_ukihai.iter().map(|ukihai| ukihai.to_string()));
+        parts.extend(
+            self.invalid_ukihai
+                .iter()
+                .map(|ukihai| format!("({})", ukihai)),
+        );
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
 impl MachiCondition {
+    /// Print self like the `Display` output, but with Unicode mahjong tiles.
+    /// See [`Hai::to_unicode`].
+    pub fn to_unicode_string(&self) -> String {
+        self.format_with(|hai| hai.to_unicode().to_string())
+    }
+
+    fn format_with<F>(&self, hai_to_string: F) -> String
+    where
+        F: Fn(&Hai) -> String,
+    {
+        let mut machihai_string = String::new();
+        let mut furiten_string = String::new();
+        for machihai in self.machihai.keys() {
+            machihai_string += &hai_to_string(machihai);
+            machihai_string += " ";
+        }
+        if self.furiten {
+            furiten_string = "!振り聴!".to_string();
+        }
+        if self.temporary_furiten {
+            furiten_string += "!同巡内振り聴!";
+        }
+        if self.karaten {
+            furiten_string += "!空聴!";
+        }
+        format!(
+            "打 {} 摸 {} 残り{}枚{}",
+            hai_to_string(&self.sutehai),
+            machihai_string,
+            self.nokori(),
+            furiten_string
+        )
+    }
+
     /// Get how many hai can waiting for.
     ///
     /// # Japanese
     /// * nokori: 残り
     pub fn nokori(&self) -> usize {
         let mut nokori = 0;
-        for (_, number) in &self.machihai {
+        for number in self.machihai.values() {
             nokori += *number as usize;
         }
         nokori
     }
 
+    /// Return the number of machihai as if 4 of each type were left, ignoring hai in tehai,
+    /// fuuro and haiyama. Dead machihai are counted too. `nokori` is the effective one.
+    ///
+    /// # Japanese
+    /// * ukeire: 受け入れ
+    pub fn raw_ukeire(&self) -> usize {
+        (self.machihai.len() + self.dead_machihai.len()) * 4
+    }
+
+    /// Return the probability of drawing any machihai as the next hai, which is nokori
+    /// divided by the number of all hai left in haiyama. 0 if haiyama is empty.
+    pub fn draw_probability(&self, haiyama: &Haiyama) -> f64 {
+        match haiyama.remaining_total() {
+            0 => 0.0,
+            total => self.nokori() as f64 / total as f64,
+        }
+    }
+
+    /// Return the shape of waiting after discarding sutehai, or `None` if not tenpai.
+    /// It is taken from the representative decomposition, and becomes `Multi` if machihai
+    /// are more than the shape can provide, except for nobetan.
+    pub fn wait_kind(&self) -> Option<WaitKind> {
+        fn number_of(hai: &Hai) -> Option<u8> {
+            match hai.suit() {
+                Suit::Ji => None,
+                _ => Some(hai.normalized().number()),
+            }
+        }
+
+        let decomposition = self.decomposition.as_ref()?;
+        let (kind, wait_number) = match decomposition.hourakei {
+            Hourakei::Mentsute => match (
+                decomposition.toitsu.len(),
+                decomposition.taatsu.len(),
+                decomposition.ukihai.len(),
+            ) {
+                (2, 0, 0) => (WaitKind::Shanpon, 2),
+                (1, 1, 0) => {
+                    let Taatsu(lhs, rhs) = decomposition.taatsu[0];
+                    match (number_of(&lhs), number_of(&rhs)) {
+                        (Some(lhs), Some(rhs)) if rhs - lhs == 2 => (WaitKind::Kanchan, 1),
+                        (Some(1), _) | (_, Some(9)) => (WaitKind::Penchan, 1),
+                        _ => (WaitKind::Ryanmen, 2),
+                    }
+                }
+                (0, 0, 1) => (WaitKind::Tanki, 1),
+                _ => return None,
+            },
+            Hourakei::Chiitoitsu => {
+                match (decomposition.toitsu.len(), decomposition.ukihai.len()) {
+                    (6, 1) => (WaitKind::Tanki, 1),
+                    _ => return None,
+                }
+            }
+            Hourakei::Kokushimusou => {
+                if decomposition.ukihai.len() != 13 {
+                    return None;
+                }
+                let distinct: BTreeSet<Hai> = decomposition
+                    .ukihai
+                    .iter()
+                    .map(|Ukihai(hai)| hai.normalized())
+                    .collect();
+                if distinct.len() == 13 {
+                    (WaitKind::Multi, 13)
+                } else {
+                    (WaitKind::Tanki, 1)
+                }
+            }
+        };
+
+        let waits: BTreeSet<Hai> = self
+            .machihai
+            .keys()
+            .chain(self.dead_machihai.iter())
+            .map(|hai| hai.normalized())
+            .collect();
+        if waits.len() <= wait_number {
+            return Some(kind);
+        }
+        // Nobetan waits on both ends of a 4-hai juntsu shape, like 1m and 4m of 1234m.
+        let waits: Vec<Hai> = waits.into_iter().collect();
+        let nobetan = kind == WaitKind::Tanki
+            && waits.len() == 2
+            && waits[0].suit() == waits[1].suit()
+            && match (number_of(&waits[0]), number_of(&waits[1])) {
+                (Some(lhs), Some(rhs)) => rhs == lhs + 3,
+                _ => false,
+            };
+        if nobetan {
+            Some(WaitKind::Nobetan)
+        } else {
+            Some(WaitKind::Multi)
+        }
+    }
+
     pub fn to_json(&self) -> serde_json::Value {
         let mut machi_hai_json_vec = vec![];
         for (hai, num) in &self.machihai {
@@ -998,22 +2920,77 @@ impl MachiCondition {
                 "number": num
             }));
         }
-        json!({
+        let mut json = json!({
             "sutehai": self.sutehai.to_string(),
             "furiten": self.furiten,
+            "temporary_furiten": self.temporary_furiten,
+            "karaten": self.karaten,
             "machihai_number": self.nokori(),
-            "machihai": machi_hai_json_vec
-        })
+            "raw_ukeire": self.raw_ukeire(),
+            "effective_ukeire": self.nokori(),
+            "machihai": machi_hai_json_vec,
+            "wait_kind": self.wait_kind().map(|kind| match kind {
+                WaitKind::Ryanmen => "ryanmen",
+                WaitKind::Kanchan => "kanchan",
+                WaitKind::Penchan => "penchan",
+                WaitKind::Shanpon => "shanpon",
+                WaitKind::Tanki => "tanki",
+                WaitKind::Nobetan => "nobetan",
+                WaitKind::Multi => "multi",
+            }),
+        });
+        if let Some(draw_probability) = self.draw_probability {
+            json["draw_probability"] = json!(draw_probability);
+        }
+        json
+    }
+
+    /// Print self to json like `to_json`, with `"by_shape"` pairing each shape with its
+    /// machihai, like `{"shape":"4p5p","machihai":["3p","6p"]}`.
+    pub fn to_verbose_json(&self) -> serde_json::Value {
+        let mut json = self.to_json();
+        json["by_shape"] = self
+            .by_shape
+            .iter()
+            .map(|(shape, machihai)| {
+                let machihai: Vec<String> = machihai.iter().map(|hai| hai.to_string()).collect();
+                json!({ "shape": shape, "machihai": machihai })
+            })
+            .collect();
+        json
+    }
+
+    /// Print self to json with machihai as a single object, like `{"1m":3,"4m":4}`.
+    pub fn to_compact_json(&self) -> serde_json::Value {
+        let mut machihai_json = serde_json::Map::new();
+        for (hai, num) in &self.machihai {
+            machihai_json.insert(hai.to_string(), json!(num));
+        }
+        let mut json = self.to_json();
+        json["machihai"] = serde_json::Value::Object(machihai_json);
+        json
     }
 
     fn new(sutehai: Hai) -> Self {
         Self {
             sutehai,
             machihai: BTreeMap::new(),
+            dead_machihai: BTreeSet::new(),
+            karaten: false,
             furiten: false,
+            temporary_furiten: false,
+            draw_probability: None,
+            decomposition: None,
+            by_shape: BTreeMap::new(),
         }
     }
 
+    /// Insert a machihai provided by a shape, see `by_shape`.
+    fn insert_machihai(&mut self, shape: String, hai: Hai) {
+        self.machihai.insert(hai, 4);
+        self.by_shape.entry(shape).or_default().insert(hai);
+    }
+
     fn handle(
         &mut self,
         decomposer: &Decomposer,
@@ -1027,14 +3004,11 @@ impl MachiCondition {
         // If invalid_ukihai_vec does not contain sutehai, no need to analyze.
         if !decomposer
             .invalid_ukihai_vec
-            .contains(&Ukihai { 0: self.sutehai })
+            .contains(&Ukihai(self.sutehai))
         {
             // But Chiitoitsu is a little special.
             if decomposer.hourakei == Hourakei::Chiitoitsu {
-                if !decomposer
-                    .valid_ukihai_vec
-                    .contains(&Ukihai { 0: self.sutehai })
-                {
+                if !decomposer.valid_ukihai_vec.contains(&Ukihai(self.sutehai)) {
                     return Ok(self);
                 }
             } else {
@@ -1062,45 +3036,28 @@ impl MachiCondition {
         decomposer: &Decomposer,
         player_number: PlayerNumber,
     ) -> Result<&mut Self, String> {
-        for taatsu in &decomposer.taatsu_vec {
-            match (taatsu.0, taatsu.1) {
-                (Hai::Manzu(lhs), Hai::Manzu(rhs)) => {
-                    if rhs - lhs == 2 {
-                        self.machihai.insert(Hai::Manzu(lhs + 1), 4);
-                    } else if rhs - lhs == 1 {
-                        if let Some(machi) = taatsu.0.previous(player_number, false) {
-                            self.machihai.insert(machi, 4);
-                        }
-                        if let Some(machi) = taatsu.1.next(player_number, false) {
-                            self.machihai.insert(machi, 4);
-                        }
+        for Taatsu(lhs, rhs) in &decomposer.taatsu_vec {
+            if lhs.suit() != rhs.suit() || lhs.suit() == Suit::Ji {
+                return Err("Logic error: Code cannot reach here.".to_string());
+            }
+            let shape = Taatsu(*lhs, *rhs).to_string();
+            match rhs.number() - lhs.number() {
+                // Kanchan
+                2 => {
+                    if let Some(machi) = Hai::new(lhs.suit(), lhs.number() + 1) {
+                        self.insert_machihai(shape.clone(), machi);
                     }
                 }
-                (Hai::Pinzu(lhs), Hai::Pinzu(rhs)) => {
-                    if rhs - lhs == 2 {
-                        self.machihai.insert(Hai::Pinzu(lhs + 1), 4);
-                    } else if rhs - lhs == 1 {
-                        if let Some(machi) = taatsu.0.previous(player_number, false) {
-                            self.machihai.insert(machi, 4);
-                        }
-                        if let Some(machi) = taatsu.1.previous(player_number, false) {
-                            self.machihai.insert(machi, 4);
-                        }
+                // Ryanmen or penchan
+                1 => {
+                    if let Some(machi) = lhs.previous(player_number, false) {
+                        self.insert_machihai(shape.clone(), machi);
                     }
-                }
-                (Hai::Souzu(lhs), Hai::Souzu(rhs)) => {
-                    if rhs - lhs == 2 {
-                        self.machihai.insert(Hai::Souzu(lhs + 1), 4);
-                    } else if rhs - lhs == 1 {
-                        if let Some(machi) = taatsu.0.previous(player_number, false) {
-                            self.machihai.insert(machi, 4);
-                        }
-                        if let Some(machi) = taatsu.1.next(player_number, false) {
-                            self.machihai.insert(machi, 4);
-                        }
+                    if let Some(machi) = rhs.next(player_number, false) {
+                        self.insert_machihai(shape.clone(), machi);
                     }
                 }
-                _ => return Err("Logic error: Code cannot reach here.".to_string()),
+                _ => (),
             }
         }
 
@@ -1110,53 +3067,57 @@ impl MachiCondition {
     fn finally(&mut self, tehai: &Tehai, game_manager: Option<&GameManager>) {
         // If interactive mode.
         if let Some(game_manager) = game_manager {
+            self.temporary_furiten = game_manager.temporary_furiten();
+            // Checked on all machihai before removing dead ones.
+            self.furiten = self.furiten || game_manager.is_furiten(&self.machihai);
             let mut zero_nokori_hai = vec![];
             for (key, value) in self.machihai.iter_mut() {
-                if !self.furiten {
-                    if game_manager.sutehai_type().contains(key) {
-                        self.furiten = true;
-                    }
-                }
-                *value = game_manager.haiyama()[key];
+                *value = game_manager.haiyama().count(key);
                 if *value == 0 {
                     zero_nokori_hai.push(*key);
                 }
             }
             for hai in zero_nokori_hai {
                 self.machihai.remove(&hai);
+                self.dead_machihai.insert(hai);
             }
+            self.draw_probability = Some(self.draw_probability(game_manager.haiyama()));
         }
         // If non-interactive mode.
         else {
             // Remove hai whose number is 0.
-            let check_count = |machihai: &mut BTreeMap<_, _>, item| {
+            let machihai = &mut self.machihai;
+            let dead_machihai = &mut self.dead_machihai;
+            let mut check_count = |item: &Hai| {
+                let item = &item.normalized();
                 if machihai.contains_key(item) {
                     if machihai[item] > 1 {
                         machihai.insert(*item, machihai[item] - 1);
                     } else if machihai[item] == 1 {
                         machihai.remove(item);
+                        dead_machihai.insert(*item);
                     }
                 }
             };
             for item in &tehai.juntehai {
-                check_count(&mut self.machihai, item);
+                check_count(item);
             }
 
             for mentsu in &tehai.fuuro {
                 match mentsu {
                     Mentsu::Juntsu(a, b, c) => {
-                        for item in vec![a, b, c] {
-                            check_count(&mut self.machihai, item);
+                        for item in [a, b, c] {
+                            check_count(item);
                         }
                     }
                     Mentsu::Koutsu(item) => {
                         for _ in 0..3 {
-                            check_count(&mut self.machihai, item);
+                            check_count(item);
                         }
                     }
                     Mentsu::Kantsu(item) => {
                         for _ in 0..4 {
-                            check_count(&mut self.machihai, item);
+                            check_count(item);
                         }
                     }
                 }
@@ -1170,6 +3131,8 @@ impl MachiCondition {
         player_number: PlayerNumber,
         juntehai_number: usize,
     ) -> Result<&mut Self, String> {
+        // k+1 blocks for 3*k+2 juntehai, that is 5 for 14 hai and 6 for 17 hai. Juntehai
+        // here still includes sutehai.
         let max_mentsu_toitsu_taatsu = (juntehai_number + 1) / 3;
 
         // If taatsu overload, no need to analyze.
@@ -1191,7 +3154,7 @@ impl MachiCondition {
         // If more than 1 toitsu, analyze toitsu.
         if decomposer.toitsu_vec.len() > 1 {
             for toitsu in &decomposer.toitsu_vec {
-                self.machihai.insert(toitsu.0, 4);
+                self.insert_machihai(toitsu.to_string(), toitsu.0);
             }
         }
 
@@ -1201,7 +3164,7 @@ impl MachiCondition {
         {
             // Toitsu to koutsu
             for toitsu in decomposer.toitsu_vec.iter() {
-                self.machihai.insert(toitsu.0, 4);
+                self.insert_machihai(toitsu.to_string(), toitsu.0);
             }
 
             // Ukihai to taatsu or toitsu
@@ -1211,7 +3174,7 @@ impl MachiCondition {
                 }
 
                 // Ukihai to toitsu
-                self.machihai.insert(ukihai.0, 4);
+                self.insert_machihai(ukihai.to_string(), ukihai.0);
                 // Ukihai to taatsu
                 if decomposer.mentsu_vec.len() + decomposer.taatsu_vec.len()
                     < max_mentsu_toitsu_taatsu - 1
@@ -1222,15 +3185,15 @@ impl MachiCondition {
                     }
 
                     if let Some(machi) = ukihai.0.previous(player_number, false) {
-                        self.machihai.insert(machi, 4);
+                        self.insert_machihai(ukihai.to_string(), machi);
                         if let Some(machi_2) = machi.previous(player_number, false) {
-                            self.machihai.insert(machi_2, 4);
+                            self.insert_machihai(ukihai.to_string(), machi_2);
                         }
                     }
                     if let Some(machi) = ukihai.0.next(player_number, false) {
-                        self.machihai.insert(machi, 4);
+                        self.insert_machihai(ukihai.to_string(), machi);
                         if let Some(machi_2) = machi.next(player_number, false) {
-                            self.machihai.insert(machi_2, 4);
+                            self.insert_machihai(ukihai.to_string(), machi_2);
                         }
                     }
                 }
@@ -1249,7 +3212,7 @@ impl MachiCondition {
         if decomposer.toitsu_vec.len() + decomposer.valid_ukihai_vec.len() >= 7 {
             for Ukihai(hai) in &decomposer.valid_ukihai_vec {
                 if hai != &self.sutehai {
-                    self.machihai.insert(*hai, 4);
+                    self.insert_machihai(hai.to_string(), *hai);
                 }
             }
         }
@@ -1264,7 +3227,7 @@ impl MachiCondition {
 
             // The rest is wanted hai.
             for hai in all_hai {
-                self.machihai.insert(hai, 4);
+                self.insert_machihai("chiitoitsu".to_string(), hai);
             }
         }
 
@@ -1296,7 +3259,7 @@ impl MachiCondition {
         // If no yaochuupai pair, waiting for all yaochuupais.
         if !yaochuupai_pair {
             for yaochuupai in yaochuupai_iter {
-                self.machihai.insert(*yaochuupai, 4);
+                self.insert_machihai("kokushimusou".to_string(), *yaochuupai);
             }
             return Ok(self);
         }
@@ -1306,11 +3269,11 @@ impl MachiCondition {
         let mut kokushimusou_valid_value = kokushimusou_valid_iter.next();
         let mut yaochuupai_used = false;
 
-        while yaochuupai_value != None && kokushimusou_valid_value != None {
+        while yaochuupai_value.is_some() && kokushimusou_valid_value.is_some() {
             if let (Some(lhs), Some(Ukihai(rhs))) = (yaochuupai_value, kokushimusou_valid_value) {
                 if lhs < rhs {
                     if !yaochuupai_used {
-                        self.machihai.insert(*lhs, 4);
+                        self.insert_machihai("kokushimusou".to_string(), *lhs);
                     }
                     yaochuupai_used = false;
                     yaochuupai_value = yaochuupai_iter.next();
@@ -1324,37 +3287,57 @@ impl MachiCondition {
         }
         if !yaochuupai_pair {
             if let Some(yaochuupai) = yaochuupai_value {
-                self.machihai.insert(*yaochuupai, 4);
+                self.insert_machihai("kokushimusou".to_string(), *yaochuupai);
             }
         }
         for rest in yaochuupai_iter {
-            self.machihai.insert(*rest, 4);
+            self.insert_machihai("kokushimusou".to_string(), *rest);
         }
 
         Ok(self)
     }
 }
 
-impl std::fmt::Display for MachiCondition {
+impl DiscardEfficiency {
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = self.condition.to_json();
+        json["shanten_number"] = json!(self.shanten);
+        if let Some(tenpai_machihai) = self.tenpai_machihai {
+            json["tenpai_machihai_number"] = json!(tenpai_machihai);
+        }
+        json
+    }
+}
+
+impl std::fmt::Display for DiscardEfficiency {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shanten_string = if self.shanten == 0 {
+            "聴牌".to_string()
+        } else {
+            format!("向聴{}", self.shanten)
+        };
         let mut machihai_string = String::new();
-        let mut furiten_string = String::new();
-        let mut nokori = 0;
-        for (machihai, number) in self.machihai.iter() {
+        for machihai in self.condition.machihai.keys() {
             machihai_string += &machihai.to_string();
             machihai_string += " ";
-            nokori += *number as usize;
-        }
-        if self.furiten {
-            furiten_string = "!振り聴!".to_string();
         }
         write!(
             f,
-            "打 {} 摸 {} 残り{}枚{}",
-            self.sutehai.to_string(),
+            "打 {} {} 摸 {}残り{}枚",
+            self.condition.sutehai,
+            shanten_string,
             machihai_string,
-            nokori,
-            furiten_string
-        )
+            self.condition.nokori()
+        )?;
+        if let Some(tenpai_machihai) = self.tenpai_machihai {
+            write!(f, " 聴牌時最大{}枚", tenpai_machihai)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for MachiCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format_with(|hai| hai.to_string()))
     }
 }
@@ -0,0 +1,323 @@
+use super::{Hai, Hourakei, Mentsu, PlayerNumber, Suit, Tehai, Toitsu};
+use std::collections::BTreeSet;
+
+/// Conditions of agari which can not be known from tehai.
+///
+/// # Japanese
+/// * agari_hai: 和了牌
+/// * riichi: 立直
+/// * seat_wind: 自風
+/// * round_wind: 場風
+/// * dora_indicators: ドラ表示牌
+///
+/// # Member
+/// * agari_hai: the hai completing tehai, which must be in juntehai.
+/// * is_tsumo: tsumo if true, ron otherwise.
+/// * riichi: if riichi is declared.
+/// * seat_wind: `1z`\~`4z`.
+/// * round_wind: `1z`\~`4z`.
+/// * dora_indicators: not used for yaku, kept for scoring.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AgariContext {
+    pub agari_hai: Hai,
+    pub is_tsumo: bool,
+    pub riichi: bool,
+    pub seat_wind: Hai,
+    pub round_wind: Hai,
+    pub dora_indicators: Vec<Hai>,
+}
+
+/// Yaku of agari tehai.
+///
+/// # Japanese
+/// * Yaku: 役
+/// * Riichi: 立直
+/// * MenzenTsumo: 門前清自摸和
+/// * Pinfu: 平和
+/// * Tanyao: 断幺九
+/// * Iipeikou: 一盃口
+/// * Yakuhai: 役牌
+/// * Sanshoku: 三色同順
+/// * Ittsuu: 一気通貫
+/// * Chanta: 混全帯幺九
+/// * Junchan: 純全帯幺九
+/// * Toitoi: 対々和
+/// * Honitsu: 混一色
+/// * Chinitsu: 清一色
+/// * Chiitoitsu: 七対子
+/// * Kokushimusou: 国士無双
+/// * Suukantsu: 四槓子
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Yaku {
+    Riichi,
+    MenzenTsumo,
+    Pinfu,
+    Tanyao,
+    Iipeikou,
+    Yakuhai(Hai),
+    Sanshoku,
+    Ittsuu,
+    Chanta,
+    Junchan,
+    Toitoi,
+    Honitsu,
+    Chinitsu,
+    Chiitoitsu,
+    Kokushimusou,
+    Suukantsu,
+}
+
+impl Yaku {
+    /// Return han of yaku. Some yaku lose one han if tehai is not menzen (kuisagari).
+    ///
+    /// # Japanese
+    /// * han: 翻
+    /// * menzen: 門前
+    pub fn han(&self, menzen: bool) -> u32 {
+        let kuisagari = if menzen { 0 } else { 1 };
+        match self {
+            Yaku::Riichi
+            | Yaku::MenzenTsumo
+            | Yaku::Pinfu
+            | Yaku::Tanyao
+            | Yaku::Iipeikou
+            | Yaku::Yakuhai(_) => 1,
+            Yaku::Sanshoku | Yaku::Ittsuu | Yaku::Chanta => 2 - kuisagari,
+            Yaku::Junchan | Yaku::Honitsu => 3 - kuisagari,
+            Yaku::Toitoi | Yaku::Chiitoitsu => 2,
+            Yaku::Chinitsu => 6 - kuisagari,
+            Yaku::Kokushimusou | Yaku::Suukantsu => 13,
+        }
+    }
+}
+
+impl std::fmt::Display for Yaku {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Yaku::Riichi => write!(f, "立直"),
+            Yaku::MenzenTsumo => write!(f, "門前清自摸和"),
+            Yaku::Pinfu => write!(f, "平和"),
+            Yaku::Tanyao => write!(f, "断幺九"),
+            Yaku::Iipeikou => write!(f, "一盃口"),
+            Yaku::Yakuhai(hai) => write!(f, "役牌 {}", hai),
+            Yaku::Sanshoku => write!(f, "三色同順"),
+            Yaku::Ittsuu => write!(f, "一気通貫"),
+            Yaku::Chanta => write!(f, "混全帯幺九"),
+            Yaku::Junchan => write!(f, "純全帯幺九"),
+            Yaku::Toitoi => write!(f, "対々和"),
+            Yaku::Honitsu => write!(f, "混一色"),
+            Yaku::Chinitsu => write!(f, "清一色"),
+            Yaku::Chiitoitsu => write!(f, "七対子"),
+            Yaku::Kokushimusou => write!(f, "国士無双"),
+            Yaku::Suukantsu => write!(f, "四槓子"),
+        }
+    }
+}
+
+impl Tehai {
+    /// Detect yaku of agari tehai, choosing the decomposition with the most han.
+    /// Return an empty vec if tehai is not agari or the agari hai is not in juntehai.
+    ///
+    /// Tehai with ankan is still menzen, see `Tehai::is_menzen`.
+    pub fn detect_yaku(&self, context: &AgariContext, player_number: PlayerNumber) -> Vec<Yaku> {
+        let agari_hai = context.agari_hai.normalized();
+        if !self
+            .juntehai
+            .iter()
+            .any(|hai| hai.normalized() == agari_hai)
+        {
+            return vec![];
+        }
+        let decomposition_vec = match self.winning_decompositions(player_number) {
+            Ok(decomposition_vec) => decomposition_vec,
+            Err(_) => return vec![],
+        };
+
+        let menzen = self.is_menzen();
+        let mut all_hai: Vec<Hai> = self.juntehai.iter().map(Hai::normalized).collect();
+        for mentsu in &self.fuuro {
+            all_hai.extend(mentsu_hai(mentsu));
+        }
+        let yaochuupai_type = Hai::yaochuupai_type();
+        let yakuhai_type: BTreeSet<Hai> = [
+            Hai::Jihai(5),
+            Hai::Jihai(6),
+            Hai::Jihai(7),
+            context.seat_wind,
+            context.round_wind,
+        ]
+        .iter()
+        .copied()
+        .collect();
+
+        let mut best: Vec<Yaku> = vec![];
+        for decomposition in &decomposition_vec {
+            let mut yaku_vec = vec![];
+            if decomposition.hourakei == Hourakei::Kokushimusou {
+                yaku_vec.push(Yaku::Kokushimusou);
+            } else if self.is_suukantsu() {
+                // Yakuman, other yaku are not counted.
+                yaku_vec.push(Yaku::Suukantsu);
+            } else {
+                if context.riichi && menzen {
+                    yaku_vec.push(Yaku::Riichi);
+                }
+                if context.is_tsumo && menzen {
+                    yaku_vec.push(Yaku::MenzenTsumo);
+                }
+                if all_hai.iter().all(|hai| !yaochuupai_type.contains(hai)) {
+                    yaku_vec.push(Yaku::Tanyao);
+                }
+                match suits_of(&all_hai) {
+                    (1, false) => yaku_vec.push(Yaku::Chinitsu),
+                    (1, true) => yaku_vec.push(Yaku::Honitsu),
+                    _ => (),
+                }
+                if decomposition.hourakei == Hourakei::Chiitoitsu {
+                    yaku_vec.push(Yaku::Chiitoitsu);
+                } else {
+                    let toitsu = match decomposition.toitsu.first() {
+                        Some(Toitsu(hai)) => *hai,
+                        None => continue,
+                    };
+                    let mut mentsu_vec = decomposition.mentsu.clone();
+                    mentsu_vec.extend(self.fuuro.iter());
+                    mentsute_yaku(
+                        &mut yaku_vec,
+                        &decomposition.mentsu,
+                        &mentsu_vec,
+                        toitsu,
+                        agari_hai,
+                        menzen,
+                        context,
+                        &yakuhai_type,
+                    );
+                }
+            }
+            yaku_vec.sort();
+
+            let han = |yaku_vec: &Vec<Yaku>| -> u32 {
+                yaku_vec.iter().map(|yaku| yaku.han(menzen)).sum()
+            };
+            if han(&yaku_vec) > han(&best) {
+                best = yaku_vec;
+            }
+        }
+
+        best
+    }
+}
+
+/// Return all hai of a mentsu.
+fn mentsu_hai(mentsu: &Mentsu) -> Vec<Hai> {
+    match mentsu {
+        Mentsu::Juntsu(a, b, c) => vec![*a, *b, *c],
+        Mentsu::Koutsu(hai) => vec![*hai; 3],
+        Mentsu::Kantsu(hai) => vec![*hai; 4],
+    }
+}
+
+/// Return the number of suits of shuupai and if there is any jihai.
+fn suits_of(hai_vec: &[Hai]) -> (usize, bool) {
+    let suits: BTreeSet<Suit> = hai_vec.iter().map(Hai::suit).collect();
+    let jihai = suits.contains(&Suit::Ji);
+    (suits.len() - jihai as usize, jihai)
+}
+
+/// A part of function of detect_yaku. Detect yaku depending on mentsu and toitsu.
+///
+/// # Parameters
+/// * closed_mentsu: mentsu in juntehai.
+/// * mentsu_vec: mentsu in juntehai and fuuro.
+#[allow(clippy::too_many_arguments)]
+fn mentsute_yaku(
+    yaku_vec: &mut Vec<Yaku>,
+    closed_mentsu: &[Mentsu],
+    mentsu_vec: &[Mentsu],
+    toitsu: Hai,
+    agari_hai: Hai,
+    menzen: bool,
+    context: &AgariContext,
+    yakuhai_type: &BTreeSet<Hai>,
+) {
+    let juntsu_vec: Vec<(Hai, Hai, Hai)> = mentsu_vec
+        .iter()
+        .filter_map(|mentsu| match mentsu {
+            Mentsu::Juntsu(a, b, c) => Some((*a, *b, *c)),
+            _ => None,
+        })
+        .collect();
+
+    // Pinfu needs a ryanmen wait.
+    if menzen && juntsu_vec.len() == 4 && !yakuhai_type.contains(&toitsu) {
+        let ryanmen = closed_mentsu.iter().any(|mentsu| match mentsu {
+            Mentsu::Juntsu(a, _, c) => {
+                (*a == agari_hai && c.number() != 9) || (*c == agari_hai && a.number() != 1)
+            }
+            _ => false,
+        });
+        if ryanmen {
+            yaku_vec.push(Yaku::Pinfu);
+        }
+    }
+
+    if menzen {
+        let mut juntsu_set = BTreeSet::new();
+        if juntsu_vec.iter().any(|juntsu| !juntsu_set.insert(*juntsu)) {
+            yaku_vec.push(Yaku::Iipeikou);
+        }
+    }
+
+    for mentsu in mentsu_vec {
+        if let Mentsu::Koutsu(hai) | Mentsu::Kantsu(hai) = mentsu {
+            // Double wind counts twice.
+            for yakuhai in [Hai::Jihai(5), Hai::Jihai(6), Hai::Jihai(7)]
+                .iter()
+                .chain([context.seat_wind, context.round_wind].iter())
+            {
+                if yakuhai == hai {
+                    yaku_vec.push(Yaku::Yakuhai(*hai));
+                }
+            }
+        }
+    }
+
+    let has_juntsu = |numbers: (u8, u8, u8), suit: fn(u8) -> Hai| {
+        juntsu_vec.contains(&(suit(numbers.0), suit(numbers.1), suit(numbers.2)))
+    };
+    if juntsu_vec.iter().any(|(a, b, c)| {
+        let numbers = (a.number(), b.number(), c.number());
+        has_juntsu(numbers, Hai::Manzu)
+            && has_juntsu(numbers, Hai::Pinzu)
+            && has_juntsu(numbers, Hai::Souzu)
+    }) {
+        yaku_vec.push(Yaku::Sanshoku);
+    }
+    let suits: [fn(u8) -> Hai; 3] = [Hai::Manzu, Hai::Pinzu, Hai::Souzu];
+    if suits.iter().any(|suit| {
+        has_juntsu((1, 2, 3), *suit) && has_juntsu((4, 5, 6), *suit) && has_juntsu((7, 8, 9), *suit)
+    }) {
+        yaku_vec.push(Yaku::Ittsuu);
+    }
+
+    let yaochuupai_type = Hai::yaochuupai_type();
+    let with_yaochuu = mentsu_vec.iter().all(|mentsu| {
+        mentsu_hai(mentsu)
+            .iter()
+            .any(|hai| yaochuupai_type.contains(hai))
+    }) && yaochuupai_type.contains(&toitsu);
+    if with_yaochuu && !juntsu_vec.is_empty() {
+        let jihai = matches!(toitsu, Hai::Jihai(_))
+            || mentsu_vec.iter().any(|mentsu| {
+                matches!(
+                    mentsu,
+                    Mentsu::Koutsu(Hai::Jihai(_)) | Mentsu::Kantsu(Hai::Jihai(_))
+                )
+            });
+        yaku_vec.push(if jihai { Yaku::Chanta } else { Yaku::Junchan });
+    }
+
+    if juntsu_vec.is_empty() {
+        yaku_vec.push(Yaku::Toitoi);
+    }
+}
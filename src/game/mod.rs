@@ -1,9 +1,17 @@
+mod error;
 mod game_manager;
 mod mahjong;
+mod scoring;
 
+pub use error::MahjongError;
 pub use game_manager::{
-    GameManager, HaiyamaOperation, Kan, Naku, Operation, State, TehaiOperation,
+    GameManager, HaiyamaOperation, HistoryEntry, Kan, Naku, Operation, Ruleset, Safety, State,
+    TehaiOperation,
 };
+pub use mahjong::shanten;
 pub use mahjong::{
-    Hai, Haiyama, MachiCondition, Mentsu, PlayerNumber, Taatsu, Tehai, Toitsu, Ukihai,
+    AgariContext, Decomposition, DecompositionCount, DiscardEfficiency, Hai, Haiyama,
+    HandBreakdown, Hourakei, MachiCondition, Mentsu, MentsuError, PlayerNumber, Suit, Taatsu,
+    Tehai, Toitsu, Ukihai, WaitKind, Yaku,
 };
+pub use scoring::{score, Score, ScoringRules};
@@ -0,0 +1,110 @@
+use super::PlayerNumber;
+
+/// Rules about how points are paid.
+///
+/// # Japanese
+/// * noten_bappu: 不聴罰符
+/// * nagashi_mangan: 流し満貫
+///
+/// # Member
+/// * noten_bappu: total points paid by noten players to tenpai players at ryuukyoku.
+/// * nagashi_mangan: if nagashi mangan is adopted. When any player achieves it, they
+///   collect mangan as tsumo and noten bappu is not paid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ScoringRules {
+    pub noten_bappu: u32,
+    pub nagashi_mangan: bool,
+}
+
+impl ScoringRules {
+    /// Create common rules. Noten bappu is 3000 on 4-players mode and 2000 on 3-players mode.
+    pub fn new(player_number: PlayerNumber) -> Self {
+        Self {
+            noten_bappu: match player_number {
+                PlayerNumber::Four => 3000,
+                PlayerNumber::Three => 2000,
+            },
+            nagashi_mangan: true,
+        }
+    }
+}
+
+/// Points paid for a winning hand, see `score`.
+///
+/// # Member
+/// * base: basic points, `fu * 2^(han + 2)` limited to mangan, or the limit of higher hands.
+/// * ron: points paid by the discarder on ron, 0 on tsumo.
+/// * tsumo_non_dealer: points paid by each non-dealer on tsumo, 0 on ron.
+/// * tsumo_dealer: points paid by the dealer on tsumo, 0 on ron or when the dealer wins.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Score {
+    pub base: u32,
+    pub ron: u32,
+    pub tsumo_non_dealer: u32,
+    pub tsumo_dealer: u32,
+}
+
+impl Score {
+    /// Return total points received on 4-players mode.
+    pub fn total(&self) -> u32 {
+        if self.ron > 0 {
+            self.ron
+        } else if self.tsumo_dealer > 0 {
+            self.tsumo_dealer + self.tsumo_non_dealer * 2
+        } else {
+            self.tsumo_non_dealer * 3
+        }
+    }
+}
+
+/// Look up points of a winning hand by the standard table. Fu is rounded up to 10 except
+/// 25 of chiitoitsu. Han of 0 scores nothing.
+///
+/// Limits are mangan for 5 han or `base` over 2000, haneman for 6, baiman for 8,
+/// sanbaiman for 11, and counted yakuman for 13 or more. With `kiriage_mangan`, 4 han 30 fu
+/// and 3 han 60 fu are rounded up to mangan.
+///
+/// # Japanese
+/// * mangan: 満貫
+/// * haneman: 跳満
+/// * baiman: 倍満
+/// * sanbaiman: 三倍満
+/// * yakuman: 役満
+/// * kiriage mangan: 切り上げ満貫
+pub fn score(han: u32, fu: u32, is_dealer: bool, is_tsumo: bool, kiriage_mangan: bool) -> Score {
+    let fu = if fu == 25 { fu } else { fu.div_ceil(10) * 10 };
+    let base = match han {
+        0 => 0,
+        1..=4 => {
+            let base = (fu << (han + 2)).min(2000);
+            if kiriage_mangan && base == 1920 {
+                2000
+            } else {
+                base
+            }
+        }
+        5 => 2000,
+        6..=7 => 3000,
+        8..=10 => 4000,
+        11..=12 => 6000,
+        _ => 8000,
+    };
+
+    let round_up = |points: u32| points.div_ceil(100) * 100;
+    let mut score = Score {
+        base,
+        ron: 0,
+        tsumo_non_dealer: 0,
+        tsumo_dealer: 0,
+    };
+    match (is_tsumo, is_dealer) {
+        (false, true) => score.ron = round_up(base * 6),
+        (false, false) => score.ron = round_up(base * 4),
+        (true, true) => score.tsumo_non_dealer = round_up(base * 2),
+        (true, false) => {
+            score.tsumo_non_dealer = round_up(base);
+            score.tsumo_dealer = round_up(base * 2);
+        }
+    }
+    score
+}
@@ -4,14 +4,42 @@ use crate::game;
 pub enum Command {
     Interactive,
     Noninteractive,
+    Reset,
     OutputFormat(OutputFormat),
     PlayerNumber(game::PlayerNumber),
     TehaiInput(game::Tehai),
     GameOperation(game::Operation),
-    Back { haiyama_sensitive: bool },
+    Edit {
+        old: game::Hai,
+        new: game::Hai,
+    },
+    Macro {
+        name: String,
+        body: String,
+    },
+    Honitsu(Option<game::Tehai>),
+    ShapesPlus(Option<game::Tehai>),
+    Matrix(Option<game::Tehai>),
+    Lock(Vec<game::Hai>),
+    Unlock(Vec<game::Hai>),
+    Locks,
+    SkipRon,
+    Defense,
+    Unseen,
+    Dora {
+        indicators: Vec<game::Hai>,
+        discard_from_haiyama: bool,
+    },
+    Back {
+        haiyama_sensitive: bool,
+    },
+    Save(String),
+    Load(String),
     State,
     Display,
+    Shanten,
     History,
+    Export,
     Help,
     Exit,
 }
@@ -21,6 +49,7 @@ impl Command {
         match &*command {
             "ni" | "noninteractive" => Ok(Command::Noninteractive),
             "i" | "interactive" => Ok(Command::Interactive),
+            "reset" => Ok(Command::Reset),
             "q" | "quit" | "exit" => Ok(Command::Exit),
             "s" | "state" => Ok(Command::State),
             "b" | "back" => Ok(Command::Back {
@@ -30,12 +59,121 @@ impl Command {
                 haiyama_sensitive: false,
             }),
             "d" | "display" => Ok(Command::Display),
+            "shanten" => Ok(Command::Shanten),
             "log" | "history" => Ok(Command::History),
+            "export" => Ok(Command::Export),
             "h" | "help" => Ok(Command::Help),
             "3pl" | "3-player" => Ok(Command::PlayerNumber(game::PlayerNumber::Three)),
             "4pl" | "4-player" => Ok(Command::PlayerNumber(game::PlayerNumber::Four)),
             "std" | "standard" => Ok(Command::OutputFormat(OutputFormat::Standard)),
             "json" => Ok(Command::OutputFormat(OutputFormat::Json)),
+            "csv" => Ok(Command::OutputFormat(OutputFormat::Csv)),
+            "honitsu?" => Ok(Command::Honitsu(None)),
+            "shapes+" => Ok(Command::ShapesPlus(None)),
+            "matrix" => Ok(Command::Matrix(None)),
+            "locks" => Ok(Command::Locks),
+            "skip-ron" => Ok(Command::SkipRon),
+            "defense" => Ok(Command::Defense),
+            "unseen" => Ok(Command::Unseen),
+            "kita" => Ok(Command::GameOperation(game::Operation::Tehai(
+                game::TehaiOperation::Kita {
+                    haiyama_sensitive: true,
+                },
+            ))),
+            "kita!" => Ok(Command::GameOperation(game::Operation::Tehai(
+                game::TehaiOperation::Kita {
+                    haiyama_sensitive: false,
+                },
+            ))),
+            "unlock" => Ok(Command::Unlock(vec![])),
+            _ if command.starts_with("dora ") => Ok(Command::Dora {
+                indicators: game::Hai::from_string_unordered(&command[5..], player_number)?,
+                discard_from_haiyama: false,
+            }),
+            _ if command.starts_with("dora* ") => Ok(Command::Dora {
+                indicators: game::Hai::from_string_unordered(&command[6..], player_number)?,
+                discard_from_haiyama: true,
+            }),
+            _ if command.starts_with("save ") => Ok(Command::Save(command[5..].trim().to_string())),
+            _ if command.starts_with("load ") => Ok(Command::Load(command[5..].trim().to_string())),
+            _ if command.starts_with("set ") => {
+                let hai_vec = game::Hai::from_string_unordered(&command[4..], player_number)?;
+                if hai_vec.len() == 1 {
+                    Ok(Command::GameOperation(game::Operation::Tehai(
+                        game::TehaiOperation::SetAside(hai_vec[0]),
+                    )))
+                } else {
+                    Err("Can only set aside one hai when use 'set'.".to_string())
+                }
+            }
+            _ if command.starts_with("river ") => {
+                Ok(Command::GameOperation(game::Operation::River {
+                    hai_vec: game::Hai::from_string_unordered(&command[6..], player_number)?,
+                    haiyama_sensitive: true,
+                }))
+            }
+            _ if command.starts_with("river! ") => {
+                Ok(Command::GameOperation(game::Operation::River {
+                    hai_vec: game::Hai::from_string_unordered(&command[7..], player_number)?,
+                    haiyama_sensitive: false,
+                }))
+            }
+            _ if command.starts_with("lock ") => Ok(Command::Lock(
+                game::Hai::from_string_unordered(&command[5..], player_number)?,
+            )),
+            _ if command.starts_with("unlock ") => Ok(Command::Unlock(
+                game::Hai::from_string_unordered(&command[7..], player_number)?,
+            )),
+            _ if command.starts_with("shapes+ ") => Ok(Command::ShapesPlus(Some(
+                game::Tehai::new(&command[8..], player_number)?,
+            ))),
+            _ if command.starts_with("matrix ") => Ok(Command::Matrix(Some(game::Tehai::new(
+                &command[7..],
+                player_number,
+            )?))),
+            _ if command.starts_with("honitsu? ") => Ok(Command::Honitsu(Some(game::Tehai::new(
+                &command[9..],
+                player_number,
+            )?))),
+            _ if command.starts_with('{') => {
+                let value: serde_json::Value = serde_json::from_str(&command)
+                    .map_err(|error| format!("Invalid json input: {}.", error))?;
+                Ok(Command::TehaiInput(game::Tehai::from_json(
+                    &value,
+                    player_number,
+                )?))
+            }
+            _ if command.starts_with("edit ") => {
+                let hai_vec = game::Hai::from_string_unordered(&command[5..], player_number)?;
+                if hai_vec.len() == 2 {
+                    Ok(Command::Edit {
+                        old: hai_vec[0],
+                        new: hai_vec[1],
+                    })
+                } else {
+                    Err("Need exactly one old hai and one new hai when use 'edit'.".to_string())
+                }
+            }
+            _ if command.starts_with("macro ") => {
+                let (name, body) = command[6..]
+                    .split_once('=')
+                    .ok_or("Need '=' when use 'macro'.".to_string())?;
+                let (name, body) = (name.trim(), body.trim());
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|chr| chr.is_ascii_alphanumeric() || chr == '_')
+                {
+                    Err(format!("Invalid macro name '{}'.", name))
+                } else if body.is_empty() {
+                    Err("Macro body can not be empty.".to_string())
+                } else {
+                    Ok(Command::Macro {
+                        name: name.to_string(),
+                        body: body.to_string(),
+                    })
+                }
+            }
             _ => Command::parse_with_argument(command, player_number),
         }
     }
@@ -50,11 +188,7 @@ impl Command {
             return Err(format!("Unresolved command: {}.", command));
         }
 
-        let haiyama_sensitive = if let '!' = bytes[1] as char {
-            false
-        } else {
-            true
-        };
+        let haiyama_sensitive = bytes[1] as char != '!';
 
         match bytes[0] as char {
             '+' => {
@@ -112,7 +246,7 @@ impl Command {
                             haiyama_sensitive,
                         }))
                     }
-                    _ => return Err(format!("Unresolved command: {}.", command)),
+                    _ => Err(format!("Unresolved command: {}.", command)),
                 }
             }
             '>' => {
@@ -120,79 +254,85 @@ impl Command {
                 if !haiyama_sensitive {
                     command.remove(0);
                 }
+                // Force the type of kan instead of inferring it.
+                let forced = match command.chars().next() {
+                    Some(chr @ ('d' | 'a' | 'k')) => {
+                        command.remove(0);
+                        Some(chr)
+                    }
+                    _ => None,
+                };
+                let make_kan = |kantsu, rinshanhai| match forced {
+                    Some('d') => game::Kan::Daiminkan { kantsu, rinshanhai },
+                    Some('a') => game::Kan::Ankan { kantsu, rinshanhai },
+                    Some('k') => game::Kan::Kakan { kantsu, rinshanhai },
+                    _ => game::Kan::Unknown { kantsu, rinshanhai },
+                };
                 let mut hai_vec = game::Hai::from_string_unordered(&command, player_number)?;
                 match hai_vec.len() {
-                    3 => {
-                        let mentsu = game::Mentsu::new(&hai_vec, player_number);
-                        if let Some(mentsu) = mentsu {
-                            match mentsu {
-                                game::Mentsu::Juntsu(..) => Ok(Command::GameOperation(
-                                    game::Operation::Tehai(game::TehaiOperation::Naku {
-                                        kind: game::Naku::Chii {
-                                            juntsu: mentsu,
-                                            nakihai: hai_vec[2],
-                                        },
-                                        haiyama_sensitive,
-                                    }),
-                                )),
-                                game::Mentsu::Koutsu(..) => Ok(Command::GameOperation(
-                                    game::Operation::Tehai(game::TehaiOperation::Naku {
-                                        kind: game::Naku::Pon(mentsu),
-                                        haiyama_sensitive,
-                                    }),
-                                )),
-                                _ => Err("Logic error: Code never reach here.".to_string()),
-                            }
-                        } else {
-                            Err(format!("'{}' is not a valid mentsu.", command))
-                        }
+                    3 if forced.is_some() => {
+                        Err("Only kan can be forced as daiminkan, ankan or kakan.".to_string())
                     }
-                    4 => {
-                        if hai_vec[0] == hai_vec[1]
-                            && hai_vec[0] == hai_vec[2]
-                            && hai_vec[0] == hai_vec[3]
-                        {
-                            Ok(Command::GameOperation(game::Operation::Tehai(
-                                game::TehaiOperation::Naku {
-                                    kind: game::Naku::Kan(game::Kan::Unknown {
-                                        kantsu: game::Mentsu::Kantsu(hai_vec[0]),
-                                        rinshanhai: None,
-                                    }),
-                                    haiyama_sensitive,
+                    3 => match game::Mentsu::try_new(&hai_vec, player_number).map_err(|error| {
+                        format!("'{}' is not a valid mentsu. {}", command, error)
+                    })? {
+                        mentsu @ game::Mentsu::Juntsu(..) => Ok(Command::GameOperation(
+                            game::Operation::Tehai(game::TehaiOperation::Naku {
+                                kind: game::Naku::Chii {
+                                    juntsu: mentsu,
+                                    nakihai: hai_vec[2],
                                 },
-                            )))
-                        } else {
-                            Err(format!("'{}' is not a valid mentsu.", command))
-                        }
+                                haiyama_sensitive,
+                                taken: vec![],
+                            }),
+                        )),
+                        mentsu @ game::Mentsu::Koutsu(..) => Ok(Command::GameOperation(
+                            game::Operation::Tehai(game::TehaiOperation::Naku {
+                                kind: game::Naku::Pon(mentsu),
+                                haiyama_sensitive,
+                                taken: vec![],
+                            }),
+                        )),
+                        _ => Err("Logic error: Code never reach here.".to_string()),
+                    },
+                    4 => {
+                        let kantsu =
+                            game::Mentsu::try_new(&hai_vec, player_number).map_err(|error| {
+                                format!("'{}' is not a valid mentsu. {}", command, error)
+                            })?;
+                        Ok(Command::GameOperation(game::Operation::Tehai(
+                            game::TehaiOperation::Naku {
+                                kind: game::Naku::Kan(make_kan(kantsu, None)),
+                                haiyama_sensitive,
+                                taken: vec![],
+                            },
+                        )))
                     }
                     5 => {
-                        hai_vec.sort();
-                        let (kantsuhai, rinshanhai) = if hai_vec[0] == hai_vec[1]
-                            && hai_vec[0] == hai_vec[2]
-                            && hai_vec[0] == hai_vec[3]
-                            && hai_vec[0] != hai_vec[4]
-                        {
-                            (hai_vec[0], hai_vec[4])
-                        } else if hai_vec[4] == hai_vec[1]
-                            && hai_vec[4] == hai_vec[2]
-                            && hai_vec[4] == hai_vec[3]
-                            && hai_vec[4] != hai_vec[0]
-                        {
-                            (hai_vec[4], hai_vec[0])
-                        } else {
-                            return Err(format!("'{}' is not a valid mentsu.", command));
-                        };
+                        // Aka is regarded as five for kantsu, but kept as rinshanhai.
+                        hai_vec.sort_by_key(game::Hai::sort_key);
+                        let same =
+                            |a: usize, b: usize| hai_vec[a].normalized() == hai_vec[b].normalized();
+                        let (kantsuhai, rinshanhai) =
+                            if same(0, 1) && same(0, 2) && same(0, 3) && !same(0, 4) {
+                                (hai_vec[0].normalized(), hai_vec[4])
+                            } else if same(4, 1) && same(4, 2) && same(4, 3) && !same(4, 0) {
+                                (hai_vec[4].normalized(), hai_vec[0])
+                            } else {
+                                return Err(format!("'{}' is not a valid mentsu.", command));
+                            };
                         Ok(Command::GameOperation(game::Operation::Tehai(
                             game::TehaiOperation::Naku {
-                                kind: game::Naku::Kan(game::Kan::Unknown {
-                                    kantsu: game::Mentsu::Kantsu(kantsuhai),
-                                    rinshanhai: Some(rinshanhai),
-                                }),
+                                kind: game::Naku::Kan(make_kan(
+                                    game::Mentsu::Kantsu(kantsuhai),
+                                    Some(rinshanhai),
+                                )),
                                 haiyama_sensitive,
+                                taken: vec![],
                             },
                         )))
                     }
-                    _ => return Err(format!("Unresolved command: {}.", command)),
+                    _ => Err(format!("Unresolved command: {}.", command)),
                 }
             }
             _ => Ok(Command::TehaiInput(game::Tehai::new(
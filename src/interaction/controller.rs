@@ -1,18 +1,81 @@
 use super::Command;
 use crate::game;
 use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug)]
 pub struct Controller {
     game_manager: Option<game::GameManager>,
     player_number: game::PlayerNumber,
     output_format: OutputFormat,
+    show_shapes: bool,
+    show_dead: bool,
+    compact_json: bool,
+    json_pretty: bool,
+    diagnostics: bool,
+    timeout: Option<Duration>,
+    kuikae_allowed: bool,
+    unicode: bool,
+    sort_key: SortKey,
+    closed_only: bool,
+    tenpai_only: bool,
+    locks: BTreeMap<game::Hai, u8>,
+    macros: BTreeMap<String, String>,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub enum OutputFormat {
     Standard,
     Json,
+    Csv,
+}
+
+/// Order of conditions when printing machihai. Ties keep the order of analysis.
+///
+/// # Member
+/// * Ukeire: by the number of machihai left descending, the order of analysis.
+/// * Sutehai: by sutehai.
+/// * WaitQuality: by the shape of waiting for tenpai, multi and ryanmen first, then by
+///   the number of machihai left. Not tenpai conditions keep the order of analysis.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Ukeire,
+    Sutehai,
+    WaitQuality,
+}
+
+/// Output of a command. Json is kept as a value until printed, so that it is serialized
+/// only once. Format it with `{:#}` to get indented json.
+#[derive(Clone, Debug, PartialEq)]
+enum Output {
+    Text(String),
+    Json(serde_json::Value),
+}
+
+impl std::fmt::Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Output::Text(text) => write!(f, "{}", text),
+            Output::Json(json) if f.alternate() => write!(f, "{:#}", json),
+            Output::Json(json) => write!(f, "{}", json),
+        }
+    }
+}
+
+/// Options of printing machihai, taken from the controller.
+#[derive(Copy, Clone, Debug)]
+struct MachiPrintOptions<'a> {
+    format: OutputFormat,
+    show_shapes: bool,
+    show_dead: bool,
+    compact_json: bool,
+    diagnostics: bool,
+    unicode: bool,
+    sort_key: SortKey,
+    player_number: game::PlayerNumber,
+    locks: &'a BTreeMap<game::Hai, u8>,
 }
 
 impl Controller {
@@ -29,16 +92,210 @@ impl Controller {
             },
             player_number,
             output_format,
+            show_shapes: false,
+            show_dead: false,
+            compact_json: false,
+            json_pretty: false,
+            diagnostics: false,
+            timeout: None,
+            kuikae_allowed: false,
+            unicode: false,
+            sort_key: SortKey::Ukeire,
+            closed_only: false,
+            tenpai_only: false,
+            macros: BTreeMap::new(),
+            locks: BTreeMap::new(),
         }
     }
 
+    /// Attach a representative decomposition to each condition when printing machihai.
+    pub fn with_show_shapes(mut self, show_shapes: bool) -> Self {
+        self.show_shapes = show_shapes;
+        self
+    }
+
+    /// Keep machihai with no hai left when printing machihai, marked as dead.
+    pub fn with_show_dead(mut self, show_dead: bool) -> Self {
+        self.show_dead = show_dead;
+        self
+    }
+
+    /// Print maps like haiyama and machihai as single json objects instead of vecs of
+    /// single-key objects.
+    pub fn with_compact_json(mut self, compact_json: bool) -> Self {
+        self.compact_json = compact_json;
+        self
+    }
+
+    /// Print json with indents and newlines for reading. Default output is minified.
+    pub fn with_json_pretty(mut self, json_pretty: bool) -> Self {
+        self.json_pretty = json_pretty;
+        self
+    }
+
+    /// Print how many decompositions are considered by each analysis.
+    pub fn with_diagnostics(mut self, diagnostics: bool) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Limit the time of each analysis. Analysis exceeding it fails with a `TIMEOUT` error.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Allow discarding hai forbidden by kuikae after chii and pon.
+    pub fn with_kuikae_allowed(mut self, kuikae_allowed: bool) -> Self {
+        self.kuikae_allowed = kuikae_allowed;
+        self.game_manager = self
+            .game_manager
+            .map(|game_manager| game_manager.with_kuikae_allowed(kuikae_allowed));
+        self
+    }
+
+    /// Print tehai and machihai with Unicode mahjong tiles on standard output.
+    pub fn with_unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    /// Set the order of conditions when printing machihai. The vec from analysis is not
+    /// changed, so the default `SortKey::Ukeire` keeps it as is.
+    pub fn with_sort_key(mut self, sort_key: SortKey) -> Self {
+        self.sort_key = sort_key;
+        self
+    }
+
+    /// Refuse to analyze tehai with fuuro, for drills of closed tehai.
+    pub fn with_closed_only(mut self, closed_only: bool) -> Self {
+        self.closed_only = closed_only;
+        self
+    }
+
+    /// Skip tehai which are not tenpai in batch mode, without output. See `Tehai::is_tenpai`.
+    pub fn with_tenpai_only(mut self, tenpai_only: bool) -> Self {
+        self.tenpai_only = tenpai_only;
+        self
+    }
+
     pub fn output_format(&self) -> OutputFormat {
         self.output_format
     }
 
     pub fn execute(&mut self, command: String, exit: &mut bool) {
-        let result = self.execute_core(command, exit);
+        let command_vec = match self.expand_macro(command) {
+            Ok(command_vec) => command_vec,
+            Err(error) => {
+                self.print_result(Err(error), exit);
+                return;
+            }
+        };
+
+        for command in command_vec {
+            let result = self.execute_core(command, exit);
+            self.print_result(result, exit);
+            if *exit {
+                break;
+            }
+        }
+    }
+
+    /// Execute each line as a single command and return all output at once, for batch mode.
+    /// Empty lines are skipped, so are tehai not tenpai with `with_tenpai_only`. An error
+    /// only fails its own line, reported with the line number starting from 1. At json mode,
+    /// output is a json array with an element for each line having output, and errors are
+    /// `{"line":3,"error":"..."}`.
+    pub fn execute_batch<'a, T>(&mut self, lines: T) -> String
+    where
+        T: IntoIterator<Item = &'a str>,
+    {
+        let mut exit = false;
+        let mut output_vec = vec![];
+        let mut json_vec = vec![];
+        for (index, line) in lines.into_iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if self.tenpai_only && self.game_manager.is_none() {
+                if let Ok(Command::TehaiInput(tehai)) =
+                    Command::parse(line.to_string(), self.player_number)
+                {
+                    if !tehai.is_tenpai(self.player_number) {
+                        continue;
+                    }
+                }
+            }
+            let result = self.execute_core(line.to_string(), &mut exit);
+            match (self.output_format, result) {
+                (OutputFormat::Json, Ok(Some(Output::Json(json)))) => json_vec.push(json),
+                (OutputFormat::Json, Ok(Some(Output::Text(text)))) => {
+                    json_vec.push(serde_json::Value::String(text))
+                }
+                (OutputFormat::Json, Err(error)) => {
+                    json_vec.push(json!({ "line": index + 1, "error": error }))
+                }
+                (OutputFormat::Standard, Err(error)) => {
+                    output_vec.push(format!("{}行目：{}", index + 1, error))
+                }
+                (OutputFormat::Csv, Err(error)) => {
+                    output_vec.push(format!("# error,line {}: {}", index + 1, error))
+                }
+                (_, Ok(Some(output))) => output_vec.push(output.to_string()),
+                (_, Ok(None)) => (),
+            }
+            if exit {
+                break;
+            }
+        }
 
+        match self.output_format {
+            OutputFormat::Json if self.json_pretty => format!("{:#}", json!(json_vec)),
+            OutputFormat::Json => json!(json_vec).to_string(),
+            _ => output_vec.join("\n"),
+        }
+    }
+
+    /// Expand `@name` to the commands of macro `name`, which are separated by `;`.
+    /// Macros used in a macro are expanded too. Other commands are returned as is.
+    pub fn expand_macro(&self, command: String) -> Result<Vec<String>, String> {
+        fn expand(
+            macros: &BTreeMap<String, String>,
+            command: String,
+            expanding: &mut Vec<String>,
+            output: &mut Vec<String>,
+        ) -> Result<(), String> {
+            let name = match command.strip_prefix('@') {
+                Some(name) => name.trim().to_string(),
+                None => {
+                    output.push(command);
+                    return Ok(());
+                }
+            };
+            if expanding.contains(&name) {
+                return Err(format!("Recursive macro '{}'.", name));
+            }
+            let body = macros
+                .get(&name)
+                .ok_or(format!("Unknown macro '{}'.", name))?;
+            expanding.push(name);
+            for command in body.split(';') {
+                let command = command.trim();
+                if !command.is_empty() {
+                    expand(macros, command.to_string(), expanding, output)?;
+                }
+            }
+            expanding.pop();
+            Ok(())
+        }
+
+        let mut output = vec![];
+        expand(&self.macros, command, &mut vec![], &mut output)?;
+        Ok(output)
+    }
+
+    fn print_result(&self, result: Result<Option<Output>, String>, exit: &bool) {
         match result {
             Ok(Some(output)) => {
                 if !*exit {
@@ -53,7 +310,11 @@ impl Controller {
                         )
                     }
                 }
-                println!("{}", output);
+                if self.json_pretty {
+                    println!("{:#}", output);
+                } else {
+                    println!("{}", output);
+                }
             }
             Err(error) => match self.output_format {
                 OutputFormat::Standard => {
@@ -67,64 +328,372 @@ impl Controller {
                     );
                     println!("{}", error);
                 }
-                OutputFormat::Json => println!("{}", json!({ "error": error })),
+                OutputFormat::Csv => println!("# error,{}", error),
+                OutputFormat::Json => {
+                    let json = json!({ "error": error });
+                    if self.json_pretty {
+                        println!("{:#}", json);
+                    } else {
+                        println!("{}", json);
+                    }
+                }
             },
             _ => (),
         }
     }
 
-    fn execute_core(&mut self, command: String, exit: &mut bool) -> Result<Option<String>, String> {
+    /// Print locked hai.
+    fn locks_output(&self) -> Output {
+        let mut hai_vec = vec![];
+        for (hai, number) in &self.locks {
+            for _ in 0..*number {
+                hai_vec.push(hai.to_string());
+            }
+        }
+        match self.output_format {
+            OutputFormat::Standard | OutputFormat::Csv => {
+                Output::Text(format!("固定：{}", hai_vec.join(" ")))
+            }
+            OutputFormat::Json => Output::Json(json!({ "locks": hai_vec })),
+        }
+    }
+
+    /// Return the tehai given along with a command, or the current tehai at interactive mode.
+    fn given_or_current_tehai(
+        &self,
+        tehai: Option<game::Tehai>,
+        command_name: &str,
+    ) -> Result<game::Tehai, String> {
+        match (tehai, &self.game_manager) {
+            (Some(tehai), _) => Ok(tehai),
+            (None, Some(game_manager)) => Ok(game_manager
+                .tehai()
+                .ok_or("Not initialized.".to_string())?
+                .clone()),
+            (None, None) => Err(format!(
+                "Need tehai when use '{}' at non-interactive mode.",
+                command_name
+            )),
+        }
+    }
+
+    /// Execute a single command and return its output instead of printing it.
+    /// Macros are not expanded.
+    fn execute_core(&mut self, command: String, exit: &mut bool) -> Result<Option<Output>, String> {
+        fn check_closed_only(closed_only: bool, fuuro: &[game::Mentsu]) -> Result<(), String> {
+            if closed_only && !fuuro.is_empty() {
+                let fuuro_vec: Vec<String> =
+                    fuuro.iter().map(|mentsu| mentsu.to_string()).collect();
+                return Err(format!(
+                    "Fuuro {} not allowed in closed-only mode.",
+                    fuuro_vec.join("")
+                ));
+            }
+            Ok(())
+        }
+
+        /// Every analysis goes here, so tehai with fuuro is refused in closed-only mode.
+        /// Numbers of decompositions are also returned for diagnostics.
+        fn analyze(
+            closed_only: bool,
+            tehai: &game::Tehai,
+            player_number: game::PlayerNumber,
+            game_manager: Option<&game::GameManager>,
+            deadline: Option<Instant>,
+        ) -> Result<(i32, Vec<game::MachiCondition>, game::DecompositionCount), String> {
+            check_closed_only(closed_only, &tehai.fuuro)?;
+            tehai.analyze_with_decomposition_count(player_number, game_manager, deadline)
+        }
+
         fn print_machi(
             tehai: &game::Tehai,
             shanten: i32,
             conditions: Vec<game::MachiCondition>,
-            format: OutputFormat,
-        ) -> String {
-            match format {
+            decomposition_count: game::DecompositionCount,
+            options: MachiPrintOptions,
+            agari_hai: Option<game::Hai>,
+        ) -> Output {
+            // Show the first winning decomposition, mentsute preferred.
+            let winning_decomposition = if shanten == -1 {
+                tehai
+                    .winning_decompositions(options.player_number)
+                    .ok()
+                    .and_then(|decomposition_vec| decomposition_vec.into_iter().next())
+            } else {
+                None
+            };
+
+            // Never suggest discarding locked hai.
+            let mut conditions: Vec<game::MachiCondition> = conditions
+                .into_iter()
+                .filter(|condition| match options.locks.get(&condition.sutehai) {
+                    Some(locked) => {
+                        tehai
+                            .juntehai
+                            .iter()
+                            .filter(|hai| **hai == condition.sutehai)
+                            .count()
+                            > *locked as usize
+                    }
+                    None => true,
+                })
+                .collect();
+
+            // Hint when close to chuurenpoutou.
+            let chuuren_hint = tehai
+                .chuuren_shanten(options.player_number)
+                .filter(|(_, shanten)| *shanten <= 2);
+
+            // Distinct types and total number of machihai of the best wait when tenpai.
+            let wait_summary = if shanten == 0 {
+                conditions
+                    .first()
+                    .map(|condition| (condition.machihai.len(), condition.nokori()))
+            } else {
+                None
+            };
+
+            // Conditions come sorted by ukeire, and sorting is stable.
+            match options.sort_key {
+                SortKey::Ukeire => (),
+                SortKey::Sutehai => conditions.sort_by_key(|condition| condition.sutehai),
+                SortKey::WaitQuality => {
+                    conditions.sort_by_key(|condition| match condition.wait_kind() {
+                        Some(game::WaitKind::Multi) => 0,
+                        Some(game::WaitKind::Ryanmen) => 1,
+                        Some(game::WaitKind::Nobetan) => 2,
+                        Some(game::WaitKind::Shanpon) => 3,
+                        Some(game::WaitKind::Kanchan | game::WaitKind::Penchan) => 4,
+                        Some(game::WaitKind::Tanki) => 5,
+                        None => 6,
+                    })
+                }
+            }
+
+            // Number of decompositions with minimum shanten and of all explored.
+            let decomposition_count = options.diagnostics.then_some(decomposition_count);
+
+            Output::Text(match options.format {
                 OutputFormat::Standard => format!(
-                    "手牌：{}\n{}",
-                    tehai,
+                    "手牌：{}\n{}{}{}",
+                    if options.unicode {
+                        tehai.to_unicode_string()
+                    } else {
+                        tehai.to_string()
+                    },
+                    match chuuren_hint {
+                        Some((_, -1)) => "九蓮宝燈の形\n".to_string(),
+                        Some((_, 0)) => "九蓮宝燈まで：聴牌\n".to_string(),
+                        Some((_, shanten)) => format!("九蓮宝燈まで：{}向聴\n", shanten),
+                        None => String::new(),
+                    },
                     if shanten == -1 {
-                        format!("和了")
+                        let mut agari_string = "和了".to_string();
+                        if let Some(decomposition) = winning_decomposition {
+                            agari_string += &format!("\n形：{}", decomposition);
+                            if !tehai.fuuro.is_empty() {
+                                agari_string += " |";
+                                for mentsu in &tehai.fuuro {
+                                    agari_string += &format!(" {}", mentsu);
+                                }
+                            }
+                            if let Some(agari_hai) = agari_hai {
+                                agari_string += &format!(" + {}", agari_hai);
+                            }
+                        }
+                        agari_string
                     } else {
                         let mut conditions_string = String::new();
                         for i in conditions {
-                            conditions_string += &format!("\n{}", i);
+                            if options.unicode {
+                                conditions_string += &format!("\n{}", i.to_unicode_string());
+                            } else {
+                                conditions_string += &format!("\n{}", i);
+                            }
+                            if options.show_shapes {
+                                if let Some(decomposition) = &i.decomposition {
+                                    conditions_string += &format!("\n    形：{}", decomposition);
+                                }
+                            }
+                            if options.show_dead && !i.dead_machihai.is_empty() {
+                                let dead_vec: Vec<String> =
+                                    i.dead_machihai.iter().map(|hai| hai.to_string()).collect();
+                                conditions_string += &format!("\n    枯れ：{}", dead_vec.join(" "));
+                            }
                         }
                         format!(
                             "{}\n--------{}",
                             if shanten == 0 {
-                                format!("聴牌")
+                                match wait_summary {
+                                    Some((types, tiles)) => {
+                                        format!("聴牌\n待ち：{}種 {}枚", types, tiles)
+                                    }
+                                    None => "聴牌".to_string(),
+                                }
                             } else {
                                 format!("向聴：{}", shanten)
                             },
                             conditions_string
                         )
+                    },
+                    match decomposition_count {
+                        Some((minimal, explored)) => {
+                            format!("\n分解：最小{} 探索{}", minimal, explored)
+                        }
+                        None => String::new(),
                     }
                 ),
+                OutputFormat::Csv => {
+                    let mut csv = format!("# shanten,{}\nsutehai,machihai,count,furiten", shanten);
+                    for condition in conditions {
+                        let machihai_vec: Vec<String> = condition
+                            .machihai
+                            .keys()
+                            .map(|hai| hai.to_string())
+                            .collect();
+                        csv += &format!(
+                            "\n{},{},{},{}",
+                            condition.sutehai,
+                            machihai_vec.join(" "),
+                            condition.nokori(),
+                            condition.furiten
+                        );
+                    }
+                    csv
+                }
                 OutputFormat::Json => {
                     let mut condition_json_vec = vec![];
                     for i in conditions {
-                        condition_json_vec.push(i.to_json());
+                        let mut condition_json = if options.compact_json {
+                            i.to_compact_json()
+                        } else {
+                            i.to_json()
+                        };
+                        if options.show_shapes {
+                            condition_json["decomposition"] = match &i.decomposition {
+                                Some(decomposition) => decomposition.to_json(),
+                                None => serde_json::Value::Null,
+                            };
+                            condition_json["by_shape"] = i.to_verbose_json()["by_shape"].take();
+                        }
+                        if options.show_dead {
+                            // Dead machihai are listed with number 0.
+                            let mut machihai = i.machihai.clone();
+                            for hai in &i.dead_machihai {
+                                machihai.insert(*hai, 0);
+                            }
+                            condition_json["machihai"] = if options.compact_json {
+                                machihai
+                                    .iter()
+                                    .map(|(hai, number)| (hai.to_string(), json!(number)))
+                                    .collect::<serde_json::Map<_, _>>()
+                                    .into()
+                            } else {
+                                machihai
+                                    .iter()
+                                    .map(|(hai, number)| {
+                                        json!({ "tile": hai.to_string(), "number": number })
+                                    })
+                                    .collect()
+                            };
+                        }
+                        condition_json_vec.push(condition_json);
                     }
-                    json!({
+                    let mut json = json!({
                         "tehai": tehai.to_json(),
                         "shanten_number": shanten,
                         "conditions": condition_json_vec
-                    })
-                    .to_string()
+                    });
+                    if let Some((suit, shanten)) = chuuren_hint {
+                        json["chuuren_hint"] =
+                            json!({ "suit": suit.to_string(), "shanten_number": shanten });
+                    }
+                    if let Some((minimal, explored)) = decomposition_count {
+                        json["decompositions"] =
+                            json!({ "minimal": minimal, "explored": explored });
+                    }
+                    if let Some((types, tiles)) = wait_summary {
+                        json["wait_summary"] = json!({ "types": types, "tiles": tiles });
+                    }
+                    if let Some(decomposition) = winning_decomposition {
+                        json["agari"] = json!({
+                            "decomposition": decomposition.to_json(),
+                            "agari_hai": agari_hai.map(|hai| hai.to_string()),
+                        });
+                    }
+                    return Output::Json(json);
+                }
+            })
+        }
+
+        /// Put warnings of a game operation before the output, or into it as `"warnings"`
+        /// for json.
+        fn print_warnings(
+            output: Option<Output>,
+            warnings: &[String],
+            format: OutputFormat,
+        ) -> Option<Output> {
+            if warnings.is_empty() {
+                return output;
+            }
+            match format {
+                OutputFormat::Standard | OutputFormat::Csv => {
+                    let mut string = String::new();
+                    for warning in warnings {
+                        string += &match format {
+                            OutputFormat::Csv => format!("# warning,{}\n", warning),
+                            _ => format!("警告：{}\n", warning),
+                        };
+                    }
+                    match output {
+                        Some(output) => Some(Output::Text(string + &output.to_string())),
+                        None => Some(Output::Text(string.trim_end().to_string())),
+                    }
+                }
+                OutputFormat::Json => {
+                    let mut json = match output {
+                        Some(Output::Json(json)) => json,
+                        _ => json!({}),
+                    };
+                    json["warnings"] = json!(warnings);
+                    Some(Output::Json(json))
                 }
             }
         }
 
         *exit = false;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
         let command = Command::parse(command, self.player_number)?;
+        let options = MachiPrintOptions {
+            format: self.output_format,
+            show_shapes: self.show_shapes,
+            show_dead: self.show_dead,
+            compact_json: self.compact_json,
+            diagnostics: self.diagnostics,
+            unicode: self.unicode,
+            sort_key: self.sort_key,
+            player_number: self.player_number,
+            locks: &self.locks,
+        };
         match command {
             Command::Exit => *exit = true,
             Command::Noninteractive => self.game_manager = None,
             Command::Interactive => {
-                self.game_manager = Some(game::GameManager::new(self.player_number))
+                self.game_manager = Some(
+                    game::GameManager::new(self.player_number)
+                        .with_kuikae_allowed(self.kuikae_allowed),
+                )
             }
+            Command::Reset => match &mut self.game_manager {
+                Some(game_manager) => {
+                    game_manager.reinitialize(self.player_number);
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
             Command::OutputFormat(output_format) => self.output_format = output_format,
             Command::PlayerNumber(player_number) => {
                 self.player_number = player_number;
@@ -134,17 +703,379 @@ impl Controller {
             }
             Command::GameOperation(op) => match &mut self.game_manager {
                 Some(game_manager) => {
+                    // Checked before operating, so the naku is not applied.
+                    if let Some(fuuro) = game_manager.fuuro_of(&op) {
+                        check_closed_only(self.closed_only, &[fuuro])?;
+                    }
                     game_manager.operate(op)?;
-                    if let game::State::FullHai = game_manager.state {
+                    let output = if let game::State::FullHai = game_manager.state {
                         let tehai = game_manager.tehai().ok_or("Not initialized.".to_string())?;
-                        let (shanten, conditions) = game_manager.tehai_analyze()?;
+                        let (shanten, conditions, decomposition_count) = analyze(
+                            self.closed_only,
+                            tehai,
+                            game_manager.player_number(),
+                            Some(game_manager),
+                            deadline,
+                        )?;
+                        Some(print_machi(
+                            tehai,
+                            shanten,
+                            conditions,
+                            decomposition_count,
+                            options,
+                            game_manager.last_drawn_hai(),
+                        ))
+                    } else {
+                        None
+                    };
+                    return Ok(print_warnings(
+                        output,
+                        game_manager.warnings(),
+                        self.output_format,
+                    ));
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
+            Command::Macro { name, body } => {
+                self.macros.insert(name, body);
+            }
+            Command::ShapesPlus(tehai) => {
+                let tehai = self.given_or_current_tehai(tehai, "shapes+")?;
+                let (shanten, conditions, _) =
+                    analyze(self.closed_only, &tehai, self.player_number, None, deadline)?;
+                let shapes: Vec<_> = conditions
+                    .iter()
+                    .filter_map(|condition| {
+                        condition.decomposition.as_ref().map(|decomposition| {
+                            (
+                                condition.sutehai,
+                                decomposition,
+                                decomposition.improvement_tiles(self.player_number),
+                            )
+                        })
+                    })
+                    .collect();
+
+                return Ok(Some(match self.output_format {
+                    OutputFormat::Standard | OutputFormat::Csv => {
+                        let mut string = format!(
+                            "手牌：{}\n{}\n--------",
+                            tehai,
+                            match shanten {
+                                -1 => "和了".to_string(),
+                                0 => "聴牌".to_string(),
+                                _ => format!("向聴：{}", shanten),
+                            }
+                        );
+                        for (sutehai, decomposition, tiles) in &shapes {
+                            let tile_vec: Vec<String> =
+                                tiles.iter().map(|hai| hai.to_string()).collect();
+                            string += &format!(
+                                "\n打 {}\n    形：{}\n    改良：{}",
+                                sutehai,
+                                decomposition,
+                                tile_vec.join(" ")
+                            );
+                        }
+                        Output::Text(string)
+                    }
+                    OutputFormat::Json => Output::Json(json!({
+                        "tehai": tehai.to_json(),
+                        "shanten_number": shanten,
+                        "shapes": shapes
+                            .iter()
+                            .map(|(sutehai, decomposition, tiles)| json!({
+                                "sutehai": sutehai.to_string(),
+                                "decomposition": decomposition.to_json(),
+                                "improvement_tiles": tiles
+                                    .iter()
+                                    .map(|hai| hai.to_string())
+                                    .collect::<Vec<_>>(),
+                            }))
+                            .collect::<Vec<_>>(),
+                    })),
+                }));
+            }
+            Command::Lock(hai_vec) => {
+                for hai in hai_vec {
+                    *self.locks.entry(hai).or_insert(0) += 1;
+                }
+                return Ok(Some(self.locks_output()));
+            }
+            Command::Unlock(hai_vec) => {
+                if hai_vec.is_empty() {
+                    self.locks.clear();
+                }
+                for hai in hai_vec {
+                    match self.locks.get_mut(&hai) {
+                        Some(locked) if *locked > 1 => *locked -= 1,
+                        Some(_) => {
+                            self.locks.remove(&hai);
+                        }
+                        None => return Err(format!("'{}' is not locked.", hai)),
+                    }
+                }
+                return Ok(Some(self.locks_output()));
+            }
+            Command::Locks => return Ok(Some(self.locks_output())),
+            Command::SkipRon => match &mut self.game_manager {
+                Some(game_manager) => {
+                    game_manager.skip_ron()?;
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => {
+                            Output::Text("同巡内振り聴".to_string())
+                        }
+                        OutputFormat::Json => Output::Json(json!({ "temporary_furiten": true })),
+                    }));
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
+            Command::Defense => match &self.game_manager {
+                Some(game_manager) => {
+                    let defense_hint = game_manager.defense_hint();
+                    let group = |safety: game::Safety| {
+                        defense_hint
+                            .iter()
+                            .filter(|(_, other)| **other == safety)
+                            .map(|(hai, _)| hai.to_string())
+                            .collect::<Vec<String>>()
+                    };
+                    let safety_vec = [
+                        game::Safety::Genbutsu,
+                        game::Safety::Suji,
+                        game::Safety::NoChance,
+                        game::Safety::OneChance,
+                        game::Safety::Dangerous,
+                    ];
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => Output::Text(
+                            safety_vec
+                            .iter()
+                            .map(|safety| {
+                                let hai_vec = group(*safety);
+                                format!(
+                                    "{}：{}",
+                                    safety,
+                                    if hai_vec.is_empty() {
+                                        "無し".to_string()
+                                    } else {
+                                        hai_vec.join(" ")
+                                    }
+                                )
+                            })
+                            .collect::<Vec<String>>()
+                            .join("\n"),
+                        ),
+                        OutputFormat::Json => {
+                            let mut map = serde_json::Map::new();
+                            for safety in safety_vec.iter() {
+                                if let serde_json::Value::String(key) = safety.to_json() {
+                                    map.insert(key, json!(group(*safety)));
+                                }
+                            }
+                            Output::Json(serde_json::Value::Object(map))
+                        }
+                    }));
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
+            Command::Unseen => match &self.game_manager {
+                Some(game_manager) => {
+                    let unseen = game_manager.unseen();
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => {
+                            let mut string = String::new();
+                            for (hai, number) in &unseen {
+                                string += &format!("{}:{}", hai, number);
+                                match hai {
+                                    game::Hai::Manzu(9) | game::Hai::Pinzu(9) | game::Hai::Souzu(9) => {
+                                        string += "\n"
+                                    }
+                                    _ => string += " ",
+                                }
+                            }
+                            Output::Text(format!(
+                                "見えていない牌：{}枚\n{}",
+                                unseen.values().map(|number| *number as u32).sum::<u32>(),
+                                string.trim_end()
+                            ))
+                        }
+                        OutputFormat::Json => {
+                            let mut map = serde_json::Map::new();
+                            for (hai, number) in &unseen {
+                                map.insert(hai.to_string(), json!(number));
+                            }
+                            Output::Json(serde_json::Value::Object(map))
+                        }
+                    }));
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
+            Command::Dora {
+                indicators,
+                discard_from_haiyama,
+            } => match &mut self.game_manager {
+                Some(game_manager) => {
+                    game_manager.add_dora_indicators(&indicators, discard_from_haiyama)?;
+                    let to_string = |hai_vec: &[game::Hai]| {
+                        hai_vec
+                            .iter()
+                            .map(|hai| hai.to_string())
+                            .collect::<Vec<String>>()
+                    };
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => Output::Text(format!(
+                            "ドラ表示牌：{}\nドラ：{}",
+                            to_string(game_manager.dora_indicators()).join(" "),
+                            to_string(&game_manager.dora()).join(" ")
+                        )),
+                        OutputFormat::Json => Output::Json(json!({
+                            "dora_indicators": to_string(game_manager.dora_indicators()),
+                            "dora": to_string(&game_manager.dora()),
+                        })),
+                    }));
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
+            Command::Honitsu(tehai) => {
+                let tehai = self.given_or_current_tehai(tehai, "honitsu?")?;
+                let (shanten, ..) =
+                    analyze(self.closed_only, &tehai, self.player_number, None, deadline)?;
+                let suits: &[(game::Suit, &str)] = match self.player_number {
+                    game::PlayerNumber::Four => &[
+                        (game::Suit::Man, "萬子"),
+                        (game::Suit::Pin, "筒子"),
+                        (game::Suit::Sou, "索子"),
+                    ],
+                    game::PlayerNumber::Three => {
+                        &[(game::Suit::Pin, "筒子"), (game::Suit::Sou, "索子")]
+                    }
+                };
+                let projections: Vec<(game::Suit, &str, Option<i32>)> = suits
+                    .iter()
+                    .map(|(suit, name)| {
+                        (
+                            *suit,
+                            *name,
+                            tehai.honitsu_shanten(*suit, self.player_number).ok(),
+                        )
+                    })
+                    .collect();
+                let cheapest = projections
+                    .iter()
+                    .filter_map(|(_, name, projection)| projection.map(|p| (p - shanten, *name)))
+                    .min();
+
+                return Ok(Some(match self.output_format {
+                    OutputFormat::Standard | OutputFormat::Csv => {
+                        let mut string = format!("手牌：{}\n向聴：{}", tehai, shanten);
+                        for (_, name, projection) in &projections {
+                            string += &match projection {
+                                Some(projection) => format!(
+                                    "\n{}：向聴{}（+{}）",
+                                    name,
+                                    projection,
+                                    projection - shanten
+                                ),
+                                None => format!("\n{}：不可", name),
+                            };
+                        }
+                        if let Some((cost, name)) = cheapest {
+                            if cost <= 1 {
+                                string += &format!("\n染手代价小：{}", name);
+                            }
+                        }
+                        Output::Text(string)
+                    }
+                    OutputFormat::Json => Output::Json(json!({
+                        "tehai": tehai.to_json(),
+                        "shanten_number": shanten,
+                        "honitsu": projections
+                            .iter()
+                            .map(|(suit, _, projection)| json!({
+                                "suit": suit.to_string(),
+                                "shanten_number": projection,
+                                "cost": projection.map(|p| p - shanten),
+                            }))
+                            .collect::<Vec<_>>(),
+                    })),
+                }));
+            }
+            Command::Matrix(tehai) => {
+                // Count ukeire by haiyama only when analyzing current tehai.
+                let game_manager = match tehai {
+                    Some(_) => None,
+                    None => self.game_manager.as_ref(),
+                };
+                let tehai = self.given_or_current_tehai(tehai, "matrix")?;
+                check_closed_only(self.closed_only, &tehai.fuuro)?;
+                let efficiency_vec =
+                    tehai.discard_matrix(self.player_number, game_manager, deadline)?;
+
+                return Ok(Some(match self.output_format {
+                    OutputFormat::Standard | OutputFormat::Csv => {
+                        let mut string = format!("手牌：{}\n--------", tehai);
+                        for efficiency in &efficiency_vec {
+                            string += &format!("\n{}", efficiency);
+                        }
+                        Output::Text(string)
+                    }
+                    OutputFormat::Json => Output::Json(json!({
+                        "tehai": tehai.to_json(),
+                        "matrix": efficiency_vec
+                            .iter()
+                            .map(|efficiency| efficiency.to_json())
+                            .collect::<Vec<_>>(),
+                    })),
+                }));
+            }
+            Command::Edit { old, new } => match &mut self.game_manager {
+                Some(game_manager) => {
+                    game_manager.edit_tehai(&old, &new)?;
+                    let tehai = game_manager.tehai().ok_or("Not initialized.".to_string())?;
+                    if let game::State::FullHai = game_manager.state {
+                        let (shanten, conditions, decomposition_count) = analyze(
+                            self.closed_only,
+                            tehai,
+                            game_manager.player_number(),
+                            Some(game_manager),
+                            deadline,
+                        )?;
                         return Ok(Some(print_machi(
-                            &tehai,
+                            tehai,
                             shanten,
                             conditions,
-                            self.output_format,
+                            decomposition_count,
+                            options,
+                            None,
                         )));
                     }
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => {
+                            Output::Text(format!("手牌：{}", tehai))
+                        }
+                        OutputFormat::Json => Output::Json(json!({ "tehai": tehai.to_json() })),
+                    }));
                 }
                 None => {
                     return Err(
@@ -154,37 +1085,50 @@ impl Controller {
             },
             Command::TehaiInput(tehai) => match &mut self.game_manager {
                 Some(game_manager) => {
+                    // Checked before initializing, so the game is kept.
+                    check_closed_only(self.closed_only, &tehai.fuuro)?;
                     game_manager.operate(game::Operation::Tehai(
                         game::TehaiOperation::Initialize(tehai),
                     ))?;
                     if let game::State::FullHai = game_manager.state {
                         let tehai = game_manager.tehai().ok_or("Not initialized.".to_string())?;
-                        let (shanten, conditions) = game_manager.tehai_analyze()?;
+                        let (shanten, conditions, decomposition_count) = analyze(
+                            self.closed_only,
+                            tehai,
+                            game_manager.player_number(),
+                            Some(game_manager),
+                            deadline,
+                        )?;
                         return Ok(Some(print_machi(
-                            &tehai,
+                            tehai,
                             shanten,
                             conditions,
-                            self.output_format,
+                            decomposition_count,
+                            options,
+                            game_manager.last_drawn_hai(),
                         )));
                     }
                 }
                 None => {
-                    let (shanten, conditions) = tehai.analyze(self.player_number, None)?;
+                    let (shanten, conditions, decomposition_count) =
+                        analyze(self.closed_only, &tehai, self.player_number, None, deadline)?;
                     return Ok(Some(print_machi(
                         &tehai,
                         shanten,
                         conditions,
-                        self.output_format,
+                        decomposition_count,
+                        options,
+                        None,
                     )));
                 }
             },
             Command::Back { haiyama_sensitive } => match &mut self.game_manager {
                 Some(game_manager) => {
                     let (op, state) = game_manager.back(haiyama_sensitive)?;
-                    return Ok(Some(format!(
+                    return Ok(Some(Output::Text(format!(
                         "Undo operation: {:?}\nBack to state: {:?}",
                         op, state
-                    )));
+                    ))));
                 }
                 None => {
                     return Err(
@@ -192,15 +1136,75 @@ impl Controller {
                     );
                 }
             },
+            Command::Save(path) => match &self.game_manager {
+                Some(game_manager) => {
+                    let string = serde_json::to_string(game_manager)
+                        .map_err(|error| format!("Cannot save game: {}.", error))?;
+                    std::fs::write(&path, string)
+                        .map_err(|error| format!("Cannot write '{}': {}.", path, error))?;
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => {
+                            Output::Text(format!("Saved to '{}'.", path))
+                        }
+                        OutputFormat::Json => Output::Json(json!({ "saved": path })),
+                    }));
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
+            Command::Load(path) => {
+                let string = std::fs::read_to_string(&path)
+                    .map_err(|error| format!("Cannot read '{}': {}.", path, error))?;
+                let game_manager: game::GameManager = serde_json::from_str(&string)
+                    .map_err(|error| format!("Invalid save file '{}': {}.", path, error))?;
+                game_manager
+                    .validate()
+                    .map_err(|error| format!("Invalid save file '{}': {}", path, error))?;
+                self.player_number = game_manager.player_number();
+                let game_manager = self.game_manager.insert(game_manager);
+                let options = MachiPrintOptions {
+                    player_number: self.player_number,
+                    ..options
+                };
+                if let game::State::FullHai = game_manager.state {
+                    let tehai = game_manager.tehai().ok_or("Not initialized.".to_string())?;
+                    let (shanten, conditions, decomposition_count) = analyze(
+                        self.closed_only,
+                        tehai,
+                        game_manager.player_number(),
+                        Some(game_manager),
+                        deadline,
+                    )?;
+                    return Ok(Some(print_machi(
+                        tehai,
+                        shanten,
+                        conditions,
+                        decomposition_count,
+                        options,
+                        game_manager.last_drawn_hai(),
+                    )));
+                }
+                return Ok(Some(match self.output_format {
+                    OutputFormat::Standard | OutputFormat::Csv => {
+                        Output::Text(format!("Loaded from '{}'.", path))
+                    }
+                    OutputFormat::Json => Output::Json(json!({ "loaded": path })),
+                }));
+            }
             Command::State => match &self.game_manager {
                 Some(game_manager) => {
-                    return Ok(Some(format!(
-                        "{}",
-                        match self.output_format {
-                            OutputFormat::Standard => game_manager.to_string(),
-                            OutputFormat::Json => game_manager.to_json().to_string(),
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => {
+                            Output::Text(game_manager.to_string())
+                        }
+                        OutputFormat::Json if self.compact_json => {
+                            Output::Json(game_manager.to_compact_json())
                         }
-                    )))
+                        OutputFormat::Json => Output::Json(game_manager.to_json()),
+                    }));
                 }
                 None => {
                     return Err(
@@ -212,13 +1216,32 @@ impl Controller {
                 Some(game_manager) => {
                     if let game::State::FullHai = game_manager.state {
                         let tehai = game_manager.tehai().ok_or("Not initialized.".to_string())?;
-                        let (shanten, conditions) = game_manager.tehai_analyze()?;
+                        let (shanten, conditions, decomposition_count) = analyze(
+                            self.closed_only,
+                            tehai,
+                            game_manager.player_number(),
+                            Some(game_manager),
+                            deadline,
+                        )?;
                         return Ok(Some(print_machi(
-                            &tehai,
+                            tehai,
                             shanten,
                             conditions,
-                            self.output_format,
+                            decomposition_count,
+                            options,
+                            game_manager.last_drawn_hai(),
                         )));
+                    } else if let game::State::WaitForRinshanhai = game_manager.state {
+                        let tehai = game_manager.tehai().ok_or("Not initialized.".to_string())?;
+                        return Ok(Some(match self.output_format {
+                            OutputFormat::Standard | OutputFormat::Csv => {
+                                Output::Text(format!("手牌：{}\n嶺上牌待ち", tehai))
+                            }
+                            OutputFormat::Json => Output::Json(json!({
+                                "tehai": tehai.to_json(),
+                                "state": format!("{:?}", game_manager.state),
+                            })),
+                        }));
                     } else {
                         return Err("Can only analyze tehai when full with hai.".to_string());
                     }
@@ -229,29 +1252,81 @@ impl Controller {
                     );
                 }
             },
+            Command::Shanten => match &self.game_manager {
+                Some(game_manager) => {
+                    if game_manager.state != game::State::FullHai {
+                        return Err("Can only analyze tehai when full with hai.".to_string());
+                    }
+                    let tehai = game_manager.tehai().ok_or("Not initialized.".to_string())?;
+                    let shanten = tehai.shanten(self.player_number)?;
+                    return Ok(Some(match self.output_format {
+                        OutputFormat::Standard => Output::Text(format!("向聴：{}", shanten)),
+                        OutputFormat::Csv => Output::Text(format!("# shanten,{}", shanten)),
+                        OutputFormat::Json => Output::Json(json!({ "shanten_number": shanten })),
+                    }));
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
             Command::History => match &self.game_manager {
                 Some(game_manager) => {
                     let iter = game_manager.history().iter();
                     match self.output_format {
-                        OutputFormat::Standard => {
+                        OutputFormat::Standard | OutputFormat::Csv => {
                             let mut string = String::from("Operation History");
-                            for (id, (op, state, _)) in iter.enumerate() {
+                            for (id, (op, state, ..)) in iter.enumerate() {
                                 string += &format!(
                                     "\n[{}]:\t\tState: {:?}\n\t\tOperation: {:?}",
                                     id, state, op
                                 );
                             }
-                            return Ok(Some(string));
+                            return Ok(Some(Output::Text(string)));
                         }
                         OutputFormat::Json => {
                             let mut json_vec = vec![];
-                            for (op, state, _) in iter {
+                            for (op, state, ..) in iter {
                                 json_vec.push(json!({
                                     "operation": op.to_json(),
                                     "state": format!("{:?}", state),
                                 }))
                             }
-                            return Ok(Some(json!({ "history": json_vec }).to_string()));
+                            return Ok(Some(Output::Json(json!({ "history": json_vec }))));
+                        }
+                    }
+                }
+                None => {
+                    return Err(
+                        "Can not execute interactive command at non-interactive mode.".to_string(),
+                    );
+                }
+            },
+            Command::Export => match &self.game_manager {
+                Some(game_manager) => {
+                    // Enter interactive mode of the same player number first, so that the
+                    // commands can be replayed by a new program.
+                    let mut command_vec = vec![
+                        match self.player_number {
+                            game::PlayerNumber::Three => "3pl",
+                            game::PlayerNumber::Four => "4pl",
+                        }
+                        .to_string(),
+                        "i".to_string(),
+                    ];
+                    command_vec.extend(
+                        game_manager
+                            .history()
+                            .iter()
+                            .map(|(op, ..)| op.to_command_string()),
+                    );
+                    match self.output_format {
+                        OutputFormat::Standard | OutputFormat::Csv => {
+                            return Ok(Some(Output::Text(command_vec.join("\n"))));
+                        }
+                        OutputFormat::Json => {
+                            return Ok(Some(Output::Json(json!({ "commands": command_vec }))));
                         }
                     }
                 }
@@ -262,32 +1337,77 @@ impl Controller {
                 }
             },
             Command::Help => {
-                return Ok(Some(format!(
-                    "Common command:\n\
+                return Ok(Some(Output::Text("Common command:\n\
                     * i,interactive -- Interactive mode. Reinitialize if already at interactive mod.\n\
                     * ni,noninteractive -- Exit interactive mode.\n\
                     * 3pl,3-player -- 3 players mahjong. Reinitialize if interactive mode.\n\
                     * 4pl,4-player -- 4 players mahjong. Reinitialize if interactive mode.\n\
                     * std, standard -- Standard output mode.\n\
                     * json -- JSON output mode.\n\
+                    * csv -- CSV output mode for tehai analysis, one row per sutehai. Other output is \
+                    the same as standard mode.\n\
                     * q,quit,exit -- Exit program.\n\
                     * h,help -- Print command list.\n\
+                    * honitsu? -- Compare shanten with going for honitsu of each suit. Use current tehai \
+                    at interactive mode, or give one like \"honitsu? 1234567m345p1s557z\".\n\
+                    * matrix -- Show shanten and ukeire of discarding every distinct hai, including \
+                    those making shanten worse. Use current tehai at interactive mode, or give one \
+                    like \"matrix 123m456p13s57s55z27z\".\n\
+                    * shapes+ -- Show the decomposition of each sutehai and hai improving its shapes \
+                    even without reducing shanten. Use current tehai at interactive mode, or give one \
+                    like \"shapes+ 123m456p13s57s55z27z\".\n\
+                    * lock -- Lock hai so that they are never suggested to discard, for an example, \
+                    \"lock 5m5m\". Use \"unlock 5m\" to unlock one, \"unlock\" to unlock all and \
+                    \"locks\" to list them.\n\
+                    * macro -- Define a macro, for an example, \"macro drill = i;123m456p789s1122z\". \
+                    Commands are separated by \";\". Use \"@drill\" to run them.\n\
                     \n\
                     Command for interactive mode:\n\
                     * + -- Add a hai to tehai. For an example, \"+4m\".\n\
-                    * - -- Discard a hai from tehai. For an example, \"-1s\".\n\
+                    * - -- Discard a hai from tehai. For an example, \"-1s\". Hai forbidden by kuikae \
+                    cannot be discarded right after chii or pon unless started with \"--allow-kuikae\".\n\
                     * *+ -- Add some hai to haiyama. Limit is 4 for each type of hai.\n\
                     * *- -- Discard some hai from haiyama. For an example, \"*-1s777z\". Note that \
                     you shouldn't use \"*-\" for nakihai.\n\
                     * > -- Naku. It means chii, pon or kan. Third hai will be regarded as nakihai if chii. \
                     You can use \">4444p5s\" to represent kan 4p and get rinshanhai 5s and also you can use \
                     \"4444p\" then \"+5s\". However, you can also write \"44p5s44p\", the order does not \
-                    matter. Note: \">4444p\" is daiminkan, \"+4p\" then \">4444p\" is kakan or ankan.\n\
+                    matter. Note: \">4444p\" is daiminkan, \"+4p\" then \">4444p\" is kakan or ankan. \
+                    While waiting for rinshanhai, \">4444p\" means rinshanhai is 4p and kan again. \
+                    Use \">d4444p\", \">a4444p\" or \">k4444p\" to force daiminkan, ankan or kakan, \
+                    which fails if tehai does not match.\n\
                     * b,back -- Undo last operation.\n\
+                    * reset -- Clear haiyama, tehai and history, keeping player number and output mode.\n\
+                    * save -- Save the whole game including history to a file, for an example, \
+                    \"save game.json\". Use \"load game.json\" to restore it, which also enters \
+                    interactive mode.\n\
                     * s,state -- Print current game state, including haiyama, types of sutehai, tehai.\n\
                     * d,display -- Normally program will print tehai analysis result after operation if \
                     tehai full with hai. You can use this command print again.\n\
+                    * shanten -- Print only the number of shanten of tehai full with hai, which is \
+                    faster than \"display\". Agari tehai is -1.\n\
                     * log,history -- Print operation history.\n\
+                    * export -- Print input commands reproducing operation history, one per line, \
+                    which can be input again to replay the game.\n\
+                    * kita -- Set aside a 4z as kita on 3-players mode. With 14 hai, the 4z is taken \
+                    from tehai and then \"+\" the replacement; with 13 hai, the 4z just drawn is set \
+                    aside at once.\n\
+                    * set -- Set aside a hai from 14 hai of tehai, for an example, \"set 5m\". It is \
+                    neither sutehai nor put back to haiyama.\n\
+                    * river -- Append hai discarded by self to the river in order, for an example, \
+                    \"river 1z9m\". They are sutehai for furiten and also discarded from haiyama. \
+                    Unlike \"*-\", which only edits haiyama.\n\
+                    * skip-ron -- Skip a ron on a hai discarded by others. It causes temporary furiten \
+                    until next \"-\", which is shown in analysis too.\n\
+                    * defense -- Show how safe each type of hai is to discard against ryanmen: \
+                    genbutsu, suji, no chance, one chance or dangerous, judged from sutehai and \
+                    hai left in haiyama.\n\
+                    * unseen -- Show how many of each type of hai are unseen, that is not in tehai, \
+                    fuuro or visible discards. Aka is counted as five.\n\
+                    * dora -- Add dora indicators, for an example, \"dora 3m5z\". Use \"dora* 3m5z\" \
+                    to also discard them from haiyama.\n\
+                    * edit -- Replace a hai in tehai for analysis only, for an example, \"edit 5m 6m\". \
+                    It is not a game operation, so haiyama and history are not changed.\n\
                     \n\
                     Haiyama errors will cause operation failure and game state recovery. \
                     If you don't care errors from haiyama, you can use following command. \
@@ -298,9 +1418,10 @@ impl Controller {
                     * *+! -- Add some hai to haiyama ignoring haiyama error.\n\
                     * *-! -- Discard some hai from haiyama ignoring haiyama error.\n\
                     * >! -- Naku ignoring haiyama error.\n\
+                    * kita! -- Kita ignoring haiyama error.\n\
+                    * river! -- River ignoring haiyama error.\n\
                     * b!,back! -- Undo operation ignoring haiyama error. Note if you use \"back\" for operations \
-                    who ignored haiyama error, \"back\" will keep reporting haiyama errors."
-                )))
+                    who ignored haiyama error, \"back\" will keep reporting haiyama errors.".to_string())))
             }
         };
         Ok(None)
@@ -2,4 +2,4 @@ mod command;
 mod controller;
 
 use command::Command;
-pub use controller::{Controller, OutputFormat};
+pub use controller::{Controller, OutputFormat, SortKey};
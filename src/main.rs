@@ -3,7 +3,8 @@
 pub mod game;
 pub mod interaction;
 use clap::Parser;
-use rustyline::{self, DefaultEditor, error::ReadlineError};
+use rustyline::{self, error::ReadlineError, DefaultEditor};
+use std::io::Write;
 
 #[derive(Parser, Debug)]
 #[command(name = "Japanese Mahjong Theory Shell")]
@@ -11,12 +12,61 @@ use rustyline::{self, DefaultEditor, error::ReadlineError};
 #[command(version = "1.19")]
 #[command(about = "Japanese Mahjong Theory Shell", long_about = None)]
 struct Args {
-    #[arg(short, long, help = "Set output format: standard | json", default_value_t = String::from("standard"))]
+    #[arg(short, long, help = "Set output format: standard | json | csv", default_value_t = String::from("standard"))]
     format_type: String,
-    #[arg(short, long, help = "Set players number: 3 | 4", default_value_t = 4)]
-    players_number: u8,
+    #[arg(
+        short,
+        long,
+        help = "Set players number: 3 | 4 | sanma | yonma",
+        default_value_t = String::from("4")
+    )]
+    players_number: String,
     #[arg(short, help = "Start with interactive mode", long)]
     interactive: bool,
+    #[arg(long, help = "Show a decomposition that yields each machihai")]
+    show_shapes: bool,
+    #[arg(long, help = "Show machihai with no hai left instead of hiding them")]
+    show_dead: bool,
+    #[arg(long, help = "Print haiyama and machihai as single JSON objects")]
+    compact_json: bool,
+    #[arg(long, help = "Print JSON with indents and newlines")]
+    json_pretty: bool,
+    #[arg(long, help = "Show how many decompositions each analysis considers")]
+    diagnostics: bool,
+    #[arg(
+        long,
+        help = "Give up analysis which takes longer than this many milliseconds"
+    )]
+    timeout_ms: Option<u64>,
+    #[arg(
+        long,
+        help = "Allow discarding hai forbidden by kuikae after chii and pon"
+    )]
+    allow_kuikae: bool,
+    #[arg(
+        long,
+        help = "Print tiles with Unicode mahjong characters on standard output"
+    )]
+    unicode: bool,
+    #[arg(long, help = "Sort machihai output by: ukeire | sutehai | wait-quality", default_value_t = String::from("ukeire"))]
+    sort: String,
+    #[arg(long, help = "Refuse to analyze tehai with fuuro")]
+    closed_only: bool,
+    #[arg(long, help = "Analyze each line of this file as a tehai, then exit")]
+    input_file: Option<String>,
+    #[arg(
+        long,
+        help = "Skip tehai which are not tenpai without output in batch mode"
+    )]
+    tenpai_only: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "100",
+        help = "Print progress of batch mode to stderr every N lines (default 100)"
+    )]
+    progress: Option<usize>,
 }
 
 fn main() -> Result<(), String> {
@@ -27,20 +77,69 @@ fn main() -> Result<(), String> {
         interaction::OutputFormat::Standard
     } else if args.format_type == "json" {
         interaction::OutputFormat::Json
+    } else if args.format_type == "csv" {
+        interaction::OutputFormat::Csv
     } else {
         return Err(format!("Unknown format type: {}.", args.format_type));
     };
 
-    let player_number = match args.players_number {
-        3 => game::PlayerNumber::Three,
-        4 => game::PlayerNumber::Four,
-        _ => return Err(format!("Not support {}-players mode.", args.players_number)),
+    let player_number: game::PlayerNumber = args.players_number.parse()?;
+
+    let sort_key = match args.sort.as_str() {
+        "ukeire" => interaction::SortKey::Ukeire,
+        "sutehai" => interaction::SortKey::Sutehai,
+        "wait-quality" => interaction::SortKey::WaitQuality,
+        _ => return Err(format!("Unknown sort key: {}.", args.sort)),
     };
 
-    let interactive = args.interactive;
+    // Batch mode never enters interactive mode.
+    let interactive = args.interactive && args.input_file.is_none();
 
     // Initialize controller.
-    let mut controller = interaction::Controller::new(output_format, player_number, interactive);
+    let mut controller = interaction::Controller::new(output_format, player_number, interactive)
+        .with_show_shapes(args.show_shapes)
+        .with_show_dead(args.show_dead)
+        .with_compact_json(args.compact_json)
+        .with_json_pretty(args.json_pretty)
+        .with_diagnostics(args.diagnostics)
+        .with_timeout(args.timeout_ms.map(std::time::Duration::from_millis))
+        .with_kuikae_allowed(args.allow_kuikae)
+        .with_unicode(args.unicode)
+        .with_sort_key(sort_key)
+        .with_closed_only(args.closed_only)
+        .with_tenpai_only(args.tenpai_only);
+
+    // Batch mode.
+    if let Some(input_file) = &args.input_file {
+        let content = std::fs::read_to_string(input_file)
+            .map_err(|e| format!("Failed to read '{}': {e}.", input_file))?;
+        let lines: Vec<&str> = content.lines().collect();
+        let total = lines.len();
+        // Progress goes to stderr, so that stdout can still be piped as is.
+        let report = |done: usize| {
+            let percent = if total == 0 {
+                100.0
+            } else {
+                done as f64 * 100.0 / total as f64
+            };
+            eprintln!("{}/{} ({:.1}%)", done, total, percent);
+            _ = std::io::stderr().flush();
+        };
+        let every = args.progress.map(|every| every.max(1));
+        let output = controller.execute_batch(lines.iter().enumerate().map(|(index, line)| {
+            if let Some(every) = every {
+                if index > 0 && index % every == 0 {
+                    report(index);
+                }
+            }
+            *line
+        }));
+        if every.is_some() {
+            report(total);
+        }
+        println!("{}", output);
+        return Ok(());
+    }
 
     // Initialize RustyLine.
     let mut rl =
@@ -50,7 +149,7 @@ fn main() -> Result<(), String> {
     loop {
         let prompt = match controller.output_format() {
             interaction::OutputFormat::Standard => ">>> ",
-            interaction::OutputFormat::Json => "",
+            interaction::OutputFormat::Json | interaction::OutputFormat::Csv => "",
         };
         match rl.readline(prompt) {
             Ok(input) => {
@@ -64,14 +163,15 @@ fn main() -> Result<(), String> {
             Err(ReadlineError::Interrupted) => {
                 println!("Ctrl-C detected, program exited.");
                 break Ok(());
-            },
+            }
             Err(ReadlineError::Eof) => {
                 println!("Ctrl-D detected, program exited.");
                 break Ok(());
-            },
+            }
             Err(_) => {
                 break Err(String::from(match controller.output_format() {
                     interaction::OutputFormat::Standard => "Failed to read input.",
+                    interaction::OutputFormat::Csv => "# error,Failed to read input.",
                     interaction::OutputFormat::Json => "{\"error\":\"Failed to read input.\"}",
                 }));
             }
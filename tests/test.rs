@@ -1,5 +1,44 @@
 extern crate japanese_mahjong_theory;
-use japanese_mahjong_theory::{Hai, Mentsu, PlayerNumber, Tehai};
+use japanese_mahjong_theory::interaction::{Controller, OutputFormat, SortKey};
+use japanese_mahjong_theory::{
+    score, shanten::fast_shanten, AgariContext, GameManager, Hai, Haiyama, HaiyamaOperation,
+    Hourakei, Kan, MahjongError, Mentsu, MentsuError, Naku, Operation, PlayerNumber, Ruleset,
+    Safety, Score, ScoringRules, State, Suit, Taatsu, Tehai, TehaiOperation, Toitsu, Ukihai,
+    WaitKind, Yaku,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::{Duration, Instant};
+
+/// Execute a command as a batch of one line, splitting its output from the line error.
+fn execute(controller: &mut Controller, command: &str) -> Result<Option<String>, String> {
+    let output = controller.execute_batch([command]);
+    match controller.output_format() {
+        OutputFormat::Json => {
+            let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+            match json.as_array().unwrap().first() {
+                Some(serde_json::Value::Object(object))
+                    if object.get("line") == Some(&serde_json::json!(1)) =>
+                {
+                    Err(object["error"].as_str().unwrap().to_string())
+                }
+                Some(serde_json::Value::String(text)) => Ok(Some(text.clone())),
+                Some(json) => Ok(Some(json.to_string())),
+                None => Ok(None),
+            }
+        }
+        format => {
+            let prefix = match format {
+                OutputFormat::Csv => "# error,line 1: ",
+                _ => "1行目：",
+            };
+            match output.strip_prefix(prefix) {
+                Some(error) => Err(error.to_string()),
+                None if output.is_empty() => Ok(None),
+                None => Ok(Some(output)),
+            }
+        }
+    }
+}
 
 #[test]
 fn test_hai() {
@@ -30,6 +69,18 @@ fn test_hai() {
     );
 }
 
+#[test]
+fn test_player_number_from_str() {
+    assert_eq!("3".parse::<PlayerNumber>(), Ok(PlayerNumber::Three));
+    assert_eq!("sanma".parse::<PlayerNumber>(), Ok(PlayerNumber::Three));
+    assert_eq!("4".parse::<PlayerNumber>(), Ok(PlayerNumber::Four));
+    assert_eq!("yonma".parse::<PlayerNumber>(), Ok(PlayerNumber::Four));
+    assert!("2".parse::<PlayerNumber>().is_err());
+    assert!("".parse::<PlayerNumber>().is_err());
+    assert_eq!(PlayerNumber::Three.to_string(), "3");
+    assert_eq!(PlayerNumber::Four.to_string(), "4");
+}
+
 #[test]
 fn test_tehai_input() {
     let tehai = Tehai::new("99m2p [5555z] 1z12m 2p45s35m", PlayerNumber::Four).unwrap();
@@ -75,14 +126,4140 @@ fn test_chiitoutsu() {
     assert_eq!(machi[2].sutehai, Hai::Pinzu(7));
     assert_eq!(machi[3].sutehai, Hai::Pinzu(8));
     assert_eq!(machi[4].sutehai, Hai::Souzu(5));
-    assert_eq!(machi[0].machihai.len(), 7);
-    assert_eq!(machi[1].machihai.len(), 7);
+    assert_eq!(machi[0].machihai.len(), 8);
+    assert_eq!(machi[1].machihai.len(), 8);
     assert_eq!(machi[2].machihai.len(), 5);
     assert_eq!(machi[3].machihai.len(), 5);
     assert_eq!(machi[4].machihai.len(), 4);
-    assert_eq!(machi[0].machihai.iter().fold(0, |x, (_, &y)| x + y), 21);
-    assert_eq!(machi[1].machihai.iter().fold(0, |x, (_, &y)| x + y), 21);
+    assert_eq!(machi[0].machihai.iter().fold(0, |x, (_, &y)| x + y), 25);
+    assert_eq!(machi[1].machihai.iter().fold(0, |x, (_, &y)| x + y), 25);
     assert_eq!(machi[2].machihai.iter().fold(0, |x, (_, &y)| x + y), 17);
     assert_eq!(machi[3].machihai.iter().fold(0, |x, (_, &y)| x + y), 17);
-    assert_eq!(machi[4].machihai.iter().fold(0, |x, (_, &y)| x + y), 15);
+    assert_eq!(machi[4].machihai.iter().fold(0, |x, (_, &y)| x + y), 16);
+}
+
+#[test]
+fn test_suukantsu() {
+    fn add(hai: Hai) -> Operation {
+        Operation::Tehai(TehaiOperation::Add {
+            hai,
+            haiyama_sensitive: true,
+        })
+    }
+    fn discard(hai: Hai) -> Operation {
+        Operation::Tehai(TehaiOperation::Discard(hai))
+    }
+    fn naku(kind: Naku) -> Operation {
+        Operation::Tehai(TehaiOperation::Naku {
+            kind,
+            haiyama_sensitive: true,
+            taken: vec![],
+        })
+    }
+    fn kan(hai: Hai) -> Operation {
+        naku(Naku::Kan(Kan::Unknown {
+            kantsu: Mentsu::Kantsu(hai),
+            rinshanhai: None,
+        }))
+    }
+
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    let tehai = Tehai::new("111m222p333s55z67z", PlayerNumber::Four).unwrap();
+    let operations = vec![
+        Operation::Tehai(TehaiOperation::Initialize(tehai)),
+        // Daiminkan 1m.
+        kan(Hai::Manzu(1)),
+        add(Hai::Jihai(1)),
+        discard(Hai::Jihai(1)),
+        // Pon 5z, then kakan it.
+        naku(Naku::Pon(Mentsu::Koutsu(Hai::Jihai(5)))),
+        discard(Hai::Jihai(6)),
+        add(Hai::Jihai(5)),
+        kan(Hai::Jihai(5)),
+        add(Hai::Jihai(2)),
+        discard(Hai::Jihai(2)),
+        // Daiminkan 2p.
+        kan(Hai::Pinzu(2)),
+        add(Hai::Jihai(3)),
+        discard(Hai::Jihai(3)),
+        // Ankan 3s.
+        add(Hai::Souzu(3)),
+        kan(Hai::Souzu(3)),
+        add(Hai::Jihai(7)),
+    ];
+    for op in operations {
+        game_manager.operate(op).unwrap();
+    }
+
+    let tehai = game_manager.tehai().unwrap();
+    assert!(tehai.is_suukantsu());
+    assert!(matches!(game_manager.state, State::FullHai));
+    let (shanten, _) = game_manager.tehai_analyze().unwrap();
+    assert_eq!(shanten, -1);
+    assert_eq!(game_manager.haiyama()[&Hai::Jihai(7)], 2);
+    let context = AgariContext {
+        agari_hai: Hai::Jihai(7),
+        is_tsumo: true,
+        riichi: false,
+        seat_wind: Hai::Jihai(1),
+        round_wind: Hai::Jihai(1),
+        dora_indicators: vec![],
+    };
+    assert_eq!(
+        tehai.detect_yaku(&context, PlayerNumber::Four),
+        vec![Yaku::Suukantsu]
+    );
+    assert_eq!(Yaku::Suukantsu.han(false), 13);
+}
+
+#[test]
+fn test_decomposition() {
+    let tehai = Tehai::new("123456m789p45s11z3m", PlayerNumber::Four).unwrap();
+    let (shanten, machi) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, 0);
+    for condition in machi {
+        let decomposition = condition.decomposition.unwrap();
+        assert_eq!(decomposition.hourakei, Hourakei::Mentsute);
+        assert_eq!(decomposition.mentsu.len(), 3);
+        assert_eq!(
+            decomposition.taatsu,
+            vec![Taatsu(Hai::Souzu(4), Hai::Souzu(5))]
+        );
+        assert_eq!(decomposition.toitsu, vec![Toitsu(Hai::Jihai(1))]);
+        assert!(decomposition.ukihai.is_empty());
+    }
+}
+
+#[test]
+fn test_sanma_manzu_dora_loop() {
+    // No 2~8m on 3-players mode, so dora of 1m is 9m and dora of 9m is 1m.
+    assert_eq!(
+        Hai::Manzu(1).next(PlayerNumber::Three, true),
+        Some(Hai::Manzu(9))
+    );
+    assert_eq!(
+        Hai::Manzu(9).next(PlayerNumber::Three, true),
+        Some(Hai::Manzu(1))
+    );
+    assert_eq!(
+        Hai::Manzu(9).previous(PlayerNumber::Three, true),
+        Some(Hai::Manzu(1))
+    );
+    assert_eq!(Hai::Manzu(1).next(PlayerNumber::Three, false), None);
+    assert_eq!(Hai::Manzu(9).previous(PlayerNumber::Three, false), None);
+    // Pinzu and souzu loop as usual.
+    assert_eq!(
+        Hai::Pinzu(9).next(PlayerNumber::Three, true),
+        Some(Hai::Pinzu(1))
+    );
+}
+
+#[test]
+fn test_analyze_deadline() {
+    let tehai = Tehai::new("1112345678999m1z", PlayerNumber::Four).unwrap();
+    let error = tehai
+        .analyze_with_deadline(PlayerNumber::Four, None, Some(Instant::now()))
+        .unwrap_err();
+    assert!(error.starts_with("TIMEOUT"));
+    let deadline = Instant::now() + Duration::from_secs(60);
+    let (shanten, _) = tehai
+        .analyze_with_deadline(PlayerNumber::Four, None, Some(deadline))
+        .unwrap();
+    assert_eq!(shanten, 0);
+}
+
+#[test]
+fn test_tehai_hash() {
+    let mut set = HashSet::new();
+    set.insert(Tehai::new("123m456p78s[111z][789m]", PlayerNumber::Four).unwrap());
+    set.insert(Tehai::new("[789m] 87s [111z] 654p 321m", PlayerNumber::Four).unwrap());
+    assert_eq!(set.len(), 1);
+    set.insert(Tehai::new("123m456p78s[111z][789p]", PlayerNumber::Four).unwrap());
+    assert_eq!(set.len(), 2);
+}
+
+#[test]
+fn test_tehai_replace() {
+    let mut tehai = Tehai::new("1111m456p789s11z23m", PlayerNumber::Four).unwrap();
+    let backup = tehai.clone();
+    assert!(tehai.replace(&Hai::Manzu(9), &Hai::Manzu(8)).is_err());
+    assert!(tehai.replace(&Hai::Manzu(2), &Hai::Manzu(1)).is_err());
+    assert_eq!(tehai, backup);
+    tehai.replace(&Hai::Manzu(3), &Hai::Jihai(1)).unwrap();
+    assert_eq!(
+        tehai,
+        Tehai::new("1111m456p789s111z2m", PlayerNumber::Four).unwrap()
+    );
+}
+
+#[test]
+fn test_winning_decompositions() {
+    let tehai = Tehai::new("112233m445566p77z", PlayerNumber::Four).unwrap();
+    let decomposition_vec = tehai.winning_decompositions(PlayerNumber::Four).unwrap();
+    assert_eq!(decomposition_vec[0].hourakei, Hourakei::Mentsute);
+    assert_eq!(decomposition_vec[0].mentsu.len(), 4);
+    assert_eq!(decomposition_vec[0].toitsu, vec![Toitsu(Hai::Jihai(7))]);
+    assert!(decomposition_vec
+        .iter()
+        .any(|decomposition| decomposition.hourakei == Hourakei::Chiitoitsu));
+
+    let tehai = Tehai::new("112233m445566p67z", PlayerNumber::Four).unwrap();
+    assert!(tehai
+        .winning_decompositions(PlayerNumber::Four)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_deterministic_json() {
+    // Decomposers are collected into a HashSet, whose order differs between instances.
+    let tehai = Tehai::new("1112345678999m1z", PlayerNumber::Four).unwrap();
+    let to_json = |tehai: &Tehai| {
+        let (_, machi) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+        machi
+            .iter()
+            .map(|condition| {
+                let mut json = condition.to_json();
+                json["decomposition"] = condition.decomposition.as_ref().unwrap().to_json();
+                json.to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+    let expected = to_json(&tehai);
+    for _ in 0..20 {
+        assert_eq!(to_json(&tehai), expected);
+    }
+}
+
+#[test]
+fn test_tenpai_players_settlement() {
+    let game_manager = GameManager::new(PlayerNumber::Four);
+    let rules = ScoringRules::new(PlayerNumber::Four);
+    let no_nagashi = [false; 4];
+    let settle = |tenpai: [bool; 4]| {
+        game_manager
+            .tenpai_players_settlement(&tenpai, &no_nagashi, 0, &rules)
+            .unwrap()
+    };
+    assert_eq!(settle([false, false, false, false]), vec![0, 0, 0, 0]);
+    assert_eq!(
+        settle([true, false, false, false]),
+        vec![3000, -1000, -1000, -1000]
+    );
+    assert_eq!(
+        settle([true, false, true, false]),
+        vec![1500, -1500, 1500, -1500]
+    );
+    assert_eq!(
+        settle([true, true, false, true]),
+        vec![1000, 1000, -3000, 1000]
+    );
+    assert_eq!(settle([true, true, true, true]), vec![0, 0, 0, 0]);
+
+    let custom = ScoringRules {
+        noten_bappu: 6000,
+        ..rules
+    };
+    assert_eq!(
+        game_manager
+            .tenpai_players_settlement(&[true, false, false, false], &no_nagashi, 0, &custom)
+            .unwrap(),
+        vec![6000, -2000, -2000, -2000]
+    );
+
+    // Nagashi mangan overrides noten bappu.
+    let nagashi = [false, true, false, false];
+    assert_eq!(
+        game_manager
+            .tenpai_players_settlement(&[true, true, false, false], &nagashi, 0, &rules)
+            .unwrap(),
+        vec![-4000, 8000, -2000, -2000]
+    );
+    let no_nagashi_rules = ScoringRules {
+        nagashi_mangan: false,
+        ..rules
+    };
+    assert_eq!(
+        game_manager
+            .tenpai_players_settlement(&[true, true, false, false], &nagashi, 0, &no_nagashi_rules)
+            .unwrap(),
+        vec![1500, 1500, -1500, -1500]
+    );
+
+    assert!(game_manager
+        .tenpai_players_settlement(&[true; 3], &[false; 3], 0, &rules)
+        .is_err());
+}
+
+#[test]
+fn test_macro() {
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let mut exit = false;
+    controller.execute("macro drill = i; 123m456p789s1122z".to_string(), &mut exit);
+    controller.execute("macro twice = @drill;@drill;d".to_string(), &mut exit);
+    assert_eq!(
+        controller.expand_macro("@twice".to_string()).unwrap(),
+        vec!["i", "123m456p789s1122z", "i", "123m456p789s1122z", "d"]
+    );
+    assert_eq!(controller.expand_macro("d".to_string()).unwrap(), vec!["d"]);
+    assert!(controller.expand_macro("@unknown".to_string()).is_err());
+
+    controller.execute("macro loop = d;@loop".to_string(), &mut exit);
+    assert!(controller.expand_macro("@loop".to_string()).is_err());
+    assert!(!exit);
+}
+
+#[test]
+fn test_honitsu_shanten() {
+    let tehai = Tehai::new("1234567m345p1s557z", PlayerNumber::Four).unwrap();
+    assert_eq!(tehai.analyze(PlayerNumber::Four, None).unwrap().0, 1);
+    assert_eq!(tehai.honitsu_shanten(Suit::Man, PlayerNumber::Four), Ok(3));
+    assert_eq!(tehai.honitsu_shanten(Suit::Pin, PlayerNumber::Four), Ok(5));
+    assert_eq!(tehai.honitsu_shanten(Suit::Sou, PlayerNumber::Four), Ok(7));
+    assert_eq!(
+        tehai.shanten_without_suit(Suit::Sou, PlayerNumber::Four),
+        Ok(1)
+    );
+    assert_eq!(
+        tehai.shanten_without_suit(Suit::Pin, PlayerNumber::Four),
+        Ok(3)
+    );
+
+    let tehai = Tehai::new("1234567m1s557z[345p]", PlayerNumber::Four).unwrap();
+    assert!(tehai
+        .honitsu_shanten(Suit::Man, PlayerNumber::Four)
+        .is_err());
+    assert_eq!(tehai.honitsu_shanten(Suit::Pin, PlayerNumber::Four), Ok(5));
+
+    // Aka is regarded as five.
+    let tehai = Tehai::new("123m406p789s11222z", PlayerNumber::Four).unwrap();
+    assert_eq!(tehai.honitsu_shanten(Suit::Pin, PlayerNumber::Four), Ok(3));
+    assert_eq!(
+        tehai.shanten_without_suit(Suit::Man, PlayerNumber::Four),
+        Ok(1)
+    );
+}
+
+#[test]
+fn test_mentsu_try_new() {
+    let four = PlayerNumber::Four;
+    let three = PlayerNumber::Three;
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Pinzu(3), Hai::Pinzu(1), Hai::Pinzu(2)], four),
+        Ok(Mentsu::Juntsu(Hai::Pinzu(1), Hai::Pinzu(2), Hai::Pinzu(3)))
+    );
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Manzu(2), Hai::Manzu(3), Hai::Manzu(4)], three),
+        Err(MentsuError::InvalidHai(Hai::Manzu(2)))
+    );
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Manzu(1), Hai::Manzu(1), Hai::Manzu(9)], three),
+        Err(MentsuError::SanmaManzuJuntsu)
+    );
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Souzu(1), Hai::Souzu(2)], four),
+        Err(MentsuError::WrongNumber(2))
+    );
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Souzu(1); 3].repeat(2), four),
+        Err(MentsuError::WrongNumber(6))
+    );
+    assert_eq!(
+        Mentsu::try_new(
+            &[Hai::Souzu(1), Hai::Souzu(1), Hai::Souzu(1), Hai::Souzu(2)],
+            four
+        ),
+        Err(MentsuError::NotSame)
+    );
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Manzu(1), Hai::Pinzu(2), Hai::Souzu(3)], four),
+        Err(MentsuError::MixedSuits)
+    );
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Jihai(1), Hai::Jihai(2), Hai::Jihai(3)], four),
+        Err(MentsuError::JihaiJuntsu)
+    );
+    assert_eq!(
+        Mentsu::try_new(&[Hai::Souzu(1), Hai::Souzu(2), Hai::Souzu(4)], four),
+        Err(MentsuError::NotSequential)
+    );
+    assert_eq!(
+        Mentsu::new(&[Hai::Souzu(1), Hai::Souzu(2), Hai::Souzu(4)], four),
+        None
+    );
+}
+
+#[test]
+fn test_ambiguous_fuuro() {
+    let error = Tehai::new("123m456p789s1z[1234m]", PlayerNumber::Four).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Ambiguous meld group '[1m2m3m4m]'"));
+    let error = Tehai::new("123m456p789s1z[111222z]", PlayerNumber::Four).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Ambiguous meld group '[1z1z1z2z2z2z]'"));
+    let error = Tehai::new("123m456p789s1z[1357m]", PlayerNumber::Four).unwrap_err();
+    assert!(error.to_string().starts_with("Not a valid meld"));
+    assert!(Tehai::new("123m456p789s1z[111", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("123m456p789s1z[111z", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("123m456p789s1z[2222z]", PlayerNumber::Four).is_ok());
+}
+
+#[test]
+fn test_display_wait_for_rinshanhai() {
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, true);
+    execute(&mut controller, "123m456p789s1122z").unwrap();
+    execute(&mut controller, "+1z").unwrap();
+    execute(&mut controller, "-2z").unwrap();
+    execute(&mut controller, "+1z").unwrap();
+    execute(&mut controller, ">1111z").unwrap();
+    let output = execute(&mut controller, "d").unwrap().unwrap();
+    assert!(output.ends_with("嶺上牌待ち"));
+
+    execute(&mut controller, "+3z").unwrap();
+    assert!(execute(&mut controller, "d")
+        .unwrap()
+        .unwrap()
+        .contains("聴牌"));
+}
+
+#[test]
+fn test_dead_machihai() {
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1111m23456p789s11z", PlayerNumber::Four).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::Haiyama {
+            kind: HaiyamaOperation::Discard(vec![Hai::Pinzu(4); 3]),
+            haiyama_sensitive: true,
+        })
+        .unwrap();
+    let (_, conditions) = game_manager.tehai_analyze().unwrap();
+    assert_eq!(conditions[0].sutehai, Hai::Manzu(1));
+    assert!(!conditions[0].machihai.contains_key(&Hai::Pinzu(4)));
+    assert!(conditions[0].dead_machihai.contains(&Hai::Pinzu(4)));
+    assert_eq!(conditions[0].nokori(), 8);
+
+    let mut controller =
+        Controller::new(OutputFormat::Standard, PlayerNumber::Four, true).with_show_dead(true);
+    execute(&mut controller, "1111m23456p789s11z").unwrap();
+    let output = execute(&mut controller, "*-4p4p4p")
+        .and_then(|_| execute(&mut controller, "d"))
+        .unwrap()
+        .unwrap();
+    assert!(output.contains("枯れ：4p"));
+}
+
+#[test]
+fn test_improvement_tiles() {
+    let tehai = Tehai::new("123m4569p78s13s11z5z", PlayerNumber::Four).unwrap();
+    let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, 1);
+    let condition = conditions
+        .iter()
+        .find(|condition| condition.sutehai == Hai::Jihai(5))
+        .unwrap();
+    let tiles = condition
+        .decomposition
+        .as_ref()
+        .unwrap()
+        .improvement_tiles(PlayerNumber::Four);
+    // 8p makes 9p a taatsu, but taatsu are already enough.
+    assert!(tiles.contains(&Hai::Pinzu(8)));
+    assert!(!condition.machihai.contains_key(&Hai::Pinzu(8)));
+    let improved = Tehai::new("123m45689p78s13s11z", PlayerNumber::Four).unwrap();
+    assert_eq!(improved.analyze(PlayerNumber::Four, None).unwrap().0, 1);
+    // Ryanmen and kanchan upgraded to mentsu.
+    assert!(tiles.contains(&Hai::Souzu(6)));
+    assert!(tiles.contains(&Hai::Souzu(9)));
+    assert!(tiles.contains(&Hai::Souzu(2)));
+    assert!(!tiles.contains(&Hai::Souzu(4)));
+}
+
+#[test]
+fn test_compact_json() {
+    let haiyama = Haiyama::new(PlayerNumber::Three);
+    let legacy = haiyama.to_json();
+    let compact = haiyama.to_compact_json();
+    assert_eq!(legacy[0], serde_json::json!({ "1m": 4 }));
+    assert_eq!(legacy[1], serde_json::json!({ "9m": 4 }));
+    assert_eq!(compact["1m"], 4);
+    assert_eq!(compact["9m"], 4);
+    // 27 types of hai and aka of pinzu and souzu.
+    assert_eq!(legacy.as_array().unwrap().len(), 29);
+    assert_eq!(compact.as_object().unwrap().len(), 29);
+
+    let tehai = Tehai::new("1112345678999m1z", PlayerNumber::Four).unwrap();
+    let (_, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    let compact = conditions[0].to_compact_json();
+    assert_eq!(compact["machihai"]["1m"], 1);
+    assert_eq!(
+        compact["machihai_number"],
+        conditions[0].to_json()["machihai_number"]
+    );
+}
+
+#[test]
+fn test_chuuren_shanten() {
+    let shanten = |string: &str| {
+        Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .chuuren_shanten(PlayerNumber::Four)
+    };
+    assert_eq!(shanten("11123456789995m"), Some((Suit::Man, -1)));
+    assert_eq!(shanten("1112345678999p1z"), Some((Suit::Pin, 0)));
+    assert_eq!(shanten("1122345678999s1z"), Some((Suit::Sou, 0)));
+    assert_eq!(shanten("1123456789s99s12z"), Some((Suit::Sou, 1)));
+    assert_eq!(shanten("123456789s99s123z"), Some((Suit::Sou, 2)));
+    // Far from chuurenpoutou, so no hint in output.
+    assert!(shanten("123456789m123p45s").unwrap().1 > 2);
+    assert_eq!(shanten("123456789m45s[123p]"), None);
+}
+
+#[test]
+fn test_lock() {
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let sutehai_vec = |controller: &mut Controller| {
+        let output = execute(controller, "1112345678999m1z").unwrap().unwrap();
+        let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+        json["conditions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|condition| condition["sutehai"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert!(sutehai_vec(&mut controller).contains(&"1z".to_string()));
+
+    execute(&mut controller, "lock 1z").unwrap();
+    assert!(!sutehai_vec(&mut controller).contains(&"1z".to_string()));
+
+    // Locks are listed in order of hai.
+    execute(&mut controller, "lock 9m").unwrap();
+    let locks = execute(&mut controller, "locks").unwrap().unwrap();
+    assert_eq!(locks, r#"{"locks":["9m","1z"]}"#);
+
+    execute(&mut controller, "unlock").unwrap();
+    assert!(sutehai_vec(&mut controller).contains(&"1z".to_string()));
+    assert!(execute(&mut controller, "unlock 1z").is_err());
+}
+
+#[test]
+fn test_17_hai() {
+    let tehai = Tehai::new("11223344556677889m", PlayerNumber::Four).unwrap();
+    let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, 0);
+    assert!(conditions
+        .iter()
+        .all(|condition| condition.decomposition.as_ref().unwrap().hourakei == Hourakei::Mentsute));
+
+    // 8 toitsu are not chiitoitsu.
+    let tehai = Tehai::new("1122m3344p5566s11223z", PlayerNumber::Four).unwrap();
+    let (_, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert!(conditions
+        .iter()
+        .all(|condition| condition.decomposition.as_ref().unwrap().hourakei == Hourakei::Mentsute));
+
+    let tehai = Tehai::new("147m258p369s12345677z", PlayerNumber::Four).unwrap();
+    assert_eq!(tehai.analyze(PlayerNumber::Four, None).unwrap().0, 9);
+
+    let tehai = Tehai::new("11122233344455566m", PlayerNumber::Four).unwrap();
+    assert_eq!(tehai.analyze(PlayerNumber::Four, None).unwrap().0, -1);
+    let decompositions = tehai.winning_decompositions(PlayerNumber::Four).unwrap();
+    assert!(!decompositions.is_empty());
+    assert!(decompositions
+        .iter()
+        .all(|decomposition| decomposition.hourakei == Hourakei::Mentsute
+            && decomposition.mentsu.len() == 5));
+}
+
+#[test]
+fn test_17_hai_machihai() {
+    let machihai = |string: &str| {
+        let tehai = Tehai::new(string, PlayerNumber::Four).unwrap();
+        let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+        assert_eq!(shanten, fast_shanten(&tehai, PlayerNumber::Four));
+        (
+            shanten,
+            conditions
+                .iter()
+                .map(|condition| {
+                    (
+                        condition.sutehai,
+                        condition.machihai.keys().copied().collect::<Vec<_>>(),
+                        condition.nokori(),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let (shanten, conditions) = machihai("123m456m789m123s45s11z9p");
+    assert_eq!(shanten, 0);
+    assert_eq!(
+        conditions,
+        vec![(Hai::Pinzu(9), vec![Hai::Souzu(3), Hai::Souzu(6)], 7)]
+    );
+
+    // Junsei chuurenpoutou with an extra juntsu keeps its 9 machihai.
+    let (shanten, conditions) = machihai("1112345678999m123s5z");
+    assert_eq!(shanten, 0);
+    assert_eq!(conditions[0].0, Hai::Jihai(5));
+    assert_eq!(conditions[0].1, (1..=9).map(Hai::Manzu).collect::<Vec<_>>());
+    assert_eq!(conditions[0].2, 23);
+
+    // Fuuro takes the place of a mentsu.
+    let (shanten, conditions) = machihai("[1111p]123m456m789m123s45s11z9p");
+    assert_eq!(shanten, 0);
+    assert_eq!(
+        conditions,
+        vec![(Hai::Pinzu(9), vec![Hai::Souzu(3), Hai::Souzu(6)], 7)]
+    );
+
+    let (shanten, _) = machihai("123m456m789m13s5s9p1234z");
+    assert_eq!(shanten, 3);
+}
+
+#[test]
+fn test_20_hai() {
+    let tehai = Tehai::new("123m456m789m123s456s78s11z3z", PlayerNumber::Four).unwrap();
+    let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, 0);
+    assert_eq!(fast_shanten(&tehai, PlayerNumber::Four), 0);
+    assert_eq!(conditions.len(), 1);
+    assert_eq!(conditions[0].sutehai, Hai::Jihai(3));
+    assert_eq!(
+        conditions[0].machihai.keys().copied().collect::<Vec<_>>(),
+        vec![Hai::Souzu(3), Hai::Souzu(6), Hai::Souzu(9)]
+    );
+    assert_eq!(conditions[0].nokori(), 10);
+
+    let tehai = Tehai::new("123m456m789m123s456s789s11z", PlayerNumber::Four).unwrap();
+    assert_eq!(tehai.analyze(PlayerNumber::Four, None).unwrap().0, -1);
+    assert!(tehai.is_agari(PlayerNumber::Four));
+
+    assert!(
+        Tehai::new("123m456m789m123s456s789s11z3z", PlayerNumber::Four)
+            .unwrap()
+            .analyze(PlayerNumber::Four, None)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_json_pretty() {
+    let mut compact = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let compact_output = compact.execute_batch(["1112345678999m1z", "+1z"]);
+    assert!(!compact_output.contains('\n'));
+
+    let mut pretty =
+        Controller::new(OutputFormat::Json, PlayerNumber::Four, false).with_json_pretty(true);
+    let pretty_output = pretty.execute_batch(["1112345678999m1z", "+1z"]);
+    assert!(pretty_output.contains('\n'));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&pretty_output).unwrap(),
+        serde_json::from_str::<serde_json::Value>(&compact_output).unwrap()
+    );
+}
+
+#[test]
+fn test_temporary_furiten() {
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1112345678999m", PlayerNumber::Four).unwrap(),
+        )))
+        .unwrap();
+    assert!(!game_manager.temporary_furiten());
+    game_manager.skip_ron().unwrap();
+    assert!(game_manager.temporary_furiten());
+    assert_eq!(game_manager.to_json()["temporary_furiten"], true);
+
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Add {
+            hai: Hai::Jihai(1),
+            haiyama_sensitive: true,
+        }))
+        .unwrap();
+    // Lasts until the next own discard, and is reported apart from furiten in analysis.
+    assert!(game_manager.temporary_furiten());
+    let (_, conditions) = game_manager.tehai_analyze().unwrap();
+    let condition = conditions
+        .iter()
+        .find(|condition| condition.sutehai == Hai::Jihai(1))
+        .unwrap();
+    assert!(condition.temporary_furiten);
+    assert!(!condition.furiten);
+    assert_eq!(condition.to_json()["temporary_furiten"], true);
+    assert!(condition.to_string().ends_with("!同巡内振り聴!"));
+    // Can not skip ron with full hai.
+    assert!(game_manager.skip_ron().is_err());
+
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Discard(Hai::Jihai(1))))
+        .unwrap();
+    assert!(!game_manager.temporary_furiten());
+    let (_, conditions) = Tehai::new("1112345678999m1z", PlayerNumber::Four)
+        .unwrap()
+        .analyze(PlayerNumber::Four, Some(&game_manager))
+        .unwrap();
+    assert!(conditions
+        .iter()
+        .all(|condition| !condition.temporary_furiten));
+
+    // Restored by undoing the discard, and kept by undoing the draw.
+    game_manager.back(true).unwrap();
+    assert!(game_manager.temporary_furiten());
+    game_manager.back(true).unwrap();
+    assert!(game_manager.temporary_furiten());
+    game_manager.back(true).unwrap();
+    assert!(!game_manager.temporary_furiten());
+}
+
+#[test]
+fn test_kuikae() {
+    let player_number = PlayerNumber::Four;
+    let chii = |hai: [Hai; 3]| {
+        Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Chii {
+                juntsu: Mentsu::new(&hai, player_number).unwrap(),
+                nakihai: hai[2],
+            },
+            haiyama_sensitive: true,
+            taken: vec![],
+        })
+    };
+    let discard = |hai: Hai| Operation::Tehai(TehaiOperation::Discard(hai));
+    let init = |kuikae_allowed: bool| {
+        let mut game_manager = GameManager::new(player_number).with_kuikae_allowed(kuikae_allowed);
+        game_manager
+            .operate(Operation::Tehai(TehaiOperation::Initialize(
+                Tehai::new("2345m456p789s112z", player_number).unwrap(),
+            )))
+            .unwrap();
+        game_manager
+    };
+
+    // Chii 1m with 23m: 1m is genbutsu and 4m is suji.
+    let mut game_manager = init(false);
+    assert!(game_manager.forbidden_discards().is_empty());
+    game_manager
+        .operate(chii([Hai::Manzu(2), Hai::Manzu(3), Hai::Manzu(1)]))
+        .unwrap();
+    assert_eq!(
+        game_manager.forbidden_discards(),
+        [Hai::Manzu(1), Hai::Manzu(4)].iter().copied().collect()
+    );
+    assert!(game_manager.operate(discard(Hai::Manzu(4))).is_err());
+    assert_eq!(game_manager.state, State::FullHai);
+    // Haiyama operations do not reset kuikae.
+    game_manager
+        .operate(Operation::Haiyama {
+            kind: HaiyamaOperation::Discard(vec![Hai::Jihai(7)]),
+            haiyama_sensitive: true,
+        })
+        .unwrap();
+    assert!(game_manager.operate(discard(Hai::Manzu(4))).is_err());
+    game_manager.operate(discard(Hai::Manzu(5))).unwrap();
+    assert!(game_manager.forbidden_discards().is_empty());
+
+    // Chii 4m with 35m in the middle: only genbutsu.
+    game_manager
+        .operate(chii([Hai::Manzu(3), Hai::Manzu(5), Hai::Manzu(4)]))
+        .unwrap_err();
+    let mut game_manager = init(false);
+    game_manager
+        .operate(chii([Hai::Manzu(2), Hai::Manzu(4), Hai::Manzu(3)]))
+        .unwrap();
+    assert_eq!(
+        game_manager.forbidden_discards(),
+        [Hai::Manzu(3)].iter().copied().collect()
+    );
+    game_manager.operate(discard(Hai::Manzu(3))).unwrap_err();
+    game_manager.operate(discard(Hai::Manzu(5))).unwrap();
+
+    // Pon forbids the same hai.
+    let mut game_manager = init(false);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Pon(Mentsu::Koutsu(Hai::Jihai(1))),
+            haiyama_sensitive: true,
+            taken: vec![],
+        }))
+        .unwrap();
+    assert_eq!(
+        game_manager.forbidden_discards(),
+        [Hai::Jihai(1)].iter().copied().collect()
+    );
+    assert!(game_manager.operate(discard(Hai::Jihai(1))).is_err());
+    // Still reported after back.
+    game_manager.operate(discard(Hai::Jihai(2))).unwrap();
+    game_manager.back(true).unwrap();
+    assert!(game_manager.forbidden_discards().contains(&Hai::Jihai(1)));
+
+    // Allowed by flag, which survives reinitialization.
+    let mut game_manager = init(true);
+    game_manager.reinitialize(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("2345m456p789s112z", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(chii([Hai::Manzu(2), Hai::Manzu(3), Hai::Manzu(1)]))
+        .unwrap();
+    assert!(!game_manager.forbidden_discards().is_empty());
+    game_manager.operate(discard(Hai::Manzu(4))).unwrap();
+}
+
+/// Linear congruential generator, enough for generating random operations.
+struct Lcg(u64);
+
+impl Lcg {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as usize % n
+    }
+
+    fn choose<T: Copy>(&mut self, vec: &[T]) -> Option<T> {
+        if vec.is_empty() {
+            None
+        } else {
+            Some(vec[self.below(vec.len())])
+        }
+    }
+}
+
+/// Generate a random legal operation for current state of game manager.
+fn random_operation(
+    game_manager: &GameManager,
+    player_number: PlayerNumber,
+    rng: &mut Lcg,
+) -> Operation {
+    // Aka are picked apart from five, so draws and calls also take them.
+    let available: Vec<Hai> = Hai::all_type(player_number)
+        .into_iter()
+        .flat_map(|hai| std::iter::once(hai).chain(hai.make_aka()))
+        .filter(|hai| game_manager.haiyama().remaining(hai) > 0)
+        .collect();
+    // Pon and kan name five for aka, so only call them while a five is left.
+    let available_type: Vec<Hai> = available
+        .iter()
+        .copied()
+        .filter(|hai| !hai.is_aka())
+        .collect();
+    let draw = |rng: &mut Lcg| {
+        Operation::Tehai(TehaiOperation::Add {
+            hai: rng.choose(&available).unwrap(),
+            haiyama_sensitive: true,
+        })
+    };
+    let wall = |rng: &mut Lcg| Operation::Haiyama {
+        kind: HaiyamaOperation::Discard(vec![rng.choose(&available).unwrap()]),
+        haiyama_sensitive: true,
+    };
+    let kan = |hai: Hai, rng: &mut Lcg| {
+        let rinshanhai = if rng.below(2) == 0 {
+            None
+        } else {
+            // Leave out the hai daiminkan may take from haiyama first.
+            let mut haiyama = game_manager.haiyama().clone();
+            let _ = haiyama.discard(&hai);
+            let candidates: Vec<Hai> = available
+                .iter()
+                .copied()
+                .filter(|rinshanhai| haiyama.remaining(rinshanhai) > 0)
+                .collect();
+            rng.choose(&candidates)
+        };
+        Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Kan(Kan::Unknown {
+                kantsu: Mentsu::Kantsu(hai),
+                rinshanhai,
+            }),
+            haiyama_sensitive: true,
+            taken: vec![],
+        })
+    };
+
+    match game_manager.state {
+        State::WaitToInit => {
+            let mut haiyama = game_manager.haiyama().clone();
+            let mut juntehai = vec![];
+            while juntehai.len() < 13 {
+                let hai = rng.choose(&available).unwrap();
+                if haiyama.discard(&hai).is_ok() {
+                    juntehai.push(hai);
+                }
+            }
+            let mut tehai = Tehai::new("", player_number).unwrap();
+            tehai.juntehai = juntehai;
+            tehai.juntehai.sort();
+            Operation::Tehai(TehaiOperation::Initialize(tehai))
+        }
+        State::WaitForRinshanhai => {
+            let tehai = game_manager.tehai().unwrap();
+            // Kan again if rinshanhai can be the fourth hai.
+            let ankan: Vec<Hai> = available_type
+                .iter()
+                .copied()
+                .filter(|hai| {
+                    tehai
+                        .juntehai
+                        .iter()
+                        .filter(|x| x.normalized() == *hai)
+                        .count()
+                        == 3
+                })
+                .collect();
+            match rng.below(2) {
+                0 if !ankan.is_empty() => kan(rng.choose(&ankan).unwrap(), rng),
+                _ => draw(rng),
+            }
+        }
+        State::FullHai => {
+            let tehai = game_manager.tehai().unwrap();
+            let count = |hai: &Hai| {
+                tehai
+                    .juntehai
+                    .iter()
+                    .filter(|x| x.normalized() == *hai)
+                    .count()
+            };
+            let ankan: Vec<Hai> = tehai
+                .juntehai
+                .iter()
+                .map(Hai::normalized)
+                .filter(|hai| count(hai) == 4)
+                .collect();
+            let kakan: Vec<Hai> = tehai
+                .fuuro
+                .iter()
+                .filter_map(|mentsu| match mentsu {
+                    Mentsu::Koutsu(hai) if count(hai) > 0 => Some(*hai),
+                    _ => None,
+                })
+                .collect();
+            // Four same hai are rare, so always take the chance of ankan.
+            if !ankan.is_empty() {
+                return kan(rng.choose(&ankan).unwrap(), rng);
+            }
+            match rng.below(6) {
+                0 | 1 if !kakan.is_empty() => kan(rng.choose(&kakan).unwrap(), rng),
+                2 => wall(rng),
+                _ => {
+                    let forbidden = game_manager.forbidden_discards();
+                    let discardable: Vec<Hai> = tehai
+                        .juntehai
+                        .iter()
+                        .copied()
+                        .filter(|hai| !forbidden.contains(&hai.normalized()))
+                        .collect();
+                    match rng.choose(&discardable) {
+                        Some(hai) => Operation::Tehai(TehaiOperation::Discard(hai)),
+                        None => wall(rng),
+                    }
+                }
+            }
+        }
+        State::LackOneHai => {
+            let tehai = game_manager.tehai().unwrap();
+            let count = |hai: &Hai| {
+                tehai
+                    .juntehai
+                    .iter()
+                    .filter(|x| x.normalized() == *hai)
+                    .count()
+            };
+            let pon: Vec<Hai> = available_type
+                .iter()
+                .copied()
+                .filter(|hai| count(hai) >= 2)
+                .collect();
+            let daiminkan: Vec<Hai> = available_type
+                .iter()
+                .copied()
+                .filter(|hai| count(hai) == 3)
+                .collect();
+            let mut chii = vec![];
+            for a in &tehai.juntehai {
+                for b in &tehai.juntehai {
+                    for c in &available {
+                        if a < b {
+                            if let Some(juntsu) = Mentsu::new(&[*a, *b, *c], player_number) {
+                                if let Mentsu::Juntsu(..) = juntsu {
+                                    chii.push((juntsu, *c));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            match rng.below(8) {
+                0 if !pon.is_empty() => Operation::Tehai(TehaiOperation::Naku {
+                    kind: Naku::Pon(Mentsu::Koutsu(rng.choose(&pon).unwrap())),
+                    haiyama_sensitive: true,
+                    taken: vec![],
+                }),
+                1 if !chii.is_empty() => {
+                    let (juntsu, nakihai) = rng.choose(&chii).unwrap();
+                    Operation::Tehai(TehaiOperation::Naku {
+                        kind: Naku::Chii { juntsu, nakihai },
+                        haiyama_sensitive: true,
+                        taken: vec![],
+                    })
+                }
+                2 if !daiminkan.is_empty() => kan(rng.choose(&daiminkan).unwrap(), rng),
+                // Draw the fourth hai to give ankan a chance.
+                3 if !daiminkan.is_empty() => Operation::Tehai(TehaiOperation::Add {
+                    hai: rng.choose(&daiminkan).unwrap(),
+                    haiyama_sensitive: true,
+                }),
+                4 => wall(rng),
+                _ => draw(rng),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_back_round_trip() {
+    let mut covered = HashSet::new();
+    for (seed, player_number) in (0..40u64).map(|seed| {
+        (
+            seed,
+            if seed % 4 == 0 {
+                PlayerNumber::Three
+            } else {
+                PlayerNumber::Four
+            },
+        )
+    }) {
+        let mut rng = Lcg(seed);
+        let mut game_manager = GameManager::new(player_number);
+        let mut snapshots = vec![];
+        for _ in 0..60 {
+            let op = random_operation(&game_manager, player_number, &mut rng);
+            covered.insert(match (&op, game_manager.state) {
+                (Operation::Tehai(TehaiOperation::Naku { kind, .. }), state) => match kind {
+                    Naku::Chii { .. } => "chii",
+                    Naku::Pon(..) => "pon",
+                    Naku::Kan(..) if state == State::LackOneHai => "daiminkan",
+                    Naku::Kan(Kan::Unknown {
+                        kantsu: Mentsu::Kantsu(hai),
+                        ..
+                    }) if game_manager
+                        .tehai()
+                        .unwrap()
+                        .fuuro
+                        .contains(&Mentsu::Koutsu(*hai)) =>
+                    {
+                        "kakan"
+                    }
+                    _ => "ankan",
+                },
+                (Operation::Tehai(TehaiOperation::Add { .. }), _) => "draw",
+                (Operation::Tehai(TehaiOperation::Discard(..)), _) => "discard",
+                (Operation::Haiyama { .. }, _) => "wall",
+                _ => "other",
+            });
+            let snapshot = (
+                game_manager.haiyama().clone(),
+                game_manager.tehai().cloned(),
+                game_manager.sutehai_type().clone(),
+                game_manager.state,
+            );
+            game_manager
+                .operate(op.clone())
+                .unwrap_or_else(|error| panic!("seed {}: {:?} failed: {}", seed, op, error));
+            match game_manager.history().last() {
+                Some((Operation::Tehai(TehaiOperation::Add { hai, .. }), ..)) if hai.is_aka() => {
+                    covered.insert("aka draw");
+                }
+                Some((
+                    Operation::Tehai(TehaiOperation::Naku {
+                        kind: Naku::Chii { nakihai, .. },
+                        ..
+                    }),
+                    ..,
+                )) if nakihai.is_aka() => {
+                    covered.insert("aka nakihai");
+                }
+                Some((Operation::Tehai(TehaiOperation::Naku { taken, .. }), ..))
+                    if taken.iter().any(Hai::is_aka) =>
+                {
+                    covered.insert("aka taken");
+                }
+                _ => (),
+            }
+            snapshots.push(snapshot);
+        }
+
+        while let Some((haiyama, tehai, sutehai_type, state)) = snapshots.pop() {
+            let (op, _) = game_manager
+                .back(true)
+                .unwrap_or_else(|error| panic!("seed {}: back failed: {}", seed, error));
+            assert_eq!(game_manager.haiyama(), &haiyama, "seed {}: {:?}", seed, op);
+            assert_eq!(
+                game_manager.tehai(),
+                tehai.as_ref(),
+                "seed {}: {:?}",
+                seed,
+                op
+            );
+            assert_eq!(game_manager.sutehai_type(), &sutehai_type);
+            assert_eq!(game_manager.state, state, "seed {}: {:?}", seed, op);
+        }
+        assert!(game_manager.history().is_empty());
+        assert_eq!(game_manager.haiyama(), &Haiyama::new(player_number));
+        assert!(game_manager.tehai().is_none());
+        assert_eq!(game_manager.state, State::WaitToInit);
+    }
+    for kind in &[
+        "draw",
+        "discard",
+        "chii",
+        "pon",
+        "daiminkan",
+        "ankan",
+        "kakan",
+        "wall",
+        "aka draw",
+        "aka nakihai",
+        "aka taken",
+    ] {
+        assert!(covered.contains(kind), "'{}' was never generated", kind);
+    }
+}
+
+#[test]
+fn test_back_naku_aka() {
+    let player_number = PlayerNumber::Four;
+    // Naku after `setup`, which must take exactly `taken` and give it back on undo.
+    let round_trip = |tehai: &str, setup: Vec<Operation>, kind: Naku, taken: Vec<Hai>| {
+        let mut game_manager = GameManager::new(player_number);
+        game_manager
+            .operate(Operation::Tehai(TehaiOperation::Initialize(
+                Tehai::new(tehai, player_number).unwrap(),
+            )))
+            .unwrap();
+        for op in setup {
+            game_manager.operate(op).unwrap();
+        }
+        let haiyama = game_manager.haiyama().clone();
+        let tehai = game_manager.tehai().cloned();
+        let state = game_manager.state;
+        game_manager
+            .operate(Operation::Tehai(TehaiOperation::Naku {
+                kind,
+                haiyama_sensitive: true,
+                taken: vec![],
+            }))
+            .unwrap();
+        assert!(matches!(
+            game_manager.history().last(),
+            Some((Operation::Tehai(TehaiOperation::Naku { taken: recorded, .. }), ..))
+                if *recorded == taken
+        ));
+        game_manager.back(true).unwrap();
+        assert_eq!(game_manager.state, state);
+        assert_eq!(game_manager.haiyama(), &haiyama);
+        assert_eq!(game_manager.tehai().cloned(), tehai);
+    };
+    let kan = |hai: Hai| {
+        Naku::Kan(Kan::Unknown {
+            kantsu: Mentsu::Kantsu(hai),
+            rinshanhai: None,
+        })
+    };
+
+    // Aka as nakihai.
+    round_trip(
+        "46p123456789s11z",
+        vec![],
+        Naku::Chii {
+            juntsu: Mentsu::Juntsu(Hai::Pinzu(4), Hai::Pinzu(5), Hai::Pinzu(6)),
+            nakihai: Hai::Pinzu(0),
+        },
+        vec![Hai::Pinzu(4), Hai::Pinzu(6)],
+    );
+    // Aka taken from juntehai.
+    round_trip(
+        "0p7p123456789s11z",
+        vec![],
+        Naku::Chii {
+            juntsu: Mentsu::Juntsu(Hai::Pinzu(5), Hai::Pinzu(6), Hai::Pinzu(7)),
+            nakihai: Hai::Pinzu(6),
+        },
+        vec![Hai::Pinzu(0), Hai::Pinzu(7)],
+    );
+    round_trip(
+        "05m123p456s789s11z",
+        vec![],
+        Naku::Pon(Mentsu::Koutsu(Hai::Manzu(5))),
+        vec![Hai::Manzu(5), Hai::Manzu(0)],
+    );
+    round_trip(
+        "055m123p456s789s1z",
+        vec![],
+        kan(Hai::Manzu(5)),
+        vec![Hai::Manzu(5), Hai::Manzu(5), Hai::Manzu(0)],
+    );
+    round_trip(
+        "0555m123p456s789s1z",
+        vec![],
+        kan(Hai::Manzu(5)),
+        vec![Hai::Manzu(5), Hai::Manzu(5), Hai::Manzu(5), Hai::Manzu(0)],
+    );
+    round_trip(
+        "55m123p456s789s11z",
+        vec![
+            Operation::Tehai(TehaiOperation::Naku {
+                kind: Naku::Pon(Mentsu::Koutsu(Hai::Manzu(5))),
+                haiyama_sensitive: true,
+                taken: vec![],
+            }),
+            Operation::Tehai(TehaiOperation::Discard(Hai::Jihai(1))),
+            Operation::Tehai(TehaiOperation::Add {
+                hai: Hai::Manzu(0),
+                haiyama_sensitive: true,
+            }),
+        ],
+        kan(Hai::Manzu(5)),
+        vec![Hai::Manzu(0)],
+    );
+}
+
+#[test]
+fn test_aka() {
+    assert!(Hai::Pinzu(0).is_aka());
+    assert!(!Hai::Pinzu(5).is_aka());
+    assert!(!Hai::Jihai(5).is_aka());
+    assert_eq!(Hai::Pinzu(0).normalized(), Hai::Pinzu(5));
+    assert_eq!(Hai::Souzu(3).normalized(), Hai::Souzu(3));
+    assert_eq!(Hai::Manzu(5).make_aka(), Some(Hai::Manzu(0)));
+    assert_eq!(Hai::Souzu(0).make_aka(), Some(Hai::Souzu(0)));
+    assert_eq!(Hai::Souzu(4).make_aka(), None);
+    assert_eq!(Hai::Jihai(5).make_aka(), None);
+    assert_eq!(Hai::Pinzu(0).to_string(), "0p");
+
+    assert!(Hai::check_iter_aka(
+        [Hai::Manzu(0), Hai::Pinzu(0), Hai::Souzu(0), Hai::Pinzu(5)].iter()
+    ));
+    assert!(!Hai::check_iter_aka(
+        [Hai::Pinzu(0), Hai::Pinzu(5), Hai::Pinzu(0)].iter()
+    ));
+}
+
+#[test]
+fn test_decomposition_count() {
+    let tehai = Tehai::new("2345678m234p5678s", PlayerNumber::Four).unwrap();
+    let (minimal, explored) = tehai.decomposition_count(PlayerNumber::Four).unwrap();
+    assert_eq!(minimal, 6);
+    assert!(explored > minimal);
+
+    let mut controller =
+        Controller::new(OutputFormat::Json, PlayerNumber::Four, false).with_diagnostics(true);
+    let output = execute(&mut controller, "2345678m234p5678s")
+        .unwrap()
+        .unwrap();
+    let json = serde_json::from_str::<serde_json::Value>(&output).unwrap();
+    assert_eq!(json["decompositions"]["minimal"], 6);
+    assert_eq!(json["decompositions"]["explored"], explored);
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let output = execute(&mut controller, "2345678m234p5678s")
+        .unwrap()
+        .unwrap();
+    assert!(!output.contains("decompositions"));
+
+    // Counted in the same pass as analysis, so the timeout also applies.
+    let (_, _, count) = tehai
+        .analyze_with_decomposition_count(PlayerNumber::Four, None, None)
+        .unwrap();
+    assert_eq!(count, (minimal, explored));
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false)
+        .with_diagnostics(true)
+        .with_timeout(Some(Duration::ZERO));
+    assert!(execute(&mut controller, "2345678m234p5678s")
+        .unwrap_err()
+        .starts_with("TIMEOUT"));
+}
+
+#[test]
+fn test_with_fuuro() {
+    let tehai = Tehai::with_fuuro(
+        vec![
+            Hai::Souzu(3),
+            Hai::Manzu(2),
+            Hai::Manzu(3),
+            Hai::Manzu(4),
+            Hai::Souzu(3),
+        ],
+        vec![Mentsu::Koutsu(Hai::Jihai(5)), Mentsu::Kantsu(Hai::Pinzu(1))],
+        PlayerNumber::Four,
+    )
+    .unwrap();
+    assert_eq!(
+        tehai,
+        Tehai::new("234m33s[555z][1111p]", PlayerNumber::Four).unwrap()
+    );
+    assert_eq!(
+        tehai.juntehai,
+        vec![
+            Hai::Manzu(2),
+            Hai::Manzu(3),
+            Hai::Manzu(4),
+            Hai::Souzu(3),
+            Hai::Souzu(3)
+        ]
+    );
+    assert_eq!(tehai.fuuro_tile_count(), 7);
+
+    // Fifth 1p with the kantsu.
+    assert!(Tehai::with_fuuro(
+        vec![Hai::Pinzu(1)],
+        vec![Mentsu::Kantsu(Hai::Pinzu(1))],
+        PlayerNumber::Four
+    )
+    .is_err());
+    // Not a juntsu.
+    assert!(Tehai::with_fuuro(
+        vec![],
+        vec![Mentsu::Juntsu(Hai::Pinzu(1), Hai::Pinzu(2), Hai::Pinzu(4))],
+        PlayerNumber::Four
+    )
+    .is_err());
+    // No 2m on 3-players mode.
+    assert!(Tehai::with_fuuro(vec![Hai::Manzu(2)], vec![], PlayerNumber::Three).is_err());
+}
+
+#[test]
+fn test_discard_matrix() {
+    let tehai = Tehai::new("123m456p13s57s55z27z", PlayerNumber::Four).unwrap();
+    let matrix = tehai
+        .discard_matrix(PlayerNumber::Four, None, None)
+        .unwrap();
+    let sutehai_set: HashSet<Hai> = matrix
+        .iter()
+        .map(|efficiency| efficiency.condition.sutehai)
+        .collect();
+    assert_eq!(sutehai_set, tehai.juntehai.iter().copied().collect());
+    assert_eq!(matrix.len(), sutehai_set.len());
+
+    // The best discards are the same as analysis.
+    let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(matrix[0].shanten, shanten);
+    assert_eq!(matrix[0].condition.sutehai, conditions[0].sutehai);
+    assert_eq!(matrix[0].condition.machihai, conditions[0].machihai);
+    assert_eq!(matrix[0].tenpai_machihai, Some(4));
+
+    // Discarding 1m makes shanten worse.
+    let discard_1m = matrix
+        .iter()
+        .find(|efficiency| efficiency.condition.sutehai == Hai::Manzu(1))
+        .unwrap();
+    assert_eq!(discard_1m.shanten, 2);
+    assert_eq!(discard_1m.tenpai_machihai, None);
+    assert_eq!(discard_1m.condition.nokori(), 15);
+}
+
+#[test]
+fn test_aka_tehai() {
+    let tehai = Tehai::new("2340m456p789s1122z", PlayerNumber::Four).unwrap();
+    assert!(tehai.juntehai.contains(&Hai::Manzu(0)));
+    // Aka is sorted as five, right after the normal one if any.
+    assert_eq!(tehai.to_json()["juntehai"][3], "0m");
+    let normal = Tehai::new("2345m456p789s1122z", PlayerNumber::Four).unwrap();
+    let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    let (normal_shanten, normal_conditions) = normal.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, normal_shanten);
+    assert_eq!(conditions.len(), normal_conditions.len());
+
+    // Aka is discarded only if there is no other five, and counted as five for machihai.
+    let tehai = Tehai::new("1112340m456p789s1z", PlayerNumber::Four).unwrap();
+    let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, 0);
+    assert!(conditions
+        .iter()
+        .any(|condition| condition.sutehai == Hai::Manzu(0)));
+    assert!(!conditions
+        .iter()
+        .any(|condition| condition.sutehai == Hai::Manzu(5)));
+    let discard_1z = conditions
+        .iter()
+        .find(|condition| condition.sutehai == Hai::Jihai(1))
+        .unwrap();
+    assert_eq!(discard_1z.machihai.get(&Hai::Manzu(5)), Some(&3));
+
+    // Aka in fuuro is regarded as five.
+    let tehai = Tehai::new("123m456p789s11z[406p]", PlayerNumber::Four).unwrap();
+    assert_eq!(
+        tehai.fuuro[0],
+        Mentsu::Juntsu(Hai::Pinzu(4), Hai::Pinzu(5), Hai::Pinzu(6))
+    );
+
+    assert!(Tehai::new("00m123p456s11122z", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("05555p123m456s112z", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("0m123p456s1112233z", PlayerNumber::Three).is_err());
+    assert!(Tehai::new("0z123p456s1112233z", PlayerNumber::Four).is_err());
+
+    let mut haiyama = Haiyama::new(PlayerNumber::Four);
+    assert_eq!(haiyama[&Hai::Pinzu(5)], 3);
+    assert_eq!(haiyama[&Hai::Pinzu(0)], 1);
+    assert_eq!(haiyama.count(&Hai::Pinzu(5)), 4);
+    // The fourth five is aka.
+    for _ in 0..4 {
+        haiyama.discard(&Hai::Pinzu(5)).unwrap();
+    }
+    assert_eq!(haiyama[&Hai::Pinzu(0)], 0);
+    assert!(haiyama.discard(&Hai::Pinzu(5)).is_err());
+    for _ in 0..4 {
+        haiyama.add(&Hai::Pinzu(5)).unwrap();
+    }
+    assert_eq!(haiyama, Haiyama::new(PlayerNumber::Four));
+    assert!(haiyama.add(&Hai::Pinzu(0)).is_err());
+}
+
+#[test]
+fn test_shanten() {
+    let shanten = |string: &str| {
+        Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .shanten(PlayerNumber::Four)
+    };
+    assert_eq!(shanten("123m456p789s11z222z"), Ok(-1));
+    assert_eq!(shanten("19m19p19s1234567z1z"), Ok(-1));
+    assert_eq!(shanten("1122m3344p5566s77z"), Ok(-1));
+    assert_eq!(shanten("1112345678999m1z"), Ok(0));
+    assert_eq!(shanten("123m456p11z[789s][2222z]"), Ok(-1));
+    assert_eq!(shanten("123m458p11z[789s][2222z]"), Ok(0));
+    assert_eq!(shanten("1357m1357p1357s22z"), Ok(3));
+    assert!(shanten("123m456p789s11z22z").is_err());
+
+    for string in &[
+        "1112345678999m1z",
+        "123m458p11z[789s][2222z]",
+        "2345678m234p5678s",
+    ] {
+        let tehai = Tehai::new(string, PlayerNumber::Four).unwrap();
+        let (expected, _) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+        assert_eq!(tehai.shanten(PlayerNumber::Four), Ok(expected));
+    }
+}
+
+#[test]
+fn test_is_agari() {
+    let is_agari = |string: &str, player_number: PlayerNumber| {
+        Tehai::new(string, player_number)
+            .unwrap()
+            .is_agari(player_number)
+    };
+    assert!(is_agari("123m456p789s11z222z", PlayerNumber::Four));
+    assert!(is_agari("11123456789999m", PlayerNumber::Four));
+    assert!(is_agari("19m19p19s1234567z1z", PlayerNumber::Four));
+    assert!(is_agari("1122m3344p5566s77z", PlayerNumber::Four));
+    assert!(is_agari("1122m3344p0566s77z", PlayerNumber::Four));
+    assert!(is_agari("123m456p11z[789s][2222z]", PlayerNumber::Four));
+    assert!(is_agari("11z[123m][456p][789s][2222z]", PlayerNumber::Four));
+    // Chiitoitsu needs 7 different toitsu.
+    assert!(!is_agari("1111m3344p5566s77z", PlayerNumber::Four));
+    // Leftover ukihai.
+    assert!(!is_agari("123m456p789s11z23z", PlayerNumber::Four));
+    assert!(!is_agari("123m458p11z[789s][2222z]", PlayerNumber::Four));
+    // Not 3*k+2.
+    assert!(!is_agari("123m456p789s11z22z", PlayerNumber::Four));
+    assert!(is_agari("99m123p456p789s111z", PlayerNumber::Three));
+
+    // Same as shanten -1 on random tehai.
+    let mut rng = Lcg(1503);
+    let mut agari_number = 0;
+    for _ in 0..200 {
+        let mut haiyama = Haiyama::new(PlayerNumber::Four);
+        let all_type: Vec<Hai> = Hai::all_type(PlayerNumber::Four).into_iter().collect();
+        // Use a narrow range of hai so that agari appears sometimes.
+        let all_type = &all_type[..12];
+        let mut juntehai = vec![];
+        while juntehai.len() < 14 {
+            let hai = rng.choose(all_type).unwrap();
+            if haiyama.discard(&hai).is_ok() {
+                juntehai.push(hai);
+            }
+        }
+        let tehai = Tehai::with_fuuro(juntehai, vec![], PlayerNumber::Four).unwrap();
+        let agari = tehai.is_agari(PlayerNumber::Four);
+        assert_eq!(
+            agari,
+            tehai.shanten(PlayerNumber::Four) == Ok(-1),
+            "{}",
+            tehai
+        );
+        if agari {
+            agari_number += 1;
+        }
+    }
+    assert!(agari_number > 0);
+}
+
+#[test]
+fn test_calculate_fu() {
+    let fu = |string: &str, agari_hai: Hai, is_tsumo: bool| {
+        Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .calculate_fu(agari_hai, is_tsumo, PlayerNumber::Four)
+    };
+    // Pinfu
+    assert_eq!(fu("123456m456p678s22s", Hai::Manzu(6), true), Ok(20));
+    assert_eq!(fu("123456m456p678s22s", Hai::Manzu(6), false), Ok(30));
+    // Kanchan and penchan
+    assert_eq!(fu("123456m456p678s22s", Hai::Manzu(5), false), Ok(40));
+    assert_eq!(fu("123456m456p678s22s", Hai::Manzu(5), true), Ok(30));
+    assert_eq!(fu("123456m456p789s22s", Hai::Souzu(7), false), Ok(40));
+    // Anko of yaochuupai
+    assert_eq!(fu("111z456p678s22s789m", Hai::Manzu(9), false), Ok(40));
+    // Koutsu completed by ron is minko.
+    assert_eq!(fu("222s333p456p678s11m", Hai::Pinzu(3), false), Ok(40));
+    assert_eq!(fu("222s333p456p678s11m", Hai::Pinzu(3), true), Ok(30));
+    // Toitsu of sangenpai
+    assert_eq!(fu("123456m456p678s55z", Hai::Manzu(6), false), Ok(40));
+    // Tanki
+    assert_eq!(fu("123456m456p678s55z", Hai::Jihai(5), true), Ok(30));
+    // Open tehai without fu
+    assert_eq!(fu("456m456p678s22s[123m]", Hai::Manzu(6), false), Ok(30));
+    // Kantsu in fuuro
+    assert_eq!(fu("456m456p678s22s[1111z]", Hai::Manzu(6), true), Ok(40));
+    // Ankan counts as anko, and keeps tehai menzen for ron.
+    assert_eq!(fu("456m456p678s22s a1111z", Hai::Manzu(6), true), Ok(60));
+    assert_eq!(fu("456m456p678s22s a1111z", Hai::Manzu(6), false), Ok(70));
+    // Chiitoitsu
+    assert_eq!(fu("1122m3344p5566s77z", Hai::Jihai(7), false), Ok(25));
+
+    assert!(fu("123456m456p678s23s", Hai::Manzu(6), false).is_err());
+    assert!(fu("123456m456p678s22s", Hai::Manzu(9), false).is_err());
+
+    // Toitsu of wind
+    let fu_with_winds = |string: &str, seat_wind: Hai, round_wind: Hai| {
+        Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .calculate_fu_with_context(
+                &AgariContext {
+                    agari_hai: Hai::Manzu(6),
+                    is_tsumo: false,
+                    riichi: false,
+                    seat_wind,
+                    round_wind,
+                    dora_indicators: vec![],
+                },
+                PlayerNumber::Four,
+            )
+    };
+    assert_eq!(
+        fu_with_winds("123456m456p678s33z", Hai::Jihai(2), Hai::Jihai(1)),
+        Ok(30)
+    );
+    assert_eq!(
+        fu_with_winds("123456m456p678s22z", Hai::Jihai(2), Hai::Jihai(1)),
+        Ok(40)
+    );
+    assert_eq!(
+        fu_with_winds("123456m456p678s11z", Hai::Jihai(2), Hai::Jihai(1)),
+        Ok(40)
+    );
+    // Double East gives 4 fu.
+    assert_eq!(
+        fu_with_winds("123456m999p678s11z", Hai::Jihai(2), Hai::Jihai(1)),
+        Ok(40)
+    );
+    assert_eq!(
+        fu_with_winds("123456m999p678s11z", Hai::Jihai(1), Hai::Jihai(1)),
+        Ok(50)
+    );
+}
+
+#[test]
+fn test_detect_yaku() {
+    let context = |agari_hai: Hai, is_tsumo: bool| AgariContext {
+        agari_hai,
+        is_tsumo,
+        riichi: false,
+        seat_wind: Hai::Jihai(2),
+        round_wind: Hai::Jihai(1),
+        dora_indicators: vec![],
+    };
+    let yaku = |string: &str, context: &AgariContext| {
+        Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .detect_yaku(context, PlayerNumber::Four)
+    };
+
+    // Pinfu, tanyao, iipeikou and sanshoku.
+    assert_eq!(
+        yaku("234456m234p234s66s", &context(Hai::Manzu(4), false)),
+        vec![Yaku::Pinfu, Yaku::Tanyao, Yaku::Sanshoku]
+    );
+    assert_eq!(
+        yaku("223344m234p234s66s", &context(Hai::Manzu(2), true)),
+        vec![
+            Yaku::MenzenTsumo,
+            Yaku::Pinfu,
+            Yaku::Tanyao,
+            Yaku::Iipeikou,
+            Yaku::Sanshoku
+        ]
+    );
+    // Kanchan is not pinfu.
+    assert_eq!(
+        yaku("234456m234p234s66s", &context(Hai::Manzu(5), false)),
+        vec![Yaku::Tanyao, Yaku::Sanshoku]
+    );
+    // Ittsuu with double wind of seat and round, but no kuisagari for yakuhai.
+    let mut double_east = context(Hai::Manzu(9), false);
+    double_east.seat_wind = Hai::Jihai(1);
+    assert_eq!(
+        yaku("123456789m111z22p", &double_east),
+        vec![
+            Yaku::Yakuhai(Hai::Jihai(1)),
+            Yaku::Yakuhai(Hai::Jihai(1)),
+            Yaku::Ittsuu
+        ]
+    );
+    // Toitoi and honitsu, open.
+    let toitoi = yaku("222m99m555z[777m][666z]", &context(Hai::Manzu(2), false));
+    assert_eq!(
+        toitoi,
+        vec![
+            Yaku::Yakuhai(Hai::Jihai(5)),
+            Yaku::Yakuhai(Hai::Jihai(6)),
+            Yaku::Toitoi,
+            Yaku::Honitsu
+        ]
+    );
+    assert_eq!(toitoi.iter().map(|yaku| yaku.han(false)).sum::<u32>(), 6);
+    // Chinitsu
+    assert_eq!(
+        yaku("11123455678999p", &context(Hai::Pinzu(5), false)),
+        vec![Yaku::Chinitsu]
+    );
+    // Junchan and chanta
+    assert_eq!(
+        yaku("123789m123p789s99s", &context(Hai::Souzu(9), false)),
+        vec![Yaku::Pinfu, Yaku::Junchan]
+    );
+    assert_eq!(
+        yaku("123789m123p11z[789s]", &context(Hai::Jihai(1), false)),
+        vec![Yaku::Chanta]
+    );
+    // Chiitoitsu and kokushimusou
+    assert_eq!(
+        yaku("2233m4466p5588s66z", &context(Hai::Jihai(6), false)),
+        vec![Yaku::Chiitoitsu]
+    );
+    assert_eq!(
+        yaku("19m19p19s1234567z1z", &context(Hai::Jihai(1), false)),
+        vec![Yaku::Kokushimusou]
+    );
+    // Riichi only counts for menzen.
+    let mut riichi = context(Hai::Manzu(9), false);
+    riichi.riichi = true;
+    assert_eq!(
+        yaku("123789m123p789s99s", &riichi),
+        vec![Yaku::Riichi, Yaku::Pinfu, Yaku::Junchan]
+    );
+    // Yakuless and not agari.
+    assert!(yaku("123456m22s678s[789p]", &context(Hai::Souzu(8), false)).is_empty());
+    assert!(yaku("123456m456p789s12z", &context(Hai::Jihai(1), false)).is_empty());
+}
+
+#[test]
+fn test_memoized_split() {
+    // Checked against fast_shanten, which does not split, on random tehai of every size and
+    // player number: each sutehai keeps the shanten, and machihai are exactly the hai
+    // reducing it.
+    let mut rng = Lcg(1506);
+    let mut corpus = vec![];
+    for &player_number in [PlayerNumber::Four, PlayerNumber::Three].iter() {
+        let all_type: Vec<Hai> = Hai::all_type(player_number).into_iter().collect();
+        for &size in [5, 8, 11, 14, 14, 14].iter() {
+            for _ in 0..20 {
+                let mut haiyama = Haiyama::new(player_number);
+                let mut juntehai = vec![];
+                while juntehai.len() < size {
+                    let hai = rng.choose(&all_type).unwrap();
+                    if haiyama.discard(&hai).is_ok() {
+                        juntehai.push(hai);
+                    }
+                }
+                corpus.push((
+                    Tehai::with_fuuro(juntehai, vec![], player_number).unwrap(),
+                    player_number,
+                ));
+            }
+        }
+    }
+    corpus.push((
+        Tehai::new("1112345678999m5p", PlayerNumber::Four).unwrap(),
+        PlayerNumber::Four,
+    ));
+    corpus.push((
+        Tehai::new("2233445566778m5p", PlayerNumber::Four).unwrap(),
+        PlayerNumber::Four,
+    ));
+
+    for (tehai, player_number) in corpus.iter() {
+        let player_number = *player_number;
+        let (shanten, conditions) = tehai.analyze(player_number, None).unwrap();
+        assert_eq!(shanten, fast_shanten(tehai, player_number), "{}", tehai);
+        for condition in conditions.iter() {
+            let mut discarded = tehai.clone();
+            let index = discarded
+                .juntehai
+                .iter()
+                .position(|hai| *hai == condition.sutehai)
+                .unwrap();
+            discarded.juntehai.remove(index);
+            assert_eq!(
+                fast_shanten(&discarded, player_number),
+                shanten,
+                "{}",
+                tehai
+            );
+
+            let expected: BTreeSet<Hai> = Hai::all_type(player_number)
+                .into_iter()
+                .filter(|hai| {
+                    let mut drawn = discarded.clone();
+                    drawn.juntehai.push(*hai);
+                    fast_shanten(&drawn, player_number) < shanten
+                })
+                .collect();
+            let mut machihai: BTreeSet<Hai> = condition.machihai.keys().copied().collect();
+            machihai.extend(condition.dead_machihai.iter());
+            assert_eq!(machihai, expected, "{} -{}", tehai, condition.sutehai);
+        }
+    }
+}
+
+#[test]
+fn test_discard_ukeire() {
+    let tehai = Tehai::new("123m456p13s57s55z27z", PlayerNumber::Four).unwrap();
+    let ukeire = tehai.discard_ukeire(PlayerNumber::Four, None);
+    let matrix = tehai
+        .discard_matrix(PlayerNumber::Four, None, None)
+        .unwrap();
+    assert_eq!(ukeire.len(), matrix.len());
+    for efficiency in matrix.iter() {
+        assert!(ukeire.contains(&(
+            efficiency.condition.sutehai,
+            efficiency.shanten,
+            efficiency.condition.nokori()
+        )));
+    }
+    assert!(ukeire.windows(2).all(|pair| pair[0].2 >= pair[1].2));
+    assert!(ukeire.contains(&(Hai::Manzu(1), 2, 15)));
+
+    let tehai = Tehai::new("123m456p13s57s55z2z", PlayerNumber::Four).unwrap();
+    assert!(tehai.discard_ukeire(PlayerNumber::Four, None).is_empty());
+}
+
+#[test]
+fn test_analyze_lookahead() {
+    let tehai = Tehai::new("13m46p79s1z", PlayerNumber::Four).unwrap();
+    assert!(tehai
+        .analyze_lookahead(2, PlayerNumber::Four, None)
+        .is_err());
+
+    let tehai = Tehai::new("13m46p79s15z", PlayerNumber::Four).unwrap();
+    assert!(tehai
+        .analyze_lookahead(0, PlayerNumber::Four, None)
+        .is_err());
+    assert!(tehai
+        .analyze_lookahead(3, PlayerNumber::Four, None)
+        .is_err());
+
+    // Depth 1 is the same as discard_ukeire.
+    let lookahead = tehai
+        .analyze_lookahead(1, PlayerNumber::Four, None)
+        .unwrap();
+    let mut ukeire = tehai.discard_ukeire(PlayerNumber::Four, None);
+    ukeire.sort_by_key(|ukeire| ukeire.0);
+    let mut from_lookahead: Vec<(Hai, i32, usize)> = lookahead
+        .iter()
+        .map(|&(hai, shanten, ukeire, average)| {
+            assert_eq!(ukeire as f64, average);
+            (hai, shanten, ukeire)
+        })
+        .collect();
+    from_lookahead.sort_by_key(|ukeire| ukeire.0);
+    assert_eq!(from_lookahead, ukeire);
+
+    // Discarding jihai keeps all three kanchan, which is the best after the next draw too.
+    let lookahead = tehai
+        .analyze_lookahead(2, PlayerNumber::Four, None)
+        .unwrap();
+    assert_eq!(lookahead.len(), 8);
+    assert!(matches!(lookahead[0].0, Hai::Jihai(_)));
+    assert_eq!(lookahead[0].2, 33);
+    assert!(lookahead
+        .iter()
+        .all(|&(_, _, _, average)| average > 0.0 && average <= lookahead[0].3));
+
+    // Tenpai has nothing to look ahead.
+    let tehai = Tehai::new("123m45p11z5z", PlayerNumber::Four).unwrap();
+    let lookahead = tehai
+        .analyze_lookahead(2, PlayerNumber::Four, None)
+        .unwrap();
+    assert_eq!(lookahead[0], (Hai::Jihai(5), 0, 8, 0.0));
+}
+
+#[test]
+fn test_naki_marker() {
+    let tehai = Tehai::new("123m456p11z c789s a2222z", PlayerNumber::Four).unwrap();
+    assert_eq!(
+        tehai.fuuro,
+        vec![
+            Mentsu::Juntsu(Hai::Souzu(7), Hai::Souzu(8), Hai::Souzu(9)),
+            Mentsu::Kantsu(Hai::Jihai(2)),
+        ]
+    );
+    assert_eq!(
+        tehai.ankan().iter().collect::<Vec<_>>(),
+        vec![&Hai::Jihai(2)]
+    );
+    assert!(tehai.is_called(&Mentsu::Juntsu(Hai::Souzu(7), Hai::Souzu(8), Hai::Souzu(9))));
+    assert!(!tehai.is_called(&Mentsu::Kantsu(Hai::Jihai(2))));
+    let json = tehai.to_json();
+    assert_eq!(json["fuuro"][0]["called"], true);
+    assert_eq!(json["fuuro"][1]["called"], false);
+
+    // Marker letters shared with suits.
+    let tehai = Tehai::new("123m11z p555m m7777s k1111p", PlayerNumber::Four).unwrap();
+    assert_eq!(tehai.fuuro.len(), 3);
+    assert!(tehai.ankan().is_empty());
+    // Old syntax is still called.
+    let tehai = Tehai::new("123m456p11z[789s][2222z]", PlayerNumber::Four).unwrap();
+    assert!(tehai.is_called(&Mentsu::Kantsu(Hai::Jihai(2))));
+
+    assert!(Tehai::new("123m456p11z p789s", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("123m456p11z c", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("123m456p11z pp555s", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("123m456p11z p[555s]", PlayerNumber::Four).is_err());
+    assert!(Tehai::new("123m456p11z [a555s]", PlayerNumber::Four).is_err());
+}
+
+#[test]
+fn test_tehai_from_json() {
+    let tehai = Tehai::new("123m406p789s1z c789s a2222z", PlayerNumber::Four).unwrap();
+    let from_json = Tehai::from_json(&tehai.to_json(), PlayerNumber::Four).unwrap();
+    assert_eq!(from_json, tehai);
+    assert_eq!(from_json.ankan(), tehai.ankan());
+
+    let value = serde_json::json!({
+        "juntehai": ["1m", "2m", "3m", "4p", "5p", "6p", "7s", "8s", "9s", "1z"],
+        "fuuro": [
+            {"type": "koutsu", "hai": "5z"},
+            {"type": "juntsu", "hai": ["3s", "1s", "2s"]}
+        ]
+    });
+    let tehai = Tehai::from_json(&value, PlayerNumber::Four).unwrap();
+    assert_eq!(
+        tehai,
+        Tehai::new("123m456p789s1z[555z][123s]", PlayerNumber::Four).unwrap()
+    );
+
+    let error =
+        |value: serde_json::Value| Tehai::from_json(&value, PlayerNumber::Four).unwrap_err();
+    assert_eq!(
+        error(serde_json::json!({"juntehai": ["1m", "1m", "1m", "1m", "1m"]})),
+        "Fifth 1m found."
+    );
+    assert_eq!(
+        error(serde_json::json!({"juntehai": ["0p", "0p"]})),
+        "More than one aka of a suit found."
+    );
+    error(serde_json::json!({"juntehai": "123m"}));
+    error(serde_json::json!({"juntehai": ["12m"]}));
+    error(
+        serde_json::json!({"juntehai": ["1m"], "fuuro": [{"type": "koutsu", "hai": ["1z", "2z", "3z"]}]}),
+    );
+    error(serde_json::json!({"juntehai": ["1m"], "fuuro": [{"type": "juntsu", "hai": "1m"}]}));
+    error(
+        serde_json::json!({"juntehai": ["1m"], "fuuro": [{"type": "kantsu", "hai": ["1z", "1z", "1z"]}]}),
+    );
+
+    // Same output and errors as string input.
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let mut execute = |command: String| execute(&mut controller, &command);
+    let value = serde_json::json!({
+        "juntehai": ["1m", "2m", "3m", "4p", "5p", "6p", "7s", "8s", "9s", "1z", "1z"],
+        "fuuro": [{"type": "koutsu", "hai": "5z"}]
+    });
+    assert_eq!(
+        execute(value.to_string()).unwrap(),
+        execute("123m456p789s11z[555z]".to_string()).unwrap()
+    );
+    let value = serde_json::json!({
+        "juntehai": ["1m", "2m", "3m", "4p", "5p", "6p", "7s", "8s", "9s", "1z"],
+        "fuuro": [{"type": "koutsu", "hai": "5z"}]
+    });
+    assert_eq!(
+        execute(value.to_string()).unwrap_err(),
+        execute("123m456p789s1z[555z]".to_string()).unwrap_err()
+    );
+    assert!(execute("{\"juntehai\":".to_string()).is_err());
+}
+
+#[test]
+fn test_haiyama_insensitive_clamp() {
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("123m456p789s1122z", PlayerNumber::Four).unwrap(),
+        )))
+        .unwrap();
+    let haiyama_operation = |kind: HaiyamaOperation, haiyama_sensitive: bool| Operation::Haiyama {
+        kind,
+        haiyama_sensitive,
+    };
+    let hai_vec = Hai::from_string_unordered("1111m2m", PlayerNumber::Four).unwrap();
+    assert!(game_manager
+        .operate(haiyama_operation(
+            HaiyamaOperation::Discard(hai_vec.clone()),
+            true
+        ))
+        .is_err());
+    assert_eq!(game_manager.haiyama().count(&Hai::Manzu(2)), 3);
+
+    // Keep 0 for 1m and still discard 2m.
+    game_manager
+        .operate(haiyama_operation(HaiyamaOperation::Discard(hai_vec), false))
+        .unwrap();
+    assert_eq!(game_manager.haiyama().count(&Hai::Manzu(1)), 0);
+    assert_eq!(game_manager.haiyama().count(&Hai::Manzu(2)), 2);
+
+    // Keep 4 for 3m and still add 2m.
+    let hai_vec = Hai::from_string_unordered("33m2m", PlayerNumber::Four).unwrap();
+    game_manager
+        .operate(haiyama_operation(HaiyamaOperation::Add(hai_vec), false))
+        .unwrap();
+    assert_eq!(game_manager.haiyama().count(&Hai::Manzu(3)), 4);
+    assert_eq!(game_manager.haiyama().count(&Hai::Manzu(2)), 3);
+}
+
+#[test]
+fn test_dora_from_indicator() {
+    let dora = |string: &str, player_number: PlayerNumber| {
+        Hai::from_string_unordered(string, player_number).unwrap()[0]
+            .dora_from_indicator(player_number)
+    };
+    assert_eq!(dora("3m", PlayerNumber::Four), Hai::Manzu(4));
+    assert_eq!(dora("9m", PlayerNumber::Four), Hai::Manzu(1));
+    assert_eq!(dora("9p", PlayerNumber::Four), Hai::Pinzu(1));
+    assert_eq!(dora("9s", PlayerNumber::Four), Hai::Souzu(1));
+    assert_eq!(dora("0p", PlayerNumber::Four), Hai::Pinzu(6));
+    // Winds and sangenpai loop separately.
+    assert_eq!(dora("3z", PlayerNumber::Four), Hai::Jihai(4));
+    assert_eq!(dora("4z", PlayerNumber::Four), Hai::Jihai(1));
+    assert_eq!(dora("5z", PlayerNumber::Four), Hai::Jihai(6));
+    assert_eq!(dora("7z", PlayerNumber::Four), Hai::Jihai(5));
+    // 3-players mode.
+    assert_eq!(dora("1m", PlayerNumber::Three), Hai::Manzu(9));
+    assert_eq!(dora("9m", PlayerNumber::Three), Hai::Manzu(1));
+    assert_eq!(dora("4z", PlayerNumber::Three), Hai::Jihai(1));
+
+    let hand = Hai::from_string_unordered("11m40p55z77z", PlayerNumber::Four).unwrap();
+    let count = |indicators: &str| {
+        let indicators = Hai::from_string_unordered(indicators, PlayerNumber::Four).unwrap();
+        Hai::count_dora(&hand, &indicators, PlayerNumber::Four)
+    };
+    assert_eq!(count(""), 0);
+    assert_eq!(count("9m"), 2);
+    assert_eq!(count("4p"), 1);
+    assert_eq!(count("9m9m"), 4);
+    assert_eq!(count("6z4z"), 2);
+    assert_eq!(count("4z9m3p"), 3);
+}
+
+#[test]
+fn test_haiyama_iter() {
+    let mut haiyama = Haiyama::new(PlayerNumber::Four);
+    assert_eq!(haiyama.remaining_total(), 136);
+    assert_eq!(haiyama.iter().count(), 37);
+    haiyama.discard(&Hai::Pinzu(5)).unwrap();
+    haiyama.discard(&Hai::Pinzu(0)).unwrap();
+    assert_eq!(haiyama.remaining_total(), 134);
+    assert_eq!(haiyama.remaining(&Hai::Pinzu(5)), 2);
+    assert_eq!(haiyama.remaining(&Hai::Pinzu(0)), 0);
+    assert_eq!(haiyama.count(&Hai::Pinzu(5)), 2);
+
+    // Same order as hai, and the same numbers as index.
+    let hai_vec: Vec<Hai> = haiyama.iter().map(|(hai, _)| *hai).collect();
+    assert!(hai_vec.windows(2).all(|pair| pair[0] < pair[1]));
+    for (hai, number) in haiyama.iter() {
+        assert_eq!(haiyama[hai], number);
+    }
+    assert_eq!(
+        haiyama.iter().map(|(_, number)| number as u32).sum::<u32>(),
+        haiyama.remaining_total()
+    );
+
+    // No panic for hai out of 3-players mode.
+    let haiyama = Haiyama::new(PlayerNumber::Three);
+    assert_eq!(haiyama.remaining(&Hai::Manzu(2)), 0);
+    assert_eq!(haiyama.remaining(&Hai::Manzu(0)), 0);
+    assert_eq!(haiyama.remaining_total(), 108);
+}
+
+#[test]
+fn test_haiyama_warnings() {
+    let mut haiyama = Haiyama::new(PlayerNumber::Four);
+    for _ in 0..4 {
+        assert_eq!(haiyama.discard_checked(&Hai::Jihai(1)), 1);
+    }
+    assert_eq!(haiyama.discard_checked(&Hai::Jihai(1)), 0);
+    assert_eq!(haiyama.count(&Hai::Jihai(1)), 0);
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+    let mut execute = |command: &str| execute(&mut controller, command);
+    execute("123m456p789s1122z").unwrap();
+    assert_eq!(execute("*-1z").unwrap(), None);
+    // Only one 1z left, the second one is ignored with a warning.
+    let output = execute("*!-11z").unwrap().unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(
+        json["warnings"],
+        serde_json::json!(["No '1z' left in haiyama, ignored."])
+    );
+    // Drawing an exhausted hai still analyzes, with the warning attached.
+    let output = execute("+!1z").unwrap().unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(
+        json["warnings"],
+        serde_json::json!(["No '1z' left in haiyama, ignored."])
+    );
+    assert!(json.as_object().unwrap().len() > 1);
+    // Warnings are only for the last operation.
+    let output = execute("-1z").unwrap();
+    assert_eq!(output, None);
+    assert!(execute("*-1z").is_err());
+}
+
+#[test]
+fn test_canonical_string() {
+    let canonical = |string: &str| {
+        Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .to_canonical_string()
+    };
+    assert_eq!(
+        canonical("45p 8s14 4m[11 1z]2 5m44p 3m"),
+        "123445m4445p8s[111z]"
+    );
+    assert_eq!(canonical("1z[789s]2z[123m]1z"), "112z[123m][789s]");
+    assert_eq!(canonical("5p0p5p1p 4m a2222z c789s"), "4m1550p[789s]a2222z");
+    assert_eq!(canonical(""), "");
+
+    // Idempotent, and the same tehai after parsing again.
+    for string in [
+        "45p 8s14 4m[11 1z]2 5m44p 3m",
+        "5p0p5p1p 4m a2222z c789s",
+        "19m19p19s1234567z1z",
+    ]
+    .iter()
+    {
+        let tehai = Tehai::new(string, PlayerNumber::Four).unwrap();
+        let canonical = tehai.to_canonical_string();
+        let parsed = Tehai::new(&canonical, PlayerNumber::Four).unwrap();
+        assert_eq!(parsed, tehai);
+        assert_eq!(parsed.to_canonical_string(), canonical);
+    }
+}
+
+#[test]
+fn test_winds() {
+    let game_manager = GameManager::new(PlayerNumber::Four);
+    assert_eq!(game_manager.seat_wind(), Hai::Jihai(1));
+    assert_eq!(game_manager.round_wind(), Hai::Jihai(1));
+
+    let mut game_manager = GameManager::new(PlayerNumber::Four)
+        .with_winds(Hai::Jihai(3), Hai::Jihai(2))
+        .unwrap();
+    assert_eq!(game_manager.seat_wind(), Hai::Jihai(3));
+    assert_eq!(game_manager.round_wind(), Hai::Jihai(2));
+    assert_eq!(game_manager.to_json()["seat_wind"], "3z");
+    assert_eq!(game_manager.to_json()["round_wind"], "2z");
+    game_manager.reinitialize(PlayerNumber::Three);
+    assert_eq!(game_manager.seat_wind(), Hai::Jihai(3));
+
+    assert!(GameManager::new(PlayerNumber::Four)
+        .with_winds(Hai::Jihai(5), Hai::Jihai(1))
+        .is_err());
+    assert!(GameManager::new(PlayerNumber::Four)
+        .with_winds(Hai::Jihai(1), Hai::Manzu(1))
+        .is_err());
+}
+
+#[test]
+fn test_dora_indicators() {
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .add_dora_indicators(&[Hai::Manzu(3), Hai::Jihai(4)], false)
+        .unwrap();
+    assert_eq!(game_manager.dora(), vec![Hai::Manzu(4), Hai::Jihai(1)]);
+    assert_eq!(game_manager.haiyama()[&Hai::Manzu(3)], 4);
+    game_manager
+        .add_dora_indicators(&[Hai::Jihai(7)], true)
+        .unwrap();
+    assert_eq!(game_manager.haiyama()[&Hai::Jihai(7)], 3);
+    assert_eq!(
+        game_manager.to_json()["dora_indicators"],
+        serde_json::json!(["3m", "4z", "7z"])
+    );
+    assert_eq!(
+        game_manager.to_json()["dora"],
+        serde_json::json!(["4m", "1z", "5z"])
+    );
+    // At most 5 indicators.
+    assert!(game_manager
+        .add_dora_indicators(&[Hai::Pinzu(1), Hai::Pinzu(2), Hai::Pinzu(3)], false)
+        .is_err());
+    assert_eq!(game_manager.dora_indicators().len(), 3);
+
+    // Nothing changes if haiyama has no such hai left.
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .operate(Operation::Haiyama {
+            kind: HaiyamaOperation::Discard(vec![Hai::Souzu(9); 4]),
+            haiyama_sensitive: true,
+        })
+        .unwrap();
+    assert!(game_manager
+        .add_dora_indicators(&[Hai::Souzu(1), Hai::Souzu(9)], true)
+        .is_err());
+    assert!(game_manager.dora_indicators().is_empty());
+    assert_eq!(game_manager.haiyama()[&Hai::Souzu(1)], 4);
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, true);
+    let output = execute(&mut controller, "dora* 9p").unwrap().unwrap();
+    assert_eq!(output, "ドラ表示牌：9p\nドラ：1p");
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, false);
+    assert!(execute(&mut controller, "dora 9p").is_err());
+}
+
+#[test]
+fn test_analyze_verbose() {
+    let tehai = Tehai::new("12335m4569p789s11z", PlayerNumber::Four).unwrap();
+    let (shanten, conditions, breakdown_vec) =
+        tehai.analyze_verbose(PlayerNumber::Four, None).unwrap();
+    let (expected_shanten, expected_conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, expected_shanten);
+    assert_eq!(
+        format!("{:?}", conditions),
+        format!("{:?}", expected_conditions)
+    );
+    assert_eq!(breakdown_vec.len(), 1);
+    let breakdown = &breakdown_vec[0];
+    assert_eq!(breakdown.hourakei, Hourakei::Mentsute);
+    assert_eq!(breakdown.mentsu.len(), 3);
+    assert_eq!(breakdown.toitsu, vec![Toitsu(Hai::Jihai(1))]);
+    assert_eq!(breakdown.taatsu, vec![Taatsu(Hai::Manzu(3), Hai::Manzu(5))]);
+    assert!(breakdown.valid_ukihai.is_empty());
+    assert_eq!(breakdown.invalid_ukihai, vec![Ukihai(Hai::Pinzu(9))]);
+    assert_eq!(
+        breakdown.to_string(),
+        "[1m2m3m] [4p5p6p] [7s8s9s] 1z1z 3m5m (9p)"
+    );
+    assert_eq!(
+        breakdown.to_json()["invalid_ukihai"],
+        serde_json::json!(["9p"])
+    );
+
+    // Chiitoitsu and mentsute of the same shanten are both reported, mentsute first.
+    let tehai = Tehai::new("1122m3344p5566s17z", PlayerNumber::Four).unwrap();
+    let (_, _, breakdown_vec) = tehai.analyze_verbose(PlayerNumber::Four, None).unwrap();
+    assert!(breakdown_vec
+        .windows(2)
+        .all(|pair| (pair[0].hourakei, pair[0].to_string())
+            < (pair[1].hourakei, pair[1].to_string())));
+    assert!(breakdown_vec
+        .iter()
+        .any(|breakdown| breakdown.hourakei == Hourakei::Chiitoitsu));
+}
+
+#[test]
+fn test_wait_kind() {
+    let wait_kind = |string: &str, sutehai: Hai| {
+        let (_, conditions) = Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .analyze(PlayerNumber::Four, None)
+            .unwrap();
+        conditions
+            .iter()
+            .find(|condition| condition.sutehai == sutehai)
+            .unwrap()
+            .wait_kind()
+    };
+    assert_eq!(
+        wait_kind("123m456s789s11z459p", Hai::Pinzu(9)),
+        Some(WaitKind::Ryanmen)
+    );
+    assert_eq!(
+        wait_kind("123m456s789s11z469p", Hai::Pinzu(9)),
+        Some(WaitKind::Kanchan)
+    );
+    assert_eq!(
+        wait_kind("123m456s789s11z129p", Hai::Pinzu(9)),
+        Some(WaitKind::Penchan)
+    );
+    assert_eq!(
+        wait_kind("123m456s789s1122z9p", Hai::Pinzu(9)),
+        Some(WaitKind::Shanpon)
+    );
+    assert_eq!(
+        wait_kind("123m456s1234p111z9s", Hai::Pinzu(1)),
+        Some(WaitKind::Tanki)
+    );
+    assert_eq!(
+        wait_kind("123m456s1234p111z9s", Hai::Souzu(9)),
+        Some(WaitKind::Nobetan)
+    );
+    assert_eq!(
+        wait_kind("1112345678999m1z", Hai::Jihai(1)),
+        Some(WaitKind::Multi)
+    );
+    assert_eq!(
+        wait_kind("1122m3344p5566s17z", Hai::Jihai(7)),
+        Some(WaitKind::Tanki)
+    );
+    assert_eq!(
+        wait_kind("19m19p19s1234567z5p", Hai::Pinzu(5)),
+        Some(WaitKind::Multi)
+    );
+    // Not tenpai after discarding.
+    assert_eq!(wait_kind("123m456s789s13z469p", Hai::Pinzu(9)), None);
+
+    let (_, conditions) = Tehai::new("123m456s789s11z469p", PlayerNumber::Four)
+        .unwrap()
+        .analyze(PlayerNumber::Four, None)
+        .unwrap();
+    assert_eq!(conditions[0].to_json()["wait_kind"], "kanchan");
+}
+
+#[test]
+fn test_tehai_from_counts() {
+    let from_counts = |manzu, pinzu, souzu, jihai, fuuro| {
+        Tehai::from_counts(manzu, pinzu, souzu, jihai, fuuro, PlayerNumber::Four)
+    };
+    let cases = [
+        (
+            "123m456p789s11222z",
+            [1, 1, 1, 0, 0, 0, 0, 0, 0],
+            [0, 0, 0, 1, 1, 1, 0, 0, 0],
+            [0, 0, 0, 0, 0, 0, 1, 1, 1],
+            [2, 3, 0, 0, 0, 0, 0],
+            vec![],
+        ),
+        (
+            "19m19p19s12345677z",
+            [1, 0, 0, 0, 0, 0, 0, 0, 1],
+            [1, 0, 0, 0, 0, 0, 0, 0, 1],
+            [1, 0, 0, 0, 0, 0, 0, 0, 1],
+            [1, 1, 1, 1, 1, 1, 2],
+            vec![],
+        ),
+        (
+            "11123456789m[456p]",
+            [3, 1, 1, 1, 1, 1, 1, 1, 1],
+            [0; 9],
+            [0; 9],
+            [0; 7],
+            vec![Mentsu::Juntsu(Hai::Pinzu(4), Hai::Pinzu(5), Hai::Pinzu(6))],
+        ),
+        (
+            "55s[777z][1111m]",
+            [0; 9],
+            [0; 9],
+            [0, 0, 0, 0, 2, 0, 0, 0, 0],
+            [0; 7],
+            vec![Mentsu::Koutsu(Hai::Jihai(7)), Mentsu::Kantsu(Hai::Manzu(1))],
+        ),
+    ];
+    for (string, manzu, pinzu, souzu, jihai, fuuro) in cases.iter() {
+        let tehai = from_counts(*manzu, *pinzu, *souzu, *jihai, fuuro.clone()).unwrap();
+        let expected = Tehai::new(string, PlayerNumber::Four).unwrap();
+        assert_eq!(tehai.juntehai, expected.juntehai);
+        assert_eq!(tehai.fuuro, expected.fuuro);
+        assert_eq!(
+            tehai.analyze(PlayerNumber::Four, None).unwrap().0,
+            expected.analyze(PlayerNumber::Four, None).unwrap().0
+        );
+    }
+
+    // More than 4 of a hai.
+    assert!(from_counts([5, 0, 0, 0, 0, 0, 0, 0, 0], [0; 9], [0; 9], [0; 7], vec![]).is_err());
+    // Not 3*k+2.
+    assert!(from_counts([3, 0, 0, 0, 0, 0, 0, 0, 0], [0; 9], [0; 9], [0; 7], vec![]).is_err());
+    // Fifth hai together with fuuro.
+    assert!(from_counts(
+        [2, 0, 0, 0, 0, 0, 0, 0, 0],
+        [0; 9],
+        [0; 9],
+        [0; 7],
+        vec![Mentsu::Koutsu(Hai::Manzu(1))]
+    )
+    .is_err());
+    // No 2m in 3-player mode.
+    assert!(Tehai::from_counts(
+        [0, 2, 0, 0, 0, 0, 0, 0, 0],
+        [0; 9],
+        [0; 9],
+        [0; 7],
+        vec![],
+        PlayerNumber::Three
+    )
+    .is_err());
+}
+
+#[test]
+fn test_sanma_manzu_no_taatsu() {
+    let player_number = PlayerNumber::Three;
+    let has_manzu_shape = |string: &str| {
+        let (_, _, breakdown_vec) = Tehai::new(string, player_number)
+            .unwrap()
+            .analyze_verbose(player_number, None)
+            .unwrap();
+        breakdown_vec.iter().any(|breakdown| {
+            breakdown
+                .taatsu
+                .iter()
+                .any(|Taatsu(lhs, _)| matches!(lhs, Hai::Manzu(_)))
+                || breakdown
+                    .mentsu
+                    .iter()
+                    .any(|mentsu| matches!(mentsu, Mentsu::Juntsu(Hai::Manzu(_), ..)))
+        })
+    };
+
+    // 1m and 9m are never a taatsu, even though they are next to each other with dora loop.
+    let tehai = Tehai::new("19m123p456s789s11z2z", player_number).unwrap();
+    assert_eq!(tehai.shanten(player_number).unwrap(), 1);
+    assert!(!has_manzu_shape("19m123p456s789s11z2z"));
+
+    // Manzu terminals work as toitsu and koutsu.
+    let tehai = Tehai::new("1199m123p456s789s1z", player_number).unwrap();
+    assert_eq!(tehai.shanten(player_number).unwrap(), 0);
+    assert!(!has_manzu_shape("1199m123p456s789s1z"));
+    let (_, conditions) = tehai.analyze(player_number, None).unwrap();
+    assert_eq!(conditions.len(), 1);
+    assert_eq!(conditions[0].sutehai, Hai::Jihai(1));
+    assert_eq!(
+        conditions[0].machihai.keys().copied().collect::<Vec<Hai>>(),
+        vec![Hai::Manzu(1), Hai::Manzu(9)]
+    );
+    let tehai = Tehai::new("111999m123p456s11z", player_number).unwrap();
+    assert!(tehai.is_agari(player_number));
+    assert_eq!(tehai.shanten(player_number).unwrap(), -1);
+
+    // Kokushimusou without 2m ~ 8m.
+    let tehai = Tehai::new("19m19p19s1234567z1m", player_number).unwrap();
+    assert_eq!(tehai.shanten(player_number).unwrap(), -1);
+}
+
+#[test]
+fn test_csv_output() {
+    let mut controller = Controller::new(OutputFormat::Csv, PlayerNumber::Four, false);
+    let output = execute(&mut controller, "123m456s789s1122z9p")
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        output,
+        "# shanten,0\nsutehai,machihai,count,furiten\n9p,1z 2z,4,false"
+    );
+
+    let output = execute(&mut controller, "123m456s789s13z469p")
+        .unwrap()
+        .unwrap();
+    let mut lines = output.lines();
+    assert_eq!(lines.next(), Some("# shanten,1"));
+    assert_eq!(lines.next(), Some("sutehai,machihai,count,furiten"));
+    for line in lines {
+        assert_eq!(line.split(',').count(), 4);
+    }
+
+    let output = execute(&mut controller, "123m456s789s11z222z")
+        .unwrap()
+        .unwrap();
+    assert_eq!(output, "# shanten,-1\nsutehai,machihai,count,furiten");
+}
+
+#[test]
+fn test_execute_batch() {
+    let lines = "123m456s789s1122z9p\n\n1z\n123m456s789s11z222z\n";
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let output: serde_json::Value =
+        serde_json::from_str(&controller.execute_batch(lines.lines())).unwrap();
+    let results = output.as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["shanten_number"], 0);
+    assert_eq!(results[1]["line"], 3);
+    assert!(results[1]["error"].is_string());
+    assert_eq!(results[2]["shanten_number"], -1);
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, false);
+    let output = controller.execute_batch(lines.lines());
+    assert!(output.contains("聴牌"));
+    assert!(output.contains("3行目："));
+    assert!(output.contains("和了"));
+
+    // Stop at exit.
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let output = controller.execute_batch("exit\n123m456s789s1122z9p".lines());
+    assert_eq!(output, "[]");
+}
+
+#[test]
+fn test_draw_probability() {
+    let tehai = Tehai::new("123m456s789s1122z9p", PlayerNumber::Four).unwrap();
+    let (_, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(conditions[0].draw_probability, None);
+    assert!(conditions[0].to_json().get("draw_probability").is_none());
+
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("123m456s789s1122z", PlayerNumber::Four).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Add {
+            hai: Hai::Pinzu(9),
+            haiyama_sensitive: true,
+        }))
+        .unwrap();
+    let (_, conditions) = game_manager.tehai_analyze().unwrap();
+    let haiyama = game_manager.haiyama();
+    // 136 - 14 hai left, and 2 of 1z and 2z each.
+    assert_eq!(haiyama.remaining_total(), 122);
+    assert_eq!(conditions[0].draw_probability, Some(4.0 / 122.0));
+    assert_eq!(conditions[0].draw_probability(haiyama), 4.0 / 122.0);
+    assert_eq!(
+        conditions[0].to_json()["draw_probability"],
+        serde_json::json!(4.0 / 122.0)
+    );
+}
+
+#[test]
+fn test_consecutive_kan() {
+    let player_number = PlayerNumber::Four;
+    let kan = |hai: Hai| {
+        Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Kan(Kan::Unknown {
+                kantsu: Mentsu::Kantsu(hai),
+                rinshanhai: None,
+            }),
+            haiyama_sensitive: true,
+            taken: vec![],
+        })
+    };
+    let mut game_manager = GameManager::new(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1111222m345p678s", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Add {
+            hai: Hai::Jihai(5),
+            haiyama_sensitive: true,
+        }))
+        .unwrap();
+    game_manager.operate(kan(Hai::Manzu(1))).unwrap();
+    assert_eq!(game_manager.state, State::WaitForRinshanhai);
+
+    // Rinshanhai is 2m, which makes another ankan.
+    game_manager.operate(kan(Hai::Manzu(2))).unwrap();
+    assert_eq!(game_manager.state, State::WaitForRinshanhai);
+    let tehai = game_manager.tehai().unwrap();
+    assert_eq!(tehai.fuuro.len(), 2);
+    assert!(tehai.ankan().contains(&Hai::Manzu(2)));
+    assert_eq!(game_manager.haiyama()[&Hai::Manzu(2)], 0);
+    assert!(matches!(
+        game_manager.history().last(),
+        Some((
+            Operation::Tehai(TehaiOperation::Naku {
+                kind: Naku::Kan(Kan::Ankan { .. }),
+                ..
+            }),
+            State::WaitForRinshanhai,
+            ..
+        ))
+    ));
+
+    // Impossible kan changes nothing.
+    assert!(game_manager.operate(kan(Hai::Pinzu(3))).is_err());
+    assert_eq!(game_manager.state, State::WaitForRinshanhai);
+    assert_eq!(game_manager.haiyama()[&Hai::Pinzu(3)], 3);
+    assert_eq!(game_manager.tehai().unwrap().juntehai.len(), 7);
+
+    // Back undoes both the kan and drawing 2m.
+    game_manager.back(true).unwrap();
+    assert_eq!(game_manager.state, State::WaitForRinshanhai);
+    let tehai = game_manager.tehai().unwrap();
+    assert_eq!(tehai.fuuro.len(), 1);
+    assert_eq!(
+        tehai
+            .juntehai
+            .iter()
+            .filter(|hai| **hai == Hai::Manzu(2))
+            .count(),
+        3
+    );
+    assert_eq!(game_manager.haiyama()[&Hai::Manzu(2)], 1);
+
+    // With four in tehai already, rinshanhai has to be drawn first.
+    let mut game_manager = GameManager::new(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("11112222m345p67s", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Add {
+            hai: Hai::Souzu(9),
+            haiyama_sensitive: true,
+        }))
+        .unwrap();
+    game_manager.operate(kan(Hai::Manzu(1))).unwrap();
+    assert!(game_manager.operate(kan(Hai::Manzu(2))).is_err());
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Add {
+            hai: Hai::Souzu(8),
+            haiyama_sensitive: true,
+        }))
+        .unwrap();
+    game_manager.operate(kan(Hai::Manzu(2))).unwrap();
+    assert_eq!(game_manager.state, State::WaitForRinshanhai);
+}
+
+#[test]
+fn test_mahjong_error() {
+    let error = Tehai::new("11111m345p678s11z", PlayerNumber::Four).unwrap_err();
+    assert_eq!(error, MahjongError::FifthTile(Hai::Manzu(1)));
+    assert_eq!(error.to_string(), "Fifth 1m found.");
+    // All types over the limit are reported at once, fuuro included.
+    let error = Tehai::new("33333m777777p1z[333z]", PlayerNumber::Four).unwrap_err();
+    assert_eq!(
+        error,
+        MahjongError::TooManyTiles(vec![(Hai::Manzu(3), 5), (Hai::Pinzu(7), 6),])
+    );
+    assert_eq!(error.to_string(), "Too many: 3m(5), 7p(6).");
+    let error = Tehai::new("3333m0555p12z[555p]", PlayerNumber::Four).unwrap_err();
+    assert_eq!(error, MahjongError::FifthTile(Hai::Pinzu(5)));
+    let error = Tehai::new("2m345p678s111222z", PlayerNumber::Three).unwrap_err();
+    assert_eq!(error, MahjongError::InvalidTile(Hai::Manzu(2)));
+    assert!(matches!(
+        Tehai::new("123m456p789s1", PlayerNumber::Four),
+        Err(MahjongError::Other(_))
+    ));
+
+    let mut haiyama = Haiyama::new(PlayerNumber::Four);
+    for _ in 0..4 {
+        haiyama.discard(&Hai::Jihai(7)).unwrap();
+    }
+    let error = haiyama.discard(&Hai::Jihai(7)).unwrap_err();
+    assert_eq!(error, MahjongError::HaiyamaExhausted(Hai::Jihai(7)));
+    assert_eq!(
+        error.to_string(),
+        "Already no '7z' in haiyama, cannot discard more one."
+    );
+
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    let error = game_manager
+        .operate(Operation::Tehai(TehaiOperation::Discard(Hai::Manzu(1))))
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        MahjongError::IllegalState {
+            state: State::WaitToInit,
+            ..
+        }
+    ));
+    assert!(error.to_string().starts_with("Unsupported opretion"));
+    let error = game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("123m456p789s1z", PlayerNumber::Four).unwrap(),
+        )))
+        .unwrap_err();
+    assert_eq!(
+        error,
+        MahjongError::WrongTileCount {
+            expected: vec![13, 14],
+            got: 10
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "Cannot initialize tehai with 10 juntehai, only 13 and 14 are supported."
+    );
+
+    let error: Box<dyn std::error::Error> = Box::new(error);
+    assert!(error.to_string().starts_with("Cannot initialize tehai"));
+    let message: String = MahjongError::FifthTile(Hai::Souzu(5)).into();
+    assert_eq!(message, "Fifth 5s found.");
+}
+
+#[test]
+fn test_serde_round_trip() {
+    let hai: Hai = serde_json::from_str("\"0p\"").unwrap();
+    assert_eq!(hai, Hai::Pinzu(0));
+    assert_eq!(serde_json::to_string(&Hai::Jihai(7)).unwrap(), "\"7z\"");
+    assert!(serde_json::from_str::<Hai>("\"12m\"").is_err());
+    assert!(serde_json::from_str::<Hai>("\"8z\"").is_err());
+
+    let player_number = PlayerNumber::Four;
+    let mut game_manager = GameManager::new(player_number)
+        .with_winds(Hai::Jihai(2), Hai::Jihai(1))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1112345678999m1z", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Discard(Hai::Jihai(1))))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Pon(Mentsu::Koutsu(Hai::Manzu(9))),
+            haiyama_sensitive: true,
+            taken: vec![],
+        }))
+        .unwrap();
+
+    let string = serde_json::to_string(&game_manager).unwrap();
+    let mut restored: GameManager = serde_json::from_str(&string).unwrap();
+    assert_eq!(format!("{:?}", restored), format!("{:?}", game_manager));
+    assert_eq!(restored.to_json(), game_manager.to_json());
+    assert_eq!(restored.state, State::FullHai);
+    assert_eq!(restored.history().len(), 3);
+    assert_eq!(restored.seat_wind(), Hai::Jihai(2));
+
+    // History is restored, so the state machine can go back.
+    restored.back(true).unwrap();
+    assert_eq!(restored.state, State::LackOneHai);
+    assert_eq!(restored.tehai().unwrap().juntehai.len(), 13);
+    assert_eq!(restored.haiyama()[&Hai::Manzu(9)], 1);
+
+    let tehai = Tehai::new("123m456p789s11z[555z]", player_number).unwrap();
+    let restored: Tehai = serde_json::from_str(&serde_json::to_string(&tehai).unwrap()).unwrap();
+    assert_eq!(restored, tehai);
+    let haiyama = game_manager.haiyama().clone();
+    let restored: Haiyama =
+        serde_json::from_str(&serde_json::to_string(&haiyama).unwrap()).unwrap();
+    assert_eq!(restored, haiyama);
+    let taatsu = Taatsu(Hai::Souzu(4), Hai::Souzu(5));
+    let restored: Taatsu = serde_json::from_str(&serde_json::to_string(&taatsu).unwrap()).unwrap();
+    assert_eq!(restored, taatsu);
+    let toitsu = Toitsu(Hai::Jihai(3));
+    let restored: Toitsu = serde_json::from_str(&serde_json::to_string(&toitsu).unwrap()).unwrap();
+    assert_eq!(restored, toitsu);
+}
+
+#[cfg(feature = "bincode")]
+#[test]
+fn test_bytes_round_trip() {
+    let player_number = PlayerNumber::Four;
+    let mut game_manager = GameManager::new(player_number)
+        .with_winds(Hai::Jihai(2), Hai::Jihai(1))
+        .unwrap();
+    for op in [
+        Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1112345678999m0p", player_number).unwrap(),
+        )),
+        Operation::Tehai(TehaiOperation::Discard(Hai::Pinzu(0))),
+        Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Pon(Mentsu::Koutsu(Hai::Manzu(9))),
+            haiyama_sensitive: true,
+            taken: vec![],
+        }),
+    ] {
+        game_manager.operate(op).unwrap();
+    }
+    game_manager
+        .add_dora_indicators(&[Hai::Souzu(3)], false)
+        .unwrap();
+
+    let bytes = game_manager.to_bytes().unwrap();
+    let mut restored = GameManager::from_bytes(&bytes).unwrap();
+    assert_eq!(format!("{:?}", restored), format!("{:?}", game_manager));
+    assert_eq!(restored.to_json(), game_manager.to_json());
+    restored.back(true).unwrap();
+    assert_eq!(restored.state, State::LackOneHai);
+
+    assert!(GameManager::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+}
+
+#[test]
+fn test_save_load() {
+    let directory = std::env::temp_dir();
+    let path = directory.join(format!("jmt_save_load_{}.json", std::process::id()));
+    let path_string = path.to_str().unwrap().to_string();
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Three, true);
+    for command in ["1112345678999p", "+1z", "-1z", ">!999p"].iter() {
+        execute(&mut controller, command).unwrap();
+    }
+    let display = execute(&mut controller, "d").unwrap().unwrap();
+    execute(&mut controller, &format!("save {}", path_string)).unwrap();
+
+    // Load in non-interactive mode of another player number.
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let output = execute(&mut controller, &format!("load {}", path_string))
+        .unwrap()
+        .unwrap();
+    assert_eq!(output, display);
+    let state: serde_json::Value =
+        serde_json::from_str(&execute(&mut controller, "s").unwrap().unwrap()).unwrap();
+    assert_eq!(state["haiyama"].as_array().unwrap().len(), 29);
+    let history: serde_json::Value =
+        serde_json::from_str(&execute(&mut controller, "log").unwrap().unwrap()).unwrap();
+    assert_eq!(history["history"].as_array().unwrap().len(), 4);
+    execute(&mut controller, "b").unwrap();
+    assert!(execute(&mut controller, "d").is_err());
+
+    // Inconsistent saves are rejected, and the game loaded before is kept.
+    let save: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let mut no_tehai = save.clone();
+    no_tehai["tehai"] = serde_json::Value::Null;
+    let mut missing_hai = save.clone();
+    missing_hai["haiyama"]["map"]
+        .as_object_mut()
+        .unwrap()
+        .remove("1p");
+    let mut fifth_hai = save.clone();
+    fifth_hai["haiyama"]["map"]["1z"] = serde_json::json!(5);
+    for invalid in [no_tehai, missing_hai, fifth_hai] {
+        std::fs::write(&path, invalid.to_string()).unwrap();
+        let error = execute(&mut controller, &format!("load {}", path_string)).unwrap_err();
+        assert!(error.starts_with("Invalid save file"));
+    }
+    assert!(execute(&mut controller, "b").is_ok());
+
+    std::fs::write(&path, "{\"haiyama\":").unwrap();
+    let error = execute(&mut controller, &format!("load {}", path_string)).unwrap_err();
+    assert!(error.starts_with("Invalid save file"));
+    std::fs::remove_file(&path).unwrap();
+    let error = execute(&mut controller, &format!("load {}", path_string)).unwrap_err();
+    assert!(error.starts_with("Cannot read"));
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, false);
+    assert!(execute(&mut controller, &format!("save {}", path_string)).is_err());
+}
+
+#[test]
+fn test_reset() {
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Three, true);
+    for command in ["1112345678999p", "+1z", "-1z", "*-5s5s"].iter() {
+        execute(&mut controller, command).unwrap();
+    }
+    assert_eq!(execute(&mut controller, "reset"), Ok(None));
+
+    let state: serde_json::Value =
+        serde_json::from_str(&execute(&mut controller, "s").unwrap().unwrap()).unwrap();
+    assert_eq!(state["tehai"], "Not initialized.");
+    let haiyama = Haiyama::new(PlayerNumber::Three);
+    assert_eq!(state["haiyama"], haiyama.to_json());
+    let history: serde_json::Value =
+        serde_json::from_str(&execute(&mut controller, "log").unwrap().unwrap()).unwrap();
+    assert!(history["history"].as_array().unwrap().is_empty());
+
+    // Still interactive, 3-player and json.
+    assert!(execute(&mut controller, "2m").is_err());
+    assert!(execute(&mut controller, "1112345678999p")
+        .unwrap()
+        .is_none());
+    assert!(matches!(controller.output_format(), OutputFormat::Json));
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    assert!(execute(&mut controller, "reset").is_err());
+}
+
+#[test]
+fn test_kita() {
+    let player_number = PlayerNumber::Three;
+    let kita = Operation::Tehai(TehaiOperation::Kita {
+        haiyama_sensitive: true,
+    });
+    let count_4z = |game_manager: &GameManager| {
+        game_manager
+            .tehai()
+            .unwrap()
+            .juntehai
+            .iter()
+            .filter(|hai| **hai == Hai::Jihai(4))
+            .count()
+    };
+    let mut game_manager = GameManager::new(player_number);
+    assert!(game_manager.operate(kita.clone()).is_err());
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("19m123p456s789s144z", player_number).unwrap(),
+        )))
+        .unwrap();
+
+    // Kita from tehai with 14 hai.
+    game_manager.operate(kita.clone()).unwrap();
+    assert_eq!(game_manager.state, State::LackOneHai);
+    assert_eq!(game_manager.kita(), 1);
+    assert_eq!(count_4z(&game_manager), 1);
+    assert_eq!(game_manager.haiyama()[&Hai::Jihai(4)], 2);
+
+    // Kita right after drawing a 4z.
+    game_manager.operate(kita.clone()).unwrap();
+    assert_eq!(game_manager.state, State::LackOneHai);
+    assert_eq!(game_manager.kita(), 2);
+    assert_eq!(game_manager.haiyama()[&Hai::Jihai(4)], 1);
+    assert_eq!(game_manager.to_json()["kita"], 2);
+    assert!(game_manager.to_string().contains("北抜き"));
+
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Add {
+            hai: Hai::Pinzu(5),
+            haiyama_sensitive: true,
+        }))
+        .unwrap();
+    assert_eq!(game_manager.tehai().unwrap().juntehai.len(), 14);
+
+    game_manager.back(true).unwrap();
+    game_manager.back(true).unwrap();
+    assert_eq!(game_manager.kita(), 1);
+    assert_eq!(game_manager.haiyama()[&Hai::Jihai(4)], 2);
+    game_manager.back(true).unwrap();
+    assert_eq!(game_manager.state, State::FullHai);
+    assert_eq!(game_manager.kita(), 0);
+    assert_eq!(count_4z(&game_manager), 2);
+
+    // Only on 3-players mode.
+    let player_number = PlayerNumber::Four;
+    let mut game_manager = GameManager::new(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("123m123p456s789s44z", player_number).unwrap(),
+        )))
+        .unwrap();
+    assert!(game_manager.operate(kita).is_err());
+    assert_eq!(count_4z(&game_manager), 2);
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Three, true);
+    execute(&mut controller, "19m123p456s789s144z").unwrap();
+    assert_eq!(execute(&mut controller, "kita"), Ok(None));
+}
+
+#[test]
+fn test_tehai_validate() {
+    let three = PlayerNumber::Three;
+    let four = PlayerNumber::Four;
+    let tehai = Tehai::new("123p456s789s11z[111m]", three).unwrap();
+    assert!(tehai.validate(three).is_ok());
+    assert!(Tehai::new("123p406s789s11z[234m]", four)
+        .unwrap()
+        .validate(four)
+        .is_ok());
+
+    // Fields edited directly.
+    let mut tehai = Tehai::new("123p456s789s11z", four).unwrap();
+    tehai.fuuro = vec![Mentsu::Juntsu(Hai::Manzu(2), Hai::Manzu(3), Hai::Manzu(4))];
+    assert!(tehai.validate(four).is_ok());
+    assert!(tehai
+        .validate(three)
+        .unwrap_err()
+        .to_string()
+        .starts_with("Invalid mentsu '[2m3m4m]'"));
+    let mut tehai = Tehai::new("11234p", four).unwrap();
+    tehai.fuuro = vec![
+        Mentsu::Koutsu(Hai::Pinzu(1)),
+        Mentsu::Juntsu(Hai::Pinzu(1), Hai::Pinzu(2), Hai::Pinzu(3)),
+    ];
+    assert_eq!(
+        tehai.validate(four),
+        Err(MahjongError::FifthTile(Hai::Pinzu(1)))
+    );
+    let mut tehai = Tehai::new("11z", four).unwrap();
+    tehai.juntehai = Hai::from_string_unordered("0p0p", four).unwrap();
+    assert!(tehai.validate(four).is_err());
+
+    // Rejected by command parsing before reaching the game.
+    let mut controller = Controller::new(OutputFormat::Json, three, false);
+    assert!(execute(&mut controller, "123p456s789s11z[234m]").is_err());
+    assert!(execute(&mut controller, "123p456s789s11z[111m]").is_ok());
+}
+
+#[test]
+fn test_defense_hint() {
+    let player_number = PlayerNumber::Four;
+    let mut game_manager = GameManager::new(player_number);
+    for op in [
+        Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("13579m13579s1234z", player_number).unwrap(),
+        )),
+        Operation::Haiyama {
+            kind: HaiyamaOperation::Discard(
+                Hai::from_string_unordered("8888s222p7777z", player_number).unwrap(),
+            ),
+            haiyama_sensitive: true,
+        },
+    ]
+    .iter()
+    {
+        game_manager.operate(op.clone()).unwrap();
+    }
+
+    let mut value = serde_json::to_value(&game_manager).unwrap();
+    value["sutehai_type"] = serde_json::json!(["4m", "0p"]);
+    let game_manager: GameManager = serde_json::from_value(value).unwrap();
+    let defense_hint = game_manager.defense_hint();
+    assert_eq!(defense_hint.len(), 34);
+    assert_eq!(defense_hint[&Hai::Manzu(4)], Safety::Genbutsu);
+    assert_eq!(defense_hint[&Hai::Pinzu(5)], Safety::Genbutsu);
+    assert_eq!(defense_hint[&Hai::Manzu(1)], Safety::Suji);
+    assert_eq!(defense_hint[&Hai::Manzu(7)], Safety::Suji);
+    assert_eq!(defense_hint[&Hai::Pinzu(8)], Safety::Suji);
+    assert_eq!(defense_hint[&Hai::Pinzu(2)], Safety::Suji);
+    assert_eq!(defense_hint[&Hai::Souzu(9)], Safety::NoChance);
+    assert_eq!(defense_hint[&Hai::Souzu(7)], Safety::Dangerous);
+    assert_eq!(defense_hint[&Hai::Pinzu(1)], Safety::OneChance);
+    assert_eq!(defense_hint[&Hai::Pinzu(4)], Safety::Dangerous);
+    assert_eq!(defense_hint[&Hai::Jihai(7)], Safety::NoChance);
+    assert_eq!(defense_hint[&Hai::Jihai(1)], Safety::Dangerous);
+
+    let mut controller = Controller::new(OutputFormat::Json, player_number, true);
+    execute(&mut controller, "*-8888s").unwrap();
+    let output: serde_json::Value =
+        serde_json::from_str(&execute(&mut controller, "defense").unwrap().unwrap()).unwrap();
+    assert_eq!(output["genbutsu"], serde_json::json!([]));
+    assert_eq!(output["no_chance"], serde_json::json!(["9s"]));
+    assert_eq!(output["dangerous"].as_array().unwrap().len(), 33);
+    execute(&mut controller, "std").unwrap();
+    let output = execute(&mut controller, "defense").unwrap().unwrap();
+    assert!(output.starts_with("現物：無し\n"));
+    assert!(output.contains("ノーチャンス：9s\n"));
+}
+
+#[test]
+fn test_wait_shapes() {
+    // Juntehai waiting, and all of its machihai. `7z` is added to each hand and
+    // discarded, so that juntehai is 3*k+1 after discarding.
+    let table = [
+        ("5m", "5m"),
+        ("1255m", "3m"),
+        ("1344m", "2m"),
+        ("2355m", "1m4m"),
+        ("1122m", "1m2m"),
+        ("1223m", "2m"),
+        ("3445m", "4m"),
+        ("2344m", "1m4m"),
+        ("4566m", "3m6m"),
+        ("1112m", "2m3m"),
+        ("1113m", "2m3m"),
+        ("3334m", "2m4m5m"),
+        ("2345m", "2m5m"),
+        ("6789s", "6s9s"),
+        ("1234567m", "1m4m7m"),
+        ("3456789m", "3m6m9m"),
+        ("2223444m", "1m2m3m4m5m"),
+        ("3334555s", "2s3s4s5s6s"),
+        ("2223456m", "1m3m4m6m7m"),
+        ("2345678m", "2m5m8m"),
+        ("4445666m", "3m4m5m6m7m"),
+        ("1112223m", "1m2m3m4m"),
+        ("1344p", "2p"),
+        ("2355p", "1p4p"),
+        ("4566p", "3p6p"),
+        ("1255p", "3p"),
+        ("8955p", "7p"),
+        ("2345p", "2p5p"),
+        ("3456789p", "3p6p9p"),
+        ("8955s", "7s"),
+        ("1112345678999m", "123456789m"),
+    ];
+    let machihai = |tehai: &str, sutehai: Hai| {
+        let tehai = Tehai::new(tehai, PlayerNumber::Four).unwrap();
+        let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+        assert_eq!(shanten, 0, "{}", tehai);
+        conditions
+            .iter()
+            .find(|condition| condition.sutehai == sutehai)
+            .unwrap_or_else(|| panic!("No condition discarding {} for {}", sutehai, tehai))
+            .machihai
+            .keys()
+            .copied()
+            .collect::<Vec<Hai>>()
+    };
+    let sorted = |string: &str| {
+        let mut hai_vec = Hai::from_string_unordered(string, PlayerNumber::Four).unwrap();
+        hai_vec.sort();
+        hai_vec
+    };
+    for (string, expected) in table.iter() {
+        assert_eq!(
+            machihai(&format!("{}7z", string), Hai::Jihai(7)),
+            sorted(expected),
+            "{}",
+            string
+        );
+    }
+
+    // Kokushimusou waiting for the one missing, or all 13 yaochuupai.
+    assert_eq!(machihai("19m19p19s1234566z5m", Hai::Manzu(5)), sorted("7z"));
+    assert_eq!(
+        machihai("19m19p19s1234567z5m", Hai::Manzu(5)),
+        sorted("19m19p19s1234567z")
+    );
+}
+
+#[test]
+fn test_acceptance() {
+    let player_number = PlayerNumber::Four;
+    let to_map = |string: &str| {
+        Hai::from_string_unordered(string, player_number)
+            .unwrap()
+            .into_iter()
+            .map(|hai| (hai, 3))
+            .collect::<std::collections::BTreeMap<Hai, u8>>()
+    };
+
+    let tehai = Tehai::new("123m456p789s1234z", player_number).unwrap();
+    assert_eq!(
+        tehai.acceptance(player_number, None).unwrap(),
+        (2, to_map("1234z"))
+    );
+
+    // For tenpai, it equals machihai.
+    let tehai = Tehai::new("123m456p789s1122z", player_number).unwrap();
+    let (shanten, acceptance) = tehai.acceptance(player_number, None).unwrap();
+    assert_eq!(shanten, 0);
+    let (_, conditions) = Tehai::new("123m456p789s1122z5z", player_number)
+        .unwrap()
+        .analyze(player_number, None)
+        .unwrap();
+    let condition = conditions
+        .iter()
+        .find(|condition| condition.sutehai == Hai::Jihai(5))
+        .unwrap();
+    assert_eq!(acceptance, condition.machihai);
+    assert_eq!(acceptance.len(), 2);
+
+    // Counts follow haiyama, without hai used up.
+    let mut game_manager = GameManager::new(player_number);
+    for op in [
+        Operation::Tehai(TehaiOperation::Initialize(tehai.clone())),
+        Operation::Haiyama {
+            kind: HaiyamaOperation::Discard(
+                Hai::from_string_unordered("11z2z", player_number).unwrap(),
+            ),
+            haiyama_sensitive: true,
+        },
+    ]
+    .iter()
+    {
+        game_manager.operate(op.clone()).unwrap();
+    }
+    let (_, acceptance) = tehai
+        .acceptance(player_number, Some(&game_manager))
+        .unwrap();
+    assert_eq!(acceptance.len(), 1);
+    assert_eq!(acceptance[&Hai::Jihai(2)], 1);
+
+    assert!(Tehai::new("123m456p789s11223z", player_number)
+        .unwrap()
+        .acceptance(player_number, None)
+        .is_err());
+}
+
+#[test]
+fn test_forced_kan() {
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+    execute(&mut controller, "1112345678999m").unwrap();
+    // Daiminkan of 1m, but it is not an ankan or kakan.
+    assert!(execute(&mut controller, ">a1111m").is_err());
+    assert!(execute(&mut controller, ">k1111m").is_err());
+    assert!(execute(&mut controller, ">d111m").is_err());
+    execute(&mut controller, ">d1111m5s").unwrap();
+    let history: serde_json::Value =
+        serde_json::from_str(&execute(&mut controller, "log").unwrap().unwrap()).unwrap();
+    assert_eq!(
+        history["history"][1]["operation"]["operation"]["naku"]["kan"]["type"],
+        "daiminkan"
+    );
+
+    let player_number = PlayerNumber::Four;
+    let ankan = |hai: Hai| {
+        Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Kan(Kan::Ankan {
+                kantsu: Mentsu::Kantsu(hai),
+                rinshanhai: None,
+            }),
+            haiyama_sensitive: true,
+            taken: vec![],
+        })
+    };
+    let mut game_manager = GameManager::new(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1111222m345p678s5z", player_number).unwrap(),
+        )))
+        .unwrap();
+    assert!(game_manager.operate(ankan(Hai::Manzu(2))).is_err());
+    assert_eq!(game_manager.state, State::FullHai);
+    game_manager.operate(ankan(Hai::Manzu(1))).unwrap();
+    assert_eq!(game_manager.state, State::WaitForRinshanhai);
+    // Rinshanhai is 2m, and kan again.
+    game_manager.operate(ankan(Hai::Manzu(2))).unwrap();
+    assert!(game_manager
+        .tehai()
+        .unwrap()
+        .ankan()
+        .contains(&Hai::Manzu(2)));
+
+    // Aka is counted as five, and the forced type is kept in history.
+    let mut game_manager = GameManager::new(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("0555m345p678s5z112z", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager.operate(ankan(Hai::Manzu(5))).unwrap();
+    assert!(matches!(
+        game_manager.history().last(),
+        Some((
+            Operation::Tehai(TehaiOperation::Naku {
+                kind: Naku::Kan(Kan::Ankan { .. }),
+                ..
+            }),
+            ..
+        ))
+    ));
+
+    let mut game_manager = GameManager::new(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1122m345p678s555z", player_number).unwrap(),
+        )))
+        .unwrap();
+    assert!(game_manager
+        .operate(Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Kan(Kan::Daiminkan {
+                kantsu: Mentsu::Kantsu(Hai::Manzu(1)),
+                rinshanhai: None,
+            }),
+            haiyama_sensitive: true,
+            taken: vec![],
+        }))
+        .is_err());
+}
+
+#[test]
+fn test_score() {
+    let ron = |han, fu, is_dealer, kiriage| score(han, fu, is_dealer, false, kiriage).ron;
+    let tsumo = |han, fu, is_dealer| {
+        let score = score(han, fu, is_dealer, true, false);
+        (score.tsumo_non_dealer, score.tsumo_dealer)
+    };
+
+    assert_eq!(ron(1, 30, false, false), 1000);
+    assert_eq!(ron(1, 110, false, false), 3600);
+    assert_eq!(ron(3, 30, false, false), 3900);
+    assert_eq!(ron(3, 30, true, false), 5800);
+    assert_eq!(ron(2, 25, false, false), 1600);
+    // Fu is rounded up.
+    assert_eq!(ron(2, 32, false, false), ron(2, 40, false, false));
+    assert_eq!(tsumo(1, 30, false), (300, 500));
+    assert_eq!(tsumo(2, 20, false), (400, 700));
+    assert_eq!(tsumo(2, 25, false), (400, 800));
+    assert_eq!(tsumo(2, 20, true), (700, 0));
+
+    // Rounding edge with or without kiriage mangan.
+    assert_eq!(ron(4, 30, false, false), 7700);
+    assert_eq!(ron(3, 60, false, false), 7700);
+    assert_eq!(ron(4, 30, true, false), 11600);
+    assert_eq!(ron(4, 30, false, true), 8000);
+    assert_eq!(ron(3, 60, false, true), 8000);
+    assert_eq!(ron(4, 30, true, true), 12000);
+
+    // Limits.
+    assert_eq!(ron(4, 40, false, false), 8000);
+    assert_eq!(ron(5, 30, false, false), 8000);
+    assert_eq!(ron(6, 30, false, false), 12000);
+    assert_eq!(ron(7, 30, true, false), 18000);
+    assert_eq!(ron(8, 30, false, false), 16000);
+    assert_eq!(ron(11, 30, false, false), 24000);
+    assert_eq!(ron(13, 30, false, false), 32000);
+    assert_eq!(ron(20, 30, true, false), 48000);
+    assert_eq!(tsumo(5, 30, false), (2000, 4000));
+    assert_eq!(tsumo(5, 30, true), (4000, 0));
+
+    assert_eq!(score(3, 40, false, true, false).total(), 5200);
+    assert_eq!(score(3, 40, true, true, false).total(), 7800);
+    assert_eq!(score(3, 40, true, false, false).total(), 7700);
+    assert_eq!(
+        score(0, 30, false, false, false),
+        Score {
+            base: 0,
+            ron: 0,
+            tsumo_non_dealer: 0,
+            tsumo_dealer: 0,
+        }
+    );
+}
+
+#[test]
+fn test_unicode() {
+    assert_eq!(Hai::Manzu(1).to_unicode(), '🀇');
+    assert_eq!(Hai::Souzu(9).to_unicode(), '🀘');
+    assert_eq!(Hai::Pinzu(1).to_unicode(), '🀙');
+    // Aka is rendered as the normal five.
+    assert_eq!(Hai::Pinzu(0).to_unicode(), '🀝');
+    let jihai: String = (1..=7).map(|num| Hai::Jihai(num).to_unicode()).collect();
+    assert_eq!(jihai, "🀀🀁🀂🀃🀆🀅🀄");
+
+    let tehai = Tehai::new("19m0s77z[1111p]", PlayerNumber::Four).unwrap();
+    assert_eq!(tehai.to_unicode_string(), "🀇🀏🀔🀄🀄[🀙🀙🀙🀙]");
+
+    let mut controller =
+        Controller::new(OutputFormat::Standard, PlayerNumber::Four, false).with_unicode(true);
+    let output = execute(&mut controller, "123m456p789s11223z")
+        .unwrap()
+        .unwrap();
+    assert!(output.starts_with("手牌：🀇🀈🀉🀜🀝🀞🀖🀗🀘🀀🀀🀁🀁🀂\n"));
+    assert!(output.contains("打 🀂 摸 🀀 🀁  残り4枚"));
+}
+
+#[test]
+fn test_unseen() {
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+    for command in ["123m055p789s1122z3z", "*-7777z5p"].iter() {
+        execute(&mut controller, command).unwrap();
+    }
+    let output = execute(&mut controller, "unseen").unwrap().unwrap();
+    let unseen: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(unseen.as_object().unwrap().len(), 34);
+    assert_eq!(unseen["1m"], 3);
+    assert_eq!(unseen["4m"], 4);
+    assert_eq!(unseen["5p"], 0);
+    assert_eq!(unseen["7z"], 0);
+    assert!(unseen.get("0p").is_none());
+
+    // Dora indicators are visible whether discarded from haiyama or not.
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+    for command in ["124m055p789s1122z3z", "dora 3m", "dora* 6m"].iter() {
+        execute(&mut controller, command).unwrap();
+    }
+    let output = execute(&mut controller, "unseen").unwrap().unwrap();
+    let unseen: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(unseen["3m"], 3);
+    assert_eq!(unseen["6m"], 3);
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, false);
+    assert!(execute(&mut controller, "unseen").is_err());
+}
+
+#[test]
+fn test_sort_key() {
+    let sutehai_order = |tehai: &str, sort_key| {
+        let mut controller =
+            Controller::new(OutputFormat::Json, PlayerNumber::Four, false).with_sort_key(sort_key);
+        let output = execute(&mut controller, tehai).unwrap().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        value["conditions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|condition| condition["sutehai"].as_str().unwrap().to_string())
+            .collect::<Vec<String>>()
+    };
+
+    let tehai = "24688m122224569s";
+    assert_eq!(sutehai_order(tehai, SortKey::Ukeire), ["9s", "2m", "6m"]);
+    assert_eq!(sutehai_order(tehai, SortKey::Sutehai), ["2m", "6m", "9s"]);
+    // Not tenpai, nothing to sort by wait.
+    assert_eq!(
+        sutehai_order(tehai, SortKey::WaitQuality),
+        ["9s", "2m", "6m"]
+    );
+
+    // Kanchan of 7s and shanpon of 2s and 8s have the same nokori.
+    let tehai = "444m234p22s456s889s";
+    assert_eq!(sutehai_order(tehai, SortKey::Ukeire), ["8s", "9s"]);
+    assert_eq!(sutehai_order(tehai, SortKey::WaitQuality), ["9s", "8s"]);
+}
+
+#[test]
+fn test_karaten() {
+    // Discarding 4s waits for 5z, all of which are in tehai.
+    let tehai = Tehai::new("123m456p789s5555z4s", PlayerNumber::Four).unwrap();
+    let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(shanten, 0);
+    assert_eq!(conditions.len(), 2);
+    assert_eq!(conditions[0].sutehai, Hai::Jihai(5));
+    assert!(!conditions[0].karaten);
+    assert_eq!(conditions[1].sutehai, Hai::Souzu(4));
+    assert!(conditions[1].karaten);
+    assert_eq!(conditions[1].nokori(), 0);
+    assert!(conditions[1].to_json()["karaten"].as_bool().unwrap());
+    assert!(conditions[1].to_string().ends_with("!空聴!"));
+
+    // Waits used up by visible hai in haiyama.
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    for op in [
+        Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("123m456p789s1122z3z", PlayerNumber::Four).unwrap(),
+        )),
+        Operation::Haiyama {
+            kind: HaiyamaOperation::Discard(
+                Hai::from_string_unordered("1122z", PlayerNumber::Four).unwrap(),
+            ),
+            haiyama_sensitive: true,
+        },
+    ]
+    .iter()
+    {
+        game_manager.operate(op.clone()).unwrap();
+    }
+    let (shanten, conditions) = game_manager.tehai_analyze().unwrap();
+    assert_eq!(shanten, 0);
+    assert_eq!(conditions.len(), 1);
+    assert!(conditions[0].karaten);
+
+    assert_eq!(
+        conditions[0].dead_machihai,
+        Hai::from_string_unordered("12z", PlayerNumber::Four)
+            .unwrap()
+            .into_iter()
+            .collect()
+    );
+}
+
+#[test]
+fn test_fast_shanten() {
+    // Every hand of 2, 5 and 8 hai in pinzu and 1z, with fuuro filling the rest.
+    fn check(counts: &mut [u8; 10], index: usize, left: u8, checked: &mut usize) {
+        if left == 0 {
+            let mut pinzu = [0; 9];
+            pinzu.copy_from_slice(&counts[..9]);
+            let number: u8 = counts.iter().sum();
+            let fuuro = (1..=(14 - number) / 3)
+                .map(|num| Mentsu::Koutsu(Hai::Souzu(num)))
+                .collect();
+            let tehai = Tehai::from_counts(
+                [0; 9],
+                pinzu,
+                [0; 9],
+                [counts[9], 0, 0, 0, 0, 0, 0],
+                fuuro,
+                PlayerNumber::Four,
+            )
+            .unwrap();
+            assert_eq!(
+                fast_shanten(&tehai, PlayerNumber::Four),
+                tehai.shanten(PlayerNumber::Four).unwrap(),
+                "{}",
+                tehai
+            );
+            *checked += 1;
+            return;
+        }
+        if index == counts.len() {
+            return;
+        }
+        for number in 0..=left.min(4) {
+            counts[index] = number;
+            check(counts, index + 1, left - number, checked);
+        }
+        counts[index] = 0;
+    }
+    let mut checked = 0;
+    for number in [2, 5, 8].iter() {
+        check(&mut [0; 10], 0, *number, &mut checked);
+    }
+    assert!(checked > 10000);
+
+    // 14 hai, with chiitoitsu and kokushimusou, and 3-players mode.
+    let cases = [
+        ("123m456p789s11222z", PlayerNumber::Four),
+        ("19m19p19s12345677z", PlayerNumber::Four),
+        ("19m19p19s1234566z5s", PlayerNumber::Four),
+        ("1122m3344p5566s77z", PlayerNumber::Four),
+        ("1111m2233p4455s66z", PlayerNumber::Four),
+        ("13579m2468p13579s", PlayerNumber::Four),
+        ("147m258p369s12345z", PlayerNumber::Four),
+        ("0m234567p11s12345z", PlayerNumber::Four),
+        ("19m123p456789s111z", PlayerNumber::Three),
+        ("1199m1357p2468s77z", PlayerNumber::Three),
+    ];
+    for (tehai, player_number) in cases.iter() {
+        let tehai = Tehai::new(tehai, *player_number).unwrap();
+        assert_eq!(
+            fast_shanten(&tehai, *player_number),
+            tehai.shanten(*player_number).unwrap(),
+            "{}",
+            tehai
+        );
+    }
+
+    // Chiitoitsu and kokushimusou of 13 hai.
+    let shanten = |string: &str| {
+        fast_shanten(
+            &Tehai::new(string, PlayerNumber::Four).unwrap(),
+            PlayerNumber::Four,
+        )
+    };
+    assert_eq!(shanten("1122m3344p5566s7z"), 0);
+    assert_eq!(shanten("19m19p19s1234567z"), 0);
+    assert_eq!(shanten("19m19p19s1234566z"), 0);
+    assert_eq!(shanten("1122m3344p5567s7z"), 1);
+}
+
+#[test]
+fn test_kokushimusou_thirteen_wait() {
+    let player_number = PlayerNumber::Four;
+    let yaochuupai = Hai::yaochuupai_type();
+
+    // Juusanmen waits for all 13 yaochuupai, 3 left each.
+    let tehai = Tehai::new("19m19p19s1234567z", player_number).unwrap();
+    let (shanten, acceptance) = tehai.acceptance(player_number, None).unwrap();
+    assert_eq!(shanten, 0);
+    assert_eq!(
+        acceptance.keys().copied().collect::<BTreeSet<Hai>>(),
+        yaochuupai
+    );
+    assert!(acceptance.values().all(|number| *number == 3));
+
+    // A toitsu and 11 others wait for the missing one only.
+    let tehai = Tehai::new("19m19p19s1234566z", player_number).unwrap();
+    let (shanten, acceptance) = tehai.acceptance(player_number, None).unwrap();
+    assert_eq!(shanten, 0);
+    assert_eq!(
+        acceptance.into_iter().collect::<Vec<_>>(),
+        [(Hai::Jihai(7), 4)]
+    );
+
+    // Waits with none left in haiyama are dropped.
+    let mut game_manager = GameManager::new(player_number);
+    for op in [
+        Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("19m19p19s1234567z", player_number).unwrap(),
+        )),
+        Operation::Haiyama {
+            kind: HaiyamaOperation::Discard(
+                Hai::from_string_unordered("111z9s", player_number).unwrap(),
+            ),
+            haiyama_sensitive: true,
+        },
+    ]
+    .iter()
+    {
+        game_manager.operate(op.clone()).unwrap();
+    }
+    let (_, acceptance) = game_manager
+        .tehai()
+        .unwrap()
+        .acceptance(player_number, Some(&game_manager))
+        .unwrap();
+    assert_eq!(acceptance.len(), 12);
+    assert!(!acceptance.contains_key(&Hai::Jihai(1)));
+    assert_eq!(acceptance[&Hai::Souzu(9)], 2);
+
+    let tehai = Tehai::new("19m19p19s1234567z5s", player_number).unwrap();
+    let (_, conditions) = tehai.analyze(player_number, None).unwrap();
+    assert_eq!(conditions[0].wait_kind(), Some(WaitKind::Multi));
+}
+
+#[test]
+fn test_closed_only() {
+    let mut controller =
+        Controller::new(OutputFormat::Standard, PlayerNumber::Four, false).with_closed_only(true);
+    assert!(execute(&mut controller, "123m456p789s1122z3z").is_ok());
+    assert_eq!(
+        execute(&mut controller, "123m456p1122z3z[789s]"),
+        Err("Fuuro [7s8s9s] not allowed in closed-only mode.".to_string())
+    );
+    assert!(execute(&mut controller, "matrix 123m4p1122z3z[456p][789s]")
+        .unwrap_err()
+        .contains("[4p5p6p][7s8s9s]"));
+
+    // Checked before naku, so the game is kept.
+    let mut controller =
+        Controller::new(OutputFormat::Standard, PlayerNumber::Four, true).with_closed_only(true);
+    execute(&mut controller, "123m456p78s1122z3z").unwrap();
+    assert_eq!(
+        execute(&mut controller, ">789s"),
+        Err("Fuuro [7s8s9s] not allowed in closed-only mode.".to_string())
+    );
+    assert!(execute(&mut controller, "+9s").is_ok());
+    assert!(execute(&mut controller, "-3z").is_ok());
+    // Ankan is also fuuro.
+    let mut controller =
+        Controller::new(OutputFormat::Standard, PlayerNumber::Four, true).with_closed_only(true);
+    execute(&mut controller, "123m456p789s1111z2z").unwrap();
+    assert_eq!(
+        execute(&mut controller, ">a1111z"),
+        Err("Fuuro [1z1z1z1z] not allowed in closed-only mode.".to_string())
+    );
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, false);
+    assert!(execute(&mut controller, "123m456p1122z3z[789s]").is_ok());
+}
+
+#[test]
+fn test_set_aside() {
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+    execute(&mut controller, "123m456p789s11222z").unwrap();
+    execute(&mut controller, "set 2z").unwrap();
+    assert!(execute(&mut controller, "set 2z").is_err());
+    assert!(execute(&mut controller, "set 1z2z").is_err());
+
+    let state = |controller: &mut Controller| {
+        let output = execute(controller, "s").unwrap().unwrap();
+        serde_json::from_str::<serde_json::Value>(&output).unwrap()
+    };
+    let json = state(&mut controller);
+    assert_eq!(json["set_aside"], serde_json::json!(["2z"]));
+    // Neither sutehai nor put back to haiyama.
+    assert_eq!(json["sutehai_type"], serde_json::json!([]));
+    assert_eq!(json["tehai"]["juntehai"].as_array().unwrap().len(), 13);
+    assert!(json["haiyama"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!({"2z": 1})));
+
+    execute(&mut controller, "b").unwrap();
+    let json = state(&mut controller);
+    assert_eq!(json["set_aside"], serde_json::json!([]));
+    assert_eq!(json["tehai"]["juntehai"].as_array().unwrap().len(), 14);
+}
+
+#[test]
+fn test_export() {
+    let replay = |commands: &[&str]| {
+        let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+        for command in commands {
+            execute(&mut controller, command).unwrap();
+        }
+        let output = execute(&mut controller, "export").unwrap().unwrap();
+        let exported: Vec<String> = serde_json::from_value(
+            serde_json::from_str::<serde_json::Value>(&output).unwrap()["commands"].clone(),
+        )
+        .unwrap();
+
+        let mut replayed = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+        for command in &exported {
+            execute(&mut replayed, command).unwrap();
+        }
+        for command in ["s", "log", "export"] {
+            assert_eq!(
+                execute(&mut controller, command),
+                execute(&mut replayed, command)
+            );
+        }
+        exported
+    };
+
+    let exported = replay(&[
+        "*-9m",
+        "111m456p789s1122z3z",
+        "-3z",
+        "+1m",
+        ">a1111m5s",
+        "-5s",
+        ">222z",
+        "-1z",
+        ">546p",
+        "-1z",
+        "+!7z",
+        "set 7z",
+        "+8s",
+        "-8s",
+        "*!+9m",
+    ]);
+    assert_eq!(
+        exported,
+        vec![
+            "4pl",
+            "i",
+            "*-9m",
+            "111m456p789s11223z",
+            "-3z",
+            "+1m",
+            ">a1111m5s",
+            "-5s",
+            ">222z",
+            "-1z",
+            ">456p",
+            "-1z",
+            "+!7z",
+            "set 7z",
+            "+8s",
+            "-8s",
+            "*!+9m",
+        ]
+    );
+
+    let exported = replay(&["3pl", "119m123p456s1122z4z", "kita", "+!5z"]);
+    assert_eq!(exported[0], "3pl");
+    assert_eq!(exported[3..], ["kita", "+!5z"]);
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, false);
+    assert!(execute(&mut controller, "export").is_err());
+}
+
+#[test]
+fn test_tenpai_only() {
+    let is_tenpai = |string: &str| {
+        Tehai::new(string, PlayerNumber::Four)
+            .unwrap()
+            .is_tenpai(PlayerNumber::Four)
+    };
+    assert!(is_tenpai("123m456p789s1122z3z"));
+    assert!(!is_tenpai("123m456p789s11222z"));
+    assert!(!is_tenpai("123m456p79s11223z4z"));
+    assert!(is_tenpai("123m456p1122z3z[789s]"));
+    assert!(is_tenpai("1122z3z[123m][456p][789s]"));
+    assert!(is_tenpai("123m456p789s1122z"));
+    assert!(is_tenpai("1122m3344p5566s7z"));
+    assert!(is_tenpai("123m456p1122z[789s]"));
+    assert!(!is_tenpai("123m456p79s11234z"));
+
+    let tehai_vec: Vec<Tehai> = [
+        "123m456p789s1122z3z",
+        "123m456p79s11223z4z",
+        "2z[123m][456p][789s][1111z]",
+    ]
+    .iter()
+    .map(|string| Tehai::new(string, PlayerNumber::Four).unwrap())
+    .collect();
+    let tenpai = Tehai::filter_tenpai(&tehai_vec, PlayerNumber::Four);
+    assert_eq!(tenpai.len(), 2);
+    assert_eq!(tenpai[0].to_canonical_string(), "123m456p789s11223z");
+
+    let lines =
+        "123m456p789s1122z3z\n123m456p79s11223z4z\n1z\n123m456p789s11222z\n123m456p1122z3z[789s]";
+    let mut controller =
+        Controller::new(OutputFormat::Json, PlayerNumber::Four, false).with_tenpai_only(true);
+    let output: serde_json::Value =
+        serde_json::from_str(&controller.execute_batch(lines.lines())).unwrap();
+    let results = output.as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["shanten_number"], 0);
+    assert_eq!(results[1]["line"], 3);
+    assert_eq!(results[2]["shanten_number"], 0);
+}
+
+#[test]
+fn test_raw_ukeire() {
+    let tehai = Tehai::new("1112345678999m5z", PlayerNumber::Four).unwrap();
+    let (_, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(conditions[0].sutehai, Hai::Jihai(5));
+    assert_eq!(conditions[0].raw_ukeire(), 36);
+    assert_eq!(conditions[0].nokori(), 23);
+    let json = conditions[0].to_json();
+    assert_eq!(json["raw_ukeire"], 36);
+    assert_eq!(json["effective_ukeire"], 23);
+    assert_eq!(json["effective_ukeire"], json["machihai_number"]);
+
+    // Dead machihai are still counted in raw ukeire.
+    let tehai = Tehai::new("123m456p789s5555z4s", PlayerNumber::Four).unwrap();
+    let (_, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+    assert_eq!(conditions[1].raw_ukeire(), 4);
+    assert_eq!(conditions[1].to_json()["effective_ukeire"], 0);
+}
+
+#[test]
+fn test_river() {
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("123m456p789s1122z", PlayerNumber::Four).unwrap(),
+        )))
+        .unwrap();
+    let river = |hai: &str, haiyama_sensitive: bool| Operation::River {
+        hai_vec: Hai::from_string_unordered(hai, PlayerNumber::Four).unwrap(),
+        haiyama_sensitive,
+    };
+    game_manager.operate(river("1z9m", true)).unwrap();
+    assert_eq!(game_manager.river(), &vec![Hai::Jihai(1), Hai::Manzu(9)]);
+    assert!(game_manager.sutehai_type().contains(&Hai::Jihai(1)));
+    assert_eq!(game_manager.haiyama().count(&Hai::Jihai(1)), 1);
+    assert_eq!(game_manager.haiyama().count(&Hai::Manzu(9)), 3);
+    assert_eq!(game_manager.state, State::LackOneHai);
+    assert_eq!(
+        game_manager.to_json()["river"],
+        serde_json::json!(["1z", "9m"])
+    );
+
+    // Nothing changes on failure.
+    assert!(game_manager.operate(river("1z1z", true)).is_err());
+    assert_eq!(game_manager.river().len(), 2);
+    assert_eq!(game_manager.haiyama().count(&Hai::Jihai(1)), 1);
+
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Add {
+            hai: Hai::Jihai(3),
+            haiyama_sensitive: true,
+        }))
+        .unwrap();
+    let (_, conditions) = game_manager.tehai_analyze().unwrap();
+    assert_eq!(conditions[0].sutehai, Hai::Jihai(3));
+    assert!(conditions[0].furiten);
+
+    game_manager.back(true).unwrap();
+    game_manager.back(true).unwrap();
+    assert!(game_manager.river().is_empty());
+    assert!(game_manager.sutehai_type().is_empty());
+    assert_eq!(game_manager.haiyama().count(&Hai::Jihai(1)), 2);
+    assert_eq!(game_manager.haiyama().count(&Hai::Manzu(9)), 4);
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+    for command in ["123m456p789s1122z", "river 1z9m", "river! 1z1z"] {
+        execute(&mut controller, command).unwrap();
+    }
+    let output = execute(&mut controller, "s").unwrap().unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(json["river"], serde_json::json!(["1z", "9m", "1z", "1z"]));
+    assert_eq!(json["sutehai_type"], serde_json::json!(["9m", "1z"]));
+    let output = execute(&mut controller, "export").unwrap().unwrap();
+    assert!(output.contains("\"river 1z9m\",\"river! 11z\""));
+}
+
+#[test]
+fn test_hai_suit_number() {
+    for hai in Hai::all_type(PlayerNumber::Four) {
+        assert_eq!(Hai::new(hai.suit(), hai.number()), Some(hai));
+    }
+    assert_eq!(Hai::Pinzu(7).suit(), Suit::Pin);
+    assert_eq!(Hai::Jihai(5).suit(), Suit::Ji);
+    assert_eq!(Hai::Souzu(0).number(), 0);
+    assert_eq!(Hai::Souzu(0).normalized().number(), 5);
+    assert_eq!(Hai::new(Suit::Man, 0), Some(Hai::Manzu(0)));
+    assert_eq!(Hai::new(Suit::Sou, 10), None);
+    assert_eq!(Hai::new(Suit::Ji, 0), None);
+    assert_eq!(Hai::new(Suit::Ji, 8), None);
+}
+
+#[test]
+fn test_pinzu_taatsu() {
+    let machihai = |string: &str| {
+        let tehai = Tehai::new(string, PlayerNumber::Four).unwrap();
+        let (shanten, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+        assert_eq!(shanten, 0);
+        conditions[0].machihai.keys().copied().collect::<Vec<_>>()
+    };
+    assert_eq!(
+        machihai("123m456s789s45p11z9p"),
+        vec![Hai::Pinzu(3), Hai::Pinzu(6)]
+    );
+    assert_eq!(machihai("123m456s789s12p11z9p"), vec![Hai::Pinzu(3)]);
+    assert_eq!(machihai("123m456s789s89p11z1p"), vec![Hai::Pinzu(7)]);
+    assert_eq!(
+        machihai("123m456m789m123p45p11z9s"),
+        vec![Hai::Pinzu(3), Hai::Pinzu(6)]
+    );
+}
+
+#[test]
+fn test_possible_naku() {
+    let possible_naku = |string: &str, discard: Hai, player_number: PlayerNumber| {
+        let tehai = Tehai::new(string, player_number).unwrap();
+        tehai
+            .possible_naku(discard, player_number)
+            .iter()
+            .map(|naku| match naku {
+                Naku::Chii { juntsu, .. } => format!("chii {}", juntsu),
+                Naku::Pon(koutsu) => format!("pon {}", koutsu),
+                Naku::Kan(Kan::Daiminkan { kantsu, .. }) => format!("kan {}", kantsu),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<String>>()
+    };
+
+    assert_eq!(
+        possible_naku("2346777p111z", Hai::Pinzu(5), PlayerNumber::Four),
+        vec!["chii [3p4p5p]", "chii [4p5p6p]", "chii [5p6p7p]"]
+    );
+    assert_eq!(
+        possible_naku("1234777p111z", Hai::Pinzu(7), PlayerNumber::Four),
+        vec!["pon [7p7p7p]", "kan [7p7p7p7p]"]
+    );
+    assert_eq!(
+        possible_naku("0p5p4p6p112z", Hai::Pinzu(0), PlayerNumber::Four),
+        vec!["chii [4p5p6p]", "pon [5p5p5p]"]
+    );
+    assert_eq!(
+        possible_naku("23s11556z[123m]", Hai::Souzu(1), PlayerNumber::Four),
+        vec!["chii [1s2s3s]"]
+    );
+    assert!(possible_naku("23s11556z[123m]", Hai::Jihai(2), PlayerNumber::Four).is_empty());
+    assert_eq!(
+        possible_naku("23s11556z[123m]", Hai::Jihai(5), PlayerNumber::Four),
+        vec!["pon [5z5z5z]"]
+    );
+    assert_eq!(
+        possible_naku("99m123456p789s12z", Hai::Manzu(9), PlayerNumber::Three),
+        vec!["pon [9m9m9m]"]
+    );
+    assert!(possible_naku("2346777p111z1s", Hai::Pinzu(5), PlayerNumber::Four).is_empty());
+}
+
+#[test]
+fn test_naku_with_aka() {
+    // Pon 5m with 05m on hand.
+    let mut tehai = Tehai::new("05m123p456s789s11z", PlayerNumber::Four).unwrap();
+    tehai.pon(&Mentsu::Koutsu(Hai::Manzu(5))).unwrap();
+    assert_eq!(tehai.to_string(), "1p2p3p4s5s6s7s8s9s1z1z[5m5m5m]");
+
+    // Chii 3m with 40m on hand.
+    let mut tehai = Tehai::new("40m123p456s789s11z", PlayerNumber::Four).unwrap();
+    tehai
+        .chii(
+            &Mentsu::Juntsu(Hai::Manzu(3), Hai::Manzu(4), Hai::Manzu(5)),
+            &Hai::Manzu(3),
+        )
+        .unwrap();
+    assert_eq!(tehai.to_string(), "1p2p3p4s5s6s7s8s9s1z1z[3m4m5m]");
+
+    // Ankan 5m with 0555m on hand, and normal five is taken before aka.
+    let mut tehai = Tehai::new("0555m123p456s789s1z", PlayerNumber::Four).unwrap();
+    let kan = tehai.kan(&Mentsu::Kantsu(Hai::Manzu(5)), &None).unwrap();
+    assert!(matches!(kan, Kan::Ankan { .. }));
+    assert_eq!(tehai.to_string(), "1p2p3p4s5s6s7s8s9s1z[5m5m5m5m]");
+    let mut tehai = Tehai::new("0555m123p456s789s", PlayerNumber::Four).unwrap();
+    tehai.pon(&Mentsu::Koutsu(Hai::Manzu(5))).unwrap();
+    assert_eq!(tehai.to_string(), "5m0m1p2p3p4s5s6s7s8s9s[5m5m5m]");
+
+    // Kakan 5m with aka on hand.
+    let mut tehai = Tehai::new("0m123p456s789s1z[555m]", PlayerNumber::Four).unwrap();
+    let kan = tehai.kan(&Mentsu::Kantsu(Hai::Manzu(5)), &None).unwrap();
+    assert!(matches!(kan, Kan::Kakan { .. }));
+    assert_eq!(tehai.to_string(), "1p2p3p4s5s6s7s8s9s1z[5m5m5m5m]");
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, true);
+    execute(&mut controller, "05m123p456s789s11z").unwrap();
+    execute(&mut controller, ">055m").unwrap();
+    execute(&mut controller, "-1z").unwrap();
+    execute(&mut controller, "+0p").unwrap();
+    execute(&mut controller, "-1p").unwrap();
+    execute(&mut controller, "+5p").unwrap();
+    execute(&mut controller, "-4s").unwrap();
+    execute(&mut controller, "+5p").unwrap();
+    execute(&mut controller, "-7s").unwrap();
+    execute(&mut controller, "+5p").unwrap();
+    // Ankan with aka, getting rinshanhai at the same time.
+    let output = execute(&mut controller, ">0555p7s").unwrap().unwrap();
+    assert!(output.contains("[5p5p5p5p]"));
+}
+
+#[test]
+fn test_ruleset() {
+    let default = Ruleset::default();
+    assert!(default.kuitan && default.atozuke && default.kita_dora);
+    assert!(!default.kuikae_allowed);
+    assert_eq!(default.aka_count, 3);
+    assert_eq!(GameManager::new(PlayerNumber::Four).ruleset(), default);
+
+    // Kuikae allowed by the ruleset.
+    let player_number = PlayerNumber::Four;
+    let ruleset = Ruleset {
+        kuikae_allowed: true,
+        ..Ruleset::default()
+    };
+    let mut game_manager = GameManager::new(player_number).with_ruleset(ruleset);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("2345m456p789s112z", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Chii {
+                juntsu: Mentsu::Juntsu(Hai::Manzu(1), Hai::Manzu(2), Hai::Manzu(3)),
+                nakihai: Hai::Manzu(1),
+            },
+            haiyama_sensitive: true,
+            taken: vec![],
+        }))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Discard(Hai::Manzu(4))))
+        .unwrap();
+
+    // No kita without kita dora, which is kept after reinitializing.
+    let player_number = PlayerNumber::Three;
+    let ruleset = Ruleset {
+        kita_dora: false,
+        ..Ruleset::default()
+    };
+    let mut game_manager = GameManager::new(player_number).with_ruleset(ruleset);
+    game_manager.reinitialize(player_number);
+    assert_eq!(game_manager.ruleset(), ruleset);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("19m123p456s789s144z", player_number).unwrap(),
+        )))
+        .unwrap();
+    let kita = Operation::Tehai(TehaiOperation::Kita {
+        haiyama_sensitive: true,
+    });
+    assert!(game_manager.operate(kita.clone()).is_err());
+    assert_eq!(game_manager.kita(), 0);
+    let mut game_manager = GameManager::new(player_number);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("19m123p456s789s144z", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager.operate(kita).unwrap();
+    assert_eq!(game_manager.kita(), 1);
+}
+
+#[test]
+fn test_machihai_by_shape() {
+    let by_shape = |string: &str, sutehai: Hai| {
+        let tehai = Tehai::new(string, PlayerNumber::Four).unwrap();
+        let (_, conditions) = tehai.analyze(PlayerNumber::Four, None).unwrap();
+        let condition = conditions
+            .into_iter()
+            .find(|condition| condition.sutehai == sutehai)
+            .unwrap();
+        condition
+            .by_shape
+            .iter()
+            .map(|(shape, machihai)| {
+                let machihai: Vec<String> = machihai.iter().map(|hai| hai.to_string()).collect();
+                format!("{}:{}", shape, machihai.join(","))
+            })
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(
+        by_shape("1239m456789s45p11z", Hai::Manzu(9)),
+        ["4p5p:3p,6p"]
+    );
+    assert_eq!(
+        by_shape("1239m456789s55p11z", Hai::Manzu(9)),
+        ["1z1z:1z", "5p5p:5p"]
+    );
+    assert_eq!(by_shape("1199m1155p1166s7z1s", Hai::Souzu(1)), ["7z:7z"]);
+
+    let mut controller =
+        Controller::new(OutputFormat::Json, PlayerNumber::Four, false).with_show_shapes(true);
+    let output = execute(&mut controller, "1239m456789s45p11z")
+        .unwrap()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let condition = &json["conditions"][0];
+    assert_eq!(condition["sutehai"], "9m");
+    assert_eq!(
+        condition["by_shape"],
+        serde_json::json!([{ "shape": "4p5p", "machihai": ["3p", "6p"] }])
+    );
+    assert_eq!(condition["machihai"].as_array().unwrap().len(), 2);
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    let output = execute(&mut controller, "1239m456789s45p11z")
+        .unwrap()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert!(json["conditions"][0].get("by_shape").is_none());
+}
+
+#[test]
+fn test_hai_sort_key() {
+    let mut hai_vec =
+        Hai::from_string_unordered("7654321z9s0p5p1p5s09m5m1m", PlayerNumber::Four).unwrap();
+    hai_vec.sort_by_key(Hai::sort_key);
+    let sorted: Vec<String> = hai_vec.iter().map(|hai| hai.to_string()).collect();
+    assert_eq!(
+        sorted,
+        [
+            "1m", "5m", "0m", "9m", "1p", "5p", "0p", "5s", "9s", "1z", "2z", "3z", "4z", "5z",
+            "6z", "7z"
+        ]
+    );
+
+    let tehai = Tehai::new("7z0p5p9s1z0m1m", PlayerNumber::Four).unwrap();
+    assert_eq!(
+        tehai.juntehai,
+        [
+            Hai::Manzu(1),
+            Hai::Manzu(0),
+            Hai::Pinzu(5),
+            Hai::Pinzu(0),
+            Hai::Souzu(9),
+            Hai::Jihai(1),
+            Hai::Jihai(7)
+        ]
+    );
+}
+
+#[test]
+fn test_is_furiten() {
+    let mut game_manager = GameManager::new(PlayerNumber::Four);
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("123m456p789s1122z", PlayerNumber::Four).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::River {
+            hai_vec: Hai::from_string_unordered("7z0p1m", PlayerNumber::Four).unwrap(),
+            haiyama_sensitive: true,
+        })
+        .unwrap();
+    assert_eq!(
+        game_manager.sutehai_type_sorted(),
+        [Hai::Manzu(1), Hai::Pinzu(0), Hai::Jihai(7)]
+    );
+
+    let waits = |hai: &str| -> BTreeMap<Hai, u8> {
+        Hai::from_string_unordered(hai, PlayerNumber::Four)
+            .unwrap()
+            .into_iter()
+            .map(|hai| (hai, 4))
+            .collect()
+    };
+    assert!(game_manager.is_furiten(&waits("14m")));
+    assert!(game_manager.is_furiten(&waits("5p")));
+    assert!(game_manager.is_furiten(&waits("7z")));
+    assert!(!game_manager.is_furiten(&waits("25m")));
+    assert!(!game_manager.is_furiten(&BTreeMap::new()));
+}
+
+#[test]
+fn test_count_iipeikou() {
+    let max_iipeikou = |string: &str| {
+        let tehai = Tehai::new(string, PlayerNumber::Four).unwrap();
+        let (shanten, _, breakdowns) = tehai.analyze_verbose(PlayerNumber::Four, None).unwrap();
+        assert_eq!(shanten, -1);
+        breakdowns
+            .iter()
+            .map(|breakdown| breakdown.count_iipeikou())
+            .max()
+            .unwrap()
+    };
+    assert_eq!(max_iipeikou("112233m456p789s11z"), 1);
+    assert_eq!(max_iipeikou("112233445566m11z"), 2);
+    assert_eq!(max_iipeikou("123m123p456789s11z"), 0);
+
+    // Chiitoitsu breakdowns never count.
+    let tehai = Tehai::new("112233445566m11z", PlayerNumber::Four).unwrap();
+    let (_, _, breakdowns) = tehai.analyze_verbose(PlayerNumber::Four, None).unwrap();
+    assert!(breakdowns
+        .iter()
+        .any(|breakdown| breakdown.hourakei == Hourakei::Chiitoitsu));
+    for breakdown in &breakdowns {
+        if breakdown.hourakei == Hourakei::Chiitoitsu {
+            assert_eq!(breakdown.count_iipeikou(), 0);
+        }
+    }
+}
+
+#[test]
+fn test_is_menzen() {
+    let player_number = PlayerNumber::Four;
+    let is_menzen = |string: &str| Tehai::new(string, player_number).unwrap().is_menzen();
+    assert!(is_menzen("123m456p789s1122z"));
+    assert!(is_menzen("123m456p789s5z a2222z"));
+    assert!(!is_menzen("123m456p789s5z m2222z"));
+    assert!(!is_menzen("123m456p789s5z k2222z"));
+    assert!(!is_menzen("123m456p789s5z [2222z]"));
+    assert!(!is_menzen("123m456p789s5z p222z"));
+    assert!(!is_menzen("123m456p5z c789s a2222z"));
+
+    // Riichi and menzen tsumo are available with ankan.
+    let context = AgariContext {
+        agari_hai: Hai::Jihai(5),
+        is_tsumo: true,
+        riichi: true,
+        seat_wind: Hai::Jihai(2),
+        round_wind: Hai::Jihai(1),
+        dora_indicators: vec![],
+    };
+    let yaku = Tehai::new("234m456p789s55z a1111z", player_number)
+        .unwrap()
+        .detect_yaku(&context, player_number);
+    assert!(yaku.contains(&Yaku::Riichi));
+    assert!(yaku.contains(&Yaku::MenzenTsumo));
+    let yaku = Tehai::new("234m456p789s55z m1111z", player_number)
+        .unwrap()
+        .detect_yaku(&context, player_number);
+    assert!(!yaku.contains(&Yaku::MenzenTsumo));
+
+    let mut game_manager = GameManager::new(player_number);
+    assert!(game_manager.is_menzen());
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Initialize(
+            Tehai::new("1111222m345p678s5z", player_number).unwrap(),
+        )))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Kan(Kan::Ankan {
+                kantsu: Mentsu::Kantsu(Hai::Manzu(1)),
+                rinshanhai: Some(Hai::Jihai(5)),
+            }),
+            haiyama_sensitive: true,
+            taken: vec![],
+        }))
+        .unwrap();
+    assert!(game_manager.is_menzen());
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Discard(Hai::Manzu(2))))
+        .unwrap();
+    game_manager
+        .operate(Operation::Tehai(TehaiOperation::Naku {
+            kind: Naku::Pon(Mentsu::Koutsu(Hai::Jihai(5))),
+            haiyama_sensitive: true,
+            taken: vec![],
+        }))
+        .unwrap();
+    assert!(!game_manager.is_menzen());
+    game_manager.back(true).unwrap();
+    assert!(game_manager.is_menzen());
+}
+
+#[test]
+fn test_shanten_command() {
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, true);
+    assert!(execute(&mut controller, "shanten").is_err());
+    execute(&mut controller, "123m456p789s1122z").unwrap();
+    // Not full with hai.
+    assert!(execute(&mut controller, "shanten").is_err());
+    execute(&mut controller, "+3z").unwrap();
+    let output = execute(&mut controller, "shanten").unwrap().unwrap();
+    assert_eq!(output, r#"{"shanten_number":0}"#);
+    execute(&mut controller, "-3z").unwrap();
+    execute(&mut controller, "+1z").unwrap();
+    let output = execute(&mut controller, "shanten").unwrap().unwrap();
+    assert_eq!(output, r#"{"shanten_number":-1}"#);
+
+    let mut controller = Controller::new(OutputFormat::Standard, PlayerNumber::Four, true);
+    execute(&mut controller, "159m159p159s1234z").unwrap();
+    execute(&mut controller, "+5z").unwrap();
+    let tehai = Tehai::new("159m159p159s12345z", PlayerNumber::Four).unwrap();
+    assert_eq!(
+        execute(&mut controller, "shanten").unwrap(),
+        Some(format!(
+            "向聴：{}",
+            tehai.shanten(PlayerNumber::Four).unwrap()
+        ))
+    );
+
+    let mut controller = Controller::new(OutputFormat::Json, PlayerNumber::Four, false);
+    assert!(execute(&mut controller, "shanten").is_err());
 }